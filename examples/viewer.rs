@@ -9,8 +9,9 @@ use axum::{
     routing::get,
     Router,
 };
+use sinatra::domain::key_token;
 use std::env;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::{
@@ -25,6 +26,8 @@ const VIEWER_HTML: &str = include_str!("viewer/index.html");
 #[derive(Clone)]
 struct AppState {
     client: Client,
+    /// The `KEY_SECRET` of the server, to issue tokens for its key endpoint.
+    key_secret: Option<String>,
 }
 
 #[tokio::main]
@@ -58,11 +61,15 @@ async fn main() {
         .build();
 
     let client = Client::from_conf(s3_config);
-    let state = AppState { client };
+    let key_secret = env::var("KEY_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty());
+    let state = AppState { client, key_secret };
 
     let app = Router::new()
         .route("/", get(serve_viewer))
         .route("/presign", get(get_presigned_url))
+        .route("/key-token", get(get_key_token))
         .with_state(state);
 
     let listener = tokio::net::TcpListener::bind(&addr)
@@ -155,3 +162,35 @@ async fn get_presigned_url(
         uuid_key: key,
     }))
 }
+
+#[derive(Deserialize)]
+struct KeyTokenRequest {
+    video_id: String,
+}
+
+#[derive(Serialize)]
+struct KeyTokenResponse {
+    token: String,
+}
+
+/// A token for the content keys of an encrypted video. The viewer lets anyone
+/// watch anything; a real application would check who is asking first.
+async fn get_key_token(
+    State(state): State<AppState>,
+    Query(params): Query<KeyTokenRequest>,
+) -> Result<Json<KeyTokenResponse>, (axum::http::StatusCode, String)> {
+    let Some(secret) = &state.key_secret else {
+        return Err((
+            axum::http::StatusCode::NOT_FOUND,
+            "KEY_SECRET is not set".to_string(),
+        ));
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let expires = now + 3600; // 1 hour
+
+    Ok(Json(KeyTokenResponse {
+        token: key_token::issue(secret, &params.video_id, expires),
+    }))
+}
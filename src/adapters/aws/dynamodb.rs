@@ -1,11 +1,16 @@
+use crate::domain::encryption::ContentKey;
 use crate::domain::jobs::VideoStatus;
+use crate::domain::renditions::SubtitleTrack;
+use crate::domain::segment_plan::Keyframe;
+use crate::ports::keys::KeyStorePort;
 use crate::ports::repository::VideoStateRepository;
 use async_trait::async_trait;
 use aws_sdk_dynamodb::types::AttributeValue;
 use aws_sdk_dynamodb::Client;
+use std::collections::HashMap;
 use std::error::Error;
 
-/// DynamoAdapter implements VideoStateRepository for AWS DynamoDB.
+/// DynamoAdapter implements VideoStateRepository and KeyStorePort for AWS DynamoDB.
 #[derive(Clone)]
 pub struct DynamoAdapter {
     client: Client,
@@ -24,7 +29,16 @@ impl VideoStateRepository for DynamoAdapter {
         &self,
         status: &VideoStatus,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let segment_durations_json = serde_json::to_string(&status.segment_durations)?;
+        let segment_plan_json = serde_json::to_string(&status.segment_plan)?;
+        let renditions_json = serde_json::to_string(&status.renditions)?;
+        let audio_tracks_json = serde_json::to_string(&status.audio_tracks)?;
+        let subtitle_tracks_json = serde_json::to_string(&status.subtitle_tracks)?;
+        let packaging_json = serde_json::to_string(&status.packaging)?;
+        let container_json = serde_json::to_string(&status.container)?;
+        let loudness_json = serde_json::to_string(&status.loudness)?;
+        let audio_decisions_json = serde_json::to_string(&status.audio_decisions)?;
+        let preflight_json = serde_json::to_string(&status.preflight)?;
+        let encryption_json = serde_json::to_string(&status.encryption)?;
 
         self.client
             .put_item()
@@ -43,10 +57,20 @@ impl VideoStateRepository for DynamoAdapter {
                 AttributeValue::N(status.total_segments.to_string()),
             )
             .item("completed_segments", AttributeValue::N("0".to_string()))
-            .item(
-                "segment_durations",
-                AttributeValue::S(segment_durations_json),
-            )
+            .item("segment_sizes", AttributeValue::M(HashMap::new()))
+            .item("keyframes", AttributeValue::M(HashMap::new()))
+            .item("content_keys", AttributeValue::M(HashMap::new()))
+            .item("captions", AttributeValue::M(HashMap::new()))
+            .item("segment_plan", AttributeValue::S(segment_plan_json))
+            .item("renditions", AttributeValue::S(renditions_json))
+            .item("audio_tracks", AttributeValue::S(audio_tracks_json))
+            .item("subtitle_tracks", AttributeValue::S(subtitle_tracks_json))
+            .item("packaging", AttributeValue::S(packaging_json))
+            .item("container", AttributeValue::S(container_json))
+            .item("loudness", AttributeValue::S(loudness_json))
+            .item("audio_decisions", AttributeValue::S(audio_decisions_json))
+            .item("preflight", AttributeValue::S(preflight_json))
+            .item("encryption", AttributeValue::S(encryption_json))
             .send()
             .await?;
         Ok(())
@@ -85,8 +109,67 @@ impl VideoStateRepository for DynamoAdapter {
                 .and_then(|v| v.as_n().ok())
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(0);
-            let segment_durations: Vec<f64> = item
-                .get("segment_durations")
+            let segment_plan = item
+                .get("segment_plan")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let renditions = item
+                .get("renditions")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let audio_tracks = item
+                .get("audio_tracks")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let mut subtitle_tracks: Vec<SubtitleTrack> = item
+                .get("subtitle_tracks")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            // Sidecar captions, a JSON string per track name.
+            let mut captions: Vec<SubtitleTrack> = item
+                .get("captions")
+                .and_then(|v| v.as_m().ok())
+                .into_iter()
+                .flat_map(|captions| captions.values())
+                .filter_map(|v| v.as_s().ok())
+                .filter_map(|s| serde_json::from_str(s).ok())
+                .collect();
+            captions.sort_by(|a, b| a.name.cmp(&b.name));
+            for track in captions {
+                subtitle_tracks.retain(|t| t.name != track.name);
+                subtitle_tracks.push(track);
+            }
+            let packaging = item
+                .get("packaging")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let container = item
+                .get("container")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let loudness = item
+                .get("loudness")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let audio_decisions = item
+                .get("audio_decisions")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let preflight = item
+                .get("preflight")
+                .and_then(|v| v.as_s().ok())
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default();
+            let encryption = item
+                .get("encryption")
                 .and_then(|v| v.as_s().ok())
                 .and_then(|s| serde_json::from_str(s).ok())
                 .unwrap_or_default();
@@ -96,7 +179,16 @@ impl VideoStateRepository for DynamoAdapter {
                 source_path,
                 hls_dir,
                 total_segments,
-                segment_durations,
+                segment_plan,
+                renditions,
+                audio_tracks,
+                subtitle_tracks,
+                packaging,
+                container,
+                loudness,
+                audio_decisions,
+                preflight,
+                encryption,
             }))
         } else {
             Ok(None)
@@ -136,13 +228,200 @@ impl VideoStateRepository for DynamoAdapter {
         Ok(status.map(|s| s.total_segments).unwrap_or(0))
     }
 
+    async fn record_segment_size(
+        &self,
+        video_id: &str,
+        rendition: &str,
+        segment_index: usize,
+        size: u64,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Sizes live in a flat map keyed "<rendition>:<index>", created empty by
+        // save_video_status so that entries can be set without a read.
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .update_expression("SET segment_sizes.#segment = :size")
+            .expression_attribute_names("#segment", format!("{}:{}", rendition, segment_index))
+            .expression_attribute_values(":size", AttributeValue::N(size.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn get_segment_sizes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+    ) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .projection_expression("segment_sizes")
+            .send()
+            .await?;
+
+        let prefix = format!("{}:", rendition);
+        let mut sizes = Vec::new();
+        let entries = resp
+            .item
+            .and_then(|mut item| item.remove("segment_sizes"))
+            .and_then(|v| v.as_m().ok().cloned())
+            .unwrap_or_default();
+        for (key, value) in entries {
+            let index = key
+                .strip_prefix(&prefix)
+                .and_then(|index| index.parse::<usize>().ok());
+            let size = value.as_n().ok().and_then(|n| n.parse::<u64>().ok());
+            if let (Some(index), Some(size)) = (index, size) {
+                if sizes.len() <= index {
+                    sizes.resize(index + 1, 0);
+                }
+                sizes[index] = size;
+            }
+        }
+        Ok(sizes)
+    }
+
+    async fn record_keyframes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+        segment_index: usize,
+        keyframes: &[Keyframe],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Keyframes are JSON strings in a flat map keyed like segment_sizes.
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .update_expression("SET keyframes.#segment = :keyframes")
+            .expression_attribute_names("#segment", format!("{}:{}", rendition, segment_index))
+            .expression_attribute_values(
+                ":keyframes",
+                AttributeValue::S(serde_json::to_string(keyframes)?),
+            )
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn get_keyframes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+    ) -> Result<Vec<Vec<Keyframe>>, Box<dyn Error + Send + Sync>> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .projection_expression("keyframes")
+            .send()
+            .await?;
+
+        let prefix = format!("{}:", rendition);
+        let mut keyframes = Vec::new();
+        let entries = resp
+            .item
+            .and_then(|mut item| item.remove("keyframes"))
+            .and_then(|v| v.as_m().ok().cloned())
+            .unwrap_or_default();
+        for (key, value) in entries {
+            let index = key
+                .strip_prefix(&prefix)
+                .and_then(|index| index.parse::<usize>().ok());
+            let segment = value
+                .as_s()
+                .ok()
+                .and_then(|s| serde_json::from_str::<Vec<Keyframe>>(s).ok());
+            if let (Some(index), Some(segment)) = (index, segment) {
+                if keyframes.len() <= index {
+                    keyframes.resize(index + 1, Vec::new());
+                }
+                keyframes[index] = segment;
+            }
+        }
+        Ok(keyframes)
+    }
+
+    async fn add_subtitle_track(
+        &self,
+        video_id: &str,
+        track: &SubtitleTrack,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Tracks are JSON strings in a map keyed by track name, created empty by
+        // save_video_status and left by cleanup_video: setting one leaves the others.
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .update_expression("SET captions.#name = :track")
+            .condition_expression("attribute_exists(video_id)")
+            .expression_attribute_names("#name", &track.name)
+            .expression_attribute_values(":track", AttributeValue::S(serde_json::to_string(track)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+
     async fn cleanup_video(&self, video_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         self.client
-            .delete_item()
+            .update_item()
             .table_name(&self.table_name)
             .key("video_id", AttributeValue::S(video_id.to_string()))
+            .update_expression("REMOVE completed_segments, segment_sizes, keyframes")
             .send()
             .await?;
         Ok(())
     }
 }
+
+#[async_trait]
+impl KeyStorePort for DynamoAdapter {
+    async fn save_key(
+        &self,
+        video_id: &str,
+        period: usize,
+        key: &ContentKey,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Keys are hex strings in a map keyed by period, created empty by
+        // save_video_status and left by cleanup_video.
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .update_expression("SET content_keys.#period = :key")
+            .expression_attribute_names("#period", period.to_string())
+            .expression_attribute_values(":key", AttributeValue::S(key.to_hex()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn get_key(
+        &self,
+        video_id: &str,
+        period: usize,
+    ) -> Result<Option<ContentKey>, Box<dyn Error + Send + Sync>> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("video_id", AttributeValue::S(video_id.to_string()))
+            .projection_expression("content_keys.#period")
+            .expression_attribute_names("#period", period.to_string())
+            .send()
+            .await?;
+
+        let hex = resp
+            .item
+            .and_then(|mut item| item.remove("content_keys"))
+            .and_then(|v| v.as_m().ok().cloned())
+            .and_then(|mut keys| keys.remove(&period.to_string()))
+            .and_then(|v| v.as_s().ok().cloned());
+        Ok(hex.map(|hex| ContentKey::from_hex(&hex)).transpose()?)
+    }
+}
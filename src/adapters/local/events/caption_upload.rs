@@ -0,0 +1,36 @@
+use crate::application::orchestrator::OrchestratorService;
+use crate::domain::options::CaptionOptions;
+use crate::ports::{
+    keys::KeyStorePort, queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub async fn handle<S, Q, R, K>(
+    path: PathBuf,
+    metadata: Option<HashMap<String, String>>,
+    orchestrator: Arc<OrchestratorService<S, Q, R, K>>,
+) where
+    S: StoragePort,
+    Q: JobQueuePort,
+    R: VideoStateRepository,
+    K: KeyStorePort,
+{
+    println!("Event: CaptionUpload for {:?}", path);
+    let metadata = metadata.unwrap_or_default();
+
+    let options = match CaptionOptions::from_metadata(&metadata) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Rejecting captions {:?}: {}", path, e);
+            return;
+        }
+    };
+
+    let key = path.to_string_lossy().to_string();
+
+    if let Err(e) = orchestrator.handle_captions(&key, &options).await {
+        eprintln!("Error enqueuing captions: {:?}", e);
+    }
+}
@@ -1,14 +1,19 @@
 use super::hub::EventHub;
 use super::FileEvent;
 use crate::application::orchestrator::OrchestratorService;
-use crate::ports::{queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort};
+use crate::ports::{
+    keys::KeyStorePort, queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort,
+};
 use std::sync::Arc;
 
-pub fn start<S, Q, R>(event_hub: Arc<EventHub>, orchestrator: Arc<OrchestratorService<S, Q, R>>)
-where
+pub fn start<S, Q, R, K>(
+    event_hub: Arc<EventHub>,
+    orchestrator: Arc<OrchestratorService<S, Q, R, K>>,
+) where
     S: StoragePort + Send + Sync + 'static,
     Q: JobQueuePort + Send + Sync + 'static,
     R: VideoStateRepository + Send + Sync + 'static,
+    K: KeyStorePort + Send + Sync + 'static,
 {
     let mut rx = event_hub.subscribe();
 
@@ -29,6 +34,13 @@ where
                 } => {
                     super::nle_upload::handle(path, metadata, orchestrator.clone()).await;
                 }
+                FileEvent::CaptionUpload {
+                    path,
+                    bucket: _,
+                    metadata,
+                } => {
+                    super::caption_upload::handle(path, metadata, orchestrator.clone()).await;
+                }
             }
         }
     });
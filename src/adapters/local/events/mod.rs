@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+pub mod caption_upload;
 pub mod hub;
 pub mod listener;
 pub mod nle_upload;
@@ -19,4 +20,10 @@ pub enum FileEvent {
         bucket: String,
         metadata: Option<HashMap<String, String>>,
     },
+    /// Sidecar captions for a video already uploaded.
+    CaptionUpload {
+        path: PathBuf,
+        bucket: String,
+        metadata: Option<HashMap<String, String>>,
+    },
 }
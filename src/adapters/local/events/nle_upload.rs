@@ -1,17 +1,20 @@
 use crate::application::orchestrator::OrchestratorService;
-use crate::ports::{queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort};
+use crate::ports::{
+    keys::KeyStorePort, queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort,
+};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-pub async fn handle<S, Q, R>(
+pub async fn handle<S, Q, R, K>(
     _path: PathBuf,
     metadata: Option<HashMap<String, String>>,
-    _orchestrator: Arc<OrchestratorService<S, Q, R>>,
+    _orchestrator: Arc<OrchestratorService<S, Q, R, K>>,
 ) where
     S: StoragePort,
     Q: JobQueuePort,
     R: VideoStateRepository,
+    K: KeyStorePort,
 {
     println!("Event: NleUpload - Not Implemented (Orchestrator ignored)");
     if let Some(meta) = metadata {
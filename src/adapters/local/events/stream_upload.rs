@@ -1,27 +1,40 @@
 use crate::application::orchestrator::OrchestratorService;
-use crate::ports::{queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort};
+use crate::domain::options::VideoOptions;
+use crate::ports::{
+    keys::KeyStorePort, queue::JobQueuePort, repository::VideoStateRepository, storage::StoragePort,
+};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-pub async fn handle<S, Q, R>(
+pub async fn handle<S, Q, R, K>(
     path: PathBuf,
     metadata: Option<HashMap<String, String>>,
-    orchestrator: Arc<OrchestratorService<S, Q, R>>,
+    orchestrator: Arc<OrchestratorService<S, Q, R, K>>,
 ) where
     S: StoragePort,
     Q: JobQueuePort,
     R: VideoStateRepository,
+    K: KeyStorePort,
 {
     println!("Event: StreamUpload for {:?}", path);
-    if let Some(meta) = metadata {
-        println!("  Metadata: {:?}", meta);
+    let metadata = metadata.unwrap_or_default();
+    if !metadata.is_empty() {
+        println!("  Metadata: {:?}", metadata);
     }
 
+    let options = match VideoOptions::from_metadata(&metadata) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Rejecting upload {:?}: {}", path, e);
+            return;
+        }
+    };
+
     // Convert path to key. For local FS, key is the path string.
     let key = path.to_string_lossy().to_string();
 
-    if let Err(e) = orchestrator.handle_new_video(&key).await {
+    if let Err(e) = orchestrator.handle_new_video(&key, &options).await {
         eprintln!("Error enqueuing: {:?}", e);
     }
 }
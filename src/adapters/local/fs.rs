@@ -51,4 +51,9 @@ impl StoragePort for FsAdapter {
         }
         Ok(())
     }
+
+    async fn delete(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        tokio::fs::remove_file(key).await?;
+        Ok(())
+    }
 }
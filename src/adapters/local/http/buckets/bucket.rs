@@ -1,4 +1,5 @@
 use super::super::super::events::FileEvent;
+use crate::domain::options::CONTAINER_KEY;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
@@ -18,6 +19,8 @@ pub struct Bucket {
     pub allow_get: bool,
     pub events_enabled: bool,
     pub upload_event_builder: Option<UploadEventFactory>,
+    /// Metadata applied to every upload, unless the upload sets the key itself.
+    pub default_metadata: &'static [(&'static str, &'static str)],
 }
 
 fn stream_upload_factory(
@@ -44,6 +47,18 @@ fn nle_upload_factory(
     }
 }
 
+fn caption_upload_factory(
+    path: PathBuf,
+    bucket: String,
+    metadata: Option<HashMap<String, String>>,
+) -> FileEvent {
+    FileEvent::CaptionUpload {
+        path,
+        bucket,
+        metadata,
+    }
+}
+
 pub const STREAM: Bucket = Bucket {
     name: "stream",
     access: BucketAccess::Authenticated,
@@ -51,6 +66,18 @@ pub const STREAM: Bucket = Bucket {
     allow_get: true,
     events_enabled: true,
     upload_event_builder: Some(stream_upload_factory),
+    default_metadata: &[],
+};
+
+/// Like `STREAM`, packaged as MPEG-TS for legacy players.
+pub const STREAM_TS: Bucket = Bucket {
+    name: "stream-ts",
+    access: BucketAccess::Authenticated,
+    allow_put: true,
+    allow_get: true,
+    events_enabled: true,
+    upload_event_builder: Some(stream_upload_factory),
+    default_metadata: &[(CONTAINER_KEY, "ts")],
 };
 
 pub const NLE: Bucket = Bucket {
@@ -60,6 +87,19 @@ pub const NLE: Bucket = Bucket {
     allow_get: true,
     events_enabled: true,
     upload_event_builder: Some(nle_upload_factory),
+    default_metadata: &[],
+};
+
+/// Sidecar `.srt`/`.vtt` captions, attached to a processed video through
+/// `x-amz-meta-video-id` and `x-amz-meta-language`.
+pub const CAPTIONS: Bucket = Bucket {
+    name: "captions",
+    access: BucketAccess::Authenticated,
+    allow_put: true,
+    allow_get: true,
+    events_enabled: true,
+    upload_event_builder: Some(caption_upload_factory),
+    default_metadata: &[],
 };
 
 pub const HLS: Bucket = Bucket {
@@ -69,10 +109,30 @@ pub const HLS: Bucket = Bucket {
     allow_get: true,
     events_enabled: false,
     upload_event_builder: None,
+    default_metadata: &[],
 };
 
-pub const ALL_BUCKETS: &[Bucket] = &[STREAM, NLE, HLS];
+pub const ALL_BUCKETS: &[Bucket] = &[STREAM, STREAM_TS, NLE, CAPTIONS, HLS];
 
 pub fn find(name: &str) -> Option<&'static Bucket> {
     ALL_BUCKETS.iter().find(|b| b.name == name)
 }
+
+impl Bucket {
+    /// The metadata of an upload, completed with the bucket defaults.
+    pub fn upload_metadata(
+        &self,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Option<HashMap<String, String>> {
+        if self.default_metadata.is_empty() {
+            return metadata;
+        }
+        let mut metadata = metadata.unwrap_or_default();
+        for (key, value) in self.default_metadata {
+            metadata
+                .entry(key.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        Some(metadata)
+    }
+}
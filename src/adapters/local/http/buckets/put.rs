@@ -61,7 +61,7 @@ pub async fn handle(
             .await
             .map_err(|e| S3Error::with_source(S3ErrorCode::InternalError, Box::new(e)))?;
 
-        let metadata = input.metadata;
+        let metadata = bucket_config.upload_metadata(input.metadata);
 
         if bucket_config.events_enabled {
             if let Some(factory) = bucket_config.upload_event_builder {
@@ -0,0 +1,259 @@
+//! Content key endpoint for encrypted HLS, and ClearKey license server for Common
+//! Encryption.
+//!
+//! Players get the key of a key period at `/keys/<video id>/<period>`, the URI of
+//! the playlists' `EXT-X-KEY` tags. EME players using ClearKey post the key IDs
+//! they need to `/keys/<video id>/license`, the license URL of the DASH manifest,
+//! and get a license of their keys. Keys are only served with a token signed for
+//! the video, issued by whoever decides who may watch it (see
+//! [`crate::domain::key_token`]), given as `Authorization: Bearer <token>` or
+//! `?token=<token>`.
+
+use crate::domain::cenc::{License, LicenseRequest};
+use crate::domain::key_token;
+use crate::ports::keys::KeyStorePort;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct KeyService<K> {
+    keys: K,
+    secret: String,
+}
+
+/// Routes of the key endpoint, to be nested under the path of the key URL.
+pub fn router<K>(keys: K, secret: String) -> Router
+where
+    K: KeyStorePort + 'static,
+{
+    Router::new()
+        .route("/:video_id/license", post(get_license::<K>))
+        .route("/:video_id/:period", get(get_key::<K>))
+        .with_state(Arc::new(KeyService { keys, secret }))
+}
+
+/// Whether the request carries a valid token for `video_id`, or the status to
+/// answer it with.
+fn authorize(
+    secret: &str,
+    video_id: &str,
+    query: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Result<(), StatusCode> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(token) = bearer.or(query.get("token").map(String::as_str)) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    match key_token::verify(secret, video_id, token.trim(), now) {
+        true => Ok(()),
+        false => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+async fn get_key<K>(
+    State(service): State<Arc<KeyService<K>>>,
+    Path((video_id, period)): Path<(String, usize)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response
+where
+    K: KeyStorePort + 'static,
+{
+    if let Err(status) = authorize(&service.secret, &video_id, &query, &headers) {
+        return status.into_response();
+    }
+
+    match service.keys.get_key(&video_id, period).await {
+        Ok(Some(key)) => (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            key.0.to_vec(),
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            eprintln!(
+                "Failed to get key {} of video {}: {:?}",
+                period, video_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// A ClearKey license of the keys of the video asked for. Key IDs of other videos
+/// or of keys that don't exist are left out; a license of none is a 404.
+async fn get_license<K>(
+    State(service): State<Arc<KeyService<K>>>,
+    Path(video_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response
+where
+    K: KeyStorePort + 'static,
+{
+    if let Err(status) = authorize(&service.secret, &video_id, &query, &headers) {
+        return status.into_response();
+    }
+    // EME players don't all say the request is JSON.
+    let Ok(request) = serde_json::from_slice::<LicenseRequest>(&body) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let mut keys = Vec::new();
+    for key_id in request.key_ids() {
+        let Some(period) = key_id.period(&video_id) else {
+            continue;
+        };
+        match service.keys.get_key(&video_id, period).await {
+            Ok(Some(key)) => keys.push((key_id, key)),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!(
+                    "Failed to get key {} of video {}: {:?}",
+                    period, video_id, e
+                );
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    if keys.is_empty() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let license = License::new(&keys, request.session_type.as_deref());
+    match serde_json::to_vec(&license) {
+        Ok(body) => (
+            [
+                (header::CONTENT_TYPE, "application/json"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::cenc::base64url;
+    use crate::domain::encryption::{ContentKey, Encryption, EncryptionMethod, KeyId};
+    use async_trait::async_trait;
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::error::Error;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "secret";
+    const VIDEO_ID: &str = "6f1c0d2e-8a4b-4c3d-9e2f-1a2b3c4d5e6f";
+
+    /// The one key of `VIDEO_ID`.
+    struct Keys;
+
+    #[async_trait]
+    impl KeyStorePort for Keys {
+        async fn save_key(
+            &self,
+            _video_id: &str,
+            _period: usize,
+            _key: &ContentKey,
+        ) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Err("Read only".into())
+        }
+
+        async fn get_key(
+            &self,
+            video_id: &str,
+            period: usize,
+        ) -> Result<Option<ContentKey>, Box<dyn Error + Send + Sync>> {
+            Ok((video_id == VIDEO_ID && period == 0).then_some(ContentKey([7; 16])))
+        }
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Bytes) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body)
+    }
+
+    fn get(uri: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::get(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_key_requests() {
+        // Mounted as the monolith does, at the key URL of the playlists.
+        let app = Router::new().nest("/keys", router(Keys, SECRET.to_string()));
+        let encryption = Encryption {
+            method: EncryptionMethod::Cbcs,
+            key_rotation: None,
+            key_url: "/keys".to_string(),
+        };
+        let key_uri = encryption.key_uri(VIDEO_ID, 0);
+        let expires = 4_102_444_800; // 2100
+        let token = key_token::issue(SECRET, VIDEO_ID, expires);
+
+        let (status, body) = send(&app, get(&key_uri, Some(&token))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[..], [7; 16]);
+        let in_query = key_token::with_token(&key_uri, &token);
+        assert_eq!(send(&app, get(&in_query, None)).await.0, StatusCode::OK);
+
+        // A token for the name of the upload, not its video id.
+        let upload = key_token::issue(SECRET, "movie", expires);
+        assert_eq!(
+            send(&app, get(&key_uri, Some(&upload))).await.0,
+            StatusCode::FORBIDDEN
+        );
+        let expired = key_token::issue(SECRET, VIDEO_ID, 1_000);
+        assert_eq!(
+            send(&app, get(&key_uri, Some(&expired))).await.0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&app, get(&key_uri, None)).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let license = |token: Option<&str>| {
+            let body = format!(
+                r#"{{"kids":["{}"],"type":"temporary"}}"#,
+                base64url(&KeyId::new(VIDEO_ID, 0).0)
+            );
+            let mut request = Request::post(encryption.license_url(VIDEO_ID));
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.body(Body::from(body)).unwrap()
+        };
+        let (status, body) = send(&app, license(Some(&token))).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["keys"][0]["k"], base64url(&[7; 16]));
+        assert_eq!(send(&app, license(None)).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,9 +1,10 @@
 //! HTTP/S3-compatible inbound adapter.
 //!
 //! This module provides an S3-compatible HTTP API for external clients
-//! to upload and download files.
+//! to upload and download files, and the content keys of encrypted videos.
 
 pub mod buckets;
+pub mod keys;
 mod s3;
 
 pub use s3::LocalS3;
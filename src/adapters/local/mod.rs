@@ -6,5 +6,5 @@ pub mod http;
 pub mod redis;
 
 pub use events::hub::EventHub;
-pub use http::{buckets, LocalS3};
+pub use http::{buckets, keys, LocalS3};
 pub use redis::{RedisPool, RedisQueue};
@@ -0,0 +1,39 @@
+//! Redis KeyStorePort implementation.
+
+use super::error::QueueError;
+use super::pool::RedisPool;
+use super::VIDEO_KEYS_PREFIX;
+use crate::domain::encryption::ContentKey;
+use crate::ports::keys::KeyStorePort;
+use async_trait::async_trait;
+use deadpool_redis::redis::AsyncCommands;
+
+/// The keys of a video are a hash of period to hex key, never cleaned up since
+/// players need them for as long as the video is served.
+#[async_trait]
+impl KeyStorePort for RedisPool {
+    async fn save_key(
+        &self,
+        video_id: &str,
+        period: usize,
+        key: &ContentKey,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let hash = format!("{}{}", VIDEO_KEYS_PREFIX, video_id);
+        conn.hset::<_, _, _, ()>(&hash, period, key.to_hex())
+            .await
+            .map_err(QueueError::from)?;
+        Ok(())
+    }
+
+    async fn get_key(
+        &self,
+        video_id: &str,
+        period: usize,
+    ) -> Result<Option<ContentKey>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let hash = format!("{}{}", VIDEO_KEYS_PREFIX, video_id);
+        let hex: Option<String> = conn.hget(&hash, period).await.map_err(QueueError::from)?;
+        Ok(hex.map(|hex| ContentKey::from_hex(&hex)).transpose()?)
+    }
+}
@@ -3,8 +3,10 @@
 //! This module provides Redis-backed implementations of:
 //! - `JobQueuePort` for job enqueueing/dequeueing
 //! - `VideoStateRepository` for video status tracking
+//! - `KeyStorePort` for content keys
 
 mod error;
+mod keys;
 mod pool;
 mod queue;
 mod repository;
@@ -20,3 +22,7 @@ const SEGMENT_QUEUE_HIGH_PRIORITY: &str = "sinatra:segment_jobs:high";
 const SEGMENT_QUEUE_NORMAL: &str = "sinatra:segment_jobs:normal";
 const VIDEO_STATUS_PREFIX: &str = "sinatra:video:";
 const VIDEO_COMPLETED_PREFIX: &str = "sinatra:video_completed:";
+const VIDEO_SEGMENT_SIZES_PREFIX: &str = "sinatra:video_segment_sizes:";
+const VIDEO_KEYFRAMES_PREFIX: &str = "sinatra:video_keyframes:";
+const VIDEO_CAPTIONS_PREFIX: &str = "sinatra:video_captions:";
+const VIDEO_KEYS_PREFIX: &str = "sinatra:video_keys:";
@@ -16,7 +16,11 @@ impl JobQueuePort for RedisPool {
 
         let is_high_priority = match &job {
             Job::Segment(seg) => seg.segment_index < 2,
+            Job::AudioSegment(seg) => seg.segment_index < 2,
+            Job::Subtitles(_) => false,
+            Job::Captions(_) => false,
             Job::ThumbnailStrip(_) => false,
+            Job::CoverArt(_) => false,
         };
 
         let queue_key = if is_high_priority {
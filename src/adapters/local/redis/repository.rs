@@ -2,11 +2,17 @@
 
 use super::error::QueueError;
 use super::pool::RedisPool;
-use super::{VIDEO_COMPLETED_PREFIX, VIDEO_STATUS_PREFIX};
+use super::{
+    VIDEO_CAPTIONS_PREFIX, VIDEO_COMPLETED_PREFIX, VIDEO_KEYFRAMES_PREFIX,
+    VIDEO_SEGMENT_SIZES_PREFIX, VIDEO_STATUS_PREFIX,
+};
 use crate::domain::jobs::VideoStatus;
+use crate::domain::renditions::SubtitleTrack;
+use crate::domain::segment_plan::Keyframe;
 use crate::ports::repository::VideoStateRepository;
 use async_trait::async_trait;
 use deadpool_redis::redis::AsyncCommands;
+use std::collections::HashMap;
 
 #[async_trait]
 impl VideoStateRepository for RedisPool {
@@ -34,10 +40,28 @@ impl VideoStateRepository for RedisPool {
         let mut conn = self.pool.get().await.map_err(QueueError::from)?;
         let key = format!("{}{}", VIDEO_STATUS_PREFIX, video_id);
         let json: Option<String> = conn.get(&key).await.map_err(QueueError::from)?;
-        match json {
-            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
-            None => Ok(None),
+        let Some(data) = json else {
+            return Ok(None);
+        };
+        let mut status: VideoStatus = serde_json::from_str(&data)?;
+
+        // Sidecar captions, kept apart from the status so that adding one never
+        // overwrites it.
+        let captions_key = format!("{}{}", VIDEO_CAPTIONS_PREFIX, video_id);
+        let fields: HashMap<String, String> = conn
+            .hgetall(&captions_key)
+            .await
+            .map_err(QueueError::from)?;
+        let mut captions = fields
+            .into_values()
+            .map(|json| serde_json::from_str::<SubtitleTrack>(&json))
+            .collect::<Result<Vec<_>, _>>()?;
+        captions.sort_by(|a, b| a.name.cmp(&b.name));
+        for track in captions {
+            status.subtitle_tracks.retain(|t| t.name != track.name);
+            status.subtitle_tracks.push(track);
         }
+        Ok(Some(status))
     }
 
     async fn mark_segment_complete(
@@ -66,14 +90,120 @@ impl VideoStateRepository for RedisPool {
         }
     }
 
+    async fn record_segment_size(
+        &self,
+        video_id: &str,
+        rendition: &str,
+        segment_index: usize,
+        size: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let key = format!("{}{}", VIDEO_SEGMENT_SIZES_PREFIX, video_id);
+        let field = format!("{}:{}", rendition, segment_index);
+        conn.hset::<_, _, _, ()>(&key, field, size)
+            .await
+            .map_err(QueueError::from)?;
+        Ok(())
+    }
+
+    async fn get_segment_sizes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+    ) -> Result<Vec<u64>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let key = format!("{}{}", VIDEO_SEGMENT_SIZES_PREFIX, video_id);
+        let fields: HashMap<String, u64> = conn.hgetall(&key).await.map_err(QueueError::from)?;
+
+        let prefix = format!("{}:", rendition);
+        let mut sizes = Vec::new();
+        for (field, size) in fields {
+            let Some(index) = field
+                .strip_prefix(&prefix)
+                .and_then(|index| index.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if sizes.len() <= index {
+                sizes.resize(index + 1, 0);
+            }
+            sizes[index] = size;
+        }
+        Ok(sizes)
+    }
+
+    async fn record_keyframes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+        segment_index: usize,
+        keyframes: &[Keyframe],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let key = format!("{}{}", VIDEO_KEYFRAMES_PREFIX, video_id);
+        let field = format!("{}:{}", rendition, segment_index);
+        conn.hset::<_, _, _, ()>(&key, field, serde_json::to_string(keyframes)?)
+            .await
+            .map_err(QueueError::from)?;
+        Ok(())
+    }
+
+    async fn get_keyframes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+    ) -> Result<Vec<Vec<Keyframe>>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let key = format!("{}{}", VIDEO_KEYFRAMES_PREFIX, video_id);
+        let fields: HashMap<String, String> = conn.hgetall(&key).await.map_err(QueueError::from)?;
+
+        let prefix = format!("{}:", rendition);
+        let mut keyframes = Vec::new();
+        for (field, json) in fields {
+            let Some(index) = field
+                .strip_prefix(&prefix)
+                .and_then(|index| index.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if keyframes.len() <= index {
+                keyframes.resize(index + 1, Vec::new());
+            }
+            keyframes[index] = serde_json::from_str(&json)?;
+        }
+        Ok(keyframes)
+    }
+
+    async fn add_subtitle_track(
+        &self,
+        video_id: &str,
+        track: &SubtitleTrack,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await.map_err(QueueError::from)?;
+        let key = format!("{}{}", VIDEO_STATUS_PREFIX, video_id);
+        let exists: bool = conn.exists(&key).await.map_err(QueueError::from)?;
+        if !exists {
+            return Err("No status".into());
+        }
+
+        // One field per track: concurrent caption jobs and status writes don't
+        // drop each other's tracks. Kept by cleanup_video, like the status.
+        let captions_key = format!("{}{}", VIDEO_CAPTIONS_PREFIX, video_id);
+        conn.hset::<_, _, _, ()>(&captions_key, &track.name, serde_json::to_string(track)?)
+            .await
+            .map_err(QueueError::from)?;
+        Ok(())
+    }
+
     async fn cleanup_video(
         &self,
         video_id: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let mut conn = self.pool.get().await.map_err(QueueError::from)?;
-        let status_key = format!("{}{}", VIDEO_STATUS_PREFIX, video_id);
         let completed_key = format!("{}{}", VIDEO_COMPLETED_PREFIX, video_id);
-        conn.del::<_, ()>(&[status_key, completed_key])
+        let sizes_key = format!("{}{}", VIDEO_SEGMENT_SIZES_PREFIX, video_id);
+        let keyframes_key = format!("{}{}", VIDEO_KEYFRAMES_PREFIX, video_id);
+        conn.del::<_, ()>(&[completed_key, sizes_key, keyframes_key])
             .await
             .map_err(QueueError::from)?;
         Ok(())
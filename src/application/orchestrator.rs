@@ -1,35 +1,92 @@
+use crate::domain::av::audio_stream::AudioStream;
 use crate::domain::av::av::AV;
-use crate::domain::jobs::{Job, SegmentJob, ThumbnailStripJob, VideoStatus};
+use crate::domain::av::crypto::generate_key;
+use crate::domain::av::encode::check_renditions;
+use crate::domain::av::subtitle_stream::SubtitleStream;
+use crate::domain::color::VideoRange;
+use crate::domain::encryption::{Encryption, EncryptionMethod, DEFAULT_KEY_URL};
+use crate::domain::jobs::{
+    AudioSegmentJob, CaptionJob, CoverArtJob, Job, SegmentJob, SubtitleJob, ThumbnailStripJob,
+    VideoStatus,
+};
+use crate::domain::loudness::LoudnessTarget;
+use crate::domain::options::{CaptionOptions, Container, VideoOptions};
+use crate::domain::preflight::{check_source, Preflight, ProcessingPlan, Source, SourceVideo};
+use crate::domain::renditions::{
+    default_ladder, for_video_range, is_passthrough, ladder_for_source, language_name,
+    language_tag, AudioCodec, AudioDecision, AudioTrack, Rendition, SubtitleTrack, VideoCodec,
+    AUDIO_TRACK_BITRATE,
+};
+use crate::domain::segment_plan::{SegmentPlan, SegmentTarget};
+use crate::domain::webvtt::is_text_codec;
+use crate::ports::keys::KeyStorePort;
 use crate::ports::queue::JobQueuePort;
 use crate::ports::repository::VideoStateRepository;
 use crate::ports::storage::StoragePort;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tempfile::NamedTempFile;
 use uuid::Uuid;
 
-pub struct OrchestratorService<S, Q, R> {
+pub struct OrchestratorService<S, Q, R, K> {
     storage: S,
     queue: Q,
     repo: R,
+    keys: K,
+    ladder: Vec<Rendition>,
+    segment_target: SegmentTarget,
+    loudness_target: Option<LoudnessTarget>,
+    key_url: String,
 }
 
-impl<S, Q, R> OrchestratorService<S, Q, R>
+impl<S, Q, R, K> OrchestratorService<S, Q, R, K>
 where
     S: StoragePort,
     Q: JobQueuePort,
     R: VideoStateRepository,
+    K: KeyStorePort,
 {
-    pub fn new(storage: S, queue: Q, repo: R) -> Self {
+    pub fn new(storage: S, queue: Q, repo: R, keys: K) -> Self {
         Self {
             storage,
             queue,
             repo,
+            keys,
+            ladder: default_ladder(),
+            segment_target: SegmentTarget::default(),
+            loudness_target: None,
+            key_url: DEFAULT_KEY_URL.to_string(),
         }
     }
 
+    /// Replace the default rendition ladder.
+    pub fn with_ladder(mut self, ladder: Vec<Rendition>) -> Self {
+        self.ladder = ladder;
+        self
+    }
+
+    /// Replace the default segment duration.
+    pub fn with_segment_target(mut self, segment_target: SegmentTarget) -> Self {
+        self.segment_target = segment_target;
+        self
+    }
+
+    /// Normalize encoded audio to `loudness_target`; without one, audio keeps the
+    /// loudness of the source.
+    pub fn with_loudness_target(mut self, loudness_target: Option<LoudnessTarget>) -> Self {
+        self.loudness_target = loudness_target;
+        self
+    }
+
+    /// Replace the base URL players get the content keys of encrypted videos from.
+    pub fn with_key_url(mut self, key_url: String) -> Self {
+        self.key_url = key_url;
+        self
+    }
+
     pub async fn handle_new_video(
         &self,
         video_key: &str,
+        options: &VideoOptions,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         // 1. Prepare temp file for analysis
         let temp_file = NamedTempFile::new()?;
@@ -54,65 +111,694 @@ where
         // HLS directory structure (logical path in storage)
         let hls_dir_key = PathBuf::from("hls").join(&file_stem);
 
-        let segment_count = if video.segments.len() > 1 {
-            video.segments.len() - 1
-        } else {
-            0
+        // Check the source against what we can package before queueing anything. A
+        // rejected upload keeps a status saying why, with nothing to process.
+        let source = Source {
+            format: video.format.clone(),
+            duration: video.duration,
+            video: video
+                .video_streams
+                .iter()
+                .map(|stream| {
+                    let (width, height) = stream.display_size();
+                    SourceVideo {
+                        codec: stream.codec.clone(),
+                        pix_fmt: stream.pix_fmt.clone(),
+                        width: u32::from(width),
+                        height: u32::from(height),
+                        field_order: stream.field_order,
+                        frame_timing: stream.frame_timing,
+                    }
+                })
+                .collect(),
+            audio_streams: video.audio_streams.len(),
+        };
+        let compatibility = match check_source(&source, options.container) {
+            Ok(compatibility) => compatibility,
+            Err(reason) => {
+                return self
+                    .reject(&video_id, video_key, &hls_dir_key, options, reason)
+                    .await
+            }
         };
 
+        // Audio-only sources are packaged as audio renditions in fMP4, whatever the
+        // container asked for.
+        let audio_only = video.video_streams.is_empty();
+        let container = match audio_only {
+            true => Container::Fmp4,
+            false => options.container,
+        };
+        if container != options.container {
+            println!(
+                "{} is audio only, packaging it as {} rather than {}",
+                video_key,
+                container.extension(),
+                options.container.extension()
+            );
+        }
+
+        // Editors may publish only part of an upload: the window between its trim
+        // points, within the span of the source.
+        let source_start = video.segments.first().copied().unwrap_or(0.0);
+        let source_end = video.segments.last().copied().unwrap_or(video.duration);
+        let trim = match options.is_trimmed() {
+            true => {
+                let start = options.trim_start.unwrap_or(0.0).max(source_start);
+                let end = options.trim_end.unwrap_or(source_end).min(source_end);
+                if end <= start {
+                    let reason = format!(
+                        "The trim points are outside the {:.3}s of {}",
+                        source_end - source_start,
+                        video_key
+                    );
+                    return self
+                        .reject(&video_id, video_key, &hls_dir_key, options, reason)
+                        .await;
+                }
+                println!("Trimming {} to {:.3}s-{:.3}s", video_key, start, end);
+                Some((start, end))
+            }
+            false => None,
+        };
+        let (start, end) = trim.unwrap_or((source_start, source_end));
+
+        // Group the source keyframes into segments of about the target duration.
+        // When keyframes are too sparse for that, cut anywhere and force keyframes
+        // at the cuts by re-encoding. Every audio frame is a sync point, so audio
+        // alone is cut at fixed durations.
+        let mut segment_plan = match (audio_only, trim) {
+            (true, _) => SegmentPlan::fixed(start, end, &self.segment_target),
+            (false, Some(_)) => {
+                SegmentPlan::trimmed(&video.segments, &self.segment_target, start, end)
+            }
+            (false, None) => SegmentPlan::new(&video.segments, &self.segment_target),
+        };
+        if segment_plan.exceeds(&self.segment_target) {
+            println!(
+                "Keyframes of {} are too sparse to segment, forcing keyframes",
+                video_key
+            );
+            segment_plan = SegmentPlan::forced(&[start, end], &self.segment_target);
+        }
+        let segment_count = segment_plan.len();
+
         if segment_count == 0 {
             return Err("No segments found in video".into());
         }
 
-        let mut segment_durations = Vec::with_capacity(segment_count);
-        for i in 0..segment_count {
-            let duration = video.segments[i + 1] - video.segments[i];
-            segment_durations.push(duration);
+        // Renditions are resolved against the displayed source size so that none is
+        // upscaled, and encoded ones come out upright.
+        let (width, height) = video
+            .video_streams
+            .first()
+            .map(|stream| stream.display_size())
+            .map(|(width, height)| (u32::from(width), u32::from(height)))
+            .unwrap_or((0, 0));
+        let mut renditions = match audio_only {
+            true => Vec::new(),
+            false => ladder_for_source(&self.ladder, width, height),
+        };
+        if segment_plan.forced_keyframes {
+            renditions = renditions.iter().map(Rendition::encoded).collect();
+        }
+        if let Some(reason) = &compatibility.video_copy_blocker {
+            println!("Re-encoding the video of {}: {}", video_key, reason);
+            renditions = renditions.iter().map(Rendition::encoded).collect();
+        }
+        // Watermarks are burnt in while encoding, so copied video is encoded too.
+        let mut watermark_notes: Vec<String> = options
+            .watermarks
+            .iter()
+            .map(|watermark| watermark.describe())
+            .collect();
+        if audio_only && !watermark_notes.is_empty() {
+            watermark_notes = vec!["The file has no video to watermark".to_string()];
+        } else if !watermark_notes.is_empty() && renditions.iter().any(Rendition::is_copy) {
+            println!("Re-encoding the video of {}: it is watermarked", video_key);
+            renditions = renditions.iter().map(Rendition::encoded).collect();
+        }
+        // Frames are dropped or repeated to a constant rate while encoding too.
+        let frame_rate = options.frame_rate.and_then(|target| {
+            target.rate_for(
+                video
+                    .video_streams
+                    .first()
+                    .and_then(|stream| stream.frame_timing.as_ref()),
+            )
+        });
+        let frame_rate_notes: Vec<String> = frame_rate
+            .map(|rate| format!("The video is normalized to a constant {}", rate))
+            .into_iter()
+            .collect();
+        if frame_rate.is_some() && renditions.iter().any(Rendition::is_copy) {
+            println!(
+                "Re-encoding the video of {}: it is normalized to a constant frame rate",
+                video_key
+            );
+            renditions = renditions.iter().map(Rendition::encoded).collect();
+        }
+
+        // HDR sources keep HDR where the codec allows it, with an SDR fallback.
+        let color = video
+            .video_streams
+            .first()
+            .map(|stream| stream.color.clone())
+            .unwrap_or_default();
+        if color.range() != VideoRange::Sdr {
+            println!(
+                "{} is {} HDR, adding a tone-mapped SDR rendition if needed",
+                video_key,
+                color.range().as_str()
+            );
+        }
+        renditions = for_video_range(&renditions, color.range(), width, height);
+        // Encoded video is deinterlaced first when the source is interlaced.
+        let field_order = video
+            .video_streams
+            .first()
+            .map(|stream| stream.field_order)
+            .unwrap_or_default();
+        for rendition in renditions.iter_mut() {
+            rendition.watermarks = options.watermarks.clone();
+            rendition.field_order = field_order;
+            rendition.frame_rate = frame_rate;
+        }
+
+        // Encoders depend on the FFmpeg build: fail the upload here rather than
+        // every one of its jobs.
+        check_renditions(&renditions)?;
+        if container == Container::MpegTs
+            && renditions.iter().any(|r| r.video_codec == VideoCodec::Av1)
+        {
+            return Err("AV1 renditions cannot be packaged as MPEG-TS".into());
+        }
+
+        // SAMPLE-AES is only defined here for H.264 and AAC in MPEG-TS: copied video
+        // of another codec is re-encoded, and so is audio below.
+        let sample_aes = options.encryption == Some(EncryptionMethod::SampleAes);
+        let mut encryption_notes = Vec::new();
+        if sample_aes {
+            if container != Container::MpegTs {
+                return Err("SAMPLE-AES encryption needs MPEG-TS segments".into());
+            }
+            if renditions
+                .iter()
+                .any(|r| matches!(r.video_codec, VideoCodec::Hevc | VideoCodec::Av1))
+            {
+                return Err("SAMPLE-AES encryption covers H.264 renditions only".into());
+            }
+            let copied_codec = video
+                .video_streams
+                .first()
+                .map(|stream| &stream.codec)
+                .filter(|codec| *codec != "h264");
+            if let Some(codec) = copied_codec.filter(|_| renditions.iter().any(Rendition::is_copy))
+            {
+                let note = format!("{} video is re-encoded to H.264 for SAMPLE-AES", codec);
+                println!("Re-encoding the video of {}: {}", video_key, note);
+                renditions = renditions.iter().map(Rendition::encoded).collect();
+                encryption_notes.push(note);
+            }
+        }
+        // Common Encryption is defined here for H.264 and HEVC in fragmented MP4;
+        // audio of any codec is encrypted whole.
+        if let Some(method) = options.encryption.filter(EncryptionMethod::is_common) {
+            if container != Container::Fmp4 {
+                return Err(format!("{} encryption needs fMP4 segments", method.as_str()).into());
+            }
+            if renditions.iter().any(|r| r.video_codec == VideoCodec::Av1) {
+                return Err("Common Encryption covers H.264 and HEVC renditions only".into());
+            }
+            let copied_codec = video
+                .video_streams
+                .first()
+                .map(|stream| &stream.codec)
+                .filter(|codec| !matches!(codec.as_str(), "h264" | "hevc"));
+            if let Some(codec) = copied_codec.filter(|_| renditions.iter().any(Rendition::is_copy))
+            {
+                let note = format!("{} video is re-encoded for Common Encryption", codec);
+                println!("Re-encoding the video of {}: {}", video_key, note);
+                renditions = renditions.iter().map(Rendition::encoded).collect();
+                encryption_notes.push(note);
+            }
+        }
+
+        // The partial GOPs at the trim points are re-encoded to the source codec,
+        // with headers of their own in fMP4. Segments encrypted whole would need
+        // those headers encrypted too, so their video is re-encoded throughout.
+        let mut trim_notes = Vec::new();
+        if let Some((start, end)) = trim {
+            trim_notes.push(format!("Trimmed to {:.3}s-{:.3}s", start, end));
         }
+        if segment_plan.has_partial() && renditions.iter().any(Rendition::is_copy) {
+            let source_codec = video
+                .video_streams
+                .first()
+                .map(|stream| stream.codec.as_str())
+                .unwrap_or_default();
+            let note = if container == Container::Fmp4
+                && options.encryption == Some(EncryptionMethod::Aes128)
+            {
+                renditions = renditions.iter().map(Rendition::encoded).collect();
+                "Video trimmed inside GOPs is re-encoded for AES-128".to_string()
+            } else {
+                let partial: Vec<Rendition> = renditions
+                    .iter()
+                    .filter(|r| r.is_copy())
+                    .map(|r| r.for_partial_gops(source_codec))
+                    .collect();
+                check_renditions(&partial)?;
+                format!(
+                    "The trim points are inside GOPs, re-encoded to {}",
+                    source_codec
+                )
+            };
+            println!("Re-encoding the video of {}: {}", video_key, note);
+            trim_notes.push(note);
+        }
+
+        // Audio players can't decode is transcoded to AAC, even along copied video.
+        // Every stream's decision is kept with the status.
+        let mut audio_decisions: Vec<AudioDecision> = video
+            .audio_streams
+            .iter()
+            .map(|stream| {
+                AudioDecision::new(stream.index, &stream.codec, stream.sample_rate, container)
+            })
+            .collect();
+        if sample_aes {
+            for decision in audio_decisions
+                .iter_mut()
+                .filter(|decision| decision.codec == AudioCodec::Copy)
+                .filter(|decision| decision.source_codec != "aac")
+            {
+                decision.codec = AudioCodec::Aac;
+                decision.reason = Some(format!(
+                    "{} audio is not covered by SAMPLE-AES",
+                    decision.source_codec
+                ));
+            }
+        }
+        for decision in &audio_decisions {
+            if let Some(reason) = &decision.reason {
+                println!(
+                    "Transcoding audio stream {} of {} to AAC: {}",
+                    decision.stream_index, video_key, reason
+                );
+            }
+        }
+        let main_audio_codec = main_audio(&video.audio_streams)
+            .and_then(|main| {
+                audio_decisions
+                    .iter()
+                    .find(|decision| decision.stream_index == main.index)
+            })
+            .map(|decision| decision.codec)
+            .unwrap_or_default();
+        if main_audio_codec == AudioCodec::Aac {
+            for rendition in renditions.iter_mut().filter(|r| r.is_copy()) {
+                rendition.audio_codec = AudioCodec::Aac;
+                rendition.audio_bitrate = AUDIO_TRACK_BITRATE;
+            }
+        }
+
+        // Encoded audio is brought to the loudness target by a gain constant over the
+        // whole video, so that segments encoded apart match. Copied audio keeps its
+        // loudness, which is stored for players to apply their own gain.
+        let loudness = main_audio(&video.audio_streams).and_then(|stream| stream.loudness);
+        if let (Some(target), Some(measured)) = (&self.loudness_target, &loudness) {
+            let gain = target.gain(measured);
+            println!(
+                "{} measures {:.1} LUFS, {:+.1} dB to the {:.1} LUFS target",
+                video_key, measured.integrated, gain, target.integrated
+            );
+            for rendition in renditions
+                .iter_mut()
+                .filter(|r| !r.is_copy() || r.audio_codec == AudioCodec::Aac)
+            {
+                rendition.audio_gain = gain;
+            }
+        }
+
+        // Multi-language sources get each audio track as its own rendition, leaving
+        // the video renditions video-only. Without video, every audio stream is one.
+        let audio_tracks = if video.audio_streams.len() > 1 || audio_only {
+            for rendition in renditions.iter_mut() {
+                rendition.muxed_audio = false;
+            }
+            audio_tracks(
+                &video.audio_streams,
+                &audio_decisions,
+                is_passthrough(&renditions),
+                self.loudness_target.as_ref(),
+            )
+        } else {
+            Vec::new()
+        };
+
+        // Text subtitles become WebVTT renditions; bitmap ones can't be converted.
+        let subtitle_tracks = subtitle_tracks(&video.subtitle_streams);
+        let skipped_subtitles: Vec<String> = video
+            .subtitle_streams
+            .iter()
+            .filter(|stream| !is_text_codec(&stream.codec))
+            .map(|stream| {
+                format!(
+                    "{} subtitles (stream {}) are images and are left out",
+                    stream.codec, stream.index
+                )
+            })
+            .collect();
+
+        // The plan and why it was chosen are kept with the status and carried by
+        // every job.
+        let plan = ProcessingPlan::for_outputs(&renditions, &audio_tracks);
+        let preflight = Preflight {
+            plan,
+            reasons: compatibility
+                .video_copy_blocker
+                .into_iter()
+                .chain(
+                    audio_decisions
+                        .iter()
+                        .filter_map(|decision| decision.reason.clone()),
+                )
+                .chain(compatibility.notes)
+                .chain(trim_notes)
+                .chain(watermark_notes)
+                .chain(frame_rate_notes)
+                .chain(encryption_notes)
+                .chain(skipped_subtitles)
+                .collect(),
+        };
+        println!("Processing {} as {}", video_key, plan.as_str());
+
+        let encryption = options.encryption.map(|method| Encryption {
+            method,
+            key_rotation: options.key_rotation,
+            key_url: self.key_url.clone(),
+        });
 
         let status = VideoStatus {
             id: video_id.clone(),
             source_path: PathBuf::from(video_key), // Key is the source
             hls_dir: hls_dir_key.clone(),
-            total_segments: segment_count,
-            segment_durations: segment_durations.clone(),
+            // Subtitle jobs write every segment of their track and complete once.
+            total_segments: segment_count * (renditions.len() + audio_tracks.len())
+                + subtitle_tracks.len(),
+            segment_plan: segment_plan.clone(),
+            renditions: renditions.clone(),
+            audio_tracks: audio_tracks.clone(),
+            subtitle_tracks: subtitle_tracks.clone(),
+            packaging: options.packaging,
+            container,
+            loudness,
+            audio_decisions,
+            preflight,
+            encryption: encryption.clone(),
         };
 
         // 4. Save Status
         self.repo.save_video_status(&status).await?;
 
-        // 5. Enqueue Segments
-        for i in 0..segment_count {
-            let job = SegmentJob {
+        // Every content key exists before the first segment is encrypted under it.
+        if let Some(encryption) = &encryption {
+            for period in 0..encryption.key_count(segment_count) {
+                let key = generate_key()
+                    .map_err(|e| format!("Failed to generate a content key: {}", e))?;
+                self.keys.save_key(&video_id, period, &key).await?;
+            }
+            println!(
+                "Encrypting {} with {} under {} keys",
+                video_id,
+                encryption.method.as_str(),
+                encryption.key_count(segment_count)
+            );
+        }
+
+        // 5. Enqueue Segments, one job per segment per rendition. Segments are the
+        // outer loop so the start of every rendition becomes playable first.
+        for (i, segment) in segment_plan.segments.iter().enumerate() {
+            for rendition in &renditions {
+                let job = SegmentJob {
+                    id: Uuid::new_v4().to_string(),
+                    video_id: video_id.clone(),
+                    segment_index: i,
+                    rendition: rendition.clone(),
+                    source_path: PathBuf::from(video_key), // Source is the key
+                    output_path: hls_dir_key.join(&rendition.name).join(format!(
+                        "segment_{}.{}",
+                        i,
+                        container.extension()
+                    )), // Dest key
+                    start_time: segment.start,
+                    duration: segment.duration,
+                    partial: segment.partial,
+                    container,
+                    plan,
+                    encryption: encryption.clone(),
+                };
+                self.queue.enqueue_job(Job::Segment(job)).await?;
+            }
+            for track in &audio_tracks {
+                let job = AudioSegmentJob {
+                    id: Uuid::new_v4().to_string(),
+                    video_id: video_id.clone(),
+                    segment_index: i,
+                    track: track.clone(),
+                    source_path: PathBuf::from(video_key),
+                    output_path: hls_dir_key.join(&track.name).join(format!(
+                        "segment_{}.{}",
+                        i,
+                        container.extension()
+                    )),
+                    start_time: segment.start,
+                    duration: segment.duration,
+                    container,
+                    plan,
+                    encryption: encryption.clone(),
+                };
+                self.queue.enqueue_job(Job::AudioSegment(job)).await?;
+            }
+        }
+
+        for track in &subtitle_tracks {
+            let job = SubtitleJob {
                 id: Uuid::new_v4().to_string(),
                 video_id: video_id.clone(),
-                segment_index: i,
-                source_path: PathBuf::from(video_key), // Source is the key
-                output_path: hls_dir_key.join(format!("segment_{}.mp4", i)), // Dest key
-                start_time: video.segments[i],
-                duration: segment_durations[i],
+                track: track.clone(),
+                source_path: PathBuf::from(video_key),
+                output_dir: hls_dir_key.join(&track.name),
+                segments: segment_plan.segments.clone(),
             };
-            self.queue.enqueue_job(Job::Segment(job)).await?;
+            self.queue.enqueue_job(Job::Subtitles(job)).await?;
         }
 
-        // 6. Enqueue Thumbnail Job
-        let thumbnail_job = ThumbnailStripJob {
-            id: Uuid::new_v4().to_string(),
-            video_id: video_id.clone(),
-            source_path: PathBuf::from(video_key),
-            output_path: hls_dir_key.join("thumbnails.jpg"),
-            interval_seconds: 5,
-            width: 160,
+        // 6. Enqueue Thumbnail Job. Audio has no frames to show, only the cover
+        // art it may come with.
+        let pictures = match (audio_only, video.cover_art) {
+            (false, _) => {
+                let thumbnail_job = ThumbnailStripJob {
+                    id: Uuid::new_v4().to_string(),
+                    video_id: video_id.clone(),
+                    source_path: PathBuf::from(video_key),
+                    output_path: hls_dir_key.join("thumbnails.jpg"),
+                    interval_seconds: 5,
+                    width: 160,
+                };
+                self.queue
+                    .enqueue_job(Job::ThumbnailStrip(thumbnail_job))
+                    .await?;
+                "thumbnails"
+            }
+            (true, Some(stream_index)) => {
+                let cover_art_job = CoverArtJob {
+                    id: Uuid::new_v4().to_string(),
+                    video_id: video_id.clone(),
+                    source_path: PathBuf::from(video_key),
+                    output_path: hls_dir_key.join("cover.jpg"),
+                    stream_index,
+                };
+                self.queue.enqueue_job(Job::CoverArt(cover_art_job)).await?;
+                "cover art"
+            }
+            (true, None) => "no pictures",
         };
-        self.queue
-            .enqueue_job(Job::ThumbnailStrip(thumbnail_job))
-            .await?;
 
         println!(
-            "Enqueued {} segments + thumbnails for video {} ({})",
-            segment_count, video_id, file_stem
+            "Enqueued {} segments x ({} renditions + {} audio tracks) + {} subtitle tracks + {} for video {} ({})",
+            segment_count,
+            renditions.len(),
+            audio_tracks.len(),
+            subtitle_tracks.len(),
+            pictures,
+            video_id,
+            file_stem
         );
 
         Ok(video_id)
     }
+
+    /// Attach the sidecar captions at `caption_key` to the video they name, as a
+    /// subtitle rendition. The video isn't processed again: the captions are cut
+    /// along its segment plan and added to its master playlist.
+    /// Save a status saying why the upload `video_key` is rejected, with nothing to
+    /// process, and fail with `reason`.
+    async fn reject(
+        &self,
+        video_id: &str,
+        video_key: &str,
+        hls_dir_key: &Path,
+        options: &VideoOptions,
+        reason: String,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        println!("Rejecting {}: {}", video_key, reason);
+        let status = VideoStatus {
+            id: video_id.to_string(),
+            source_path: PathBuf::from(video_key),
+            hls_dir: hls_dir_key.to_path_buf(),
+            total_segments: 0,
+            segment_plan: SegmentPlan::default(),
+            renditions: Vec::new(),
+            audio_tracks: Vec::new(),
+            subtitle_tracks: Vec::new(),
+            packaging: options.packaging,
+            container: options.container,
+            loudness: None,
+            audio_decisions: Vec::new(),
+            preflight: Preflight {
+                plan: ProcessingPlan::Reject,
+                reasons: vec![reason.clone()],
+            },
+            encryption: None,
+        };
+        self.repo.save_video_status(&status).await?;
+        Err(reason.into())
+    }
+
+    pub async fn handle_captions(
+        &self,
+        caption_key: &str,
+        options: &CaptionOptions,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let status = self
+            .repo
+            .get_video_status(&options.video_id)
+            .await?
+            .ok_or_else(|| format!("Unknown video {}", options.video_id))?;
+        if status.preflight.plan == ProcessingPlan::Reject {
+            return Err(format!("Video {} was rejected", options.video_id).into());
+        }
+
+        // Tags are case-insensitive: `EN` and `en` captions are the same track.
+        let language =
+            language_tag(&options.language).unwrap_or_else(|| options.language.to_lowercase());
+        let track = SubtitleTrack {
+            name: format!("captions_{}", language),
+            stream_index: None,
+            title: language_name(&language).unwrap_or_else(|| language.clone()),
+            language: Some(language),
+            default: false,
+            forced: false,
+        };
+        let job = CaptionJob {
+            id: Uuid::new_v4().to_string(),
+            video_id: status.id.clone(),
+            track,
+            caption_path: PathBuf::from(caption_key),
+        };
+        self.queue.enqueue_job(Job::Captions(job)).await?;
+
+        println!(
+            "Enqueued captions {} for video {} ({})",
+            options.language, status.id, caption_key
+        );
+        Ok(())
+    }
+}
+
+/// The audio stream played when the user has no language preference: the one
+/// flagged as default, or else the first one.
+fn main_audio(streams: &[AudioStream]) -> Option<&AudioStream> {
+    streams.iter().find(|s| s.default).or(streams.first())
+}
+
+/// Alternate audio renditions for the audio streams of a source. Audio is only
+/// re-encoded when the video is or when its decision says players can't decode it,
+/// then normalized to `loudness_target` if any.
+fn audio_tracks(
+    streams: &[AudioStream],
+    decisions: &[AudioDecision],
+    passthrough: bool,
+    loudness_target: Option<&LoudnessTarget>,
+) -> Vec<AudioTrack> {
+    let default = streams.iter().position(|s| s.default).unwrap_or(0);
+
+    streams
+        .iter()
+        .enumerate()
+        .map(|(i, stream)| {
+            let language = stream.language.as_deref().and_then(language_tag);
+            let title = stream
+                .title
+                .clone()
+                .or_else(|| language.clone())
+                .unwrap_or_else(|| format!("Audio {}", i + 1));
+
+            let copy = passthrough
+                && decisions
+                    .iter()
+                    .any(|d| d.stream_index == stream.index && d.codec == AudioCodec::Copy);
+            // Encoded audio is always downmixed to stereo.
+            let (codec, channels) = if copy {
+                (AudioCodec::Copy, stream.channels)
+            } else {
+                (AudioCodec::Aac, 2)
+            };
+
+            AudioTrack {
+                name: format!("audio_{}", i),
+                stream_index: stream.index,
+                language,
+                title,
+                default: i == default,
+                channels,
+                codec,
+                bitrate: AUDIO_TRACK_BITRATE,
+                loudness: stream.loudness,
+                gain: match (codec, loudness_target, &stream.loudness) {
+                    (AudioCodec::Aac, Some(target), Some(measured)) => target.gain(measured),
+                    _ => 0.0,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Subtitle renditions for the text subtitle streams of a source, with the language
+/// and forced flag of their metadata.
+fn subtitle_tracks(streams: &[SubtitleStream]) -> Vec<SubtitleTrack> {
+    streams
+        .iter()
+        .filter(|stream| is_text_codec(&stream.codec))
+        .enumerate()
+        .map(|(i, stream)| {
+            let language = stream.language.as_deref().and_then(language_tag);
+            let title = stream
+                .title
+                .clone()
+                .or_else(|| language.clone())
+                .unwrap_or_else(|| format!("Subtitles {}", i + 1));
+
+            SubtitleTrack {
+                name: format!("subs_{}", i),
+                stream_index: Some(stream.index),
+                language,
+                title,
+                default: stream.default,
+                forced: stream.forced,
+            }
+        })
+        .collect()
 }
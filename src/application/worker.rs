@@ -1,30 +1,74 @@
 use crate::domain::av::av::AV;
-use crate::domain::av::segments::{generate_init_segment, transcode_at};
-use crate::domain::av::thumbnails::generate_strip;
-use crate::domain::hls::MediaPlaylist;
-use crate::domain::jobs::{Job, SegmentJob, ThumbnailStripJob};
+use crate::domain::av::crypto::Aes128;
+use crate::domain::av::segments::{
+    generate_audio_init_segment, generate_init_segment, header_path, transcode_at,
+    transcode_audio_at,
+};
+use crate::domain::av::subtitles::extract_cues;
+use crate::domain::av::thumbnails::{extract_cover_art, generate_strip};
+use crate::domain::cenc;
+use crate::domain::color::VideoRange;
+use crate::domain::dash::{
+    AdaptationSet, ContentProtection, ContentType, Manifest, Representation, SegmentAddressing,
+};
+use crate::domain::encryption::{
+    decrypt_segment, encrypt_segment, segment_iv, Encryption, EncryptionMethod, KeyId, SegmentKey,
+    BLOCK_SIZE,
+};
+use crate::domain::hls::{
+    measured_bandwidth, set_media_group, AlternateMedia, ByteRange, IFrameStream, KeyTag,
+    MasterPlaylist, MediaPlaylist, MediaType, SessionData, VariantStream,
+};
+use crate::domain::jobs::{
+    AudioSegmentJob, CaptionJob, CoverArtJob, Job, SegmentJob, SubtitleJob, ThumbnailStripJob,
+    VideoStatus,
+};
+use crate::domain::mp4;
+use crate::domain::options::{Container, Packaging};
+use crate::domain::renditions::{Rendition, SubtitleTrack};
+use crate::domain::segment_plan::{Keyframe, PlannedSegment};
+use crate::domain::ts::Continuity;
+use crate::domain::watermark::Mark;
+use crate::domain::webvtt;
+use crate::ports::keys::KeyStorePort;
 use crate::ports::queue::JobQueuePort;
 use crate::ports::repository::VideoStateRepository;
 use crate::ports::storage::StoragePort;
+use std::path::Path;
 use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
 
-pub struct WorkerService<S, Q, R> {
+/// GROUP-ID of the alternate audio renditions in the master playlist.
+const AUDIO_GROUP_ID: &str = "audio";
+
+/// GROUP-ID of the subtitle renditions in the master playlist.
+const SUBTITLES_GROUP_ID: &str = "subs";
+
+/// DATA-ID of the source loudness in the master playlist, for players to apply
+/// their own gain to audio that was stream-copied. Alternate audio renditions have
+/// theirs under this prefix.
+const LOUDNESS_DATA_ID: &str = "com.sinatra.loudness";
+
+pub struct WorkerService<S, Q, R, K> {
     storage: S,
     queue: Q,
     repo: R,
+    keys: K,
 }
 
-impl<S, Q, R> WorkerService<S, Q, R>
+impl<S, Q, R, K> WorkerService<S, Q, R, K>
 where
     S: StoragePort + Clone + 'static,
     Q: JobQueuePort + Clone + 'static,
     R: VideoStateRepository + Clone + 'static,
+    K: KeyStorePort + Clone + 'static,
 {
-    pub fn new(storage: S, queue: Q, repo: R) -> Self {
+    pub fn new(storage: S, queue: Q, repo: R, keys: K) -> Self {
         Self {
             storage,
             queue,
             repo,
+            keys,
         }
     }
 
@@ -53,7 +97,11 @@ where
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         match job {
             Job::Segment(seg) => self.process_segment(seg, worker_id).await,
+            Job::AudioSegment(seg) => self.process_audio_segment(seg, worker_id).await,
+            Job::Subtitles(subs) => self.process_subtitles(subs, worker_id).await,
+            Job::Captions(captions) => self.process_captions(captions, worker_id).await,
             Job::ThumbnailStrip(thumb) => self.process_thumbnail(thumb, worker_id).await,
+            Job::CoverArt(cover) => self.process_cover_art(cover, worker_id).await,
         }
     }
 
@@ -63,8 +111,11 @@ where
         worker_id: usize,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         println!(
-            "[Worker {}] Processing segment {}",
-            worker_id, job.segment_index
+            "[Worker {}] Processing segment {} ({}, {})",
+            worker_id,
+            job.segment_index,
+            job.rendition.name,
+            job.plan.as_str()
         );
 
         // 1. Prepare Paths
@@ -74,32 +125,319 @@ where
         let temp_in = NamedTempFile::new()?;
         let _temp_out = NamedTempFile::new()?.into_temp_path(); // We need a path, but file might be recreated by ffmpeg?
                                                                 // Actually ffmpeg creates the file. So we define a path in temp dir.
-        let temp_out_path =
-            std::env::temp_dir().join(format!("seg_{}_{}.mp4", job.video_id, job.segment_index));
+        let temp_out_path = std::env::temp_dir().join(format!(
+            "seg_{}_{}_{}.{}",
+            job.video_id,
+            job.rendition.name,
+            job.segment_index,
+            job.container.extension()
+        ));
 
         // 2. Download
         self.storage.download(source_key, temp_in.path()).await?;
 
         // 3. Transcode
         // We need AV from local file
-        let av = AV::from_path(temp_in.path()).await?;
-        transcode_at(&av, job.segment_index, temp_out_path.clone()).await;
+        let av = AV::probe(temp_in.path()).await?;
+        let segment = PlannedSegment {
+            start: job.start_time,
+            duration: job.duration,
+            partial: job.partial,
+        };
+        let key = self
+            .segment_key(&job.video_id, job.encryption.as_ref(), job.segment_index)
+            .await?;
+        // Logos are read by the filter graph, from local copies of their assets.
+        let mut assets = Vec::new();
+        let mut rendition = job.rendition.clone();
+        for watermark in rendition.watermarks.iter_mut() {
+            if let Mark::Image(asset_key) = &watermark.mark {
+                let asset = NamedTempFile::new()?;
+                self.storage.download(asset_key, asset.path()).await?;
+                let asset_path = asset.path().to_str().ok_or("Invalid asset path")?;
+                *watermark = watermark.with_image_at(asset_path);
+                assets.push(asset);
+            }
+        }
+        let keyframes = transcode_at(
+            &av,
+            job.segment_index,
+            &segment,
+            &rendition,
+            job.container,
+            key,
+            temp_out_path.clone(),
+        )
+        .await;
 
-        // 4. Upload
-        if temp_out_path.exists() {
-            self.storage.upload(&temp_out_path, dest_key).await?;
-            tokio::fs::remove_file(&temp_out_path).await?;
-        } else {
-            return Err("Transcoding failed to produce output".into());
+        // 4. Upload and update state; the segment's own header if it was re-encoded
+        // from a copied rendition, then keyframes, the last segment to complete
+        // publishes the playlists.
+        let temp_header_path = header_path(&temp_out_path);
+        if temp_header_path.exists() {
+            let header_path = job
+                .output_path
+                .with_file_name(segment_header_name(job.segment_index));
+            let header_key = header_path.to_str().ok_or("Invalid output path")?;
+            self.storage.upload(&temp_header_path, header_key).await?;
+            tokio::fs::remove_file(&temp_header_path).await?;
         }
+        self.repo
+            .record_keyframes(
+                &job.video_id,
+                &job.rendition.name,
+                job.segment_index,
+                &keyframes,
+            )
+            .await?;
+        self.finish_segment(
+            &job.video_id,
+            &job.rendition.name,
+            job.segment_index,
+            &temp_out_path,
+            dest_key,
+            source_key,
+        )
+        .await
+    }
 
-        // 5. Update State
-        self.check_video_completion(&job.video_id, source_key)
+    async fn process_audio_segment(
+        &self,
+        job: &AudioSegmentJob,
+        worker_id: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        println!(
+            "[Worker {}] Processing audio segment {} ({}, {})",
+            worker_id,
+            job.segment_index,
+            job.track.name,
+            job.plan.as_str()
+        );
+
+        let source_key = job.source_path.to_str().ok_or("Invalid source path")?;
+        let dest_key = job.output_path.to_str().ok_or("Invalid output path")?;
+
+        let temp_in = NamedTempFile::new()?;
+        let temp_out_path = std::env::temp_dir().join(format!(
+            "seg_{}_{}_{}.{}",
+            job.video_id,
+            job.track.name,
+            job.segment_index,
+            job.container.extension()
+        ));
+
+        self.storage.download(source_key, temp_in.path()).await?;
+
+        let av = AV::probe(temp_in.path()).await?;
+        // Audio frames are all sync points, so audio is never partial.
+        let segment = PlannedSegment {
+            start: job.start_time,
+            duration: job.duration,
+            partial: false,
+        };
+        let key = self
+            .segment_key(&job.video_id, job.encryption.as_ref(), job.segment_index)
             .await?;
+        transcode_audio_at(
+            &av,
+            job.segment_index,
+            &segment,
+            &job.track,
+            job.container,
+            key,
+            temp_out_path.clone(),
+        )
+        .await;
+
+        self.finish_segment(
+            &job.video_id,
+            &job.track.name,
+            job.segment_index,
+            &temp_out_path,
+            dest_key,
+            source_key,
+        )
+        .await
+    }
+
+    async fn process_subtitles(
+        &self,
+        job: &SubtitleJob,
+        worker_id: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        println!(
+            "[Worker {}] Processing subtitles ({})",
+            worker_id, job.track.name
+        );
+
+        let source_key = job.source_path.to_str().ok_or("Invalid source path")?;
+
+        let temp_in = NamedTempFile::new()?;
+        self.storage.download(source_key, temp_in.path()).await?;
+
+        let stream_index = job.track.stream_index.ok_or("No subtitle stream")?;
+        let cues = extract_cues(temp_in.path(), stream_index).await?;
+
+        let temp_out_path =
+            std::env::temp_dir().join(format!("subs_{}_{}.vtt", job.video_id, job.track.name));
+        for (i, segment) in job.segments.iter().enumerate() {
+            let vtt = webvtt::segment(&cues, segment.start, segment.duration);
+            tokio::fs::write(&temp_out_path, vtt).await?;
+
+            let key = job.output_dir.join(format!("segment_{}.vtt", i));
+            self.storage
+                .upload(&temp_out_path, key.to_str().ok_or("Invalid output path")?)
+                .await?;
+        }
+        let _ = tokio::fs::remove_file(&temp_out_path).await;
+
+        self.check_video_completion(&job.video_id, source_key).await
+    }
+
+    /// Segment sidecar captions along the segment plan of their video, then add them
+    /// to its master playlist. A video still being processed gets them listed when
+    /// its master playlist is generated.
+    async fn process_captions(
+        &self,
+        job: &CaptionJob,
+        worker_id: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        println!(
+            "[Worker {}] Processing captions ({})",
+            worker_id, job.track.name
+        );
+
+        let status = self
+            .repo
+            .get_video_status(&job.video_id)
+            .await?
+            .ok_or("No status")?;
+        let caption_key = job.caption_path.to_str().ok_or("Invalid caption path")?;
 
+        let temp_in = NamedTempFile::new()?;
+        self.storage.download(caption_key, temp_in.path()).await?;
+        let text = String::from_utf8_lossy(&tokio::fs::read(temp_in.path()).await?).into_owned();
+        let cues = webvtt::parse_captions(&text)?;
+
+        let temp_out = NamedTempFile::new()?;
+        let track_dir = status.hls_dir.join(&job.track.name);
+        for (i, segment) in status.segment_plan.segments.iter().enumerate() {
+            let vtt = webvtt::segment(&cues, segment.start, segment.duration);
+            tokio::fs::write(temp_out.path(), vtt).await?;
+
+            let key = track_dir.join(format!("segment_{}.vtt", i));
+            self.storage
+                .upload(temp_out.path(), key.to_str().ok_or("Invalid output path")?)
+                .await?;
+        }
+
+        self.publish_subtitle_playlist(&status, &job.track.name)
+            .await?;
+        self.repo
+            .add_subtitle_track(&job.video_id, &job.track)
+            .await?;
+
+        self.sync_master_subtitles(&job.video_id, None).await?;
+        println!(
+            "Video {}: listed {} in the master playlist",
+            job.video_id, job.track.name
+        );
         Ok(())
     }
 
+    /// Have the master playlist of a video list the subtitle tracks of its status,
+    /// `listed` being those it was last written with, if known. Caption jobs and the
+    /// playlist generation write it concurrently, so the status is read again after
+    /// each upload, until it has no track the upload missed: whoever adds a track
+    /// after that reads it after this upload, and lists it. A video without a master
+    /// playlist yet gets its tracks when it is generated.
+    async fn sync_master_subtitles(
+        &self,
+        video_id: &str,
+        mut listed: Option<Vec<SubtitleTrack>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        loop {
+            let status = self
+                .repo
+                .get_video_status(video_id)
+                .await?
+                .ok_or("No status")?;
+            if listed.as_ref() == Some(&status.subtitle_tracks) {
+                return Ok(());
+            }
+
+            let master_key = status.hls_dir.join("master.m3u8");
+            let master_key = master_key.to_str().ok_or("Invalid output path")?;
+            let temp_master = NamedTempFile::new()?;
+            if self
+                .storage
+                .download(master_key, temp_master.path())
+                .await
+                .is_err()
+            {
+                println!("Video {} has no master playlist yet", video_id);
+                return Ok(());
+            }
+            let master = tokio::fs::read_to_string(temp_master.path()).await?;
+            let media: Vec<AlternateMedia> =
+                status.subtitle_tracks.iter().map(subtitle_media).collect();
+            let master = set_media_group(&master, MediaType::Subtitles, SUBTITLES_GROUP_ID, &media);
+            tokio::fs::write(temp_master.path(), master).await?;
+            self.storage.upload(temp_master.path(), master_key).await?;
+            listed = Some(status.subtitle_tracks);
+        }
+    }
+
+    /// The content key segment `index` of a video encrypted as `encryption` is
+    /// encrypted under, and how.
+    async fn segment_key(
+        &self,
+        video_id: &str,
+        encryption: Option<&Encryption>,
+        index: usize,
+    ) -> Result<Option<SegmentKey>, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(encryption) = encryption else {
+            return Ok(None);
+        };
+        let period = encryption.key_period(index);
+        let key = self
+            .keys
+            .get_key(video_id, period)
+            .await?
+            .ok_or_else(|| format!("No content key {} for video {}", period, video_id))?;
+        Ok(Some(SegmentKey {
+            method: encryption.method,
+            period,
+            key_id: KeyId::new(video_id, period),
+            key,
+        }))
+    }
+
+    /// Upload a transcoded segment of rendition `name`, keeping its size for the
+    /// master playlist's BANDWIDTH, and mark it complete.
+    async fn finish_segment(
+        &self,
+        video_id: &str,
+        name: &str,
+        segment_index: usize,
+        temp_out_path: &Path,
+        dest_key: &str,
+        source_key: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if temp_out_path.exists() {
+            let size = tokio::fs::metadata(temp_out_path).await?.len();
+            self.storage.upload(temp_out_path, dest_key).await?;
+            tokio::fs::remove_file(temp_out_path).await?;
+            self.repo
+                .record_segment_size(video_id, name, segment_index, size)
+                .await?;
+        } else {
+            return Err("Transcoding failed to produce output".into());
+        }
+
+        self.check_video_completion(video_id, source_key).await
+    }
+
     async fn process_thumbnail(
         &self,
         job: &ThumbnailStripJob,
@@ -131,6 +469,29 @@ where
         Ok(())
     }
 
+    async fn process_cover_art(
+        &self,
+        job: &CoverArtJob,
+        worker_id: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        println!("[Worker {}] Processing cover art", worker_id);
+
+        let source_key = job.source_path.to_str().ok_or("Invalid source path")?;
+        let dest_key = job.output_path.to_str().ok_or("Invalid output path")?;
+
+        let temp_in = NamedTempFile::new()?;
+        let temp_out_path = std::env::temp_dir().join(format!("cover_{}.jpg", job.video_id));
+
+        self.storage.download(source_key, temp_in.path()).await?;
+
+        extract_cover_art(temp_in.path(), &temp_out_path, job.stream_index).await?;
+
+        self.storage.upload(&temp_out_path, dest_key).await?;
+        tokio::fs::remove_file(&temp_out_path).await?;
+
+        Ok(())
+    }
+
     async fn check_video_completion(
         &self,
         video_id: &str,
@@ -160,47 +521,758 @@ where
             .await?
             .ok_or("No status")?;
 
-        let mut playlist = MediaPlaylist::new(0);
-        playlist.playlist_type = Some("VOD".to_string());
-        playlist.independent_segments = true;
-
-        // Generate init segment
-        // Need to download source again? Or cache? For simplicity, download again.
+        // Init segments are generated from the source, so download it once for all
+        // renditions.
         let temp_in = NamedTempFile::new()?;
         self.storage.download(source_key, temp_in.path()).await?;
 
-        let temp_init_path = std::env::temp_dir().join(format!("init_{}.mp4", video_id));
-        if let Err(e) = generate_init_segment(temp_in.path(), &temp_init_path).await {
-            eprintln!("Init segment gen failed: {:?}", e);
-        } else {
-            // Upload init.mp4
-            let init_key = status.hls_dir.join("init.mp4");
+        // Renditions keep the source frame rate, unless normalized to a constant one.
+        let av = AV::probe(temp_in.path()).await?;
+        let frame_rate = av.video_streams.first().and_then(|stream| stream.fps());
+        let (color, rotation) = av
+            .video_streams
+            .first()
+            .map(|stream| (stream.color.clone(), stream.rotation))
+            .unwrap_or_default();
+        // VIDEO-RANGE only matters when some variants are HDR.
+        let has_hdr = status
+            .renditions
+            .iter()
+            .any(|rendition| rendition.video_range != VideoRange::Sdr);
+        let segment_durations = status.segment_plan.durations();
+
+        let mut master = match status.container {
+            Container::Fmp4 => MasterPlaylist::new(),
+            Container::MpegTs => MasterPlaylist::transport_stream(),
+        };
+        master.independent_segments = true;
+        if let Some(loudness) = &status.loudness {
+            master.add_session_data(SessionData {
+                data_id: LOUDNESS_DATA_ID.to_string(),
+                value: loudness.to_string(),
+                language: None,
+            });
+        }
+        // DASH clients get the same fragments through a manifest of their own.
+        let mut manifest = Manifest::new(segment_durations.clone());
+        manifest.start = status
+            .segment_plan
+            .segments
+            .first()
+            .map_or(0.0, |segment| segment.start);
+
+        // Alternate audio first: every variant has to account for the audio it
+        // plays along with.
+        let mut audio_codecs: Vec<String> = Vec::new();
+        let (mut audio_bandwidth, mut audio_average_bandwidth) = (0, 0);
+        for track in &status.audio_tracks {
+            let init_path = temp_init_path(&status, &track.name);
+            let init = generate_audio_init_segment(temp_in.path(), &init_path, track).await;
+            let published = self
+                .publish_media_playlist(
+                    &status,
+                    &track.name,
+                    init.map(|_| init_path.as_path()),
+                    &[],
+                )
+                .await?;
+            master.version = master.version.max(published.version);
+            for codec in &published.codecs {
+                if !audio_codecs.contains(codec) {
+                    audio_codecs.push(codec.clone());
+                }
+            }
+
+            let sizes = self.repo.get_segment_sizes(video_id, &track.name).await?;
+            let (peak, average) = measured_bandwidth(&sizes, &segment_durations);
+            audio_bandwidth = audio_bandwidth.max(peak);
+            audio_average_bandwidth = audio_average_bandwidth.max(average);
+
+            if let Some(loudness) = &track.loudness {
+                master.add_session_data(SessionData {
+                    data_id: format!("{}.{}", LOUDNESS_DATA_ID, track.name),
+                    value: loudness.to_string(),
+                    language: track.language.clone(),
+                });
+            }
+            master.add_media(AlternateMedia {
+                media_type: MediaType::Audio,
+                group_id: AUDIO_GROUP_ID.to_string(),
+                language: track.language.clone(),
+                name: track.title.clone(),
+                default: track.default,
+                autoselect: true,
+                forced: false,
+                channels: Some(track.channels).filter(|&channels| channels > 0),
+                uri: format!("{}/playlist.m3u8", track.name),
+            });
+            manifest.add_adaptation_set(AdaptationSet {
+                content_type: ContentType::Audio,
+                language: track.language.clone(),
+                label: Some(track.title.clone()),
+                main: track.default,
+                representations: vec![Representation {
+                    id: track.name.clone(),
+                    bandwidth: peak,
+                    codecs: Some(published.codecs.join(",")).filter(|codecs| !codecs.is_empty()),
+                    resolution: None,
+                    frame_rate: None,
+                    channels: Some(track.channels).filter(|&channels| channels > 0),
+                    base_url: published.base_url,
+                    segments: published.segments,
+                }],
+            });
+        }
+
+        for track in &status.subtitle_tracks {
+            // Sidecar captions published their playlist along with their segments.
+            if track.stream_index.is_some() {
+                self.publish_subtitle_playlist(&status, &track.name).await?;
+            }
+            master.add_media(subtitle_media(track));
+        }
+
+        let mut video_representations = Vec::new();
+
+        for rendition in &status.renditions {
+            let init_path = temp_init_path(&status, &rendition.name);
+            let rendition_frame_rate = rendition.frame_rate.map(|r| r.as_f64()).or(frame_rate);
+            // Watermarks don't change what the encoder is set up with, so the header
+            // is generated without their assets.
+            let unmarked = Rendition {
+                watermarks: Vec::new(),
+                ..rendition.clone()
+            };
+            let init = generate_init_segment(
+                temp_in.path(),
+                &init_path,
+                &unmarked,
+                &color.for_rendition(rendition.tone_map),
+                rotation,
+            )
+            .await;
+            let keyframes = self.repo.get_keyframes(video_id, &rendition.name).await?;
+            let published = self
+                .publish_media_playlist(
+                    &status,
+                    &rendition.name,
+                    init.map(|_| init_path.as_path()),
+                    &keyframes,
+                )
+                .await?;
+            master.version = master.version.max(published.version);
+            let mut codecs = published.codecs.clone();
+            codecs.extend(audio_codecs.iter().cloned());
+
+            let sizes = self
+                .repo
+                .get_segment_sizes(video_id, &rendition.name)
+                .await?;
+            let (bandwidth, average_bandwidth) = measured_bandwidth(&sizes, &segment_durations);
+
+            master.add_variant(VariantStream {
+                bandwidth: bandwidth + audio_bandwidth,
+                average_bandwidth: Some(average_bandwidth + audio_average_bandwidth),
+                codecs: Some(codecs.join(",")).filter(|codecs| !codecs.is_empty()),
+                resolution: Some((rendition.width, rendition.height))
+                    .filter(|&(width, height)| width > 0 && height > 0),
+                frame_rate: rendition_frame_rate,
+                video_range: has_hdr.then_some(rendition.video_range),
+                audio: (!status.audio_tracks.is_empty()).then(|| AUDIO_GROUP_ID.to_string()),
+                subtitles: (!status.subtitle_tracks.is_empty())
+                    .then(|| SUBTITLES_GROUP_ID.to_string()),
+                uri: format!("{}/playlist.m3u8", rendition.name),
+            });
+            if let Some((bandwidth, average_bandwidth)) = published.iframes {
+                let video_codecs: Vec<&str> = published
+                    .codecs
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|codec| is_video_codec(codec))
+                    .collect();
+                master.add_iframe_stream(IFrameStream {
+                    bandwidth,
+                    average_bandwidth: Some(average_bandwidth),
+                    codecs: Some(video_codecs.join(",")).filter(|codecs| !codecs.is_empty()),
+                    resolution: Some((rendition.width, rendition.height))
+                        .filter(|&(width, height)| width > 0 && height > 0),
+                    video_range: has_hdr.then_some(rendition.video_range),
+                    uri: format!("{}/iframes.m3u8", rendition.name),
+                });
+            }
+            video_representations.push(Representation {
+                id: rendition.name.clone(),
+                bandwidth,
+                codecs: Some(published.codecs.join(",")).filter(|codecs| !codecs.is_empty()),
+                resolution: Some((rendition.width, rendition.height))
+                    .filter(|&(width, height)| width > 0 && height > 0),
+                frame_rate: rendition_frame_rate,
+                channels: None,
+                base_url: published.base_url,
+                segments: published.segments,
+            });
+        }
+        // Audio-only videos play their default audio track as the one variant.
+        if let Some(track) = status
+            .audio_tracks
+            .iter()
+            .find(|track| track.default)
+            .filter(|_| status.renditions.is_empty())
+        {
+            master.add_variant(VariantStream {
+                bandwidth: audio_bandwidth,
+                average_bandwidth: Some(audio_average_bandwidth),
+                codecs: Some(audio_codecs.join(",")).filter(|codecs| !codecs.is_empty()),
+                resolution: None,
+                frame_rate: None,
+                video_range: None,
+                audio: Some(AUDIO_GROUP_ID.to_string()),
+                subtitles: (!status.subtitle_tracks.is_empty())
+                    .then(|| SUBTITLES_GROUP_ID.to_string()),
+                uri: format!("{}/playlist.m3u8", track.name),
+            });
+        }
+        if !video_representations.is_empty() {
+            manifest.add_adaptation_set(AdaptationSet {
+                content_type: ContentType::Video,
+                language: None,
+                label: None,
+                main: true,
+                representations: video_representations,
+            });
+        }
+
+        let temp_master_path = std::env::temp_dir().join(format!("master_{}.m3u8", video_id));
+        master.write_to(&temp_master_path).await?;
+
+        let master_key = status.hls_dir.join("master.m3u8");
+        self.storage
+            .upload(&temp_master_path, master_key.to_str().unwrap())
+            .await?;
+        let _ = tokio::fs::remove_file(&temp_master_path).await;
+        // Captions added since the status was read are listed too.
+        self.sync_master_subtitles(video_id, Some(status.subtitle_tracks.clone()))
+            .await?;
+
+        // DASH only packages fragmented MP4, and of the encryption methods only knows
+        // Common Encryption. A representation has a single header, so copied video
+        // with segments re-encoded at trim points is left to HLS.
+        let dash = match &status.encryption {
+            Some(encryption) => encryption.method.is_common(),
+            None => true,
+        } && !(status.segment_plan.has_partial()
+            && status
+                .renditions
+                .iter()
+                .any(|rendition| rendition.is_copy()));
+        if status.container == Container::Fmp4 && dash {
+            manifest.content_protection = status.encryption.as_ref().and_then(|encryption| {
+                Some(ContentProtection {
+                    scheme: encryption.method.scheme()?.to_string(),
+                    default_key_id: KeyId::new(&status.id, 0),
+                    license_url: encryption.license_url(&status.id),
+                })
+            });
+            let temp_manifest_path =
+                std::env::temp_dir().join(format!("manifest_{}.mpd", video_id));
+            manifest.write_to(&temp_manifest_path).await?;
+
+            let manifest_key = status.hls_dir.join("manifest.mpd");
             self.storage
-                .upload(&temp_init_path, init_key.to_str().unwrap())
+                .upload(&temp_manifest_path, manifest_key.to_str().unwrap())
                 .await?;
-            playlist.init_segment = Some("init.mp4".to_string());
-            let _ = tokio::fs::remove_file(&temp_init_path).await;
+            let _ = tokio::fs::remove_file(&temp_manifest_path).await;
+        }
+
+        Ok(())
+    }
+
+    /// Upload the init segment and media playlist of rendition `name`; `init` is
+    /// the outcome of generating its init segment at a local path. With the
+    /// `keyframes` of its segments, its I-frame playlist is uploaded too.
+    async fn publish_media_playlist(
+        &self,
+        status: &VideoStatus,
+        name: &str,
+        init: Result<&Path, std::io::Error>,
+        keyframes: &[Vec<Keyframe>],
+    ) -> Result<PublishedMedia, Box<dyn std::error::Error + Send + Sync>> {
+        let rendition_dir = status.hls_dir.join(name);
+        let extension = status.container.extension();
+        let single_file = format!("media.{}", extension);
+
+        let mut playlist = match status.container {
+            Container::Fmp4 => MediaPlaylist::new(0),
+            Container::MpegTs => MediaPlaylist::transport_stream(0),
+        };
+        playlist.playlist_type = Some("VOD".to_string());
+        playlist.independent_segments = true;
+
+        let init = match init {
+            Err(e) => {
+                eprintln!("Init segment gen failed: {:?}", e);
+                None
+            }
+            Ok(init_path) => Some(init_path),
+        };
+        let mut codecs = Vec::new();
+        if let Some(init_path) = init {
+            codecs = mp4::codecs(&tokio::fs::read(init_path).await?);
+        }
+        // Transport streams carry their own headers; their init segment is only
+        // generated to read the codecs from.
+        let header = init.filter(|_| status.container == Container::Fmp4);
+
+        // Under Common Encryption, the init segment declares the tracks encrypted and
+        // lists the key IDs of every key period.
+        let common = status
+            .encryption
+            .as_ref()
+            .filter(|encryption| encryption.method.is_common())
+            .map(|encryption| {
+                let key_ids: Vec<KeyId> = (0..encryption.key_count(status.segment_plan.len()))
+                    .map(|period| KeyId::new(&status.id, period))
+                    .collect();
+                (encryption.method, key_ids)
+            });
+        if let (Some((method, key_ids)), Some(init_path)) = (&common, header) {
+            let clear = tokio::fs::read(init_path).await?;
+            let protected = cenc::protect_init(&clear, *method, key_ids)?;
+            tokio::fs::write(init_path, protected).await?;
+        }
+
+        // Partial segments at trim points of a copied rendition were re-encoded, so
+        // they are set apart by discontinuities, and in fMP4 come with their own
+        // header, whose codecs players need too.
+        let copied = status
+            .renditions
+            .iter()
+            .any(|rendition| rendition.name == name && rendition.is_copy());
+        let reencoded: Vec<usize> = match copied {
+            true => status
+                .segment_plan
+                .segments
+                .iter()
+                .enumerate()
+                .filter(|(_, segment)| segment.partial)
+                .map(|(i, _)| i)
+                .collect(),
+            false => Vec::new(),
+        };
+        if status.container == Container::Fmp4 {
+            for &i in &reencoded {
+                let header_key = rendition_dir.join(segment_header_name(i));
+                let header_key = header_key.to_str().ok_or("Invalid output path")?;
+                let temp_header = NamedTempFile::new()?;
+                self.storage
+                    .download(header_key, temp_header.path())
+                    .await?;
+                let clear = tokio::fs::read(temp_header.path()).await?;
+                for codec in mp4::codecs(&clear) {
+                    if !codecs.contains(&codec) {
+                        codecs.push(codec);
+                    }
+                }
+                if let Some((method, key_ids)) = &common {
+                    let protected = cenc::protect_init(&clear, *method, key_ids)?;
+                    tokio::fs::write(temp_header.path(), protected).await?;
+                    self.storage.upload(temp_header.path(), header_key).await?;
+                }
+            }
+        }
+
+        let joined = self.rewrite_segments(status, name, header).await?;
+        match (&joined, header) {
+            (Some((init_range, _)), _) => {
+                playlist.init_segment = init_range.map(|_| single_file.clone());
+                playlist.init_byte_range = *init_range;
+                // Byte ranges need version 4.
+                playlist.version = playlist.version.max(4);
+            }
+            (None, Some(init_path)) => {
+                // Upload init.mp4
+                let init_key = rendition_dir.join("init.mp4");
+                self.storage
+                    .upload(init_path, init_key.to_str().unwrap())
+                    .await?;
+                playlist.init_segment = Some("init.mp4".to_string());
+            }
+            (None, None) => {}
+        }
+        if let Some(init_path) = init {
+            let _ = tokio::fs::remove_file(init_path).await;
         }
 
         // Playlist construction
         let mut max_duration = 0.0;
-        for (i, &duration) in status.segment_durations.iter().enumerate() {
-            if duration > max_duration {
-                max_duration = duration;
+        for (i, segment) in status.segment_plan.segments.iter().enumerate() {
+            if segment.duration > max_duration {
+                max_duration = segment.duration;
+            }
+            // The IV of every segment is its sequence number, so a key tag is only
+            // needed where the key changes. Common Encryption has a single license
+            // with every key, the fragments saying which one they are encrypted with.
+            if let Some(encryption) = &status.encryption {
+                let changed = match encryption.method.is_common() {
+                    true => i == 0,
+                    false => i == 0 || encryption.key_period(i) != encryption.key_period(i - 1),
+                };
+                if changed {
+                    playlist.add_key(key_tag(encryption, &status.id, i, None));
+                }
+            }
+            let edge = reencoded.contains(&i);
+            if edge && i > 0 {
+                playlist.add_discontinuity();
+            }
+            if edge && status.container == Container::Fmp4 {
+                playlist.add_map(segment_header_name(i), None);
+            }
+            match &joined {
+                Some((_, ranges)) => {
+                    playlist.add_segment_range(segment.duration, single_file.clone(), ranges[i])
+                }
+                None => {
+                    playlist.add_segment(segment.duration, format!("segment_{}.{}", i, extension))
+                }
+            }
+            if edge && i + 1 < status.segment_plan.len() {
+                playlist.add_discontinuity();
+                if let Some(init) = &playlist.init_segment {
+                    playlist.add_map(init.clone(), playlist.init_byte_range);
+                }
             }
-            playlist.add_segment(duration, format!("segment_{}.mp4", i));
         }
         playlist.target_duration = max_duration.ceil() as u64;
 
-        let temp_pl_path = std::env::temp_dir().join(format!("playlist_{}.m3u8", video_id));
+        let temp_pl_path =
+            std::env::temp_dir().join(format!("playlist_{}_{}.m3u8", status.id, name));
         playlist.write_to(&temp_pl_path).await?;
 
-        let pl_key = status.hls_dir.join("playlist.m3u8");
+        let pl_key = rendition_dir.join("playlist.m3u8");
         self.storage
             .upload(&temp_pl_path, pl_key.to_str().unwrap())
             .await?;
         let _ = tokio::fs::remove_file(&temp_pl_path).await;
 
+        let iframes = self
+            .publish_iframe_playlist(status, name, &playlist, &joined, keyframes, &reencoded)
+            .await?;
+
+        let (base_url, segments) = match joined {
+            Some((Some(initialization), media)) => (
+                format!("{}/{}", name, single_file),
+                SegmentAddressing::List {
+                    initialization,
+                    media,
+                },
+            ),
+            _ => (
+                format!("{}/", name),
+                SegmentAddressing::Template {
+                    initialization: "init.mp4".to_string(),
+                    media: format!("segment_$Number$.{}", extension),
+                },
+            ),
+        };
+        Ok(PublishedMedia {
+            codecs,
+            base_url,
+            segments,
+            iframes,
+            version: playlist.version,
+        })
+    }
+
+    /// Upload the I-frame playlist of rendition `name`, whose media playlist is
+    /// `playlist`: every keyframe of its segments, lasting until the next one.
+    /// Segments at `reencoded` have headers of their own and are left out.
+    /// Returns its peak and average bitrates, or `None` if there are no keyframes.
+    async fn publish_iframe_playlist(
+        &self,
+        status: &VideoStatus,
+        name: &str,
+        playlist: &MediaPlaylist,
+        joined: &Option<JoinedRanges>,
+        keyframes: &[Vec<Keyframe>],
+        reencoded: &[usize],
+    ) -> Result<Option<(u64, u64)>, Box<dyn std::error::Error + Send + Sync>> {
+        let extension = status.container.extension();
+        let mut frames: Vec<(f64, usize, String, ByteRange)> = Vec::new();
+        for (i, segment_keyframes) in keyframes.iter().enumerate() {
+            if reencoded.contains(&i) {
+                continue;
+            }
+            // Keyframe offsets are within their segment, wherever it ended up.
+            let (uri, segment_offset) = match joined {
+                Some((_, ranges)) => match ranges.get(i) {
+                    Some(range) => (format!("media.{}", extension), range.offset),
+                    None => continue,
+                },
+                None => (format!("segment_{}.{}", i, extension), 0),
+            };
+            for keyframe in segment_keyframes {
+                let range = ByteRange {
+                    length: keyframe.length,
+                    offset: segment_offset + keyframe.offset,
+                };
+                frames.push((keyframe.time, i, uri.clone(), range));
+            }
+        }
+        if frames.is_empty() {
+            return Ok(None);
+        }
+        frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let end = status
+            .segment_plan
+            .segments
+            .last()
+            .map_or(0.0, |segment| segment.start + segment.duration);
+        let mut iframes = playlist.iframes();
+        let (mut sizes, mut durations) = (Vec::new(), Vec::new());
+        let mut max_duration: f64 = 0.0;
+        let mut previous_segment = None;
+        for (i, (time, segment, uri, range)) in frames.iter().enumerate() {
+            // Samples are encrypted with the IV of the segment they are in, not the
+            // sequence number of the I-frame, so the key tag gives it. Common
+            // Encryption has its IVs in the fragments.
+            if let Some(encryption) = &status.encryption {
+                let changed = match encryption.method.is_common() {
+                    true => previous_segment.is_none(),
+                    false => previous_segment != Some(*segment),
+                };
+                if changed {
+                    let iv = (!encryption.method.is_common()).then(|| segment_iv(*segment));
+                    iframes.add_key(key_tag(encryption, &status.id, *segment, iv));
+                }
+            }
+            previous_segment = Some(*segment);
+            let next = frames.get(i + 1).map_or(end, |(next, _, _, _)| *next);
+            let duration = (next - time).max(0.0);
+            max_duration = max_duration.max(duration);
+            sizes.push(range.length);
+            durations.push(duration);
+            iframes.add_segment_range(duration, uri.clone(), *range);
+        }
+        iframes.target_duration = max_duration.ceil() as u64;
+
+        let temp_pl_path =
+            std::env::temp_dir().join(format!("iframes_{}_{}.m3u8", status.id, name));
+        iframes.write_to(&temp_pl_path).await?;
+
+        let pl_key = status.hls_dir.join(name).join("iframes.m3u8");
+        self.storage
+            .upload(&temp_pl_path, pl_key.to_str().unwrap())
+            .await?;
+        let _ = tokio::fs::remove_file(&temp_pl_path).await;
+
+        Ok(Some(measured_bandwidth(&sizes, &durations)))
+    }
+
+    /// Upload the media playlist of subtitle rendition `name`, whose WebVTT segments
+    /// follow the segment plan.
+    async fn publish_subtitle_playlist(
+        &self,
+        status: &VideoStatus,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut playlist = match status.container {
+            Container::Fmp4 => MediaPlaylist::new(0),
+            Container::MpegTs => MediaPlaylist::transport_stream(0),
+        };
+        playlist.playlist_type = Some("VOD".to_string());
+
+        let mut max_duration: f64 = 0.0;
+        for (i, segment) in status.segment_plan.segments.iter().enumerate() {
+            max_duration = max_duration.max(segment.duration);
+            playlist.add_segment(segment.duration, format!("segment_{}.vtt", i));
+        }
+        playlist.target_duration = max_duration.ceil() as u64;
+
+        let temp_pl_path =
+            std::env::temp_dir().join(format!("playlist_{}_{}.m3u8", status.id, name));
+        playlist.write_to(&temp_pl_path).await?;
+
+        let pl_key = status.hls_dir.join(name).join("playlist.m3u8");
+        self.storage
+            .upload(&temp_pl_path, pl_key.to_str().unwrap())
+            .await?;
+        let _ = tokio::fs::remove_file(&temp_pl_path).await;
         Ok(())
     }
+
+    /// Go over the uploaded segments of rendition `name` in order, for what their
+    /// workers could not do on their own: transport streams get continuous
+    /// continuity counters, renumbered in the clear when encrypted whole, and with
+    /// `Packaging::SingleFile` the init segment at
+    /// `init` and the segments are joined into a single file that replaces them.
+    /// Returns the byte ranges of the init segment and of each segment in that file.
+    async fn rewrite_segments(
+        &self,
+        status: &VideoStatus,
+        name: &str,
+        init: Option<&Path>,
+    ) -> Result<Option<JoinedRanges>, Box<dyn std::error::Error + Send + Sync>> {
+        let single_file = status.packaging == Packaging::SingleFile;
+        let mut continuity = (status.container == Container::MpegTs).then(Continuity::new);
+        if !single_file && continuity.is_none() {
+            return Ok(None);
+        }
+
+        let rendition_dir = status.hls_dir.join(name);
+        let extension = status.container.extension();
+        let temp_dir = std::env::temp_dir();
+        let packed_path = temp_dir.join(format!("media_{}_{}.{}", status.id, name, extension));
+        let segment_path = temp_dir.join(format!("pack_{}_{}.{}", status.id, name, extension));
+
+        let mut packed = match single_file {
+            true => Some(tokio::fs::File::create(&packed_path).await?),
+            false => None,
+        };
+        let mut init_range = None;
+        let mut offset = 0;
+        if let (Some(packed), Some(init_path)) = (packed.as_mut(), init) {
+            let init = tokio::fs::read(init_path).await?;
+            packed.write_all(&init).await?;
+            init_range = Some(ByteRange {
+                length: init.len() as u64,
+                offset: 0,
+            });
+            offset = init.len() as u64;
+        }
+
+        // Transport streams encrypted whole are decrypted to be renumbered.
+        let whole = status
+            .encryption
+            .as_ref()
+            .filter(|encryption| encryption.method == EncryptionMethod::Aes128);
+
+        let mut segment_keys = Vec::with_capacity(status.segment_plan.len());
+        let mut segment_ranges = Vec::with_capacity(status.segment_plan.len());
+        for i in 0..status.segment_plan.len() {
+            let key = rendition_dir.join(format!("segment_{}.{}", i, extension));
+            let key = key.to_str().ok_or("Invalid segment path")?.to_string();
+            self.storage.download(&key, &segment_path).await?;
+
+            let mut segment = tokio::fs::read(&segment_path).await?;
+            if let Some(continuity) = continuity.as_mut() {
+                match self.segment_key(&status.id, whole, i).await? {
+                    Some(segment_key) => {
+                        let cipher = Aes128::new(&segment_key.key)?;
+                        let mut clear = decrypt_segment(&cipher, segment_iv(i), &segment)?;
+                        continuity.renumber(&mut clear);
+                        segment = encrypt_segment(&cipher, segment_iv(i), &clear);
+                    }
+                    None => continuity.renumber(&mut segment),
+                }
+            }
+            match packed.as_mut() {
+                Some(packed) => {
+                    packed.write_all(&segment).await?;
+                    segment_ranges.push(ByteRange {
+                        length: segment.len() as u64,
+                        offset,
+                    });
+                    offset += segment.len() as u64;
+                }
+                None => {
+                    tokio::fs::write(&segment_path, &segment).await?;
+                    self.storage.upload(&segment_path, &key).await?;
+                }
+            }
+            segment_keys.push(key);
+        }
+        let _ = tokio::fs::remove_file(&segment_path).await;
+
+        let Some(mut packed) = packed else {
+            return Ok(None);
+        };
+        packed.flush().await?;
+
+        let packed_key = rendition_dir.join(format!("media.{}", extension));
+        self.storage
+            .upload(&packed_path, packed_key.to_str().unwrap())
+            .await?;
+        let _ = tokio::fs::remove_file(&packed_path).await;
+
+        // Only drop the segments once the packed file is in place.
+        for key in &segment_keys {
+            self.storage.delete(key).await?;
+        }
+
+        Ok(Some((init_range, segment_ranges)))
+    }
+}
+
+/// Byte ranges of the init segment, if any, and of each segment in a single file.
+type JoinedRanges = (Option<ByteRange>, Vec<ByteRange>);
+
+/// A rendition once its init segment and media playlist are uploaded.
+struct PublishedMedia {
+    /// RFC 6381 codecs of its tracks, read from the init segment.
+    codecs: Vec<String>,
+    /// Where the DASH manifest finds its segments.
+    base_url: String,
+    segments: SegmentAddressing,
+    /// Peak and average bitrates of its I-frame playlist, if it has one.
+    iframes: Option<(u64, u64)>,
+    /// Version of its media playlist, e.g. 4 with byte ranges or 5 with SAMPLE-AES
+    /// keys. Its I-frame playlist only adds the version 4 of I-frame streams.
+    version: u8,
+}
+
+/// Whether RFC 6381 `codec` is a video codec, as `EXT-X-I-FRAME-STREAM-INF` lists
+/// only those.
+fn is_video_codec(codec: &str) -> bool {
+    const VIDEO: &[&str] = &[
+        "avc1", "avc3", "hvc1", "hev1", "dvh1", "dvhe", "av01", "vp09",
+    ];
+    VIDEO.iter().any(|prefix| codec.starts_with(prefix))
+}
+
+/// The `EXT-X-KEY` tag of the key segment `index` of `video_id` is encrypted with,
+/// or under Common Encryption of the license with every key of the video.
+fn key_tag(
+    encryption: &Encryption,
+    video_id: &str,
+    index: usize,
+    iv: Option<[u8; BLOCK_SIZE]>,
+) -> KeyTag {
+    let uri = match encryption.method.is_common() {
+        true => encryption.license_url(video_id),
+        false => encryption.key_uri(video_id, encryption.key_period(index)),
+    };
+    KeyTag {
+        method: encryption.method,
+        uri,
+        iv,
+    }
+}
+
+/// The `EXT-X-MEDIA` entry of a subtitle rendition.
+fn subtitle_media(track: &SubtitleTrack) -> AlternateMedia {
+    AlternateMedia {
+        media_type: MediaType::Subtitles,
+        group_id: SUBTITLES_GROUP_ID.to_string(),
+        language: track.language.clone(),
+        name: track.title.clone(),
+        default: track.default,
+        // Forced subtitles are only picked when automatically selected.
+        autoselect: true,
+        forced: track.forced,
+        channels: None,
+        uri: format!("{}/playlist.m3u8", track.name),
+    }
+}
+
+/// Name of the header of segment `index` when it was re-encoded from a copied
+/// rendition, next to the segment.
+fn segment_header_name(index: usize) -> String {
+    format!("init_{}.mp4", index)
+}
+
+/// Local path an init segment of rendition `name` is generated at.
+fn temp_init_path(status: &VideoStatus, name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("init_{}_{}.mp4", status.id, name))
 }
@@ -8,9 +8,20 @@
 //! - S3_BUCKET: S3 bucket for video storage
 //! - SQS_QUEUE_URL: SQS queue URL for jobs
 //! - DYNAMODB_TABLE: DynamoDB table for video state
+//! - LADDER: Renditions to produce (optional, e.g. "2160p@hevc,1080p,720p,480p,360p")
+//! - PACKAGING: Packaging of the video, "segments" or "single-file" (optional)
+//! - CONTAINER: Segment container, "fmp4" or "ts" for legacy players (optional)
+//! - SEGMENT_DURATION: Target segment duration in seconds (optional, e.g. "6" or "6:2:10")
+//! - LOUDNESS_TARGET: Loudness encoded audio is normalized to, in LUFS (optional, e.g. "-16")
+//! - ENCRYPTION: Segment encryption, "aes-128", "sample-aes", "cenc" or "cbcs" (optional)
+//! - KEY_ROTATION: Segments encrypted under each content key (optional, e.g. "10")
+//! - KEY_URL: Base URL players get content keys and ClearKey licenses from (optional, default "/keys")
 
 use sinatra::adapters::aws::{dynamodb::DynamoAdapter, s3::S3Adapter, sqs::SqsAdapter};
 use sinatra::application::orchestrator::OrchestratorService;
+use sinatra::config::AwsConfig;
+use sinatra::domain::encryption::EncryptionMethod;
+use sinatra::domain::options::{Container, Packaging, VideoOptions};
 use std::sync::Arc;
 
 #[tokio::main]
@@ -21,9 +32,7 @@ async fn main() {
     let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
 
     // Environment variables
-    let bucket = std::env::var("S3_BUCKET").expect("S3_BUCKET env var required");
-    let queue_url = std::env::var("SQS_QUEUE_URL").expect("SQS_QUEUE_URL env var required");
-    let table_name = std::env::var("DYNAMODB_TABLE").expect("DYNAMODB_TABLE env var required");
+    let app_config = AwsConfig::from_env();
 
     // Create AWS clients
     let s3_client = aws_sdk_s3::Client::new(&config);
@@ -31,12 +40,18 @@ async fn main() {
     let dynamo_client = aws_sdk_dynamodb::Client::new(&config);
 
     // Create adapters
-    let storage = S3Adapter::new(s3_client, bucket);
-    let queue = SqsAdapter::new(sqs_client, queue_url);
-    let repo = DynamoAdapter::new(dynamo_client, table_name);
+    let storage = S3Adapter::new(s3_client, app_config.s3_bucket);
+    let queue = SqsAdapter::new(sqs_client, app_config.sqs_queue_url);
+    let repo = DynamoAdapter::new(dynamo_client, app_config.dynamodb_table);
 
     // Create Orchestrator service
-    let orchestrator = Arc::new(OrchestratorService::new(storage, queue, repo));
+    let orchestrator = Arc::new(
+        OrchestratorService::new(storage, queue, repo.clone(), repo)
+            .with_ladder(app_config.ladder)
+            .with_segment_target(app_config.segment_target)
+            .with_loudness_target(app_config.loudness_target)
+            .with_key_url(app_config.key_url),
+    );
 
     // In Lambda context, this would be triggered by S3 event.
     // For now, read video key from environment or stdin for testing.
@@ -45,9 +60,35 @@ async fn main() {
         std::process::exit(1);
     });
 
+    // Per-video options come along with the key, like object metadata would.
+    let mut options = VideoOptions::default();
+    if let Ok(packaging) = std::env::var("PACKAGING") {
+        options.packaging =
+            Packaging::parse(&packaging).unwrap_or_else(|e| panic!("Invalid PACKAGING: {}", e));
+    }
+    if let Ok(container) = std::env::var("CONTAINER") {
+        options.container =
+            Container::parse(&container).unwrap_or_else(|e| panic!("Invalid CONTAINER: {}", e));
+    }
+    if let Ok(encryption) = std::env::var("ENCRYPTION") {
+        options.encryption = Some(
+            EncryptionMethod::parse(&encryption)
+                .unwrap_or_else(|e| panic!("Invalid ENCRYPTION: {}", e)),
+        );
+    }
+    if let Ok(rotation) = std::env::var("KEY_ROTATION") {
+        options.key_rotation = Some(
+            rotation
+                .parse()
+                .ok()
+                .filter(|&rotation| rotation > 0)
+                .unwrap_or_else(|| panic!("Invalid KEY_ROTATION: {}", rotation)),
+        );
+    }
+
     println!("Processing new video: {}", video_key);
 
-    match orchestrator.handle_new_video(&video_key).await {
+    match orchestrator.handle_new_video(&video_key, &options).await {
         Ok(video_id) => println!("Successfully enqueued video: {}", video_id),
         Err(e) => eprintln!("Failed to process video: {:?}", e),
     }
@@ -36,8 +36,8 @@ async fn main() {
     let queue = SqsAdapter::new(sqs_client, queue_url);
     let repo = DynamoAdapter::new(dynamo_client, table_name);
 
-    // Create and run Worker service
-    let worker = Arc::new(WorkerService::new(storage, queue, repo));
+    // Create and run Worker service; content keys are kept with the video state
+    let worker = Arc::new(WorkerService::new(storage, queue, repo.clone(), repo));
 
     println!("AWS Worker started, polling for jobs...");
 
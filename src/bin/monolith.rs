@@ -3,11 +3,12 @@
 //! This is the main entry point for local development and single-server deployment.
 //! It wires up:
 //! - Local adapters (filesystem, Redis)
-//! - HTTP/S3-compatible inbound adapter
+//! - HTTP/S3-compatible inbound adapter, and the content key endpoint when
+//!   `KEY_SECRET` is set
 //! - Event-driven video processing pipeline
 
 use axum::{extract::DefaultBodyLimit, Router};
-use sinatra::adapters::local::{buckets, events, fs::FsAdapter, redis::RedisQueue, LocalS3};
+use sinatra::adapters::local::{buckets, events, fs::FsAdapter, keys, redis::RedisQueue, LocalS3};
 use sinatra::application::{orchestrator::OrchestratorService, worker::WorkerService};
 use sinatra::config::LocalConfig;
 use std::path::PathBuf;
@@ -32,16 +33,24 @@ async fn main() {
     let fs_adapter = FsAdapter::new();
 
     // 2. Application Services
-    let orchestrator = Arc::new(OrchestratorService::new(
-        fs_adapter,
-        redis_queue.clone(),
-        redis_queue.clone(),
-    ));
+    let orchestrator = Arc::new(
+        OrchestratorService::new(
+            fs_adapter,
+            redis_queue.clone(),
+            redis_queue.clone(),
+            redis_queue.clone(),
+        )
+        .with_ladder(config.ladder.clone())
+        .with_segment_target(config.segment_target)
+        .with_loudness_target(config.loudness_target)
+        .with_key_url(config.key_url.clone()),
+    );
 
     let worker_service = Arc::new(WorkerService::new(
         fs_adapter,
         redis_queue.clone(),
         redis_queue.clone(),
+        redis_queue.clone(),
     ));
 
     // 3. Start Workers
@@ -77,11 +86,22 @@ async fn main() {
     // Apply CORS and body limit to the S3 service
     use tower::ServiceBuilder;
     let s3_with_cors = ServiceBuilder::new()
-        .layer(cors)
+        .layer(cors.clone())
         .layer(DefaultBodyLimit::disable())
         .service(s3_service);
 
-    let app = Router::new().fallback_service(s3_with_cors);
+    // Content keys and ClearKey licenses of encrypted videos, for players with a
+    // token. Tokens anyone could sign would let anyone get the keys, so without a
+    // secret of their own the keys aren't served at all.
+    let mut app = Router::new();
+    match &config.key_secret {
+        Some(secret) => {
+            let key_routes = keys::router(redis_queue.clone(), secret.clone()).layer(cors);
+            app = app.nest("/keys", key_routes);
+        }
+        None => println!("KEY_SECRET is not set: content keys of encrypted videos are not served"),
+    }
+    let app = app.fallback_service(s3_with_cors);
 
     // 6. Start Server
     let listener = tokio::net::TcpListener::bind(format!("{}:{}", config.addr, config.port))
@@ -1,7 +1,46 @@
 //! Configuration for different deployment environments.
 
+use crate::domain::encryption::DEFAULT_KEY_URL;
+use crate::domain::loudness::{parse_loudness_target, LoudnessTarget};
+use crate::domain::renditions::{parse_ladder, Rendition, DEFAULT_LADDER};
+use crate::domain::segment_plan::{parse_segment_target, SegmentTarget, DEFAULT_SEGMENT_DURATION};
 use std::env;
 
+/// Read the rendition ladder from the `LADDER` environment variable
+/// (e.g. `1080p,720p,480p,360p`), falling back to the default ladder.
+/// Panics if the variable is set but invalid.
+pub fn ladder_from_env() -> Vec<Rendition> {
+    let spec = env::var("LADDER").unwrap_or_else(|_| String::from(DEFAULT_LADDER));
+    parse_ladder(&spec).unwrap_or_else(|e| panic!("Invalid LADDER env var: {}", e))
+}
+
+/// Read the segment duration from the `SEGMENT_DURATION` environment variable
+/// (e.g. `6` or `6:2:10` for target, min and max seconds), falling back to the
+/// default. Panics if the variable is set but invalid.
+pub fn segment_target_from_env() -> SegmentTarget {
+    let spec =
+        env::var("SEGMENT_DURATION").unwrap_or_else(|_| String::from(DEFAULT_SEGMENT_DURATION));
+    parse_segment_target(&spec)
+        .unwrap_or_else(|e| panic!("Invalid SEGMENT_DURATION env var: {}", e))
+}
+
+/// Read the loudness encoded audio is normalized to from the `LOUDNESS_TARGET`
+/// environment variable (e.g. `-16` LUFS, or `-16:-1.5` with a true peak ceiling).
+/// Unset means no normalization. Panics if the variable is set but invalid.
+pub fn loudness_target_from_env() -> Option<LoudnessTarget> {
+    let spec = env::var("LOUDNESS_TARGET").ok()?;
+    Some(
+        parse_loudness_target(&spec)
+            .unwrap_or_else(|e| panic!("Invalid LOUDNESS_TARGET env var: {}", e)),
+    )
+}
+
+/// Read the base URL players get content keys from from the `KEY_URL` environment
+/// variable (e.g. `https://keys.example.com`), falling back to the monolith's `/keys`.
+pub fn key_url_from_env() -> String {
+    env::var("KEY_URL").unwrap_or_else(|_| String::from(DEFAULT_KEY_URL))
+}
+
 /// Configuration for local/monolith deployment.
 #[cfg(feature = "local")]
 #[derive(Clone, Debug)]
@@ -18,6 +57,17 @@ pub struct LocalConfig {
     pub aws_access_key_id: String,
     /// AWS Secret Access Key for S3-compatible API authentication
     pub aws_secret_access_key: String,
+    /// Renditions every upload is packaged into
+    pub ladder: Vec<Rendition>,
+    /// Duration segments are planned towards
+    pub segment_target: SegmentTarget,
+    /// Loudness encoded audio is normalized to, if any
+    pub loudness_target: Option<LoudnessTarget>,
+    /// Base URL of the content keys of encrypted videos
+    pub key_url: String,
+    /// Secret the tokens of the key endpoint are signed with. Without it, keys
+    /// aren't served.
+    pub key_secret: Option<String>,
 }
 
 #[cfg(feature = "local")]
@@ -35,6 +85,13 @@ impl LocalConfig {
                 .unwrap_or_else(|_| String::from("minioadmin")),
             aws_secret_access_key: env::var("AWS_SECRET_ACCESS_KEY")
                 .unwrap_or_else(|_| String::from("minioadmin")),
+            key_secret: env::var("KEY_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            ladder: ladder_from_env(),
+            segment_target: segment_target_from_env(),
+            loudness_target: loudness_target_from_env(),
+            key_url: key_url_from_env(),
         }
     }
 }
@@ -49,6 +106,14 @@ pub struct AwsConfig {
     pub sqs_queue_url: String,
     /// DynamoDB table name for video state
     pub dynamodb_table: String,
+    /// Renditions every upload is packaged into
+    pub ladder: Vec<Rendition>,
+    /// Duration segments are planned towards
+    pub segment_target: SegmentTarget,
+    /// Loudness encoded audio is normalized to, if any
+    pub loudness_target: Option<LoudnessTarget>,
+    /// Base URL of the content keys of encrypted videos
+    pub key_url: String,
 }
 
 #[cfg(any(feature = "aws_orchestrator", feature = "aws_worker"))]
@@ -60,6 +125,10 @@ impl AwsConfig {
             s3_bucket: env::var("S3_BUCKET").expect("S3_BUCKET env var required"),
             sqs_queue_url: env::var("SQS_QUEUE_URL").expect("SQS_QUEUE_URL env var required"),
             dynamodb_table: env::var("DYNAMODB_TABLE").expect("DYNAMODB_TABLE env var required"),
+            ladder: ladder_from_env(),
+            segment_target: segment_target_from_env(),
+            loudness_target: loudness_target_from_env(),
+            key_url: key_url_from_env(),
         }
     }
 }
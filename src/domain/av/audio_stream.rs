@@ -1,11 +1,21 @@
 use super::stream::FromStream;
+use crate::domain::loudness::Loudness;
 use serde_json::Value;
 
 #[derive(Debug)]
 pub(crate) struct AudioStream {
-    codec: String,
-    profile: String,
-    bit_rate: String,
+    pub index: usize,
+    pub codec: String,
+    pub profile: String,
+    pub bit_rate: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// ISO 639-2 language code from the stream metadata.
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub default: bool,
+    /// Set by `AV::from_path`, which measures it.
+    pub loudness: Option<Loudness>,
 }
 
 impl FromStream for AudioStream {
@@ -13,9 +23,28 @@ impl FromStream for AudioStream {
         if let Some(codec_type) = stream_data.get("codec_type").and_then(|v| v.as_str()) {
             match codec_type {
                 "audio" => Some(Box::new(AudioStream {
-                    codec: stream_data.get("codec_name")?.to_string(),
-                    profile: stream_data.get("profile")?.to_string(),
-                    bit_rate: stream_data.get("profile")?.to_string(),
+                    index: stream_data.get("index")?.as_u64()? as usize,
+                    codec: stream_data.get("codec_name")?.as_str()?.to_string(),
+                    profile: stream_data.get("profile")?.as_str()?.to_string(),
+                    bit_rate: stream_data.get("bit_rate")?.as_str()?.to_string(),
+                    channels: stream_data.get("channels")?.as_u64().unwrap_or(0) as u16,
+                    sample_rate: stream_data
+                        .get("sample_rate")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(0) as u32,
+                    language: stream_data
+                        .get("language")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                    title: stream_data
+                        .get("title")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                    default: stream_data
+                        .get("default")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false),
+                    loudness: None,
                 })),
                 _ => None,
             }
@@ -1,24 +1,52 @@
 use super::audio_stream::AudioStream;
+use super::interlace::sample_fields;
+use super::loudness::measure_loudness;
 use super::segments::get_segments;
 use super::stream::get_streams;
 use super::stream::FromStream;
+use super::subtitle_stream::SubtitleStream;
 use super::video_stream::VideoStream;
+use crate::domain::interlace::FieldOrder;
 use serde_json::Result;
 use std::path::Path;
 
 #[derive(Debug)]
 pub(crate) struct AV<'a> {
     pub path: &'a Path,
+    /// Name of the demuxer that opened the file.
+    pub format: String,
+    /// Duration in seconds.
+    pub duration: f64,
     pub video_streams: Vec<VideoStream>,
     pub audio_streams: Vec<AudioStream>,
+    pub subtitle_streams: Vec<SubtitleStream>,
+    /// Index of the stream of the picture attached as cover art, if any.
+    pub cover_art: Option<usize>,
     pub segments: Vec<f64>,
 }
 
 impl<'a> AV<'a> {
+    /// Analyze the file at `path`: its streams, keyframes, the loudness of its
+    /// audio and whether its video is interlaced.
     pub async fn from_path(path: &'a Path) -> Result<AV<'a>> {
-        let (streams, duration) = get_streams(path).await;
+        let mut av = Self::probe(path).await?;
+        let mut loudness = measure_loudness(path).await;
+        for stream in av.audio_streams.iter_mut() {
+            stream.loudness = loudness.remove(&stream.index);
+        }
+        if let Some(stream) = av.video_streams.first_mut() {
+            let counts = sample_fields(path, av.duration).await;
+            stream.field_order = FieldOrder::detect(stream.field_order, counts.as_ref());
+        }
+        Ok(av)
+    }
 
-        let mut segments = get_segments(path).await;
+    /// Read the streams and keyframes of the file at `path`, skipping the audio
+    /// analysis that needs it decoded.
+    pub async fn probe(path: &'a Path) -> Result<AV<'a>> {
+        let (streams, duration, format) = get_streams(path).await;
+
+        let (mut segments, frame_timing) = get_segments(path).await;
         if let Some(&last) = segments.last() {
             if duration - last > 0.1 {
                 segments.push(duration);
@@ -28,20 +56,38 @@ impl<'a> AV<'a> {
             segments.push(duration);
         }
 
+        let mut video_streams: Vec<VideoStream> = streams
+            .iter()
+            .map(|stream| VideoStream::from_stream(&stream))
+            .flatten()
+            .map(|stream| *stream)
+            .collect();
+        // Keyframes are scanned for in the first video stream.
+        if let Some(stream) = video_streams.first_mut() {
+            stream.frame_timing = frame_timing;
+        }
+
         Ok(AV {
             path,
-            video_streams: streams
+            format,
+            duration,
+            video_streams,
+            audio_streams: streams
                 .iter()
-                .map(|stream| VideoStream::from_stream(&stream))
+                .map(|stream| AudioStream::from_stream(&stream))
                 .flatten()
                 .map(|stream| *stream)
                 .collect(),
-            audio_streams: streams
+            subtitle_streams: streams
                 .iter()
-                .map(|stream| AudioStream::from_stream(&stream))
-                .flatten()
+                .filter_map(SubtitleStream::from_stream)
                 .map(|stream| *stream)
                 .collect(),
+            cover_art: streams
+                .iter()
+                .filter(|stream| stream.get("attached_pic").and_then(|v| v.as_bool()) == Some(true))
+                .find_map(|stream| stream.get("index")?.as_u64())
+                .map(|index| index as usize),
             segments,
         })
     }
@@ -0,0 +1,163 @@
+//! AES, HMAC and random bytes from libavutil, which every build that packages video
+//! links already.
+
+use crate::domain::encryption::{BlockCipher, ContentKey, BLOCK_SIZE};
+use ffmpeg::ffi;
+use ffmpeg_next as ffmpeg;
+
+/// AES-128 under a content key, with an encryption and a decryption context.
+pub struct Aes128 {
+    encrypt: *mut ffi::AVAES,
+    decrypt: *mut ffi::AVAES,
+}
+
+impl Aes128 {
+    pub fn new(key: &ContentKey) -> Result<Self, ffmpeg::Error> {
+        let context = |decrypt: i32| unsafe {
+            let aes = ffi::av_aes_alloc();
+            if aes.is_null() {
+                return Err(ffmpeg::Error::Other {
+                    errno: ffmpeg::error::ENOMEM,
+                });
+            }
+            match ffi::av_aes_init(aes, key.0.as_ptr(), 128, decrypt) {
+                0 => Ok(aes),
+                e => {
+                    ffi::av_free(aes.cast());
+                    Err(ffmpeg::Error::from(e))
+                }
+            }
+        };
+        let encrypt = context(0)?;
+        match context(1) {
+            Ok(decrypt) => Ok(Self { encrypt, decrypt }),
+            Err(e) => {
+                unsafe { ffi::av_free(encrypt.cast()) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Encrypt or decrypt `data` in place, in CBC mode chaining from `iv`, or each
+    /// block on its own without one.
+    fn crypt(&self, iv: Option<[u8; BLOCK_SIZE]>, data: &mut [u8], decrypt: bool) {
+        assert_eq!(data.len() % BLOCK_SIZE, 0, "AES takes whole blocks");
+        let mut iv = iv;
+        let iv_ptr = iv
+            .as_mut()
+            .map_or(std::ptr::null_mut(), |iv| iv.as_mut_ptr());
+        let (aes, decrypt) = match decrypt {
+            true => (self.decrypt, 1),
+            false => (self.encrypt, 0),
+        };
+        // In place, chunked so that the block count fits a C int.
+        for chunk in data.chunks_mut(BLOCK_SIZE << 20) {
+            let count = (chunk.len() / BLOCK_SIZE) as i32;
+            unsafe {
+                ffi::av_aes_crypt(
+                    aes,
+                    chunk.as_mut_ptr(),
+                    chunk.as_ptr(),
+                    count,
+                    iv_ptr,
+                    decrypt,
+                )
+            };
+        }
+    }
+}
+
+impl BlockCipher for Aes128 {
+    fn encrypt_blocks(&self, data: &mut [u8]) {
+        self.crypt(None, data, false);
+    }
+
+    fn encrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+        self.crypt(Some(iv), data, false);
+    }
+
+    fn decrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+        self.crypt(Some(iv), data, true);
+    }
+}
+
+// The contexts are only round keys, so they can move to a blocking task with the rest
+// of a segment's work.
+unsafe impl Send for Aes128 {}
+
+impl Drop for Aes128 {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::av_free(self.encrypt.cast());
+            ffi::av_free(self.decrypt.cast());
+        }
+    }
+}
+
+/// A new content key from the system's secure random source.
+pub fn generate_key() -> Result<ContentKey, ffmpeg::Error> {
+    random_bytes().map(ContentKey)
+}
+
+/// A random first IV for the samples of a segment under `cenc`, so that renditions
+/// encrypted apart under the same key don't share IVs.
+pub fn generate_iv() -> Result<u64, ffmpeg::Error> {
+    random_bytes().map(u64::from_be_bytes)
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], ffmpeg::Error> {
+    let mut bytes = [0; N];
+    match unsafe { ffi::av_random_bytes(bytes.as_mut_ptr(), bytes.len()) } {
+        0 => Ok(bytes),
+        e => Err(ffmpeg::Error::from(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::encryption::{decrypt_segment, encrypt_segment, segment_iv};
+
+    #[test]
+    fn test_aes128_vectors() {
+        // NIST SP 800-38A F.2.1, CBC-AES128 encryption.
+        let key = ContentKey::from_hex("2b7e151628aed2a6abf7158809cf4f3c").unwrap();
+        let mut iv = [0; BLOCK_SIZE];
+        for (i, byte) in iv.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut block = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        let aes = Aes128::new(&key).unwrap();
+        aes.encrypt_cbc(iv, &mut block);
+        assert_eq!(
+            block,
+            [
+                0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9,
+                0x19, 0x7d
+            ]
+        );
+        // F.1.1, ECB-AES128 encryption.
+        let mut block = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        aes.encrypt_blocks(&mut block);
+        assert_eq!(
+            block,
+            [
+                0x3a, 0xd7, 0x7b, 0xb4, 0x0d, 0x7a, 0x36, 0x60, 0xa8, 0x9e, 0xca, 0xf3, 0x24, 0x66,
+                0xef, 0x97
+            ]
+        );
+
+        let encrypted = encrypt_segment(&aes, segment_iv(7), b"segment data");
+        assert_eq!(
+            decrypt_segment(&aes, segment_iv(7), &encrypted),
+            Ok(b"segment data".to_vec())
+        );
+        assert_ne!(generate_key().unwrap(), generate_key().unwrap());
+    }
+}
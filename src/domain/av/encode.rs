@@ -0,0 +1,657 @@
+//! Re-encoding of the source into a rendition of the bitrate ladder.
+
+use super::segments::{
+    output_for, sample_entry_tag, write_segment_header, write_segment_trailer, SegmentOutput,
+    Written,
+};
+use super::stream::{color_info, rotation};
+use crate::domain::color::{ColorInfo, VideoRange};
+use crate::domain::options::Container;
+use crate::domain::renditions::{AudioTrack, Rendition, VideoCodec};
+use crate::domain::watermark::Watermark;
+use ffmpeg::ffi::{
+    AVColorPrimaries, AVColorRange, AVColorSpace, AVColorTransferCharacteristic, AVFieldOrder,
+};
+use ffmpeg::{codec, decoder, encoder, filter, format, media, ChannelLayout, Dictionary, Packet};
+use ffmpeg::{Frame, Rational};
+use ffmpeg_next as ffmpeg;
+use std::path::Path;
+
+/// Every encoded rendition carries 48 kHz stereo AAC.
+const AUDIO_SAMPLE_RATE: i32 = 48_000;
+
+/// Seconds between keyframes inside a segment; segment starts are always IDR frames.
+const KEYFRAME_INTERVAL: f64 = 2.0;
+
+/// Encoders tried for each codec, in order of preference. Any other encoder FFmpeg
+/// has for the codec comes after them.
+fn encoder_names(codec: VideoCodec) -> &'static [&'static str] {
+    match codec {
+        VideoCodec::Copy => &[],
+        VideoCodec::H264 => &["libx264"],
+        VideoCodec::Hevc => &["libx265"],
+        VideoCodec::Av1 => &["libsvtav1", "libaom-av1", "librav1e"],
+    }
+}
+
+/// The encoder used for `codec`, or why there is none. Encoders depend on how
+/// FFmpeg was built, so this is checked before any work is queued.
+pub fn find_video_encoder(codec: VideoCodec) -> Result<ffmpeg::Codec, String> {
+    let id = match codec {
+        VideoCodec::Copy => return Err("Stream copies are not encoded".to_string()),
+        VideoCodec::H264 => codec::Id::H264,
+        VideoCodec::Hevc => codec::Id::HEVC,
+        VideoCodec::Av1 => codec::Id::AV1,
+    };
+    encoder_names(codec)
+        .iter()
+        .find_map(|name| encoder::find_by_name(name))
+        .or_else(|| encoder::find(id))
+        .ok_or_else(|| {
+            format!(
+                "No {} encoder in this FFmpeg build (looked for {})",
+                codec.name(),
+                encoder_names(codec).join(", ")
+            )
+        })
+}
+
+/// Filters tone mapping relies on, which FFmpeg only has when built with zimg.
+const TONE_MAP_FILTERS: &[&str] = &["zscale", "tonemap"];
+
+/// Deinterlacing filters, in order of preference.
+const DEINTERLACE_FILTERS: &[&str] = &["bwdif", "yadif"];
+
+/// Check that this FFmpeg build can produce every rendition of `renditions`: each
+/// encoded one has an encoder, and tone-mapped, deinterlaced and watermarked ones
+/// have their filters.
+pub fn check_renditions(renditions: &[Rendition]) -> Result<(), String> {
+    ffmpeg::init().map_err(|e| format!("Failed to initialize FFmpeg: {}", e))?;
+    for rendition in renditions.iter().filter(|r| !r.is_copy()) {
+        find_video_encoder(rendition.video_codec)
+            .map_err(|e| format!("Rendition {}: {}", rendition.name, e))?;
+        if rendition.tone_map {
+            if let Some(missing) = TONE_MAP_FILTERS.iter().find(|f| filter::find(f).is_none()) {
+                return Err(format!(
+                    "Rendition {}: tone mapping needs the {} filter, missing from this FFmpeg build",
+                    rendition.name, missing
+                ));
+            }
+        }
+        if rendition.field_order.is_interlaced()
+            && DEINTERLACE_FILTERS
+                .iter()
+                .all(|f| filter::find(f).is_none())
+        {
+            return Err(format!(
+                "Rendition {}: deinterlacing needs the {} filter, missing from this FFmpeg build",
+                rendition.name,
+                DEINTERLACE_FILTERS.join(" or ")
+            ));
+        }
+        for watermark in &rendition.watermarks {
+            if let Some(missing) = watermark
+                .filters()
+                .iter()
+                .find(|f| filter::find(f).is_none())
+            {
+                return Err(format!(
+                    "Rendition {}: watermarks need the {} filter, missing from this FFmpeg build",
+                    rendition.name, missing
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Options of the encoder named `name`: a speed suited to segments encoded on
+/// demand, and forced I frames made IDR frames, which segments have to start with.
+/// HDR video of colour `hdr` gets its metadata into the bitstream where the
+/// encoder takes it.
+fn video_encoder_options(name: &str, hdr: Option<&ColorInfo>) -> Dictionary<'static> {
+    let mut options = Dictionary::new();
+    match name {
+        "libx264" => {
+            options.set("preset", "veryfast");
+            options.set("profile", "high");
+            options.set("forced-idr", "1");
+        }
+        "libx265" => {
+            options.set("preset", "veryfast");
+            options.set("forced-idr", "1");
+            match hdr.and_then(ColorInfo::x265_params) {
+                Some(params) => options.set("x265-params", &format!("log-level=error:{}", params)),
+                None => options.set("x265-params", "log-level=error"),
+            }
+        }
+        "libsvtav1" => options.set("preset", "8"),
+        "libaom-av1" => {
+            options.set("cpu-used", "6");
+            options.set("row-mt", "1");
+        }
+        "librav1e" => options.set("speed", "8"),
+        _ => {}
+    }
+    options
+}
+
+/// Decoder, filter graph and encoder for one source stream being re-encoded.
+pub(super) struct StreamEncoder {
+    medium: media::Type,
+    ost_index: usize,
+    ist_time_base: Rational,
+    decoder: decoder::Opened,
+    graph: filter::Graph,
+    encoder: encoder::Encoder,
+    /// Time base of the frames leaving the filter graph, also used by the encoder.
+    time_base: Rational,
+    /// Presentation window `[start, end)` in seconds; frames outside are dropped.
+    window: (f64, f64),
+    /// Whether the next video frame opens the window and has to be an IDR frame.
+    keyframe_pending: bool,
+}
+
+impl StreamEncoder {
+    pub(super) fn send_packet(
+        &mut self,
+        packet: &Packet,
+        output: &mut SegmentOutput,
+    ) -> Result<(), ffmpeg::Error> {
+        self.decoder.send_packet(packet)?;
+        self.drain_decoder(output)
+    }
+
+    /// Flush every stage, writing whatever the decoder, filters and encoder held back.
+    pub(super) fn finish(&mut self, output: &mut SegmentOutput) -> Result<(), ffmpeg::Error> {
+        self.decoder.send_eof()?;
+        self.drain_decoder(output)?;
+        self.graph.get("in").unwrap().source().flush()?;
+        self.drain_graph(output)?;
+        self.encoder.send_eof()?;
+        self.drain_encoder(output)
+    }
+
+    fn drain_decoder(&mut self, output: &mut SegmentOutput) -> Result<(), ffmpeg::Error> {
+        let mut decoded = unsafe { Frame::empty() };
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let Some(timestamp) = decoded.timestamp() else {
+                continue;
+            };
+            let time = f64::from(self.ist_time_base) * timestamp as f64;
+            if time < self.window.0 || time >= self.window.1 {
+                continue;
+            }
+
+            decoded.set_pts(Some(timestamp));
+            self.graph.get("in").unwrap().source().add(&decoded)?;
+            self.drain_graph(output)?;
+        }
+        Ok(())
+    }
+
+    fn drain_graph(&mut self, output: &mut SegmentOutput) -> Result<(), ffmpeg::Error> {
+        let mut filtered = unsafe { Frame::empty() };
+        while self
+            .graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            if self.medium == media::Type::Video {
+                // Frame types survive filtering; left alone they would make the
+                // encoder mirror the source GOP structure. Only the first frame is
+                // forced, so the segment starts on an IDR frame even when its start
+                // is not a source keyframe.
+                let pict_type = if self.keyframe_pending {
+                    ffmpeg::ffi::AVPictureType::AV_PICTURE_TYPE_I
+                } else {
+                    ffmpeg::ffi::AVPictureType::AV_PICTURE_TYPE_NONE
+                };
+                self.keyframe_pending = false;
+                unsafe {
+                    (*filtered.as_mut_ptr()).pict_type = pict_type;
+                }
+            }
+            self.encoder.send_frame(&filtered)?;
+            self.drain_encoder(output)?;
+        }
+        Ok(())
+    }
+
+    fn drain_encoder(&mut self, output: &mut SegmentOutput) -> Result<(), ffmpeg::Error> {
+        let ost_time_base = output.octx.stream(self.ost_index).unwrap().time_base();
+        let mut encoded = Packet::empty();
+        while self.encoder.receive_packet(&mut encoded).is_ok() {
+            encoded.set_stream(self.ost_index);
+            encoded.rescale_ts(self.time_base, ost_time_base);
+            output.write(&mut encoded)?;
+        }
+        Ok(())
+    }
+}
+
+/// Filter turning video upright when players would rotate it `rotation` degrees
+/// clockwise.
+pub(super) fn rotation_filter(rotation: u16) -> Option<&'static str> {
+    match rotation {
+        90 => Some("transpose=clock"),
+        180 => Some("hflip,vflip"),
+        270 => Some("transpose=cclock"),
+        _ => None,
+    }
+}
+
+/// Filters applied to the video of an encoded rendition: the `leading` filters,
+/// deinterlacing and frame rate conversion, rotation by `rotation` degrees,
+/// scaling, then the `tone_map` filters if any, then `watermarks`, then conversion
+/// to `pixel_format`.
+fn video_filter_spec(
+    leading: &[String],
+    rotation: u16,
+    width: u32,
+    height: u32,
+    tone_map: Option<&str>,
+    watermarks: &[Watermark],
+    pixel_format: &str,
+) -> String {
+    let mut spec = String::new();
+    for filter in leading {
+        spec.push_str(filter);
+        spec.push(',');
+    }
+    if let Some(rotate) = rotation_filter(rotation) {
+        spec.push_str(rotate);
+        spec.push(',');
+    }
+    spec.push_str(&format!("scale={}:{},setsar=1", width, height));
+    if let Some(tone_map) = tone_map {
+        spec.push(',');
+        spec.push_str(tone_map);
+    }
+    // Each mark takes the video so far from a labelled pad.
+    for (i, watermark) in watermarks.iter().enumerate() {
+        let input = format!("marked{}", i);
+        spec.push_str(&format!("[{}];", input));
+        spec.push_str(&watermark.filter_spec(width, height, &input));
+    }
+    spec.push_str(&format!(",format={}", pixel_format));
+    spec
+}
+
+/// Filters bringing any audio to what the AAC encoder is configured for, after a
+/// `gain` in dB.
+fn audio_filter_spec(gain: f64) -> String {
+    let mut spec = String::new();
+    if gain != 0.0 {
+        spec.push_str(&format!("volume={:.1}dB,", gain));
+    }
+    spec.push_str(&format!(
+        "aresample={rate},aformat=sample_fmts=fltp:sample_rates={rate}:channel_layouts=stereo",
+        rate = AUDIO_SAMPLE_RATE
+    ));
+    spec
+}
+
+/// Build a `buffer`/`abuffer` -> `spec` -> `buffersink`/`abuffersink` graph.
+fn filter_graph(
+    source: &str,
+    sink: &str,
+    args: &str,
+    spec: &str,
+) -> Result<filter::Graph, ffmpeg::Error> {
+    let mut graph = filter::Graph::new();
+    graph.add(&filter::find(source).unwrap(), "in", args)?;
+    graph.add(&filter::find(sink).unwrap(), "out", "")?;
+    graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+    graph.validate()?;
+    Ok(graph)
+}
+
+fn open_decoder(ist: &format::stream::Stream) -> Result<decoder::Decoder, ffmpeg::Error> {
+    let mut decoder = codec::context::Context::from_parameters(ist.parameters())?.decoder();
+    decoder.set_packet_time_base(ist.time_base());
+    Ok(decoder)
+}
+
+fn video_encoder(
+    ist: &format::stream::Stream,
+    octx: &mut format::context::Output,
+    rendition: &Rendition,
+    container: Container,
+    window: (f64, f64),
+) -> Result<StreamEncoder, ffmpeg::Error> {
+    let decoder = open_decoder(ist)?.video()?;
+
+    // Rotation is baked into encoded renditions, whose sizes are those displayed.
+    let rotation = rotation(&ist.parameters());
+    let (width, height) = if rendition.width > 0 && rendition.height > 0 {
+        (rendition.width, rendition.height)
+    } else if rotation % 180 == 90 {
+        (decoder.height(), decoder.width())
+    } else {
+        (decoder.width(), decoder.height())
+    };
+
+    let aspect = decoder.aspect_ratio();
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+        decoder.width(),
+        decoder.height(),
+        ffmpeg::ffi::AVPixelFormat::from(decoder.format()) as i32,
+        ist.time_base().numerator(),
+        ist.time_base().denominator(),
+        aspect.numerator().max(1),
+        aspect.denominator().max(1)
+    );
+    // HDR renditions are 10-bit and keep the source colour; tone-mapped ones are
+    // converted to BT.709 like SDR sources.
+    let color = color_info(&ist.parameters());
+    let hdr = rendition.video_range != VideoRange::Sdr;
+    let (pixel, pixel_format) = match hdr {
+        true => (format::Pixel::YUV420P10LE, "yuv420p10le"),
+        false => (format::Pixel::YUV420P, "yuv420p"),
+    };
+    let tone_map = rendition.tone_map.then(|| color.tone_map_filter());
+    // Fields are woven back at the source rate, before frames are dropped or
+    // repeated to a constant one.
+    let bwdif = filter::find(DEINTERLACE_FILTERS[0]).is_some();
+    let time_base = ist.time_base();
+    let leading: Vec<String> = [
+        rendition.field_order.deinterlace_filter(bwdif),
+        rendition
+            .frame_rate
+            .map(|rate| rate.filter((time_base.numerator(), time_base.denominator()))),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let graph = filter_graph(
+        "buffer",
+        "buffersink",
+        &args,
+        &video_filter_spec(
+            &leading,
+            rotation,
+            width,
+            height,
+            tone_map.as_deref(),
+            &rendition.watermarks,
+            pixel_format,
+        ),
+    )?;
+
+    let frame_rate = match (rendition.frame_rate, ist.avg_frame_rate()) {
+        (Some(rate), _) => Rational(rate.numerator as i32, rate.denominator as i32),
+        (None, rate) if rate.numerator() > 0 && rate.denominator() > 0 => rate,
+        _ => Rational(30, 1),
+    };
+
+    let codec = find_video_encoder(rendition.video_codec).map_err(|e| {
+        eprintln!("{}", e);
+        ffmpeg::Error::EncoderNotFound
+    })?;
+    let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+    let mut ost = octx.add_stream(codec)?;
+    let ost_index = ost.index();
+
+    let mut video = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    video.set_width(width);
+    video.set_height(height);
+    video.set_aspect_ratio(Rational(1, 1));
+    video.set_format(pixel);
+    video.set_time_base(time_base);
+    video.set_frame_rate(Some(frame_rate));
+    video.set_gop((f64::from(frame_rate) * KEYFRAME_INTERVAL).round().max(1.0) as u32);
+    video.set_bit_rate(rendition.video_bitrate as usize);
+    video.set_max_bit_rate(rendition.video_bitrate as usize * 3 / 2);
+    // Without a VBV buffer x264 ignores the max rate.
+    unsafe {
+        (*video.as_mut_ptr()).rc_buffer_size = (rendition.video_bitrate * 2) as i32;
+    }
+    unsafe {
+        let context = video.as_mut_ptr();
+        // Deinterlaced or not, what comes out of the graph is progressive.
+        (*context).field_order = AVFieldOrder::AV_FIELD_PROGRESSIVE;
+        if rendition.tone_map {
+            (*context).color_primaries = AVColorPrimaries::AVCOL_PRI_BT709;
+            (*context).color_trc = AVColorTransferCharacteristic::AVCOL_TRC_BT709;
+            (*context).colorspace = AVColorSpace::AVCOL_SPC_BT709;
+            (*context).color_range = AVColorRange::AVCOL_RANGE_MPEG;
+        } else if hdr {
+            let parameters = ist.parameters();
+            let parameters = parameters.as_ptr();
+            (*context).color_primaries = (*parameters).color_primaries;
+            (*context).color_trc = (*parameters).color_trc;
+            (*context).colorspace = (*parameters).color_space;
+            (*context).color_range = (*parameters).color_range;
+        }
+    }
+    if global_header {
+        video.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+
+    let video = video.open_with(video_encoder_options(codec.name(), hdr.then_some(&color)))?;
+
+    ost.set_parameters(&video);
+    unsafe {
+        (*ost.parameters().as_mut_ptr()).codec_tag = sample_entry_tag(codec.id(), container);
+    }
+    ost.set_time_base(time_base);
+
+    Ok(StreamEncoder {
+        medium: media::Type::Video,
+        ost_index,
+        ist_time_base: ist.time_base(),
+        decoder: decoder.0,
+        graph,
+        encoder: video.0 .0,
+        time_base,
+        window,
+        keyframe_pending: true,
+    })
+}
+
+/// Encoder of the audio stream `ist` to stereo AAC at `bitrate`, after a `gain` in
+/// dB, added to `octx`. Only frames presented within `window` are encoded.
+pub(super) fn audio_encoder(
+    ist: &format::stream::Stream,
+    octx: &mut format::context::Output,
+    bitrate: u64,
+    gain: f64,
+    window: (f64, f64),
+) -> Result<StreamEncoder, ffmpeg::Error> {
+    let decoder = open_decoder(ist)?.audio()?;
+
+    let layout = match decoder.channel_layout() {
+        layout if layout.bits() != 0 => layout,
+        _ => ChannelLayout::default(i32::from(decoder.channels())),
+    };
+    let args = format!(
+        "time_base={}/{}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+        ist.time_base().numerator(),
+        ist.time_base().denominator(),
+        decoder.rate(),
+        decoder.format().name(),
+        layout.bits()
+    );
+    let mut graph = filter_graph("abuffer", "abuffersink", &args, &audio_filter_spec(gain))?;
+    let time_base = Rational(1, AUDIO_SAMPLE_RATE);
+
+    let codec = encoder::find(codec::Id::AAC).ok_or(ffmpeg::Error::EncoderNotFound)?;
+    let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+    let mut ost = octx.add_stream(codec)?;
+    let ost_index = ost.index();
+
+    let mut audio = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .audio()?;
+    audio.set_rate(AUDIO_SAMPLE_RATE);
+    audio.set_channel_layout(ChannelLayout::STEREO);
+    audio.set_format(format::Sample::F32(format::sample::Type::Planar));
+    audio.set_bit_rate(bitrate as usize);
+    audio.set_time_base(time_base);
+    if global_header {
+        audio.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+    let audio = audio.open_as(codec)?;
+
+    ost.set_parameters(&audio);
+    ost.set_time_base(time_base);
+
+    // AAC consumes fixed-size frames, so have the sink cut them to size.
+    graph
+        .get("out")
+        .unwrap()
+        .sink()
+        .set_frame_size(audio.frame_size());
+
+    Ok(StreamEncoder {
+        medium: media::Type::Audio,
+        ost_index,
+        ist_time_base: ist.time_base(),
+        decoder: decoder.0,
+        graph,
+        encoder: audio.0 .0,
+        time_base,
+        window,
+        keyframe_pending: false,
+    })
+}
+
+/// Encoders for the source streams written to an output, keyed by source index.
+type Encoders = Vec<(usize, StreamEncoder)>;
+
+/// Encode the best video stream of `source`, and its best audio stream unless the
+/// rendition leaves audio to alternate renditions, into `rendition`, writing a
+/// fragmented MP4 (or a transport stream, following `container`) at `dest`.
+///
+/// `range` follows `remux_fragmented`: an optional `(start, duration)` in seconds,
+/// with `None` writing only the header. Unlike a stream copy, the window doesn't
+/// need to start on a source keyframe, since the first encoded frame always is one.
+pub(super) fn encode_fragmented(
+    source: &Path,
+    dest: &Path,
+    range: Option<(f64, f64)>,
+    rendition: &Rendition,
+    container: Container,
+) -> Result<Written, ffmpeg::Error> {
+    encode_streams(source, dest, range, container, |ictx, octx, window| {
+        let mut encoders = Encoders::new();
+        if let Some(ist) = ictx.streams().best(media::Type::Video) {
+            encoders.push((
+                ist.index(),
+                video_encoder(&ist, octx, rendition, container, window)?,
+            ));
+        }
+        if rendition.muxed_audio {
+            if let Some(ist) = ictx.streams().best(media::Type::Audio) {
+                let encoder = audio_encoder(
+                    &ist,
+                    octx,
+                    rendition.audio_bitrate,
+                    rendition.audio_gain,
+                    window,
+                )?;
+                encoders.push((ist.index(), encoder));
+            }
+        }
+        Ok(encoders)
+    })
+}
+
+/// Encode the audio stream of `track` alone to AAC, like `encode_fragmented`.
+pub(super) fn encode_audio_fragmented(
+    source: &Path,
+    dest: &Path,
+    range: Option<(f64, f64)>,
+    track: &AudioTrack,
+    container: Container,
+) -> Result<Written, ffmpeg::Error> {
+    encode_streams(source, dest, range, container, |ictx, octx, window| {
+        let ist = ictx
+            .stream(track.stream_index)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+        let encoder = audio_encoder(&ist, octx, track.bitrate, track.gain, window)?;
+        Ok(vec![(ist.index(), encoder)])
+    })
+}
+
+/// Open `source` and `dest`, set up the encoders returned by `open`, then encode
+/// `range` of their streams.
+fn encode_streams<F>(
+    source: &Path,
+    dest: &Path,
+    range: Option<(f64, f64)>,
+    container: Container,
+    open: F,
+) -> Result<Written, ffmpeg::Error>
+where
+    F: FnOnce(
+        &format::context::Input,
+        &mut format::context::Output,
+        (f64, f64),
+    ) -> Result<Encoders, ffmpeg::Error>,
+{
+    ffmpeg::init()?;
+
+    let mut ictx = format::input(&source)?;
+    let mut output = SegmentOutput::new(output_for(dest, container)?);
+
+    let window = range
+        .map(|(start, duration)| (start, start + duration))
+        .unwrap_or((0.0, f64::MAX));
+
+    let (ist_indices, mut encoders): (Vec<usize>, Vec<StreamEncoder>) =
+        open(&ictx, &mut output.octx, window)?.into_iter().unzip();
+    if encoders.is_empty() {
+        return Err(ffmpeg::Error::StreamNotFound);
+    }
+
+    let init_size = write_segment_header(&mut output.octx, container)?;
+
+    let Some((start, _)) = range else {
+        return Ok(output.finish(init_size));
+    };
+
+    let seek_target = (start * f64::from(ffmpeg::ffi::AV_TIME_BASE)) as i64;
+    ictx.seek(seek_target, ..seek_target)?;
+
+    // Packets are fed in decode order; any frame presented inside the window is
+    // decoded before its stream's DTS passes the window end.
+    let mut past_end = vec![false; encoders.len()];
+    for (stream, packet) in ictx.packets() {
+        let Some(position) = ist_indices.iter().position(|&i| i == stream.index()) else {
+            continue;
+        };
+        if past_end[position] {
+            continue;
+        }
+
+        if let Some(dts) = packet.dts().or(packet.pts()) {
+            if f64::from(stream.time_base()) * dts as f64 >= window.1 {
+                past_end[position] = true;
+                if past_end.iter().all(|&done| done) {
+                    break;
+                }
+                continue;
+            }
+        }
+
+        encoders[position].send_packet(&packet, &mut output)?;
+    }
+
+    for encoder in encoders.iter_mut() {
+        encoder.finish(&mut output)?;
+    }
+
+    write_segment_trailer(&mut output.octx, container)?;
+
+    Ok(output.finish(init_size))
+}
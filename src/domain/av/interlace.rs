@@ -0,0 +1,156 @@
+use super::stream::is_attached_pic;
+use crate::domain::interlace::FieldCounts;
+use ffmpeg::{codec, decoder, filter, format, frame, media};
+use ffmpeg_next as ffmpeg;
+use std::path::Path;
+use tokio::task;
+
+/// Frame metadata in which `idet` counts the frames it classified so far, looking
+/// at several at once.
+const TOP_FIRST_KEY: &str = "lavfi.idet.multiple.tff";
+const BOTTOM_FIRST_KEY: &str = "lavfi.idet.multiple.bff";
+const PROGRESSIVE_KEY: &str = "lavfi.idet.multiple.progressive";
+const UNDETERMINED_KEY: &str = "lavfi.idet.multiple.undetermined";
+
+/// Where the sample is taken, as fractions of the duration: a few runs of frames
+/// rather than the opening, which is often a progressive slate or black.
+const SAMPLE_POINTS: &[f64] = &[0.1, 0.3, 0.5, 0.7, 0.9];
+
+/// Frames decoded at each sample point.
+const FRAMES_PER_POINT: usize = 40;
+
+/// Run `idet` over a sample of the frames of the first video stream of the file at
+/// `path`, lasting `duration` seconds. `None` if there is no video or it can't be
+/// decoded.
+pub async fn sample_fields(path: &Path, duration: f64) -> Option<FieldCounts> {
+    let path = path.to_path_buf();
+
+    task::spawn_blocking(move || match sample(&path, duration) {
+        Ok(counts) => counts,
+        Err(e) => {
+            eprintln!("Failed to detect interlacing of {:?}: {}", path, e);
+            None
+        }
+    })
+    .await
+    .unwrap()
+}
+
+fn sample(path: &Path, duration: f64) -> Result<Option<FieldCounts>, ffmpeg::Error> {
+    ffmpeg::init()?;
+    let mut ictx = format::input(&path)?;
+
+    let Some(ist) = ictx
+        .streams()
+        .find(|s| s.parameters().medium() == media::Type::Video && !is_attached_pic(s))
+    else {
+        return Ok(None);
+    };
+    let index = ist.index();
+    let mut detector = Detector::new(&ist)?;
+
+    for point in SAMPLE_POINTS {
+        let target = (duration * point * f64::from(ffmpeg::ffi::AV_TIME_BASE)) as i64;
+        if ictx.seek(target, ..target).is_err() {
+            continue;
+        }
+        detector.decoder.flush();
+        let mut decoded = 0;
+        for (stream, packet) in ictx.packets() {
+            if stream.index() != index {
+                continue;
+            }
+            // Packets the decoder can't take, e.g. right after a seek, are skipped.
+            if detector.decoder.send_packet(&packet).is_err() {
+                continue;
+            }
+            decoded += detector.drain_decoder()?;
+            if decoded >= FRAMES_PER_POINT {
+                break;
+            }
+        }
+    }
+    detector.graph.get("in").unwrap().source().flush()?;
+    detector.drain_graph()?;
+    Ok(detector.counts)
+}
+
+/// An `idet` filter fed with the decoded frames of one stream.
+struct Detector {
+    decoder: decoder::Video,
+    graph: filter::Graph,
+    counts: Option<FieldCounts>,
+}
+
+impl Detector {
+    fn new(ist: &format::stream::Stream) -> Result<Self, ffmpeg::Error> {
+        let mut decoder = codec::context::Context::from_parameters(ist.parameters())?.decoder();
+        decoder.set_packet_time_base(ist.time_base());
+        let decoder = decoder.video()?;
+
+        let aspect = decoder.aspect_ratio();
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+            decoder.width(),
+            decoder.height(),
+            ffmpeg::ffi::AVPixelFormat::from(decoder.format()) as i32,
+            ist.time_base().numerator(),
+            ist.time_base().denominator(),
+            aspect.numerator().max(1),
+            aspect.denominator().max(1)
+        );
+        let mut graph = filter::Graph::new();
+        graph.add(&filter::find("buffer").unwrap(), "in", &args)?;
+        graph.add(&filter::find("buffersink").unwrap(), "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse("idet")?;
+        graph.validate()?;
+
+        Ok(Self {
+            decoder,
+            graph,
+            counts: None,
+        })
+    }
+
+    /// Pass the frames the decoder has ready to the filter, returning how many.
+    fn drain_decoder(&mut self) -> Result<usize, ffmpeg::Error> {
+        let mut decoded = frame::Video::empty();
+        let mut count = 0;
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            self.graph.get("in").unwrap().source().add(&decoded)?;
+            self.drain_graph()?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Read the counts so far off the frames the filter lets through. The last
+    /// frame has those of the whole sample.
+    fn drain_graph(&mut self) -> Result<(), ffmpeg::Error> {
+        let mut detected = frame::Video::empty();
+        while self
+            .graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut detected)
+            .is_ok()
+        {
+            let metadata = detected.metadata();
+            let count = |key: &str| metadata.get(key).and_then(|v| v.parse::<u64>().ok());
+            if let (Some(top_first), Some(bottom_first), Some(progressive)) = (
+                count(TOP_FIRST_KEY),
+                count(BOTTOM_FIRST_KEY),
+                count(PROGRESSIVE_KEY),
+            ) {
+                self.counts = Some(FieldCounts {
+                    top_first,
+                    bottom_first,
+                    progressive,
+                    undetermined: count(UNDETERMINED_KEY).unwrap_or(0),
+                });
+            }
+        }
+        Ok(())
+    }
+}
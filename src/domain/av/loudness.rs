@@ -0,0 +1,183 @@
+use crate::domain::loudness::Loudness;
+use ffmpeg::{codec, decoder, filter, format, frame, media, ChannelLayout, Packet};
+use ffmpeg_next as ffmpeg;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::task;
+
+/// Frame metadata the `ebur128` filter reports loudness so far in.
+const INTEGRATED_KEY: &str = "lavfi.r128.I";
+const RANGE_KEY: &str = "lavfi.r128.LRA";
+/// Prefix of the per channel true peaks, as linear amplitudes.
+const TRUE_PEAK_KEY: &str = "lavfi.r128.true_peaks_ch";
+/// Lowest true peak reported, -140 dBTP.
+const MIN_PEAK: f64 = 1e-7;
+
+/// Measure the EBU R128 loudness of every audio stream of the file at `path`, keyed
+/// by stream index. Streams that can't be decoded are left out.
+pub async fn measure_loudness(path: &Path) -> HashMap<usize, Loudness> {
+    let path = path.to_path_buf();
+
+    task::spawn_blocking(move || match measure(&path) {
+        Ok(loudness) => loudness,
+        Err(e) => {
+            eprintln!("Failed to measure loudness of {:?}: {}", path, e);
+            HashMap::new()
+        }
+    })
+    .await
+    .unwrap()
+}
+
+fn measure(path: &Path) -> Result<HashMap<usize, Loudness>, ffmpeg::Error> {
+    ffmpeg::init()?;
+    let mut ictx = format::input(&path)?;
+
+    let mut meters = HashMap::new();
+    for stream in ictx.streams() {
+        if stream.parameters().medium() != media::Type::Audio {
+            continue;
+        }
+        match Meter::new(&stream) {
+            Ok(meter) => {
+                meters.insert(stream.index(), meter);
+            }
+            Err(e) => eprintln!(
+                "Cannot measure loudness of stream {}: {}",
+                stream.index(),
+                e
+            ),
+        }
+    }
+    if meters.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    for (stream, packet) in ictx.packets() {
+        if let Some(meter) = meters.get_mut(&stream.index()) {
+            meter.send_packet(&packet)?;
+        }
+    }
+
+    let mut loudness = HashMap::new();
+    for (index, mut meter) in meters {
+        if let Some(measured) = meter.finish()? {
+            loudness.insert(index, measured);
+        }
+    }
+    Ok(loudness)
+}
+
+/// An `ebur128` meter fed with the decoded audio of one stream.
+struct Meter {
+    decoder: decoder::Audio,
+    graph: filter::Graph,
+    integrated: Option<f64>,
+    range: Option<f64>,
+    /// Highest true peak of any channel, as a linear amplitude.
+    true_peak: f64,
+}
+
+impl Meter {
+    fn new(ist: &format::stream::Stream) -> Result<Self, ffmpeg::Error> {
+        let mut decoder = codec::context::Context::from_parameters(ist.parameters())?.decoder();
+        decoder.set_packet_time_base(ist.time_base());
+        let decoder = decoder.audio()?;
+
+        let layout = match decoder.channel_layout() {
+            layout if layout.bits() != 0 => layout,
+            _ => ChannelLayout::default(i32::from(decoder.channels())),
+        };
+        let args = format!(
+            "time_base={}/{}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            ist.time_base().numerator(),
+            ist.time_base().denominator(),
+            decoder.rate(),
+            decoder.format().name(),
+            layout.bits()
+        );
+        let mut graph = filter::Graph::new();
+        graph.add(&filter::find("abuffer").unwrap(), "in", &args)?;
+        graph.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+        graph
+            .output("in", 0)?
+            .input("out", 0)?
+            .parse("ebur128=peak=true:metadata=1")?;
+        graph.validate()?;
+
+        Ok(Self {
+            decoder,
+            graph,
+            integrated: None,
+            range: None,
+            true_peak: 0.0,
+        })
+    }
+
+    fn send_packet(&mut self, packet: &Packet) -> Result<(), ffmpeg::Error> {
+        self.decoder.send_packet(packet)?;
+        self.drain_decoder()
+    }
+
+    /// Flush the meter, returning the loudness of the whole stream if any audio
+    /// went through it.
+    fn finish(&mut self) -> Result<Option<Loudness>, ffmpeg::Error> {
+        self.decoder.send_eof()?;
+        self.drain_decoder()?;
+        self.graph.get("in").unwrap().source().flush()?;
+        self.drain_graph()?;
+
+        Ok(self.integrated.map(|integrated| Loudness {
+            integrated,
+            range: self.range.unwrap_or(0.0),
+            // Floored so that silence doesn't give an infinite peak, which JSON
+            // can't hold.
+            true_peak: 20.0 * self.true_peak.max(MIN_PEAK).log10(),
+        }))
+    }
+
+    fn drain_decoder(&mut self) -> Result<(), ffmpeg::Error> {
+        let mut decoded = frame::Audio::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            self.graph.get("in").unwrap().source().add(&decoded)?;
+            self.drain_graph()?;
+        }
+        Ok(())
+    }
+
+    /// Read the loudness so far off the frames the meter lets through. The last
+    /// frame has the values of the whole stream.
+    fn drain_graph(&mut self) -> Result<(), ffmpeg::Error> {
+        let mut metered = frame::Audio::empty();
+        while self
+            .graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut metered)
+            .is_ok()
+        {
+            let metadata = metered.metadata();
+            let value = |key: &str| {
+                metadata
+                    .get(key)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| v.is_finite())
+            };
+            if let Some(integrated) = value(INTEGRATED_KEY) {
+                self.integrated = Some(integrated);
+            }
+            if let Some(range) = value(RANGE_KEY) {
+                self.range = Some(range);
+            }
+            for (key, peak) in metadata.iter() {
+                if key.starts_with(TRUE_PEAK_KEY) {
+                    if let Ok(peak) = peak.parse::<f64>() {
+                        self.true_peak = self.true_peak.max(peak);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
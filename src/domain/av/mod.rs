@@ -2,8 +2,14 @@
 
 pub mod audio_stream;
 pub mod av;
+pub mod crypto;
+pub mod encode;
+pub mod interlace;
+pub mod loudness;
 pub mod segments;
 pub mod stream;
+pub mod subtitle_stream;
+pub mod subtitles;
 pub mod video_stream;
 
 // Thumbnails only needed by worker (requires image crate)
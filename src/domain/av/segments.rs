@@ -1,5 +1,18 @@
 use super::av::AV;
-use ffmpeg::{codec, encoder, format, media, Dictionary, Rational};
+use super::crypto::{generate_iv, Aes128};
+use super::encode::{audio_encoder, encode_audio_fragmented, encode_fragmented, StreamEncoder};
+use super::stream::is_attached_pic;
+use crate::domain::cenc;
+use crate::domain::color::ColorInfo;
+use crate::domain::encryption::{
+    encrypt_segment, sample_aes_segment, segment_iv, EncryptionMethod, SegmentKey,
+};
+use crate::domain::frame_rate::FrameTiming;
+use crate::domain::options::Container;
+use crate::domain::renditions::{AudioCodec, AudioTrack, Rendition};
+use crate::domain::segment_plan::{Keyframe, PlannedSegment};
+use crate::domain::{mp4, ts};
+use ffmpeg::{codec, encoder, format, media, Dictionary, Packet, Rational};
 use ffmpeg_next as ffmpeg;
 use std::path::{Path, PathBuf};
 use tokio::fs;
@@ -8,9 +21,11 @@ use tokio::task;
 
 /// Muxer flags shared by the init segment and every media segment, so the fragments
 /// we emit stay compatible with the header a player has already loaded.
-const FRAGMENTED_MP4_FLAGS: &str = "frag_keyframe+empty_moov+default_base_moof";
+pub(super) const FRAGMENTED_MP4_FLAGS: &str = "frag_keyframe+empty_moov+default_base_moof";
 
-pub async fn get_segments(path: &std::path::Path) -> Vec<f64> {
+/// Keyframe times of the video of the file at `path`, in seconds, and the timing of
+/// all its frames. Empty for files without video, whose audio can be cut anywhere.
+pub async fn get_segments(path: &std::path::Path) -> (Vec<f64>, Option<FrameTiming>) {
     let path_clone = path.to_path_buf();
 
     task::spawn_blocking(move || {
@@ -20,6 +35,7 @@ pub async fn get_segments(path: &std::path::Path) -> Vec<f64> {
                 let stream_index = context
                     .streams()
                     .best(ffmpeg::media::Type::Video)
+                    .filter(|stream| !is_attached_pic(stream))
                     .map(|stream| stream.index());
 
                 if let Some(stream_index) = stream_index {
@@ -28,24 +44,28 @@ pub async fn get_segments(path: &std::path::Path) -> Vec<f64> {
                         time_base.numerator() as f64 / time_base.denominator() as f64;
 
                     let mut segments = Vec::new();
+                    let mut times = Vec::new();
 
                     for (stream, packet) in context.packets() {
-                        if stream.index() == stream_index && packet.is_key() {
-                            if let Some(pts) = packet.pts() {
-                                let time = pts as f64 * time_base_f64;
+                        if stream.index() != stream_index {
+                            continue;
+                        }
+                        if let Some(pts) = packet.pts() {
+                            let time = pts as f64 * time_base_f64;
+                            if packet.is_key() {
                                 segments.push(time);
                             }
+                            times.push(time);
                         }
                     }
-                    segments
+                    (segments, FrameTiming::from_times(&mut times))
                 } else {
-                    eprintln!("No video stream found");
-                    Vec::new()
+                    (Vec::new(), None)
                 }
             }
             Err(e) => {
                 eprintln!("Error opening input: {}", e);
-                Vec::new()
+                (Vec::new(), None)
             }
         }
     })
@@ -53,72 +73,216 @@ pub async fn get_segments(path: &std::path::Path) -> Vec<f64> {
     .unwrap()
 }
 
-/// Copy the streams of `source` into a fragmented MP4 at `dest`, without re-encoding.
-///
-/// `range` is an optional `(start, duration)` in seconds selecting which packets to
-/// copy; `None` writes the header and nothing else. Note that each call is its own
-/// muxer, so the fragment's baseMediaDecodeTime restarts at 0 rather than carrying
-/// the absolute presentation time.
+/// Current write position of `octx`. Right after `write_header` with `empty_moov`
+/// the header is complete, so this is the exact size of the init segment.
+pub(super) fn output_position(octx: &mut format::context::Output) -> Result<u64, ffmpeg::Error> {
+    // avio_tell is a static inline in C and therefore not bound, so seek by 0 from
+    // SEEK_CUR (1).
+    unsafe {
+        let position = ffmpeg::ffi::avio_seek((*octx.as_mut_ptr()).pb, 0, 1);
+        if position < 0 {
+            return Err(ffmpeg::Error::from(position as i32));
+        }
+        Ok(position as u64)
+    }
+}
+
+/// Finish a fragmented MP4 written with `FRAGMENTED_MP4_FLAGS`.
+pub(super) fn write_fragmented_trailer(
+    octx: &mut format::context::Output,
+) -> Result<(), ffmpeg::Error> {
+    // For fragmented MP4 av_write_trailer returns the size of the trailing mfra box,
+    // and ffmpeg-next's write_trailer() reports any non-zero return as an error, so
+    // call it directly and only treat a negative result as a failure.
+    let trailer = unsafe { ffmpeg::ffi::av_write_trailer(octx.as_mut_ptr()) };
+    if trailer < 0 {
+        return Err(ffmpeg::Error::from(trailer));
+    }
+    Ok(())
+}
+
+/// Codec tag of a stream of codec `id` in `container`, 0 leaving it to the muxer.
+/// HEVC is tagged `hvc1` rather than the muxer's `hev1`, as Apple devices only play
+/// the former.
+pub(super) fn sample_entry_tag(id: codec::Id, container: Container) -> u32 {
+    match (id, container) {
+        (codec::Id::HEVC, Container::Fmp4) => u32::from_le_bytes(*b"hvc1"),
+        _ => 0,
+    }
+}
+
+/// Open `dest` for writing segments in `container`.
+pub(super) fn output_for(
+    dest: &Path,
+    container: Container,
+) -> Result<format::context::Output, ffmpeg::Error> {
+    match container {
+        Container::Fmp4 => format::output_as(&dest, "mp4"),
+        Container::MpegTs => format::output_as(&dest, "mpegts"),
+    }
+}
+
+/// Write the header of `octx`, returning the byte length of the initialization
+/// section. Transport streams have none: PAT and PMT are repeated in every
+/// segment, and the muxer inserts the bitstream filters for Annex B and ADTS.
+pub(super) fn write_segment_header(
+    octx: &mut format::context::Output,
+    container: Container,
+) -> Result<u64, ffmpeg::Error> {
+    match container {
+        Container::Fmp4 => {
+            let mut options = Dictionary::new();
+            options.set("movflags", FRAGMENTED_MP4_FLAGS);
+            octx.write_header_with(options)?;
+            output_position(octx)
+        }
+        Container::MpegTs => {
+            octx.write_header()?;
+            Ok(0)
+        }
+    }
+}
+
+/// Finish a segment written with `write_segment_header`.
+pub(super) fn write_segment_trailer(
+    octx: &mut format::context::Output,
+    container: Container,
+) -> Result<(), ffmpeg::Error> {
+    match container {
+        Container::Fmp4 => write_fragmented_trailer(octx),
+        Container::MpegTs => octx.write_trailer(),
+    }
+}
+
+/// Movie fragment sequence numbers set aside for each segment. Segments hold one
+/// fragment per keyframe, far fewer than this.
+const SEQUENCE_STRIDE: u32 = 10_000;
+
+/// A segment being muxed, remembering when each of its streams starts.
+pub(super) struct SegmentOutput {
+    pub octx: format::context::Output,
+    /// Decode time, in seconds, of the first packet written to each output stream.
+    pub starts: Vec<Option<f64>>,
+}
+
+impl SegmentOutput {
+    pub fn new(octx: format::context::Output) -> Self {
+        Self {
+            octx,
+            starts: Vec::new(),
+        }
+    }
+
+    /// Write `packet`, already in the time base of its output stream.
+    pub fn write(&mut self, packet: &mut Packet) -> Result<(), ffmpeg::Error> {
+        let index = packet.stream();
+        if self.starts.len() <= index {
+            self.starts.resize(index + 1, None);
+        }
+        if let (None, Some(timestamp)) = (self.starts[index], packet.dts().or(packet.pts())) {
+            let time_base = self.octx.stream(index).unwrap().time_base();
+            self.starts[index] = Some(f64::from(time_base) * timestamp as f64);
+        }
+        packet.write_interleaved(&mut self.octx)
+    }
+
+    pub fn finish(self, init_size: u64) -> Written {
+        Written {
+            init_size,
+            starts: self.starts,
+        }
+    }
+}
+
+/// What one of the `*_fragmented` functions wrote.
+pub(super) struct Written {
+    /// Byte length of the initialization section (ftyp + moov), which is exactly
+    /// where the first fragment begins.
+    pub init_size: u64,
+    /// Decode time, in seconds, of the first packet of each output stream.
+    pub starts: Vec<Option<f64>>,
+}
+
+/// How `remux_fragmented` carries a source stream.
+#[derive(Clone, Copy)]
+enum Mapping {
+    Copy,
+    /// Transcoded to AAC at `bitrate`, after a `gain` in dB, for audio players can't
+    /// decode as it is.
+    Aac {
+        bitrate: u64,
+        gain: f64,
+    },
+}
+
+/// Copy the streams of `source` into a fragmented MP4 (or a transport stream,
+/// following `container`) at `dest`, without re-encoding.
 ///
-/// Returns the byte length of the initialization section (ftyp + moov), which is
-/// exactly where the first fragment begins.
+/// `select` picks the audio and video streams to carry from their index and type,
+/// and whether to copy them or transcode them (audio only). Subtitles are left out:
+/// they go to WebVTT renditions of their own. `range` is an
+/// optional `(start, duration)` in seconds selecting which packets to copy; `None`
+/// writes the header and nothing else. Each call is its own muxer, so the fragment's
+/// baseMediaDecodeTime is left for `write_segment` to put back on the source
+/// timeline.
 fn remux_fragmented(
     source: &Path,
     dest: &Path,
     range: Option<(f64, f64)>,
-) -> Result<u64, ffmpeg::Error> {
+    container: Container,
+    select: impl Fn(usize, media::Type) -> Option<Mapping>,
+) -> Result<Written, ffmpeg::Error> {
     ffmpeg::init()?;
 
     let mut ictx = format::input(&source)?;
-    let mut octx = format::output_as(&dest, "mp4")?;
+    let mut output = SegmentOutput::new(output_for(dest, container)?);
+    let window = range
+        .map(|(start, duration)| (start, start + duration))
+        .unwrap_or((0.0, f64::MAX));
 
-    // Map audio/video/subtitle streams across, copying codec parameters verbatim.
+    // Map audio/video streams across, copying codec parameters verbatim.
     let mut stream_mapping = vec![-1i32; ictx.nb_streams() as usize];
     let mut ist_time_bases = vec![Rational(0, 1); ictx.nb_streams() as usize];
+    let mut encoders: Vec<(usize, StreamEncoder)> = Vec::new();
     let mut mapped = Vec::new();
     let mut ost_index = 0;
 
     for (ist_index, ist) in ictx.streams().enumerate() {
         let medium = ist.parameters().medium();
-        if medium != media::Type::Audio
-            && medium != media::Type::Video
-            && medium != media::Type::Subtitle
-        {
-            continue;
-        }
+        let copyable = matches!(medium, media::Type::Audio | media::Type::Video);
+        let mapping = match select(ist_index, medium) {
+            Some(mapping) if copyable => mapping,
+            _ => continue,
+        };
 
         stream_mapping[ist_index] = ost_index;
         ist_time_bases[ist_index] = ist.time_base();
         mapped.push(ist_index);
         ost_index += 1;
 
-        let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
-        ost.set_parameters(ist.parameters());
-        // Codec tags are container specific and don't carry over between muxers.
-        unsafe {
-            (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+        match mapping {
+            Mapping::Aac { bitrate, gain } if medium == media::Type::Audio => {
+                let encoder = audio_encoder(&ist, &mut output.octx, bitrate, gain, window)?;
+                encoders.push((ist_index, encoder));
+            }
+            _ => {
+                let mut ost = output.octx.add_stream(encoder::find(codec::Id::None))?;
+                ost.set_parameters(ist.parameters());
+                // Codec tags are container specific and don't carry over between muxers.
+                unsafe {
+                    (*ost.parameters().as_mut_ptr()).codec_tag =
+                        sample_entry_tag(ist.parameters().id(), container);
+                }
+            }
         }
     }
 
-    let mut options = Dictionary::new();
-    options.set("movflags", FRAGMENTED_MP4_FLAGS);
-    octx.write_header_with(options)?;
+    let init_size = write_segment_header(&mut output.octx, container)?;
 
-    // `empty_moov` means the header is complete as soon as write_header returns, so
-    // the current output position is the exact size of the init segment. avio_tell is
-    // a static inline in C and therefore not bound, so seek by 0 from SEEK_CUR (1).
-    let init_size = unsafe {
-        let position = ffmpeg::ffi::avio_seek((*octx.as_mut_ptr()).pb, 0, 1);
-        if position < 0 {
-            return Err(ffmpeg::Error::from(position as i32));
-        }
-        position as u64
-    };
-
-    let Some((start, duration)) = range else {
-        return Ok(init_size);
+    let Some((start, _)) = range else {
+        return Ok(output.finish(init_size));
     };
-    let end = start + duration;
+    let end = window.1;
 
     // Seek to the keyframe at or before `start`; segment boundaries come from
     // get_segments, so this normally lands exactly on the requested keyframe.
@@ -135,11 +299,14 @@ fn remux_fragmented(
         }
 
         let ist_time_base = ist_time_bases[ist_index];
+        let encoder = encoders.iter_mut().find(|(index, _)| *index == ist_index);
         if let Some(pts) = packet.pts() {
             let time =
                 pts as f64 * ist_time_base.numerator() as f64 / ist_time_base.denominator() as f64;
 
-            if time < start {
+            // Transcoded streams get the packets before the window too, to prime
+            // their decoder; it drops the frames they make.
+            if time < start && encoder.is_none() {
                 continue;
             }
             if time >= end {
@@ -152,85 +319,326 @@ fn remux_fragmented(
             }
         }
 
-        let ost_time_base = octx.stream(ost_index as usize).unwrap().time_base();
+        if let Some((_, encoder)) = encoder {
+            encoder.send_packet(&packet, &mut output)?;
+            continue;
+        }
+
+        let ost_time_base = output.octx.stream(ost_index as usize).unwrap().time_base();
         packet.rescale_ts(ist_time_base, ost_time_base);
         packet.set_position(-1);
         packet.set_stream(ost_index as usize);
-        packet.write_interleaved(&mut octx)?;
+        output.write(&mut packet)?;
     }
 
-    // For fragmented MP4 av_write_trailer returns the size of the trailing mfra box,
-    // and ffmpeg-next's write_trailer() reports any non-zero return as an error, so
-    // call it directly and only treat a negative result as a failure.
-    let trailer = unsafe { ffmpeg::ffi::av_write_trailer(octx.as_mut_ptr()) };
-    if trailer < 0 {
-        return Err(ffmpeg::Error::from(trailer));
+    for (_, encoder) in encoders.iter_mut() {
+        encoder.finish(&mut output)?;
     }
 
-    Ok(init_size)
+    write_segment_trailer(&mut output.octx, container)?;
+
+    Ok(output.finish(init_size))
 }
 
-pub async fn transcode_at(av: &AV<'_>, segment: usize, at_path: PathBuf) {
-    if segment + 1 >= av.segments.len() {
-        println!(
-            "Segment {:?} was not transcoded because it do not match known segments in av",
-            segment
-        );
-        return;
+/// Write `range` of `source` as a segment for `rendition`: stream-copied for
+/// passthrough renditions, re-encoded otherwise.
+fn write_fragmented(
+    source: &Path,
+    dest: &Path,
+    range: Option<(f64, f64)>,
+    rendition: &Rendition,
+    container: Container,
+) -> Result<Written, ffmpeg::Error> {
+    if rendition.is_copy() {
+        let audio = match rendition.audio_codec {
+            _ if !rendition.muxed_audio => None,
+            AudioCodec::Copy => Some(Mapping::Copy),
+            AudioCodec::Aac => Some(Mapping::Aac {
+                bitrate: rendition.audio_bitrate,
+                gain: rendition.audio_gain,
+            }),
+        };
+        remux_fragmented(source, dest, range, container, |_, medium| match medium {
+            media::Type::Audio => audio,
+            _ => Some(Mapping::Copy),
+        })
+    } else {
+        encode_fragmented(source, dest, range, rendition, container)
     }
+}
 
-    let start_at = av.segments[segment];
-    let duration = av.segments[segment + 1] - start_at;
+/// Write `range` of the audio stream of `track` alone as a fragmented MP4, like
+/// `write_fragmented`.
+fn write_audio_fragmented(
+    source: &Path,
+    dest: &Path,
+    range: Option<(f64, f64)>,
+    track: &AudioTrack,
+    container: Container,
+) -> Result<Written, ffmpeg::Error> {
+    match track.codec {
+        AudioCodec::Copy => {
+            let stream_index = track.stream_index;
+            remux_fragmented(source, dest, range, container, |index, _| {
+                (index == stream_index).then_some(Mapping::Copy)
+            })
+        }
+        AudioCodec::Aac => encode_audio_fragmented(source, dest, range, track, container),
+    }
+}
+
+/// Write planned `segment` of `av`, the `index`-th of the plan, for `rendition` at
+/// `at_path`, in `container`, encrypted with `key` if given. Returns the keyframes
+/// of the written segment, for I-frame playlists.
+///
+/// A partial segment of a copied rendition is re-encoded, so it can't be decoded
+/// with the header of the rendition: in fMP4, the header it was muxed with is
+/// written to `header_path(&at_path)`, and if that fails, neither is left.
+pub async fn transcode_at(
+    av: &AV<'_>,
+    index: usize,
+    segment: &PlannedSegment,
+    rendition: &Rendition,
+    container: Container,
+    key: Option<SegmentKey>,
+    at_path: PathBuf,
+) -> Vec<Keyframe> {
+    let own_header = segment.partial && rendition.is_copy();
+    let rendition = match (own_header, av.video_streams.first()) {
+        (true, Some(video)) => rendition.for_partial_gops(&video.codec),
+        _ => rendition.clone(),
+    };
+    let (keyframes, header) = write_segment(
+        av,
+        index,
+        segment,
+        container,
+        key,
+        at_path.clone(),
+        move |source, dest, range| write_fragmented(source, dest, range, &rendition, container),
+    )
+    .await;
+    if own_header && container == Container::Fmp4 && !header.is_empty() {
+        if let Err(e) = fs::write(header_path(&at_path), header).await {
+            eprintln!("Failed to write the header of segment {}: {}", index, e);
+            // Without its header the fragment can't be played, so it isn't left to
+            // be uploaded: the job fails and the segment is written again.
+            let _ = fs::remove_file(header_path(&at_path)).await;
+            let _ = fs::remove_file(&at_path).await;
+            return Vec::new();
+        }
+    }
+    keyframes
+}
+
+/// Where `transcode_at` writes the header of a segment written at `at_path` that
+/// needs its own.
+pub fn header_path(at_path: &Path) -> PathBuf {
+    at_path.with_extension("init.mp4")
+}
+
+/// Like `transcode_at`, for a segment of an alternate audio rendition.
+pub async fn transcode_audio_at(
+    av: &AV<'_>,
+    index: usize,
+    segment: &PlannedSegment,
+    track: &AudioTrack,
+    container: Container,
+    key: Option<SegmentKey>,
+    at_path: PathBuf,
+) {
+    let track = track.clone();
+    write_segment(
+        av,
+        index,
+        segment,
+        container,
+        key,
+        at_path,
+        move |source, dest, range| write_audio_fragmented(source, dest, range, &track, container),
+    )
+    .await;
+}
+
+/// Write `segment` of `av` at `at_path` using `write`, one of the `*_fragmented`
+/// functions, keeping only the fragment. Fragmented MP4 segments get decode times
+/// from the source timeline and sequence numbers following the segments before
+/// them, as if the whole rendition had been muxed at once. With a `key`, the
+/// segment is encrypted before it is written, so that it never leaves the worker in
+/// the clear. Returns the keyframes of the written segment, none if it failed or is
+/// encrypted whole, and the header it was muxed with, empty if it failed.
+async fn write_segment<F>(
+    av: &AV<'_>,
+    index: usize,
+    segment: &PlannedSegment,
+    container: Container,
+    key: Option<SegmentKey>,
+    at_path: PathBuf,
+    write: F,
+) -> (Vec<Keyframe>, Vec<u8>)
+where
+    F: FnOnce(&Path, &Path, Option<(f64, f64)>) -> Result<Written, ffmpeg::Error> + Send + 'static,
+{
+    let PlannedSegment {
+        start: start_at,
+        duration,
+        ..
+    } = *segment;
 
     // Use a temporary path for the full fMP4 (header + fragment)
     let temp_path = at_path.with_extension("temp.mp4");
 
     let source = av.path.to_path_buf();
     let remux_target = temp_path.clone();
-    let remuxed = task::spawn_blocking(move || {
-        remux_fragmented(&source, &remux_target, Some((start_at, duration)))
-    })
-    .await
-    .unwrap();
+    let remuxed =
+        task::spawn_blocking(move || write(&source, &remux_target, Some((start_at, duration))))
+            .await
+            .unwrap();
 
-    let init_size = match remuxed {
-        Ok(init_size) => init_size as usize,
+    let written = match remuxed {
+        Ok(written) => written,
         Err(e) => {
-            eprintln!("FFmpeg failed for segment {}: {}", segment, e);
+            eprintln!("FFmpeg failed for segment at {:.3}s: {}", start_at, e);
             let _ = fs::remove_file(temp_path).await;
-            return;
+            return (Vec::new(), Vec::new());
         }
     };
+    let init_size = written.init_size as usize;
 
     // Drop the initialization header (ftyp + moov) to leave only the fragment
     // (moof + mdat); players load that header once, from the init segment.
-    match fs::read(&temp_path).await {
-        Ok(data) if data.len() > init_size => {
-            if let Err(e) = fs::write(&at_path, &data[init_size..]).await {
-                eprintln!("Failed to write segment {}: {}", segment, e);
-                return;
+    let data = match fs::read(&temp_path).await {
+        Ok(data) if data.len() > init_size => data,
+        Ok(_) => {
+            eprintln!("Segment at {:.3}s contains no fragment data", start_at);
+            return (Vec::new(), Vec::new());
+        }
+        Err(e) => {
+            eprintln!("Failed to read segment at {:.3}s: {}", start_at, e);
+            return (Vec::new(), Vec::new());
+        }
+    };
+    let _ = fs::remove_file(temp_path).await;
+
+    let (init, fragment) = data.split_at(init_size);
+    let mut fragment = fragment.to_vec();
+    if container == Container::Fmp4 {
+        let first_sequence = index as u32 * SEQUENCE_STRIDE + 1;
+        if let Err(e) = mp4::rebase_fragments(init, &mut fragment, &written.starts, first_sequence)
+        {
+            eprintln!("Failed to rebase segment at {:.3}s: {}", start_at, e);
+            return (Vec::new(), Vec::new());
+        }
+    }
+
+    if let Some(key) = &key {
+        fragment = match encrypt_fragment(key, index, init, &fragment) {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                eprintln!("Failed to encrypt segment at {:.3}s: {}", start_at, e);
+                return (Vec::new(), Vec::new());
             }
-            let _ = fs::remove_file(temp_path).await;
+        };
+    }
+
+    if let Err(e) = fs::write(&at_path, &fragment).await {
+        eprintln!("Failed to write segment at {:.3}s: {}", start_at, e);
+        return (Vec::new(), Vec::new());
+    }
+    let keyframes = match (container, key.map(|key| key.method)) {
+        // Keyframes can't be addressed inside a segment encrypted whole.
+        (_, Some(EncryptionMethod::Aes128)) => Vec::new(),
+        (Container::Fmp4, _) => mp4::keyframes(init, &fragment),
+        (Container::MpegTs, _) => ts::keyframe(&fragment, start_at).into_iter().collect(),
+    };
+    (keyframes, init.to_vec())
+}
+
+/// Encrypt the `index`-th segment of a rendition, muxed with header `init`, under
+/// `key`. Its IV is its media sequence number, or random for the samples of `cenc`.
+fn encrypt_fragment(
+    key: &SegmentKey,
+    index: usize,
+    init: &[u8],
+    fragment: &[u8],
+) -> Result<Vec<u8>, String> {
+    let cipher = Aes128::new(&key.key).map_err(|e| e.to_string())?;
+    match key.method {
+        EncryptionMethod::Aes128 => Ok(encrypt_segment(&cipher, segment_iv(index), fragment)),
+        EncryptionMethod::SampleAes => sample_aes_segment(&cipher, segment_iv(index), fragment),
+        EncryptionMethod::Cenc | EncryptionMethod::Cbcs => {
+            let first_iv = generate_iv().map_err(|e| e.to_string())?;
+            cenc::encrypt_fragments(&cipher, key, init, fragment, first_iv)
         }
-        Ok(_) => eprintln!("Segment {} contains no fragment data", segment),
-        Err(e) => eprintln!("Failed to read segment {}: {}", segment, e),
     }
 }
 
-/// Generate a standalone init.mp4 from the source file.
+/// Generate a standalone init.mp4 for `rendition` from the source file.
 /// Only the muxer header is written, so the result is exactly ftyp + moov.
+/// Colour boxes of `color` the muxer left out are added to the sample entry. Copied
+/// video keeps the `source_rotation` players apply to the source; encoded video has
+/// it baked in.
 #[allow(dead_code)]
 pub async fn generate_init_segment(
     source_path: &std::path::Path,
     init_path: &std::path::Path,
+    rendition: &Rendition,
+    color: &ColorInfo,
+    source_rotation: u16,
+) -> Result<(), std::io::Error> {
+    let copy = rendition.is_copy();
+    let rendition = rendition.clone();
+    write_init_segment(source_path, init_path, move |source, dest| {
+        write_fragmented(source, dest, None, &rendition, Container::Fmp4)
+    })
+    .await?;
+
+    let mut init = fs::read(init_path).await?;
+    let mut edited = false;
+    if let Some(colored) = mp4::add_color_boxes(&init, color) {
+        init = colored;
+        edited = true;
+    }
+    let rotation = if copy { source_rotation } else { 0 };
+    if let Some(rotated) = mp4::set_rotation(&init, rotation) {
+        init = rotated;
+        edited = true;
+    }
+    if edited {
+        fs::write(init_path, init).await?;
+    }
+    Ok(())
+}
+
+/// Generate the init.mp4 of an alternate audio rendition.
+pub async fn generate_audio_init_segment(
+    source_path: &std::path::Path,
+    init_path: &std::path::Path,
+    track: &AudioTrack,
 ) -> Result<(), std::io::Error> {
+    let track = track.clone();
+    write_init_segment(source_path, init_path, move |source, dest| {
+        write_audio_fragmented(source, dest, None, &track, Container::Fmp4)
+    })
+    .await
+}
+
+async fn write_init_segment<F>(
+    source_path: &std::path::Path,
+    init_path: &std::path::Path,
+    write: F,
+) -> Result<(), std::io::Error>
+where
+    F: FnOnce(&Path, &Path) -> Result<Written, ffmpeg::Error> + Send + 'static,
+{
     let source = source_path.to_path_buf();
     let destination = init_path.to_path_buf();
 
-    let init_size = task::spawn_blocking(move || remux_fragmented(&source, &destination, None))
+    let init_size = task::spawn_blocking(move || write(&source, &destination))
         .await
         .unwrap()
-        .map_err(std::io::Error::other)?;
+        .map_err(std::io::Error::other)?
+        .init_size;
 
     // Nothing follows the header, but truncate anyway so the file is exactly the
     // init segment regardless of what the muxer decided to flush.
@@ -244,18 +652,14 @@ pub async fn generate_init_segment(
 
 #[cfg(test)]
 #[tokio::test]
+#[ignore = "needs the sample videos of test_vars"]
 async fn test_parallel_transcoding() {
     use super::av::AV;
 
     // Setup paths
     let source_str = "test_vars/hls/ssstik.io_@souk.henna_1766442357114/segment_1.mp4";
     let source = PathBuf::from(source_str);
-
-    // If running in a context where test_vars isn't relative to CWD, try to find it
-    if !source.exists() {
-        println!("Skipping test: {:?} not found", source);
-        return;
-    }
+    assert!(source.exists(), "{:?} not found", source);
 
     let temp_dir = std::env::temp_dir();
     let init_out = temp_dir.join("init_verif.mp4");
@@ -263,7 +667,14 @@ async fn test_parallel_transcoding() {
 
     // 1. Test Init Generation
     // This confirms we can pull the header from the source
-    let init_res = generate_init_segment(&source, &init_out).await;
+    let init_res = generate_init_segment(
+        &source,
+        &init_out,
+        &Rendition::source(),
+        &ColorInfo::default(),
+        0,
+    )
+    .await;
     assert!(init_res.is_ok(), "generate_init_segment failed");
 
     let init_data = fs::read(&init_out).await.unwrap();
@@ -279,12 +690,30 @@ async fn test_parallel_transcoding() {
     // Note: The `path` in AV uses the same lifetime as AV.
     let av = AV {
         path: &source,
+        format: "mov,mp4,m4a,3gp,3g2,mj2".to_string(),
+        duration: 1.0,
         video_streams: vec![],
         audio_streams: vec![],
+        subtitle_streams: vec![],
+        cover_art: None,
         segments: vec![0.0, 0.5], // Trancode first 0.5s
     };
 
-    transcode_at(&av, 0, seg_out.clone()).await;
+    let segment = PlannedSegment {
+        start: av.segments[0],
+        duration: av.segments[1] - av.segments[0],
+        partial: false,
+    };
+    transcode_at(
+        &av,
+        0,
+        &segment,
+        &Rendition::source(),
+        Container::Fmp4,
+        None,
+        seg_out.clone(),
+    )
+    .await;
 
     // 3. Verify Segment Content
     let seg_data = fs::read(&seg_out).await.unwrap();
@@ -301,6 +730,11 @@ async fn test_parallel_transcoding() {
         panic!("Generated segment is too short");
     }
 
+    // 4. The init segment followed by the segment plays as one timeline, starting
+    // where the segment does in the source.
+    let media = [init_data, seg_data].concat();
+    assert_eq!(crate::domain::mp4::check_timeline(&media), Ok(()));
+
     // Cleanup
     let _ = fs::remove_file(init_out).await;
     let _ = fs::remove_file(seg_out).await;
@@ -1,3 +1,7 @@
+use crate::domain::color::{ColorInfo, ContentLight, MasteringDisplay};
+use crate::domain::interlace::FieldOrder;
+use crate::domain::mp4;
+use ffmpeg::ffi::{AVColorRange, AVFieldOrder, AVPacketSideDataType};
 use ffmpeg_next as ffmpeg;
 use serde_json::{json, Value};
 use tokio::task;
@@ -8,7 +12,9 @@ pub trait FromStream {
         Self: Sized;
 }
 
-pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
+/// Streams of the file at `path` as JSON, its duration in seconds and the name of
+/// the demuxer that opened it.
+pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64, String) {
     let path_clone = path.to_path_buf();
 
     task::spawn_blocking(move || {
@@ -16,6 +22,7 @@ pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
         match ffmpeg::format::input(&path_clone) {
             Ok(input) => {
                 let duration = input.duration() as f64 / ffmpeg::ffi::AV_TIME_BASE as f64;
+                let format = input.format().name().to_string();
                 let mut streams = Vec::new();
 
                 for stream in input.streams() {
@@ -25,6 +32,7 @@ pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
                     let codec_type = match medium {
                         ffmpeg::media::Type::Video => "video",
                         ffmpeg::media::Type::Audio => "audio",
+                        ffmpeg::media::Type::Subtitle => "subtitle",
                         _ => "unknown",
                     };
 
@@ -32,16 +40,24 @@ pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
                         continue;
                     }
 
-                    // Resolve codec name
-                    let codec_id = params.id();
-                    let codec = ffmpeg::decoder::find(codec_id);
-                    let codec_name = codec
-                        .map(|c| c.name().to_string())
-                        .unwrap_or_else(|| "unknown".to_string());
+                    // Codec name as ffprobe gives it: that of the codec, not of
+                    // whichever decoder FFmpeg would pick (e.g. "mp3", not "mp3float").
+                    let codec_name = params.id().name();
 
+                    let metadata = stream.metadata();
                     let mut json_val = json!({
+                        "index": stream.index(),
                         "codec_type": codec_type,
                         "codec_name": codec_name,
+                        "language": metadata.get("language"),
+                        "title": metadata.get("title"),
+                        "default": stream
+                            .disposition()
+                            .contains(ffmpeg::format::stream::Disposition::DEFAULT),
+                        "forced": stream
+                            .disposition()
+                            .contains(ffmpeg::format::stream::Disposition::FORCED),
+                        "attached_pic": is_attached_pic(&stream),
                     });
 
                     // Create a context to inspect details
@@ -66,11 +82,17 @@ pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
                                 } else {
                                     json_val["profile"] = json!("unknown");
                                 }
-                                json_val["color_space"] = json!("unknown");
+                                json_val["color_space"] =
+                                    json!(decoder.color_space().name().unwrap_or("unknown"));
+                                json_val["color"] = json!(color_info(&params));
+                                json_val["rotation"] = json!(rotation(&params));
+                                json_val["field_order"] = json!(field_order(&params));
                             }
                         } else if codec_type == "audio" {
                             if let Ok(decoder) = ctx.decoder().audio() {
                                 json_val["bit_rate"] = json!(decoder.bit_rate().to_string());
+                                json_val["channels"] = json!(decoder.channels());
+                                json_val["sample_rate"] = json!(decoder.rate());
                                 if let Some(profile) = Some(format!("{:?}", decoder.profile())) {
                                     json_val["profile"] = json!(profile);
                                 } else {
@@ -84,11 +106,11 @@ pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
 
                     streams.push(json_val);
                 }
-                (streams, duration)
+                (streams, duration, format)
             }
             Err(e) => {
                 eprintln!("Error opening input: {}", e);
-                (vec![], 0.0)
+                (vec![], 0.0, String::new())
             }
         }
     })
@@ -96,6 +118,86 @@ pub async fn get_streams(path: &std::path::Path) -> (Vec<Value>, f64) {
     .unwrap()
 }
 
+/// Whether `stream` is a picture attached to the file, such as the cover art of an
+/// audio file, rather than video.
+pub(crate) fn is_attached_pic(stream: &ffmpeg::format::stream::Stream) -> bool {
+    stream
+        .disposition()
+        .contains(ffmpeg::format::stream::Disposition::ATTACHED_PIC)
+}
+
+/// Colour description of a stream, with the HDR metadata it carries as side data.
+pub(crate) fn color_info(parameters: &ffmpeg::codec::Parameters) -> ColorInfo {
+    let mut color = unsafe {
+        let parameters = parameters.as_ptr();
+        ColorInfo {
+            primaries: (*parameters).color_primaries as u8,
+            transfer: (*parameters).color_trc as u8,
+            matrix: (*parameters).color_space as u8,
+            full_range: (*parameters).color_range == AVColorRange::AVCOL_RANGE_JPEG,
+            ..ColorInfo::default()
+        }
+    };
+    for (kind, data) in side_data(parameters) {
+        match kind {
+            AVPacketSideDataType::AV_PKT_DATA_MASTERING_DISPLAY_METADATA => {
+                color.mastering_display = MasteringDisplay::from_ffmpeg(data)
+            }
+            AVPacketSideDataType::AV_PKT_DATA_CONTENT_LIGHT_LEVEL => {
+                color.content_light = ContentLight::from_ffmpeg(data)
+            }
+            _ => {}
+        }
+    }
+    color
+}
+
+/// Field order of a stream as its codec parameters give it, in display order.
+/// Unknown is taken for progressive, leaving interlacing to detection.
+pub(crate) fn field_order(parameters: &ffmpeg::codec::Parameters) -> FieldOrder {
+    match unsafe { (*parameters.as_ptr()).field_order } {
+        AVFieldOrder::AV_FIELD_TT | AVFieldOrder::AV_FIELD_BT => FieldOrder::TopFirst,
+        AVFieldOrder::AV_FIELD_BB | AVFieldOrder::AV_FIELD_TB => FieldOrder::BottomFirst,
+        _ => FieldOrder::Progressive,
+    }
+}
+
+/// Clockwise rotation players apply to a stream, from its display matrix.
+pub(crate) fn rotation(parameters: &ffmpeg::codec::Parameters) -> u16 {
+    side_data(parameters)
+        .into_iter()
+        .find(|(kind, _)| *kind == AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX)
+        .and_then(|(_, data)| {
+            let mut matrix = [0i32; 9];
+            for (i, value) in matrix.iter_mut().enumerate() {
+                *value = i32::from_ne_bytes(data.get(i * 4..i * 4 + 4)?.try_into().ok()?);
+            }
+            Some(mp4::matrix_rotation(&matrix))
+        })
+        .unwrap_or(0)
+}
+
+/// Side data of a stream, which FFmpeg keeps with its codec parameters.
+fn side_data(parameters: &ffmpeg::codec::Parameters) -> Vec<(AVPacketSideDataType, &[u8])> {
+    unsafe {
+        let parameters = parameters.as_ptr();
+        let count = (*parameters).nb_coded_side_data.max(0) as usize;
+        if count == 0 || (*parameters).coded_side_data.is_null() {
+            return Vec::new();
+        }
+        std::slice::from_raw_parts((*parameters).coded_side_data, count)
+            .iter()
+            .filter(|side_data| !side_data.data.is_null())
+            .map(|side_data| {
+                (
+                    side_data.type_,
+                    std::slice::from_raw_parts(side_data.data, side_data.size),
+                )
+            })
+            .collect()
+    }
+}
+
 // Returns a vector of streams when given a valid path.
 #[tokio::test]
 #[ignore]
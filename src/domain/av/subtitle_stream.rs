@@ -0,0 +1,41 @@
+use super::stream::FromStream;
+use serde_json::Value;
+
+#[derive(Debug)]
+pub(crate) struct SubtitleStream {
+    pub index: usize,
+    pub codec: String,
+    /// ISO 639-2 language code from the stream metadata.
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub default: bool,
+    pub forced: bool,
+}
+
+impl FromStream for SubtitleStream {
+    fn from_stream(stream_data: &Value) -> Option<Box<SubtitleStream>> {
+        match stream_data.get("codec_type").and_then(|v| v.as_str()) {
+            Some("subtitle") => Some(Box::new(SubtitleStream {
+                index: stream_data.get("index")?.as_u64()? as usize,
+                codec: stream_data.get("codec_name")?.as_str()?.to_string(),
+                language: stream_data
+                    .get("language")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string),
+                title: stream_data
+                    .get("title")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string),
+                default: stream_data
+                    .get("default")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+                forced: stream_data
+                    .get("forced")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+            })),
+            _ => None,
+        }
+    }
+}
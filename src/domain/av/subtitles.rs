@@ -0,0 +1,74 @@
+use crate::domain::webvtt::Cue;
+use ffmpeg::codec::subtitle::{Rect, Subtitle};
+use ffmpeg::{codec, format};
+use ffmpeg_next as ffmpeg;
+use std::path::Path;
+use tokio::task;
+
+/// Read the cues of subtitle stream `stream_index` of the file at `path`, in order.
+/// Cues that don't say when they end last until the next one.
+pub async fn extract_cues(path: &Path, stream_index: usize) -> Result<Vec<Cue>, ffmpeg::Error> {
+    let path = path.to_path_buf();
+
+    task::spawn_blocking(move || extract(&path, stream_index))
+        .await
+        .unwrap()
+}
+
+fn extract(path: &Path, stream_index: usize) -> Result<Vec<Cue>, ffmpeg::Error> {
+    ffmpeg::init()?;
+    let mut ictx = format::input(&path)?;
+
+    let ist = ictx
+        .stream(stream_index)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let time_base = f64::from(ist.time_base());
+    let mut decoder = codec::context::Context::from_parameters(ist.parameters())?.decoder();
+    decoder.set_packet_time_base(ist.time_base());
+    let mut decoder = decoder.subtitle()?;
+
+    let mut cues: Vec<Cue> = Vec::new();
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        let mut subtitle = Subtitle::new();
+        if !decoder.decode(&packet, &mut subtitle)? {
+            continue;
+        }
+
+        // Display times are in milliseconds from the subtitle's own timestamp.
+        let Some(at) = subtitle
+            .pts()
+            .map(|pts| pts as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE))
+            .or_else(|| packet.pts().map(|pts| pts as f64 * time_base))
+        else {
+            continue;
+        };
+        let start = at + f64::from(subtitle.start()) / 1000.0;
+        let end = match subtitle.end() {
+            end if end > subtitle.start() => at + f64::from(end) / 1000.0,
+            _ if packet.duration() > 0 => start + packet.duration() as f64 * time_base,
+            _ => start,
+        };
+
+        for rect in subtitle.rects() {
+            let cue = match rect {
+                Rect::Ass(ass) => Cue::from_ass(start, end, ass.get()),
+                Rect::Text(text) => Cue::new(start, end, text.get()),
+                Rect::None(_) | Rect::Bitmap(_) => continue,
+            };
+            cues.push(cue);
+        }
+    }
+
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    for i in 0..cues.len() {
+        if cues[i].end <= cues[i].start {
+            cues[i].end = cues
+                .get(i + 1)
+                .map_or(cues[i].start, |next| next.start.max(cues[i].start));
+        }
+    }
+    Ok(cues)
+}
@@ -1,3 +1,5 @@
+use super::encode::rotation_filter;
+use super::stream::rotation;
 use ffmpeg_next as ffmpeg;
 use std::path::Path;
 
@@ -21,6 +23,10 @@ pub async fn generate_strip(
                 .best(ffmpeg::media::Type::Video)
                 .ok_or(ffmpeg::Error::StreamNotFound)?;
             let stream_index = input_stream.index();
+            // Thumbnails are turned upright, as players show the video.
+            let rotate = rotation_filter(rotation(&input_stream.parameters()))
+                .map(|filter| format!("{},", filter))
+                .unwrap_or_default();
 
             // Decoder context
             let context_decoder =
@@ -46,8 +52,8 @@ pub async fn generate_strip(
             // fps=1/interval, scale=width:-1, tile=layout needed?
             // Use [in] and [out] labels to connect to our buffer and buffersink
             let filter_spec = format!(
-                "[in]fps=1/{},scale={}:-1,tile=5x5[out]",
-                interval_seconds, width
+                "[in]{}fps=1/{},scale={}:-1,tile=5x5[out]",
+                rotate, interval_seconds, width
             );
 
             graph.parse(&filter_spec)?;
@@ -114,3 +120,37 @@ pub async fn generate_strip(
     )
     .await?
 }
+
+/// Save the picture attached to `source` as stream `stream_index`, the cover art of
+/// an audio file, to `output` in the format its extension names.
+pub async fn extract_cover_art(
+    source: &Path,
+    output: &Path,
+    stream_index: usize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let source = source.to_path_buf();
+    let output = output.to_path_buf();
+
+    tokio::task::spawn_blocking(
+        move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            ffmpeg::init()?;
+
+            // The picture is the one packet of its stream, an image file as is.
+            let mut ictx = ffmpeg::format::input(&source)?;
+            let packet = ictx
+                .packets()
+                .find(|(stream, _)| stream.index() == stream_index)
+                .map(|(_, packet)| packet)
+                .ok_or(ffmpeg::Error::StreamNotFound)?;
+            let data = packet.data().ok_or("The cover art is empty")?;
+
+            // JPEG has no alpha channel, so PNG art is flattened to RGB.
+            let picture = image::load_from_memory(data)?;
+            picture.to_rgb8().save(&output)?;
+            println!("Saved cover art to {:?}", output);
+
+            Ok(())
+        },
+    )
+    .await?
+}
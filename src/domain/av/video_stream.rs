@@ -1,19 +1,48 @@
 use super::stream::FromStream;
+use crate::domain::color::ColorInfo;
+use crate::domain::frame_rate::FrameTiming;
+use crate::domain::interlace::FieldOrder;
 use serde_json::Value;
 use std::option::Option;
 
 #[derive(Debug)]
 pub(crate) struct VideoStream {
-    codec: String,
-    profile: String,
-    pix_fmt: String,
-    color_space: String,
-    frame_rate: String,
-    bit_rate: String,
-    width: u16,
-    height: u16,
-    aspect_ratio: String,
-    is_horizontal: bool,
+    pub codec: String,
+    pub profile: String,
+    pub pix_fmt: String,
+    pub color_space: String,
+    pub frame_rate: String,
+    pub bit_rate: String,
+    pub width: u16,
+    pub height: u16,
+    pub aspect_ratio: String,
+    /// Whether the video is wider than tall once rotated for display.
+    pub is_horizontal: bool,
+    /// Clockwise rotation players apply for display, in degrees.
+    pub rotation: u16,
+    pub color: ColorInfo,
+    /// From the codec parameters when probed, and from `idet` once analyzed.
+    pub field_order: FieldOrder,
+    /// How regularly frames come, from the packets scanned for keyframes.
+    pub frame_timing: Option<FrameTiming>,
+}
+
+impl VideoStream {
+    /// Average frame rate in frames per second, if the stream reports one.
+    pub fn fps(&self) -> Option<f64> {
+        let (numerator, denominator) = self.frame_rate.split_once('/')?;
+        let numerator: f64 = numerator.parse().ok()?;
+        let denominator: f64 = denominator.parse().ok()?;
+        (numerator > 0.0 && denominator > 0.0).then(|| numerator / denominator)
+    }
+
+    /// Width and height of the video as displayed, after rotation.
+    pub fn display_size(&self) -> (u16, u16) {
+        match self.rotation {
+            90 | 270 => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
 }
 
 impl FromStream for VideoStream {
@@ -21,21 +50,42 @@ impl FromStream for VideoStream {
         if let Some(codec_type) = stream_data.get("codec_type").and_then(|v| v.as_str()) {
             match codec_type {
                 "video" => {
+                    // Cover art is a single picture, not video to package.
+                    if stream_data.get("attached_pic").and_then(|v| v.as_bool()) == Some(true) {
+                        return None;
+                    }
                     let width = stream_data.get("width")?.as_u64().unwrap_or(0) as u16;
                     let height = stream_data.get("height")?.as_u64().unwrap_or(0) as u16;
-                    let is_horizontal = width > height;
+                    let rotation = stream_data
+                        .get("rotation")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(0) as u16;
+                    let is_horizontal = match rotation {
+                        90 | 270 => height > width,
+                        _ => width > height,
+                    };
 
                     Some(Box::new(VideoStream {
-                        codec: stream_data.get("codec_name")?.to_string(),
-                        profile: stream_data.get("profile")?.to_string(),
-                        pix_fmt: stream_data.get("pix_fmt")?.to_string(),
-                        color_space: stream_data.get("color_space")?.to_string(),
-                        frame_rate: stream_data.get("frame_rate")?.to_string(),
-                        bit_rate: stream_data.get("bit_rate")?.to_string(),
+                        codec: stream_data.get("codec_name")?.as_str()?.to_string(),
+                        profile: stream_data.get("profile")?.as_str()?.to_string(),
+                        pix_fmt: stream_data.get("pix_fmt")?.as_str()?.to_string(),
+                        color_space: stream_data.get("color_space")?.as_str()?.to_string(),
+                        frame_rate: stream_data.get("frame_rate")?.as_str()?.to_string(),
+                        bit_rate: stream_data.get("bit_rate")?.as_str()?.to_string(),
                         width,
                         height,
-                        aspect_ratio: stream_data.get("aspect_ratio")?.to_string(),
+                        aspect_ratio: stream_data.get("aspect_ratio")?.as_str()?.to_string(),
                         is_horizontal,
+                        rotation,
+                        color: stream_data
+                            .get("color")
+                            .and_then(|v| serde_json::from_value(v.clone()).ok())
+                            .unwrap_or_default(),
+                        field_order: stream_data
+                            .get("field_order")
+                            .and_then(|v| serde_json::from_value(v.clone()).ok())
+                            .unwrap_or_default(),
+                        frame_timing: None,
                     }))
                 }
                 _ => None,
@@ -0,0 +1,922 @@
+//! Common Encryption (ISO/IEC 23001-7) of fragmented MP4, for players decrypting
+//! through EME: init segments declare their tracks encrypted and list every key
+//! ID, and each fragment carries what players need to decrypt its samples under
+//! the `cenc` or `cbcs` scheme. ClearKey license messages give players the keys of
+//! the key IDs they ask for.
+
+use super::encryption::{BlockCipher, ContentKey, EncryptionMethod, KeyId, SegmentKey, BLOCK_SIZE};
+use super::mp4::{self, boxes, child, edit_path, find, mp4_box, read_u32, Mp4Box, Track};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// System ID of the common `pssh` box, which lists the key IDs of the content for
+/// every key system. ClearKey players ask for the keys it lists.
+const COMMON_SYSTEM_ID: [u8; 16] = [
+    0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02, 0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb, 0x4b,
+];
+
+/// Bytes of the per-sample IV of `cenc`.
+const CENC_IV_SIZE: u8 = 8;
+
+/// Encrypted and skipped blocks of the `cbcs` pattern of video, one in ten.
+const CBCS_VIDEO_PATTERN: (usize, usize) = (1, 9);
+
+/// Bytes of every encrypted NAL unit left in the clear at its start, for its header
+/// and slice header.
+const NAL_CLEAR_LEADER: usize = 32;
+
+/// Index of the first `seig` group description of a fragment, rather than of the
+/// init segment.
+const FRAGMENT_GROUP_INDEX: u32 = 0x10001;
+
+const STSD_PATH: [&[u8; 4]; 6] = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Cenc,
+    Cbcs,
+}
+
+impl Scheme {
+    fn of(method: EncryptionMethod) -> Result<Self, String> {
+        match method {
+            EncryptionMethod::Cenc => Ok(Scheme::Cenc),
+            EncryptionMethod::Cbcs => Ok(Scheme::Cbcs),
+            _ => Err(format!("{} is not Common Encryption", method.as_str())),
+        }
+    }
+
+    fn four_cc(&self) -> &'static [u8; 4] {
+        match self {
+            Scheme::Cenc => b"cenc",
+            Scheme::Cbcs => b"cbcs",
+        }
+    }
+}
+
+/// How the samples of a track are encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Samples {
+    /// H.264 or HEVC NAL units, each after its length in `length_size` bytes:
+    /// slices are encrypted after a clear leader, other units left clear.
+    Video { hevc: bool, length_size: usize },
+    /// Encrypted whole.
+    Audio,
+}
+
+fn is_audio(kind: &[u8; 4]) -> bool {
+    matches!(kind, b"mp4a" | b"ac-3" | b"ec-3" | b"Opus" | b"fLaC")
+}
+
+/// How the samples of `entry`, an `stsd` sample entry, are encrypted; `None` for
+/// tracks left clear, such as subtitles.
+fn sample_format(entry: &Mp4Box<'_>) -> Result<Option<Samples>, String> {
+    let kind = String::from_utf8_lossy(&entry.kind);
+    // The NAL unit length size is in the low bits of a byte of the configuration.
+    let length_size = |config: &[u8; 4], at: usize| {
+        mp4::visual_children(entry.payload)
+            .and_then(|children| child(children, config))
+            .and_then(|config| config.get(at))
+            .map(|byte| usize::from(byte & 0x03) + 1)
+            .ok_or_else(|| format!("No decoder configuration in {} sample entry", kind))
+    };
+    match &entry.kind {
+        b"avc1" | b"avc3" => Ok(Some(Samples::Video {
+            hevc: false,
+            length_size: length_size(b"avcC", 4)?,
+        })),
+        b"hvc1" | b"hev1" => Ok(Some(Samples::Video {
+            hevc: true,
+            length_size: length_size(b"hvcC", 21)?,
+        })),
+        other if mp4::is_visual(other) => Err(format!(
+            "Common Encryption of {} video is not supported",
+            kind
+        )),
+        other if is_audio(other) => Ok(Some(Samples::Audio)),
+        _ => Ok(None),
+    }
+}
+
+/// Sample formats of the encrypted tracks of `init`, by track ID.
+fn track_formats(init: &[u8]) -> Result<Vec<(u32, Samples)>, String> {
+    let moov = child(init, b"moov").ok_or("No moov in init segment")?;
+    let mut formats = Vec::new();
+    for trak in boxes(moov).filter(|b| &b.kind == b"trak") {
+        let tkhd = child(trak.payload, b"tkhd").ok_or("trak without tkhd")?;
+        let id = read_u32(tkhd, if tkhd.first() == Some(&1) { 20 } else { 12 })
+            .ok_or("Truncated tkhd")?;
+        // stsd is a full box followed by an entry count.
+        let entry =
+            find(trak.payload, &STSD_PATH[2..]).and_then(|stsd| boxes(stsd.get(8..)?).next());
+        if let Some(format) = entry
+            .map(|entry| sample_format(&entry))
+            .transpose()?
+            .flatten()
+        {
+            formats.push((id, format));
+        }
+    }
+    Ok(formats)
+}
+
+/// Declare the video and audio tracks of `init` encrypted with `method`, `cenc` or
+/// `cbcs`: their sample entries become `encv` and `enca`, with a `sinf` naming
+/// their original format, the scheme and `key_ids[0]` as the default key. A common
+/// `pssh` lists all of `key_ids`, so that players get the keys of later key periods
+/// with the first one.
+pub fn protect_init(
+    init: &[u8],
+    method: EncryptionMethod,
+    key_ids: &[KeyId],
+) -> Result<Vec<u8>, String> {
+    let scheme = Scheme::of(method)?;
+    let default_key = key_ids.first().ok_or("No key to encrypt with")?;
+    if track_formats(init)?.is_empty() {
+        return Err("No video or audio track to encrypt".to_string());
+    }
+
+    let protected = edit_path(init, &STSD_PATH, &mut |stsd| {
+        let mut entries = stsd.get(..8)?.to_vec();
+        for entry in boxes(stsd.get(8..)?) {
+            let (protected_kind, format) = match sample_format(&entry).ok().flatten() {
+                Some(format @ Samples::Video { .. }) => (b"encv", format),
+                Some(format @ Samples::Audio) => (b"enca", format),
+                None => {
+                    entries.extend(mp4_box(&entry.kind, entry.payload));
+                    continue;
+                }
+            };
+            let mut payload = entry.payload.to_vec();
+            payload.extend(sinf(&entry.kind, scheme, format, default_key));
+            entries.extend(mp4_box(protected_kind, &payload));
+        }
+        Some(entries)
+    });
+    Ok(edit_path(&protected, &[b"moov"], &mut |moov| {
+        let mut moov = moov.to_vec();
+        moov.extend(pssh(key_ids));
+        Some(moov)
+    }))
+}
+
+/// The `sinf` of a sample entry of `original_format` encrypted under `key_id`.
+fn sinf(original_format: &[u8; 4], scheme: Scheme, format: Samples, key_id: &KeyId) -> Vec<u8> {
+    let mut schm = vec![0, 0, 0, 0];
+    schm.extend(scheme.four_cc());
+    schm.extend(0x0001_0000_u32.to_be_bytes());
+
+    // Pattern encryption needs version 1.
+    let version = match scheme {
+        Scheme::Cenc => 0,
+        Scheme::Cbcs => 1,
+    };
+    let mut tenc = vec![version, 0, 0, 0];
+    tenc.extend(protection(scheme, format, key_id));
+
+    let mut sinf = mp4_box(b"frma", original_format);
+    sinf.extend(mp4_box(b"schm", &schm));
+    sinf.extend(mp4_box(b"schi", &mp4_box(b"tenc", &tenc)));
+    mp4_box(b"sinf", &sinf)
+}
+
+/// The fields `tenc` and `seig` group entries share: the pattern, the IV size, the
+/// key ID and, for `cbcs`, the constant IV.
+fn protection(scheme: Scheme, format: Samples, key_id: &KeyId) -> Vec<u8> {
+    let mut fields = vec![0];
+    match (scheme, format) {
+        (Scheme::Cenc, _) => fields.extend([0, 1, CENC_IV_SIZE]),
+        (Scheme::Cbcs, Samples::Video { .. }) => {
+            let (crypt, skip) = CBCS_VIDEO_PATTERN;
+            fields.extend([((crypt << 4) | skip) as u8, 1, 0]);
+        }
+        (Scheme::Cbcs, Samples::Audio) => fields.extend([0, 1, 0]),
+    }
+    fields.extend(key_id.0);
+    if scheme == Scheme::Cbcs {
+        fields.push(BLOCK_SIZE as u8);
+        fields.extend(constant_iv(key_id));
+    }
+    fields
+}
+
+/// The IV every `cbcs` sample under `key_id` starts from: the key ID itself, which
+/// is no more public. `cbcs` only asks for one IV per key.
+fn constant_iv(key_id: &KeyId) -> [u8; BLOCK_SIZE] {
+    key_id.0
+}
+
+/// A version 1 `pssh` of the common system, listing `key_ids`.
+fn pssh(key_ids: &[KeyId]) -> Vec<u8> {
+    let mut pssh = vec![1, 0, 0, 0];
+    pssh.extend(COMMON_SYSTEM_ID);
+    pssh.extend((key_ids.len() as u32).to_be_bytes());
+    for key_id in key_ids {
+        pssh.extend(key_id.0);
+    }
+    // No system specific data.
+    pssh.extend(0_u32.to_be_bytes());
+    mp4_box(b"pssh", &pssh)
+}
+
+/// What a player needs to decrypt a sample: its `senc` entry.
+struct SampleInfo {
+    /// `cenc` IV.
+    iv: Option<u64>,
+    /// Clear then encrypted byte counts of the runs of the sample, for video.
+    subsamples: Option<Vec<(u16, u32)>>,
+}
+
+impl SampleInfo {
+    fn write(&self, senc: &mut Vec<u8>) {
+        if let Some(iv) = self.iv {
+            senc.extend(iv.to_be_bytes());
+        }
+        if let Some(subsamples) = &self.subsamples {
+            senc.extend((subsamples.len() as u16).to_be_bytes());
+            for &(clear, protected) in subsamples {
+                senc.extend(clear.to_be_bytes());
+                senc.extend(protected.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Encrypt the samples of `fragments`, the movie fragments of a segment muxed with
+/// header `init`, as `key` says. Every `traf` of an encrypted track gets a `senc`
+/// with the entries of its samples, found through `saiz` and `saio`; unless the key
+/// is the default one of the init segment, a `seig` sample group names it too.
+/// `cenc` IVs count up from `first_iv`, and must not repeat under a key.
+pub fn encrypt_fragments(
+    cipher: &impl BlockCipher,
+    key: &SegmentKey,
+    init: &[u8],
+    fragments: &[u8],
+    first_iv: u64,
+) -> Result<Vec<u8>, String> {
+    let scheme = Scheme::of(key.method)?;
+    let formats = track_formats(init)?;
+    let tracks = mp4::tracks(init);
+    let offset_of = |payload: &[u8]| payload.as_ptr() as usize - fragments.as_ptr() as usize;
+
+    // Samples are encrypted in place, then the moof boxes replaced.
+    let mut data = fragments.to_vec();
+    let mut moofs: Vec<(Range<usize>, Vec<u8>)> = Vec::new();
+    let mut iv = first_iv;
+    for moof in boxes(fragments).filter(|b| &b.kind == b"moof") {
+        // moof boxes are small enough for a 32-bit size, so an 8 byte header.
+        let moof_at = offset_of(moof.payload) - 8;
+        let mut payload = Vec::with_capacity(moof.payload.len() + 256);
+        for b in boxes(moof.payload) {
+            let track_id = child(b.payload, b"tfhd").and_then(|tfhd| read_u32(tfhd, 4));
+            let format = formats.iter().find(|(id, _)| Some(*id) == track_id);
+            let track = tracks.iter().find(|track| Some(track.id) == track_id);
+            let (Some(&(_, format)), Some(track), b"traf") = (format, track, &b.kind) else {
+                payload.extend(mp4_box(&b.kind, b.payload));
+                continue;
+            };
+
+            let mut info = Vec::new();
+            for range in sample_ranges(b.payload, track)? {
+                let sample = data
+                    .get_mut(moof_at + range.start..moof_at + range.end)
+                    .ok_or("Sample past the end of the segment")?;
+                info.push(encrypt_sample(
+                    cipher,
+                    scheme,
+                    format,
+                    &key.key_id,
+                    iv,
+                    sample,
+                )?);
+                iv = iv.wrapping_add(1);
+            }
+            let mut traf = b.payload.to_vec();
+            if key.period > 0 {
+                traf.extend(key_group(scheme, format, &key.key_id, info.len()));
+            }
+            traf.extend(auxiliary_info(&info)?);
+            payload.extend(mp4_box(b"traf", &traf));
+        }
+
+        let mut rebuilt = mp4_box(b"moof", &payload);
+        let growth = rebuilt.len() - (moof.payload.len() + 8);
+        fix_offsets(&mut rebuilt, growth)?;
+        moofs.push((
+            moof_at..offset_of(moof.payload) + moof.payload.len(),
+            rebuilt,
+        ));
+    }
+
+    let mut encrypted = Vec::with_capacity(data.len() + moofs.len() * 256);
+    let mut copied = 0;
+    for (range, moof) in moofs {
+        encrypted.extend_from_slice(&data[copied..range.start]);
+        encrypted.extend(moof);
+        copied = range.end;
+    }
+    encrypted.extend_from_slice(&data[copied..]);
+    Ok(encrypted)
+}
+
+/// Where the samples of `traf` are, from the start of its `moof`: our muxer makes
+/// that the base of data offsets (`default_base_moof`).
+fn sample_ranges(traf: &[u8], track: &Track) -> Result<Vec<Range<usize>>, String> {
+    let tfhd = child(traf, b"tfhd").ok_or("traf without tfhd")?;
+    let tfhd_flags = read_u32(tfhd, 0).ok_or("Truncated tfhd")? & 0x00ff_ffff;
+    if tfhd_flags & 0x01 != 0 {
+        return Err("Fragments with a base data offset are not supported".to_string());
+    }
+    // Optional tfhd fields: sample description index and default duration, then
+    // the default size.
+    let mut default_size = track.default_size;
+    if tfhd_flags & 0x10 != 0 {
+        let at = 8 + 4 * (tfhd_flags & 0x0a).count_ones() as usize;
+        default_size = read_u32(tfhd, at).ok_or("Truncated tfhd")?;
+    }
+
+    let mut ranges = Vec::new();
+    for trun in boxes(traf).filter(|b| &b.kind == b"trun") {
+        let trun = trun.payload;
+        let flags = read_u32(trun, 0).ok_or("Truncated trun")? & 0x00ff_ffff;
+        let count = read_u32(trun, 4).ok_or("Truncated trun")? as usize;
+        if flags & 0x01 == 0 {
+            return Err("trun without a data offset".to_string());
+        }
+        let mut offset = read_u32(trun, 8).ok_or("Truncated trun")? as usize;
+        // First sample flags, then per sample fields: duration, size, flags and
+        // composition time offset.
+        let first = if flags & 0x04 != 0 { 16 } else { 12 };
+        let fields = 4 * (flags & 0xf00).count_ones() as usize;
+        let size_at = if flags & 0x100 != 0 { 4 } else { 0 };
+        for sample in 0..count {
+            let size = match flags & 0x200 {
+                0 => default_size,
+                _ => read_u32(trun, first + sample * fields + size_at).ok_or("Truncated trun")?,
+            } as usize;
+            ranges.push(offset..offset + size);
+            offset += size;
+        }
+    }
+    Ok(ranges)
+}
+
+/// Encrypt `sample` in place and return its `senc` entry. `cenc` encrypts its runs
+/// as one counter mode stream from `iv`; `cbcs` encrypts each run on its own.
+fn encrypt_sample(
+    cipher: &impl BlockCipher,
+    scheme: Scheme,
+    format: Samples,
+    key_id: &KeyId,
+    iv: u64,
+    sample: &mut [u8],
+) -> Result<SampleInfo, String> {
+    let subsamples = match format {
+        Samples::Video { hevc, length_size } => Some(subsamples(sample, hevc, length_size)?),
+        Samples::Audio => None,
+    };
+    let runs: Vec<Range<usize>> = match &subsamples {
+        Some(subsamples) => {
+            let mut at = 0;
+            subsamples
+                .iter()
+                .map(|&(clear, protected)| {
+                    let start = at + usize::from(clear);
+                    at = start + protected as usize;
+                    start..at
+                })
+                .collect()
+        }
+        None => std::iter::once(0..sample.len()).collect(),
+    };
+
+    match scheme {
+        Scheme::Cenc => {
+            let mut stream: Vec<u8> = runs
+                .iter()
+                .flat_map(|run| sample[run.clone()].iter().copied())
+                .collect();
+            encrypt_ctr(cipher, iv, &mut stream);
+            let mut encrypted = stream.into_iter();
+            for run in runs {
+                for byte in &mut sample[run] {
+                    *byte = encrypted.next().unwrap_or(*byte);
+                }
+            }
+        }
+        Scheme::Cbcs => {
+            let pattern = match format {
+                Samples::Video { .. } => CBCS_VIDEO_PATTERN,
+                Samples::Audio => (1, 0),
+            };
+            for run in runs {
+                encrypt_pattern(cipher, constant_iv(key_id), &mut sample[run], pattern);
+            }
+        }
+    }
+    Ok(SampleInfo {
+        iv: (scheme == Scheme::Cenc).then_some(iv),
+        subsamples,
+    })
+}
+
+/// Clear and encrypted byte counts of the runs of a sample of length-prefixed NAL
+/// units: slices are encrypted in whole blocks after a clear leader, and the rest
+/// of the sample is clear. Clear counts are split to fit their 16 bits.
+fn subsamples(sample: &[u8], hevc: bool, length_size: usize) -> Result<Vec<(u16, u32)>, String> {
+    let mut subsamples = Vec::new();
+    let mut clear = 0;
+    let mut at = 0;
+    while at < sample.len() {
+        let length = sample
+            .get(at..at + length_size)
+            .ok_or("Truncated NAL unit length")?
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | usize::from(byte));
+        let nal = sample
+            .get(at + length_size..at + length_size + length)
+            .ok_or("NAL unit past the end of its sample")?;
+        let protected = match is_slice(nal, hevc) && nal.len() > NAL_CLEAR_LEADER {
+            true => (nal.len() - NAL_CLEAR_LEADER) / BLOCK_SIZE * BLOCK_SIZE,
+            false => 0,
+        };
+        clear += length_size + length - protected;
+        if protected > 0 {
+            push_subsample(&mut subsamples, clear, protected as u32);
+            clear = 0;
+        }
+        at += length_size + length;
+    }
+    if clear > 0 {
+        push_subsample(&mut subsamples, clear, 0);
+    }
+    Ok(subsamples)
+}
+
+fn push_subsample(subsamples: &mut Vec<(u16, u32)>, mut clear: usize, protected: u32) {
+    while clear > usize::from(u16::MAX) {
+        subsamples.push((u16::MAX, 0));
+        clear -= usize::from(u16::MAX);
+    }
+    subsamples.push((clear as u16, protected));
+}
+
+/// Whether `nal` is a slice, of coded picture data.
+fn is_slice(nal: &[u8], hevc: bool) -> bool {
+    match (nal.first(), hevc) {
+        (Some(header), false) => matches!(header & 0x1f, 1..=5),
+        (Some(header), true) => (header >> 1) & 0x3f <= 31,
+        (None, _) => false,
+    }
+}
+
+/// AES-CTR as `cenc` has it: the counter block is the 8 byte IV then a 64-bit
+/// block count from 0.
+fn encrypt_ctr(cipher: &impl BlockCipher, iv: u64, data: &mut [u8]) {
+    let blocks = data.len().div_ceil(BLOCK_SIZE) as u64;
+    let mut keystream: Vec<u8> = (0..blocks)
+        .flat_map(|block| ((u128::from(iv) << 64) | u128::from(block)).to_be_bytes())
+        .collect();
+    cipher.encrypt_blocks(&mut keystream);
+    for (byte, key) in data.iter_mut().zip(keystream) {
+        *byte ^= key;
+    }
+}
+
+/// AES-CBC of the first `crypt` blocks of every `crypt + skip`, chaining from `iv`
+/// across the skipped ones, as `cbcs` has it. A partial last block stays clear.
+fn encrypt_pattern(
+    cipher: &impl BlockCipher,
+    iv: [u8; BLOCK_SIZE],
+    data: &mut [u8],
+    (crypt, skip): (usize, usize),
+) {
+    let offsets: Vec<usize> = (0..data.len() / BLOCK_SIZE)
+        .filter(|block| block % (crypt + skip) < crypt)
+        .map(|block| block * BLOCK_SIZE)
+        .collect();
+    let mut blocks: Vec<u8> = offsets
+        .iter()
+        .flat_map(|&at| data[at..at + BLOCK_SIZE].iter().copied())
+        .collect();
+    cipher.encrypt_cbc(iv, &mut blocks);
+    for (block, &at) in blocks.chunks_exact(BLOCK_SIZE).zip(&offsets) {
+        data[at..at + BLOCK_SIZE].copy_from_slice(block);
+    }
+}
+
+/// `sbgp` and `sgpd` putting every one of the `samples` of a `traf` in a `seig`
+/// group of `key_id`, rather than the default key of the init segment.
+fn key_group(scheme: Scheme, format: Samples, key_id: &KeyId, samples: usize) -> Vec<u8> {
+    let entry = protection(scheme, format, key_id);
+
+    let mut sbgp = vec![0, 0, 0, 0];
+    sbgp.extend(b"seig");
+    sbgp.extend(1_u32.to_be_bytes());
+    sbgp.extend((samples as u32).to_be_bytes());
+    sbgp.extend(FRAGMENT_GROUP_INDEX.to_be_bytes());
+
+    let mut sgpd = vec![1, 0, 0, 0];
+    sgpd.extend(b"seig");
+    sgpd.extend((entry.len() as u32).to_be_bytes());
+    sgpd.extend(1_u32.to_be_bytes());
+    sgpd.extend(entry);
+
+    [mp4_box(b"sbgp", &sbgp), mp4_box(b"sgpd", &sgpd)].concat()
+}
+
+/// `saiz`, `saio` and `senc` of the samples of a `traf`, or nothing when their
+/// entries are empty (`cbcs` audio). `fix_offsets` points `saio` at the entries.
+fn auxiliary_info(info: &[SampleInfo]) -> Result<Vec<u8>, String> {
+    let mut senc = Vec::new();
+    let mut sizes = Vec::with_capacity(info.len());
+    for sample in info {
+        let at = senc.len();
+        sample.write(&mut senc);
+        sizes.push(u8::try_from(senc.len() - at).map_err(|_| "Too many subsamples in a sample")?);
+    }
+    if senc.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let flags = match info.iter().any(|sample| sample.subsamples.is_some()) {
+        true => 0x02,
+        false => 0,
+    };
+    let mut header = vec![0, 0, 0, flags];
+    header.extend((info.len() as u32).to_be_bytes());
+    header.extend(senc);
+
+    let mut saiz = vec![0, 0, 0, 0];
+    match sizes.windows(2).all(|pair| pair[0] == pair[1]) {
+        true => saiz.push(sizes[0]),
+        false => saiz.push(0),
+    }
+    saiz.extend((info.len() as u32).to_be_bytes());
+    if saiz[4] == 0 {
+        saiz.extend(&sizes);
+    }
+
+    let mut saio = vec![0, 0, 0, 0];
+    saio.extend(1_u32.to_be_bytes());
+    saio.extend(0_u32.to_be_bytes());
+
+    Ok([
+        mp4_box(b"saiz", &saiz),
+        mp4_box(b"saio", &saio),
+        mp4_box(b"senc", &header),
+    ]
+    .concat())
+}
+
+/// Once boxes are added to `moof`, which grew by `growth` bytes: move the data
+/// offsets of its `trun` boxes past the growth, and point every `saio` at the
+/// entries of the `senc` of its `traf`, from the start of the `moof`.
+fn fix_offsets(moof: &mut [u8], growth: usize) -> Result<(), String> {
+    let offset_of =
+        |payload: &[u8], moof: &[u8]| payload.as_ptr() as usize - moof.as_ptr() as usize;
+
+    let mut patches = Vec::new();
+    for traf in boxes(moof.get(8..).unwrap_or_default()).filter(|b| &b.kind == b"traf") {
+        for trun in boxes(traf.payload).filter(|b| &b.kind == b"trun") {
+            let flags = read_u32(trun.payload, 0).ok_or("Truncated trun")?;
+            if flags & 0x01 != 0 {
+                let offset = read_u32(trun.payload, 8).ok_or("Truncated trun")?;
+                let at = offset_of(trun.payload, moof) + 8;
+                patches.push((at, offset.wrapping_add(growth as u32)));
+            }
+        }
+        if let (Some(saio), Some(senc)) =
+            (child(traf.payload, b"saio"), child(traf.payload, b"senc"))
+        {
+            // Past the full box headers and the entry and sample counts.
+            let at = offset_of(saio, moof) + 8;
+            patches.push((at, (offset_of(senc, moof) + 8) as u32));
+        }
+    }
+    for (at, value) in patches {
+        moof[at..at + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    Ok(())
+}
+
+/// A ClearKey license request, as EME players send it (W3C EME 9.1.3): the key IDs
+/// they need, in base64url.
+#[derive(Debug, Deserialize)]
+pub struct LicenseRequest {
+    pub kids: Vec<String>,
+    #[serde(rename = "type", default)]
+    pub session_type: Option<String>,
+}
+
+impl LicenseRequest {
+    /// The key IDs asked for, skipping what can't be one.
+    pub fn key_ids(&self) -> Vec<KeyId> {
+        self.kids
+            .iter()
+            .filter_map(|kid| Some(KeyId(base64url_decode(kid)?.try_into().ok()?)))
+            .collect()
+    }
+}
+
+/// A ClearKey license: the keys asked for, as a JSON Web Key Set.
+#[derive(Serialize)]
+pub struct License {
+    keys: Vec<JsonWebKey>,
+    #[serde(rename = "type")]
+    session_type: String,
+}
+
+#[derive(Serialize)]
+struct JsonWebKey {
+    kty: &'static str,
+    kid: String,
+    k: String,
+}
+
+impl License {
+    /// The license of `keys` for a session of `session_type`, temporary by default.
+    pub fn new(keys: &[(KeyId, ContentKey)], session_type: Option<&str>) -> Self {
+        Self {
+            keys: keys
+                .iter()
+                .map(|(key_id, key)| JsonWebKey {
+                    kty: "oct",
+                    kid: base64url(&key_id.0),
+                    k: base64url(&key.0),
+                })
+                .collect(),
+            session_type: session_type.unwrap_or("temporary").to_string(),
+        }
+    }
+}
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Unpadded base64url, as JSON Web Keys have it.
+pub(crate) fn base64url(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0_u32, |group, (i, &byte)| {
+            group | (u32::from(byte) << (16 - 8 * i))
+        });
+        for i in 0..=chunk.len() {
+            encoded.push(char::from(
+                BASE64URL[(group >> (18 - 6 * i)) as usize & 0x3f],
+            ));
+        }
+    }
+    encoded
+}
+
+/// Bytes of base64url `text`, taking padding and the standard alphabet too.
+fn base64url_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut group, mut bits) = (0_u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' | b'+' => 62,
+            b'_' | b'/' => 63,
+            _ => return None,
+        };
+        group = ((group << 6) | u32::from(value)) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::mp4::fixtures::{audio_entry, fragment, init_segment, visual_entry};
+
+    /// Not AES: every byte is XORed with the key, and with the previous ciphertext
+    /// block in CBC mode.
+    struct XorCipher;
+
+    impl BlockCipher for XorCipher {
+        fn encrypt_blocks(&self, data: &mut [u8]) {
+            data.iter_mut().for_each(|byte| *byte ^= 0x5a);
+        }
+
+        fn encrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+            let mut previous = iv;
+            for block in data.chunks_exact_mut(BLOCK_SIZE) {
+                for i in 0..BLOCK_SIZE {
+                    block[i] ^= previous[i] ^ 0x5a;
+                }
+                previous.copy_from_slice(block);
+            }
+        }
+
+        fn decrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+            let mut previous = iv;
+            for block in data.chunks_exact_mut(BLOCK_SIZE) {
+                let ciphertext: [u8; BLOCK_SIZE] = (*block).try_into().unwrap();
+                for i in 0..BLOCK_SIZE {
+                    block[i] ^= previous[i] ^ 0x5a;
+                }
+                previous = ciphertext;
+            }
+        }
+    }
+
+    fn avc1() -> Vec<u8> {
+        visual_entry(b"avc1", mp4_box(b"avcC", &[1, 0x64, 0, 0x1f, 0xff]))
+    }
+
+    fn mp4a() -> Vec<u8> {
+        audio_entry(b"mp4a", mp4_box(b"esds", &[0; 4]))
+    }
+
+    /// A fragment of one sample per track.
+    fn one_sample_fragment(samples: &[Vec<u8>]) -> Vec<u8> {
+        let trafs: Vec<_> = samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| (i as u32 + 1, 0, vec![(3000, sample.clone())]))
+            .collect();
+        fragment(1, &trafs)
+    }
+
+    /// A video sample of an SPS of 10 bytes and an IDR slice of 100, and an audio
+    /// sample of 40 bytes.
+    fn samples() -> Vec<Vec<u8>> {
+        let mut video = vec![0, 0, 0, 10, 0x67];
+        video.extend([0x11; 9]);
+        video.extend([0, 0, 0, 100, 0x65]);
+        video.extend((1..100).map(|i| i as u8));
+        vec![video, vec![0x21; 40]]
+    }
+
+    /// The `senc` payload and the sample of track `track_id` in `fragment`, found
+    /// through `saio` and the `trun` data offset.
+    fn encrypted_sample(fragment: &[u8], track_id: u32, size: usize) -> (Option<&[u8]>, &[u8]) {
+        let moof = child(fragment, b"moof").unwrap();
+        let traf = boxes(moof)
+            .find(|b| {
+                &b.kind == b"traf"
+                    && read_u32(child(b.payload, b"tfhd").unwrap(), 4) == Some(track_id)
+            })
+            .unwrap()
+            .payload;
+        let senc = child(traf, b"senc");
+        if let (Some(saio), Some(senc)) = (child(traf, b"saio"), senc) {
+            let at = read_u32(saio, 8).unwrap() as usize;
+            assert_eq!(fragment[at..at + 8], senc[8..16]);
+        }
+        let offset = read_u32(child(traf, b"trun").unwrap(), 8).unwrap() as usize;
+        (senc, &fragment[offset..offset + size])
+    }
+
+    #[test]
+    fn test_protect_init() {
+        let init = init_segment(&[avc1(), mp4a()]);
+        let key_ids = [KeyId([1; 16]), KeyId([2; 16])];
+        let protected = protect_init(&init, EncryptionMethod::Cbcs, &key_ids).unwrap();
+
+        let moov = child(&protected, b"moov").unwrap();
+        let entries: Vec<Mp4Box<'_>> = boxes(moov)
+            .filter(|b| &b.kind == b"trak")
+            .filter_map(|trak| boxes(find(trak.payload, &STSD_PATH[2..])?.get(8..)?).next())
+            .collect();
+        assert_eq!(entries[0].kind, *b"encv");
+        assert_eq!(entries[1].kind, *b"enca");
+
+        let video = child(mp4::visual_children(entries[0].payload).unwrap(), b"sinf").unwrap();
+        assert_eq!(child(video, b"frma"), Some(&b"avc1"[..]));
+        assert_eq!(child(video, b"schm").unwrap()[4..8], *b"cbcs");
+        let tenc = find(video, &[b"schi", b"tenc"]).unwrap();
+        // Version 1, the 1:9 pattern, protected, constant IV.
+        assert_eq!(tenc[..8], [1, 0, 0, 0, 0, 0x19, 1, 0]);
+        assert_eq!(tenc[8..24], [1; 16]);
+        assert_eq!(tenc[24], 16);
+        let audio = find(&entries[1].payload[28..], &[b"sinf", b"schi", b"tenc"]).unwrap();
+        assert_eq!(audio[5], 0);
+
+        let pssh = child(moov, b"pssh").unwrap();
+        assert_eq!(pssh[4..20], COMMON_SYSTEM_ID);
+        assert_eq!(read_u32(pssh, 20), Some(2));
+        assert_eq!(pssh[40..56], [2; 16]);
+
+        let av01 = mp4_box(b"av01", &[0; 78]);
+        assert!(protect_init(&init_segment(&[av01]), EncryptionMethod::Cenc, &key_ids).is_err());
+        assert!(protect_init(&init, EncryptionMethod::Aes128, &key_ids).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_fragments_cenc() {
+        let init = init_segment(&[avc1(), mp4a()]);
+        let clear = samples();
+        let key = SegmentKey {
+            method: EncryptionMethod::Cenc,
+            period: 0,
+            key_id: KeyId([7; 16]),
+            key: ContentKey([0; 16]),
+        };
+        let encrypted =
+            encrypt_fragments(&XorCipher, &key, &init, &one_sample_fragment(&clear), 1_000)
+                .unwrap();
+
+        // SPS and slice header clear, then 64 bytes of the slice encrypted.
+        let (senc, video) = encrypted_sample(&encrypted, 1, clear[0].len());
+        let senc = senc.unwrap();
+        assert_eq!(senc[..8], [0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(senc[8..16], 1_000_u64.to_be_bytes());
+        assert_eq!(senc[16..], [0, 1, 0, 54, 0, 0, 0, 64]);
+        assert_eq!(video[..54], clear[0][..54]);
+        let mut decrypted = video[54..].to_vec();
+        encrypt_ctr(&XorCipher, 1_000, &mut decrypted);
+        assert_eq!(decrypted, clear[0][54..]);
+
+        // Audio whole, with the next IV.
+        let (senc, audio) = encrypted_sample(&encrypted, 2, 40);
+        assert_eq!(
+            senc.unwrap()[..16],
+            [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x03, 0xe9]
+        );
+        let mut decrypted = audio.to_vec();
+        encrypt_ctr(&XorCipher, 1_001, &mut decrypted);
+        assert_eq!(decrypted, clear[1]);
+        assert!(boxes(&encrypted).any(|b| &b.kind == b"mdat"));
+    }
+
+    #[test]
+    fn test_encrypt_fragments_cbcs() {
+        let init = init_segment(&[avc1(), mp4a()]);
+        let clear = samples();
+        let key = SegmentKey {
+            method: EncryptionMethod::Cbcs,
+            period: 2,
+            key_id: KeyId([7; 16]),
+            key: ContentKey([0; 16]),
+        };
+        let encrypted =
+            encrypt_fragments(&XorCipher, &key, &init, &one_sample_fragment(&clear), 0).unwrap();
+
+        // No IVs, and one block in ten encrypted.
+        let (senc, video) = encrypted_sample(&encrypted, 1, clear[0].len());
+        assert_eq!(senc.unwrap()[8..], [0, 1, 0, 54, 0, 0, 0, 64]);
+        assert_eq!(video[..54], clear[0][..54]);
+        assert_ne!(video[54..70], clear[0][54..70]);
+        assert_eq!(video[70..], clear[0][70..]);
+        let mut decrypted = video[54..70].to_vec();
+        XorCipher.decrypt_cbc(constant_iv(&key.key_id), &mut decrypted);
+        assert_eq!(decrypted, clear[0][54..70]);
+
+        // Audio entries would be empty, so there are none.
+        let (senc, audio) = encrypted_sample(&encrypted, 2, 40);
+        assert!(senc.is_none());
+        assert_ne!(audio[..32], clear[1][..32]);
+        assert_eq!(audio[32..], clear[1][32..]);
+
+        // The key isn't the default one.
+        let moof = child(&encrypted, b"moof").unwrap();
+        let traf = boxes(moof).find(|b| &b.kind == b"traf").unwrap().payload;
+        assert_eq!(child(traf, b"sbgp").unwrap()[4..8], *b"seig");
+        assert_eq!(child(traf, b"sgpd").unwrap()[16..20], [0, 0x19, 1, 0]);
+    }
+
+    #[test]
+    fn test_subsamples() {
+        // A long SEI, then a slice too short to encrypt.
+        let mut sample = vec![0, 0, 0xff, 0xff, 0x06];
+        sample.extend(vec![0; 0xfffe]);
+        sample.extend([0, 0, 0, 40, 0x41]);
+        sample.extend([0; 39]);
+        assert_eq!(
+            subsamples(&sample, false, 4).unwrap(),
+            vec![(u16::MAX, 0), (48, 0)]
+        );
+        // HEVC slices have types up to 31.
+        assert!(is_slice(&[0x26, 0x01], true));
+        assert!(!is_slice(&[0x40, 0x01], true));
+        assert!(subsamples(&[0, 0, 0, 9, 0x65], false, 4).is_err());
+    }
+
+    #[test]
+    fn test_clearkey_license() {
+        let request: LicenseRequest =
+            serde_json::from_str(r#"{"kids":["AAECAwQFBgcICQoLDA0ODw","bad"],"type":"temporary"}"#)
+                .unwrap();
+        let key_id = KeyId(std::array::from_fn(|i| i as u8));
+        assert_eq!(request.key_ids(), vec![key_id]);
+
+        let license = License::new(&[(key_id, ContentKey([0xff; 16]))], None);
+        assert_eq!(
+            serde_json::to_string(&license).unwrap(),
+            r#"{"keys":[{"kty":"oct","kid":"AAECAwQFBgcICQoLDA0ODw","k":"_____________________w"}],"type":"temporary"}"#
+        );
+        assert_eq!(
+            base64url_decode("_____________________w=="),
+            Some(vec![0xff; 16])
+        );
+    }
+}
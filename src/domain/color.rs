@@ -0,0 +1,305 @@
+//! Colour description of a video: what its samples mean, and the HDR metadata
+//! displays need to show them.
+//!
+//! Code points are those of ITU-T H.273, shared by FFmpeg, the codecs and the
+//! `colr` box. Dolby Vision profile 8.4 sources are described by their HLG base
+//! layer.
+
+use serde::{Deserialize, Serialize};
+
+pub const BT709: u8 = 1;
+pub const UNSPECIFIED: u8 = 2;
+/// BT.2020 primaries, and the BT.2020 non-constant luminance matrix.
+pub const BT2020: u8 = 9;
+/// SMPTE ST 2084 transfer, used by HDR10.
+pub const PQ: u8 = 16;
+/// ARIB STD-B67 transfer.
+pub const HLG: u8 = 18;
+
+/// Dynamic range of a video, as `VIDEO-RANGE` advertises it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VideoRange {
+    #[default]
+    Sdr,
+    Pq,
+    Hlg,
+}
+
+impl VideoRange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoRange::Sdr => "SDR",
+            VideoRange::Pq => "PQ",
+            VideoRange::Hlg => "HLG",
+        }
+    }
+}
+
+/// SMPTE ST 2086 mastering display colour volume, in the units of the `mdcv` box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MasteringDisplay {
+    /// x and y chromaticity of the green, blue and red primaries, in 0.00002 units.
+    pub primaries: [(u16, u16); 3],
+    pub white_point: (u16, u16),
+    /// In 0.0001 cd/m² units.
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    /// Read FFmpeg's `AVMasteringDisplayMetadata`: rational xy coordinates of the
+    /// red, green and blue primaries and of the white point, then the minimum and
+    /// maximum luminance, then whether each half is set.
+    pub fn from_ffmpeg(data: &[u8]) -> Option<Self> {
+        let rational = |i: usize| -> Option<f64> {
+            let field = |at: usize| -> Option<i32> {
+                Some(i32::from_ne_bytes(data.get(at..at + 4)?.try_into().ok()?))
+            };
+            let (numerator, denominator) = (field(i * 8)?, field(i * 8 + 4)?);
+            (denominator != 0).then(|| f64::from(numerator) / f64::from(denominator))
+        };
+        let flag = |at: usize| -> Option<bool> {
+            Some(i32::from_ne_bytes(data.get(at..at + 4)?.try_into().ok()?) != 0)
+        };
+        if !flag(80)? || !flag(84)? {
+            return None;
+        }
+
+        let chromaticity = |i: usize| -> Option<(u16, u16)> {
+            let x = (rational(i)? * 50_000.0).round() as u16;
+            let y = (rational(i + 1)? * 50_000.0).round() as u16;
+            Some((x, y))
+        };
+        let (red, green, blue) = (chromaticity(0)?, chromaticity(2)?, chromaticity(4)?);
+        Some(Self {
+            primaries: [green, blue, red],
+            white_point: chromaticity(6)?,
+            min_luminance: (rational(8)? * 10_000.0).round() as u32,
+            max_luminance: (rational(9)? * 10_000.0).round() as u32,
+        })
+    }
+
+    /// `G(x,y)B(x,y)R(x,y)WP(x,y)L(max,min)`, as x265 takes it.
+    fn x265_param(&self) -> String {
+        let [green, blue, red] = self.primaries;
+        format!(
+            "G({},{})B({},{})R({},{})WP({},{})L({},{})",
+            green.0,
+            green.1,
+            blue.0,
+            blue.1,
+            red.0,
+            red.1,
+            self.white_point.0,
+            self.white_point.1,
+            self.max_luminance,
+            self.min_luminance
+        )
+    }
+}
+
+/// Content light levels, in cd/m².
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentLight {
+    /// Brightest pixel of the video.
+    pub max_cll: u16,
+    /// Brightest frame average of the video.
+    pub max_fall: u16,
+}
+
+impl ContentLight {
+    /// Read FFmpeg's `AVContentLightMetadata`.
+    pub fn from_ffmpeg(data: &[u8]) -> Option<Self> {
+        let field = |at: usize| -> Option<u16> {
+            let value = u32::from_ne_bytes(data.get(at..at + 4)?.try_into().ok()?);
+            Some(value.min(u32::from(u16::MAX)) as u16)
+        };
+        Some(Self {
+            max_cll: field(0)?,
+            max_fall: field(4)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorInfo {
+    pub primaries: u8,
+    pub transfer: u8,
+    pub matrix: u8,
+    pub full_range: bool,
+    #[serde(default)]
+    pub mastering_display: Option<MasteringDisplay>,
+    #[serde(default)]
+    pub content_light: Option<ContentLight>,
+}
+
+impl Default for ColorInfo {
+    fn default() -> Self {
+        Self {
+            primaries: UNSPECIFIED,
+            transfer: UNSPECIFIED,
+            matrix: UNSPECIFIED,
+            full_range: false,
+            mastering_display: None,
+            content_light: None,
+        }
+    }
+}
+
+impl ColorInfo {
+    /// SDR video as encoded renditions carry it.
+    pub fn bt709() -> Self {
+        Self {
+            primaries: BT709,
+            transfer: BT709,
+            matrix: BT709,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the video says anything about its colour at all.
+    pub fn is_specified(&self) -> bool {
+        [self.primaries, self.transfer, self.matrix]
+            .iter()
+            .any(|&code| code != 0 && code != UNSPECIFIED)
+    }
+
+    pub fn range(&self) -> VideoRange {
+        match self.transfer {
+            PQ => VideoRange::Pq,
+            HLG => VideoRange::Hlg,
+            _ => VideoRange::Sdr,
+        }
+    }
+
+    /// Colour of a rendition produced from video of this colour.
+    pub fn for_rendition(&self, tone_map: bool) -> Self {
+        match tone_map {
+            true => Self::bt709(),
+            false => self.clone(),
+        }
+    }
+
+    /// `x265-params` carrying the HDR10 metadata in the bitstream. The colour
+    /// description itself comes from the encoder context.
+    pub fn x265_params(&self) -> Option<String> {
+        if self.range() != VideoRange::Pq {
+            return None;
+        }
+        let mut params = vec!["hdr10=1".to_string(), "repeat-headers=1".to_string()];
+        if let Some(display) = &self.mastering_display {
+            params.push(format!("master-display={}", display.x265_param()));
+        }
+        if let Some(light) = &self.content_light {
+            params.push(format!("max-cll={},{}", light.max_cll, light.max_fall));
+        }
+        Some(params.join(":"))
+    }
+
+    /// `zscale` and `tonemap` filters bringing HDR video of this colour to BT.709.
+    pub fn tone_map_filter(&self) -> String {
+        let transfer = match self.transfer {
+            HLG => "arib-std-b67",
+            _ => "smpte2084",
+        };
+        let matrix = match self.matrix {
+            BT709 => "709",
+            _ => "2020_ncl",
+        };
+        let range = if self.full_range { "full" } else { "limited" };
+        format!(
+            "zscale=tin={}:min={}:pin=2020:rin={}:t=linear:npl=100,format=gbrpf32le,\
+             zscale=p=709,tonemap=tonemap=hable:desat=0,zscale=t=709:m=709:r=limited",
+            transfer, matrix, range
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rationals(values: &[(i32, i32)], flags: [i32; 2]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (numerator, denominator) in values {
+            data.extend(numerator.to_ne_bytes());
+            data.extend(denominator.to_ne_bytes());
+        }
+        for flag in flags {
+            data.extend(flag.to_ne_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_mastering_display() {
+        // P3 primaries, D65 white point, 1000 to 0.0001 cd/m².
+        let data = rationals(
+            &[
+                (34000, 50000),
+                (16000, 50000),
+                (13250, 50000),
+                (34500, 50000),
+                (7500, 50000),
+                (3000, 50000),
+                (15635, 50000),
+                (16450, 50000),
+                (1, 10000),
+                (1000, 1),
+            ],
+            [1, 1],
+        );
+        let display = MasteringDisplay::from_ffmpeg(&data).unwrap();
+        assert_eq!(
+            display.primaries,
+            [(13250, 34500), (7500, 3000), (34000, 16000)]
+        );
+        assert_eq!(display.white_point, (15635, 16450));
+        assert_eq!(
+            (display.max_luminance, display.min_luminance),
+            (10_000_000, 1)
+        );
+        assert_eq!(
+            display.x265_param(),
+            "G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,1)"
+        );
+
+        let unset = rationals(&[(0, 1); 10], [0, 0]);
+        assert_eq!(MasteringDisplay::from_ffmpeg(&unset), None);
+        assert_eq!(MasteringDisplay::from_ffmpeg(&data[..40]), None);
+    }
+
+    #[test]
+    fn test_color_info() {
+        let mut hdr10 = ColorInfo {
+            primaries: BT2020,
+            transfer: PQ,
+            matrix: BT2020,
+            ..ColorInfo::default()
+        };
+        assert_eq!(hdr10.range(), VideoRange::Pq);
+        assert_eq!(
+            hdr10.x265_params().as_deref(),
+            Some("hdr10=1:repeat-headers=1")
+        );
+
+        let light = [1000u32.to_ne_bytes(), 400u32.to_ne_bytes()].concat();
+        hdr10.content_light = ContentLight::from_ffmpeg(&light);
+        assert!(hdr10.x265_params().unwrap().ends_with(":max-cll=1000,400"));
+        assert_eq!(hdr10.for_rendition(true), ColorInfo::bt709());
+
+        let hlg = ColorInfo {
+            transfer: HLG,
+            ..hdr10.clone()
+        };
+        assert_eq!(hlg.range(), VideoRange::Hlg);
+        assert_eq!(hlg.x265_params(), None);
+        assert!(hlg
+            .tone_map_filter()
+            .starts_with("zscale=tin=arib-std-b67:min=2020_ncl"));
+
+        assert!(!ColorInfo::default().is_specified());
+        assert!(ColorInfo::bt709().is_specified());
+        assert_eq!(ColorInfo::bt709().range(), VideoRange::Sdr);
+    }
+}
@@ -0,0 +1,481 @@
+//! MPEG-DASH manifest of the fragments packaged for HLS.
+
+use super::encryption::KeyId;
+use super::hls::ByteRange;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// Ticks per second of the segment timeline.
+const TIMESCALE: u64 = 1000;
+
+/// `schemeIdUri` of the ClearKey key system.
+const CLEARKEY_SCHEME: &str = "urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Video,
+    Audio,
+}
+
+impl ContentType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Video => "video",
+            ContentType::Audio => "audio",
+        }
+    }
+}
+
+/// Where the init segment and the segments of a representation are found,
+/// relative to its `BaseURL`.
+pub enum SegmentAddressing {
+    /// One file per segment, numbered from 0.
+    Template {
+        initialization: String,
+        media: String,
+    },
+    /// Every segment in the `BaseURL` file itself, at the given byte ranges.
+    List {
+        initialization: ByteRange,
+        media: Vec<ByteRange>,
+    },
+}
+
+pub struct Representation {
+    pub id: String,
+    /// Peak bitrate, in bits per second.
+    pub bandwidth: u64,
+    /// RFC 6381 codec strings of every track.
+    pub codecs: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+    /// Channel count, for audio.
+    pub channels: Option<u16>,
+    pub base_url: String,
+    pub segments: SegmentAddressing,
+}
+
+pub struct AdaptationSet {
+    pub content_type: ContentType,
+    /// RFC 5646 language tag.
+    pub language: Option<String>,
+    pub label: Option<String>,
+    /// Whether this is the set to play without a user preference.
+    pub main: bool,
+    pub representations: Vec<Representation>,
+}
+
+/// Common Encryption of every representation, with the keys from a ClearKey
+/// license server.
+pub struct ContentProtection {
+    /// `cenc` or `cbcs`.
+    pub scheme: String,
+    /// Key ID of the init segments; the others are in their `pssh`.
+    pub default_key_id: KeyId,
+    pub license_url: String,
+}
+
+/// A static (on demand) MPD with a single period.
+pub struct Manifest {
+    /// Duration of every segment, in seconds, shared by all representations.
+    pub segment_durations: Vec<f64>,
+    /// Source time, in seconds, of the start of the first segment. Fragments keep
+    /// their source decode times, so the timeline starts there and is offset back
+    /// to the start of the period.
+    pub start: f64,
+    pub adaptation_sets: Vec<AdaptationSet>,
+    pub content_protection: Option<ContentProtection>,
+}
+
+impl Manifest {
+    pub fn new(segment_durations: Vec<f64>) -> Self {
+        Self {
+            segment_durations,
+            start: 0.0,
+            adaptation_sets: Vec::new(),
+            content_protection: None,
+        }
+    }
+
+    pub fn add_adaptation_set(&mut self, adaptation_set: AdaptationSet) {
+        self.adaptation_sets.push(adaptation_set);
+    }
+
+    pub fn render(&self) -> String {
+        let duration: f64 = self.segment_durations.iter().sum();
+        let max_duration = self.segment_durations.iter().cloned().fold(0.0, f64::max);
+        let timeline = self.timeline();
+        let offset = (self.start * TIMESCALE as f64).round() as u64;
+
+        let mut mpd = String::new();
+        mpd.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let namespaces = match self.content_protection {
+            Some(_) => " xmlns:cenc=\"urn:mpeg:cenc:2013\" xmlns:dashif=\"https://dashif.org/CPS\"",
+            None => "",
+        };
+        let _ = writeln!(
+            mpd,
+            "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\"{} \
+             profiles=\"urn:mpeg:dash:profile:isoff-on-demand:2011,urn:mpeg:dash:profile:isoff-live:2011\" \
+             type=\"static\" mediaPresentationDuration=\"{}\" minBufferTime=\"{}\">",
+            namespaces,
+            iso_duration(duration),
+            iso_duration(max_duration.ceil())
+        );
+        mpd.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+
+        for (id, set) in self.adaptation_sets.iter().enumerate() {
+            let mut attributes = format!(
+                "id=\"{}\" contentType=\"{}\" mimeType=\"{}/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\"",
+                id,
+                set.content_type.as_str(),
+                set.content_type.as_str()
+            );
+            if let Some(language) = &set.language {
+                let _ = write!(attributes, " lang=\"{}\"", escape(language));
+            }
+            let _ = writeln!(mpd, "    <AdaptationSet {}>", attributes);
+            if let Some(protection) = &self.content_protection {
+                render_content_protection(&mut mpd, protection);
+            }
+            if let Some(label) = &set.label {
+                let _ = writeln!(mpd, "      <Label>{}</Label>", escape(label));
+            }
+            if set.content_type == ContentType::Audio {
+                let _ = writeln!(
+                    mpd,
+                    "      <Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"{}\"/>",
+                    if set.main { "main" } else { "alternate" }
+                );
+            }
+
+            for representation in &set.representations {
+                render_representation(&mut mpd, representation, &timeline, offset);
+            }
+            mpd.push_str("    </AdaptationSet>\n");
+        }
+
+        mpd.push_str("  </Period>\n</MPD>\n");
+        mpd
+    }
+
+    pub async fn write_to(&self, path: &PathBuf) -> Result<(), std::io::Error> {
+        tokio::fs::write(path, self.render()).await
+    }
+
+    /// `SegmentTimeline` of the segments, runs of equal durations collapsed.
+    /// Durations are rounded from the absolute times so that they never drift.
+    fn timeline(&self) -> String {
+        let mut runs: Vec<(u64, u64, usize)> = Vec::new();
+        let mut elapsed = self.start;
+        for &duration in &self.segment_durations {
+            let start = (elapsed * TIMESCALE as f64).round() as u64;
+            elapsed += duration;
+            let ticks = (elapsed * TIMESCALE as f64).round() as u64 - start;
+            match runs.last_mut() {
+                Some((_, last, repeat)) if *last == ticks => *repeat += 1,
+                _ => runs.push((start, ticks, 0)),
+            }
+        }
+
+        let mut timeline = String::from("<SegmentTimeline>");
+        for (i, (start, ticks, repeat)) in runs.into_iter().enumerate() {
+            timeline.push_str("<S ");
+            if i == 0 {
+                let _ = write!(timeline, "t=\"{}\" ", start);
+            }
+            let _ = write!(timeline, "d=\"{}\"", ticks);
+            if repeat > 0 {
+                let _ = write!(timeline, " r=\"{}\"", repeat);
+            }
+            timeline.push_str("/>");
+        }
+        timeline.push_str("</SegmentTimeline>");
+        timeline
+    }
+}
+
+/// The scheme of the segments, then where ClearKey players get their keys.
+fn render_content_protection(mpd: &mut String, protection: &ContentProtection) {
+    let _ = writeln!(
+        mpd,
+        "      <ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" \
+         value=\"{}\" cenc:default_KID=\"{}\"/>",
+        escape(&protection.scheme),
+        protection.default_key_id.to_uuid()
+    );
+    let _ = writeln!(
+        mpd,
+        "      <ContentProtection schemeIdUri=\"{}\" value=\"ClearKey1.0\">",
+        CLEARKEY_SCHEME
+    );
+    let _ = writeln!(
+        mpd,
+        "        <dashif:Laurl>{}</dashif:Laurl>",
+        escape(&protection.license_url)
+    );
+    mpd.push_str("      </ContentProtection>\n");
+}
+
+/// `offset` is the `presentationTimeOffset` of the segments, in timescale ticks.
+fn render_representation(
+    mpd: &mut String,
+    representation: &Representation,
+    timeline: &str,
+    offset: u64,
+) {
+    let mut attributes = format!(
+        "id=\"{}\" bandwidth=\"{}\"",
+        escape(&representation.id),
+        representation.bandwidth
+    );
+    if let Some(codecs) = &representation.codecs {
+        let _ = write!(attributes, " codecs=\"{}\"", codecs);
+    }
+    if let Some((width, height)) = representation.resolution {
+        let _ = write!(attributes, " width=\"{}\" height=\"{}\"", width, height);
+    }
+    if let Some(frame_rate) = representation.frame_rate {
+        let _ = write!(attributes, " frameRate=\"{}\"", dash_frame_rate(frame_rate));
+    }
+    let _ = writeln!(mpd, "      <Representation {}>", attributes);
+
+    if let Some(channels) = representation.channels {
+        let _ = writeln!(
+            mpd,
+            "        <AudioChannelConfiguration \
+             schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\"/>",
+            channels
+        );
+    }
+    let _ = writeln!(
+        mpd,
+        "        <BaseURL>{}</BaseURL>",
+        escape(&representation.base_url)
+    );
+
+    let offset = match offset {
+        0 => String::new(),
+        offset => format!(" presentationTimeOffset=\"{}\"", offset),
+    };
+    match &representation.segments {
+        SegmentAddressing::Template {
+            initialization,
+            media,
+        } => {
+            let _ = writeln!(
+                mpd,
+                "        <SegmentTemplate timescale=\"{}\"{} initialization=\"{}\" media=\"{}\" startNumber=\"0\">{}</SegmentTemplate>",
+                TIMESCALE,
+                offset,
+                escape(initialization),
+                escape(media),
+                timeline
+            );
+        }
+        SegmentAddressing::List {
+            initialization,
+            media,
+        } => {
+            let _ = writeln!(
+                mpd,
+                "        <SegmentList timescale=\"{}\"{}>",
+                TIMESCALE, offset
+            );
+            let _ = writeln!(
+                mpd,
+                "          <Initialization range=\"{}\"/>",
+                dash_range(initialization)
+            );
+            let _ = writeln!(mpd, "          {}", timeline);
+            for range in media {
+                let _ = writeln!(
+                    mpd,
+                    "          <SegmentURL mediaRange=\"{}\"/>",
+                    dash_range(range)
+                );
+            }
+            mpd.push_str("        </SegmentList>\n");
+        }
+    }
+    mpd.push_str("      </Representation>\n");
+}
+
+/// `xs:duration` of `seconds`, e.g. `PT1M4.500S`.
+fn iso_duration(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).floor();
+    let seconds = seconds - minutes * 60.0;
+    if minutes > 0.0 {
+        format!("PT{}M{:.3}S", minutes as u64, seconds)
+    } else {
+        format!("PT{:.3}S", seconds)
+    }
+}
+
+/// DASH frame rates are integers or fractions; NTSC rates are written over 1001.
+fn dash_frame_rate(frame_rate: f64) -> String {
+    if (frame_rate - frame_rate.round()).abs() < 0.01 {
+        format!("{}", frame_rate.round() as u64)
+    } else {
+        format!("{}/1001", (frame_rate * 1001.0).round() as u64)
+    }
+}
+
+/// Inclusive `first-last` byte range.
+fn dash_range(range: &ByteRange) -> String {
+    format!(
+        "{}-{}",
+        range.offset,
+        range.offset + range.length.saturating_sub(1)
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let mut manifest = Manifest::new(vec![6.0, 6.0, 6.0, 4.5]);
+        manifest.add_adaptation_set(AdaptationSet {
+            content_type: ContentType::Video,
+            language: None,
+            label: None,
+            main: true,
+            representations: vec![Representation {
+                id: "720p".to_string(),
+                bandwidth: 2_928_000,
+                codecs: Some("avc1.64001f".to_string()),
+                resolution: Some((1280, 720)),
+                frame_rate: Some(29.97),
+                channels: None,
+                base_url: "720p/".to_string(),
+                segments: SegmentAddressing::Template {
+                    initialization: "init.mp4".to_string(),
+                    media: "segment_$Number$.mp4".to_string(),
+                },
+            }],
+        });
+        manifest.add_adaptation_set(AdaptationSet {
+            content_type: ContentType::Audio,
+            language: Some("fr".to_string()),
+            label: Some("Français".to_string()),
+            main: false,
+            representations: vec![Representation {
+                id: "audio_1".to_string(),
+                bandwidth: 130_000,
+                codecs: Some("mp4a.40.2".to_string()),
+                resolution: None,
+                frame_rate: None,
+                channels: Some(2),
+                base_url: "audio_1/media.mp4".to_string(),
+                segments: SegmentAddressing::List {
+                    initialization: ByteRange {
+                        length: 600,
+                        offset: 0,
+                    },
+                    media: vec![ByteRange {
+                        length: 1000,
+                        offset: 600,
+                    }],
+                },
+            }],
+        });
+
+        let mpd = manifest.render();
+
+        assert!(mpd.contains("type=\"static\" mediaPresentationDuration=\"PT22.500S\""));
+        assert!(mpd.contains("minBufferTime=\"PT6.000S\""));
+        assert!(mpd.contains(
+            "<Representation id=\"720p\" bandwidth=\"2928000\" codecs=\"avc1.64001f\" \
+             width=\"1280\" height=\"720\" frameRate=\"30000/1001\">"
+        ));
+        assert!(mpd.contains(
+            "<SegmentTemplate timescale=\"1000\" initialization=\"init.mp4\" \
+             media=\"segment_$Number$.mp4\" startNumber=\"0\">\
+             <SegmentTimeline><S t=\"0\" d=\"6000\" r=\"2\"/><S d=\"4500\"/></SegmentTimeline>\
+             </SegmentTemplate>"
+        ));
+        assert!(mpd.contains("contentType=\"audio\" mimeType=\"audio/mp4\""));
+        assert!(mpd.contains("lang=\"fr\""));
+        assert!(mpd.contains("value=\"alternate\""));
+        assert!(mpd.contains("<Initialization range=\"0-599\"/>"));
+        assert!(mpd.contains("<SegmentURL mediaRange=\"600-1599\"/>"));
+    }
+
+    #[test]
+    fn test_start_offset() {
+        let mut manifest = Manifest::new(vec![0.5, 6.0]);
+        manifest.start = 9.5;
+        manifest.add_adaptation_set(AdaptationSet {
+            content_type: ContentType::Video,
+            language: None,
+            label: None,
+            main: true,
+            representations: vec![Representation {
+                id: "720p".to_string(),
+                bandwidth: 2_928_000,
+                codecs: None,
+                resolution: None,
+                frame_rate: None,
+                channels: None,
+                base_url: "720p/".to_string(),
+                segments: SegmentAddressing::Template {
+                    initialization: "init.mp4".to_string(),
+                    media: "segment_$Number$.mp4".to_string(),
+                },
+            }],
+        });
+
+        let mpd = manifest.render();
+
+        assert!(mpd.contains("mediaPresentationDuration=\"PT6.500S\""));
+        assert!(mpd.contains(
+            "<SegmentTemplate timescale=\"1000\" presentationTimeOffset=\"9500\" \
+             initialization=\"init.mp4\""
+        ));
+        assert!(mpd.contains("<S t=\"9500\" d=\"500\"/><S d=\"6000\"/>"));
+    }
+
+    #[test]
+    fn test_content_protection() {
+        let mut manifest = Manifest::new(vec![6.0]);
+        manifest.add_adaptation_set(AdaptationSet {
+            content_type: ContentType::Video,
+            language: None,
+            label: None,
+            main: true,
+            representations: Vec::new(),
+        });
+        assert!(!manifest.render().contains("ContentProtection"));
+
+        manifest.content_protection = Some(ContentProtection {
+            scheme: "cbcs".to_string(),
+            default_key_id: KeyId([0xab; 16]),
+            license_url: "/keys/v1/license".to_string(),
+        });
+        let mpd = manifest.render();
+        assert!(mpd.contains("xmlns:cenc=\"urn:mpeg:cenc:2013\""));
+        assert!(mpd.contains(
+            "<ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" value=\"cbcs\" \
+             cenc:default_KID=\"abababab-abab-abab-abab-abababababab\"/>"
+        ));
+        assert!(mpd.contains("<dashif:Laurl>/keys/v1/license</dashif:Laurl>"));
+    }
+
+    #[test]
+    fn test_iso_duration() {
+        assert_eq!(iso_duration(64.5), "PT1M4.500S");
+        assert_eq!(iso_duration(6.0), "PT6.000S");
+        assert_eq!(dash_frame_rate(25.0), "25");
+        assert_eq!(dash_frame_rate(59.94), "60000/1001");
+    }
+}
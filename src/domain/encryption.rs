@@ -0,0 +1,659 @@
+//! Segment encryption: AES-128 of whole segments, SAMPLE-AES of the H.264 and AAC
+//! samples of MPEG-TS segments, or Common Encryption of fragmented MP4 samples (see
+//! `cenc`), under content keys that can rotate every few segments.
+
+use super::ts::{self, ElementaryStream};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Where players get content keys unless configured otherwise: the monolith's key
+/// endpoint, on the host serving the playlists.
+pub const DEFAULT_KEY_URL: &str = "/keys";
+
+/// AES block size, in bytes.
+pub const BLOCK_SIZE: usize = 16;
+
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_ADTS: u8 = 0x0f;
+/// Stream types of SAMPLE-AES encrypted H.264 and AAC.
+const STREAM_TYPE_H264_SAMPLE_AES: u8 = 0xdb;
+const STREAM_TYPE_ADTS_SAMPLE_AES: u8 = 0xcf;
+
+/// Bytes of every encrypted H.264 NAL unit left in the clear at its start.
+const NAL_CLEAR_LEADER: usize = 32;
+/// Bytes left in the clear after each encrypted block of a NAL unit.
+const NAL_CLEAR_BLOCK: usize = 144;
+/// Bytes of every AAC frame left in the clear after its header.
+const AAC_CLEAR_LEADER: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EncryptionMethod {
+    /// Whole segments, AES-128 in CBC mode with PKCS#7 padding.
+    Aes128,
+    /// Only the video and audio samples, leaving the container readable so that
+    /// I-frame playlists keep working. H.264 and AAC in MPEG-TS only.
+    SampleAes,
+    /// Common Encryption `cenc` scheme, AES-CTR, of fragmented MP4 samples.
+    Cenc,
+    /// Common Encryption `cbcs` scheme, AES-CBC of one block in ten, of fragmented
+    /// MP4 samples. What FairPlay plays.
+    Cbcs,
+}
+
+impl EncryptionMethod {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "aes-128" | "aes128" => Ok(EncryptionMethod::Aes128),
+            "sample-aes" => Ok(EncryptionMethod::SampleAes),
+            "cenc" => Ok(EncryptionMethod::Cenc),
+            "cbcs" => Ok(EncryptionMethod::Cbcs),
+            _ => Err(format!("Invalid encryption '{}'", value)),
+        }
+    }
+
+    /// `METHOD` of the `EXT-X-KEY` tag.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncryptionMethod::Aes128 => "AES-128",
+            EncryptionMethod::SampleAes | EncryptionMethod::Cbcs => "SAMPLE-AES",
+            EncryptionMethod::Cenc => "SAMPLE-AES-CTR",
+        }
+    }
+
+    /// The Common Encryption scheme of the method, if it is one: players decrypt
+    /// those through EME, with the keys of the key IDs the segments carry.
+    pub fn scheme(&self) -> Option<&'static str> {
+        match self {
+            EncryptionMethod::Cenc => Some("cenc"),
+            EncryptionMethod::Cbcs => Some("cbcs"),
+            _ => None,
+        }
+    }
+
+    pub fn is_common(&self) -> bool {
+        self.scheme().is_some()
+    }
+}
+
+/// How the segments of a video are encrypted, kept with its status and carried by
+/// its segment jobs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Encryption {
+    pub method: EncryptionMethod,
+    /// Segments encrypted under each key, or `None` for a single key.
+    pub key_rotation: Option<usize>,
+    /// Where players get keys, as `<key_url>/<video id>/<key period>`.
+    pub key_url: String,
+}
+
+impl Encryption {
+    /// The key period of segment `index`, numbering the keys of the video.
+    pub fn key_period(&self, index: usize) -> usize {
+        match self.key_rotation {
+            Some(rotation) if rotation > 0 => index / rotation,
+            _ => 0,
+        }
+    }
+
+    /// How many keys `segments` segments are encrypted under.
+    pub fn key_count(&self, segments: usize) -> usize {
+        match segments {
+            0 => 0,
+            _ => self.key_period(segments - 1) + 1,
+        }
+    }
+
+    /// `URI` of the key of `period`.
+    pub fn key_uri(&self, video_id: &str, period: usize) -> String {
+        format!(
+            "{}/{}/{}",
+            self.key_url.trim_end_matches('/'),
+            video_id,
+            period
+        )
+    }
+
+    /// Where ClearKey players get the keys of a video encrypted with Common
+    /// Encryption, all of them in one license.
+    pub fn license_url(&self, video_id: &str) -> String {
+        format!(
+            "{}/{}/license",
+            self.key_url.trim_end_matches('/'),
+            video_id
+        )
+    }
+}
+
+/// IV of segment `index`: its media sequence number, which players use when the
+/// `EXT-X-KEY` tag gives no IV.
+pub fn segment_iv(index: usize) -> [u8; BLOCK_SIZE] {
+    (index as u128).to_be_bytes()
+}
+
+/// A 128-bit AES content key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ContentKey(pub [u8; BLOCK_SIZE]);
+
+impl ContentKey {
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        // Not echoed: it may be a key.
+        let invalid = || "Invalid content key".to_string();
+        if hex.len() != 2 * BLOCK_SIZE || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0; BLOCK_SIZE];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(key))
+    }
+}
+
+/// Keys stay out of logs.
+impl std::fmt::Debug for ContentKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ContentKey(..)")
+    }
+}
+
+/// The ID of a content key under Common Encryption, which players find in the
+/// segments and ask a license server for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyId(pub [u8; BLOCK_SIZE]);
+
+impl KeyId {
+    /// The ID of the key of `period` of video `video_id`: the video's UUID, or its
+    /// bytes folded into 16 when it isn't one, with the period mixed into the last
+    /// four bytes. The license server gets the period back with `period`.
+    pub fn new(video_id: &str, period: usize) -> Self {
+        let mut id = video_id_bytes(video_id);
+        for (byte, mixed) in id[12..].iter_mut().zip((period as u32).to_be_bytes()) {
+            *byte ^= mixed;
+        }
+        Self(id)
+    }
+
+    /// The key period of video `video_id` this is the ID of, if any.
+    pub fn period(&self, video_id: &str) -> Option<usize> {
+        let video = video_id_bytes(video_id);
+        if self.0[..12] != video[..12] {
+            return None;
+        }
+        let mut period = [0; 4];
+        for (i, byte) in period.iter_mut().enumerate() {
+            *byte = self.0[12 + i] ^ video[12 + i];
+        }
+        Some(u32::from_be_bytes(period) as usize)
+    }
+
+    /// The UUID form of DASH `cenc:default_KID`.
+    pub fn to_uuid(&self) -> String {
+        let hex: String = self.0.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
+
+fn video_id_bytes(video_id: &str) -> [u8; BLOCK_SIZE] {
+    let hex: String = video_id.chars().filter(|&c| c != '-').collect();
+    if let Ok(uuid) = ContentKey::from_hex(&hex) {
+        return uuid.0;
+    }
+    let mut bytes = [0; BLOCK_SIZE];
+    for (i, byte) in video_id.bytes().enumerate() {
+        bytes[i % BLOCK_SIZE] ^= byte;
+    }
+    bytes
+}
+
+/// The content key a segment is encrypted under, and how.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentKey {
+    pub method: EncryptionMethod,
+    /// Key period of the segment.
+    pub period: usize,
+    pub key_id: KeyId,
+    pub key: ContentKey,
+}
+
+/// AES-128 under a content key, in CBC mode or block by block.
+pub trait BlockCipher {
+    /// Encrypt `data`, a whole number of blocks, in place, each block on its own
+    /// (ECB), as counter mode needs.
+    fn encrypt_blocks(&self, data: &mut [u8]);
+
+    /// Encrypt `data`, a whole number of blocks, in place, chaining from `iv`.
+    fn encrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]);
+
+    /// Decrypt `data`, a whole number of blocks, in place, chaining from `iv`.
+    fn decrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]);
+}
+
+/// Encrypt a whole segment for `METHOD=AES-128`, padded with PKCS#7.
+pub fn encrypt_segment(cipher: &impl BlockCipher, iv: [u8; BLOCK_SIZE], data: &[u8]) -> Vec<u8> {
+    let padding = BLOCK_SIZE - data.len() % BLOCK_SIZE;
+    let mut encrypted = Vec::with_capacity(data.len() + padding);
+    encrypted.extend_from_slice(data);
+    encrypted.resize(data.len() + padding, padding as u8);
+    cipher.encrypt_cbc(iv, &mut encrypted);
+    encrypted
+}
+
+/// Decrypt a segment encrypted by `encrypt_segment`.
+pub fn decrypt_segment(
+    cipher: &impl BlockCipher,
+    iv: [u8; BLOCK_SIZE],
+    data: &[u8],
+) -> Result<Vec<u8>, String> {
+    if data.is_empty() || !data.len().is_multiple_of(BLOCK_SIZE) {
+        return Err("Encrypted segment is not a whole number of blocks".to_string());
+    }
+    let mut decrypted = data.to_vec();
+    cipher.decrypt_cbc(iv, &mut decrypted);
+
+    let padding = usize::from(decrypted[decrypted.len() - 1]);
+    let padded = (1..=BLOCK_SIZE).contains(&padding)
+        && decrypted[decrypted.len() - padding..]
+            .iter()
+            .all(|&byte| usize::from(byte) == padding);
+    if !padded {
+        return Err("Invalid padding, wrong key?".to_string());
+    }
+    decrypted.truncate(decrypted.len() - padding);
+    Ok(decrypted)
+}
+
+/// Encrypt the samples of an MPEG-TS segment for `METHOD=SAMPLE-AES`, following
+/// Apple's MPEG-2 Stream Encryption Format for HTTP Live Streaming: H.264 slices
+/// and AAC frames are partly encrypted, the IV starting over for each, and their
+/// streams are declared encrypted in the program map table. Other streams are left
+/// as they are.
+pub fn sample_aes_segment(
+    cipher: &impl BlockCipher,
+    iv: [u8; BLOCK_SIZE],
+    segment: &[u8],
+) -> Result<Vec<u8>, String> {
+    let streams = ts::streams(segment).ok_or("No program map table in segment")?;
+    let pid_of = |stream_type: u8| -> Vec<u16> {
+        streams
+            .iter()
+            .filter(|stream| stream.stream_type == stream_type)
+            .map(|stream| stream.pid)
+            .collect()
+    };
+    let video = pid_of(STREAM_TYPE_H264);
+    let audio = pid_of(STREAM_TYPE_ADTS);
+    let pids: Vec<u16> = video.iter().chain(&audio).copied().collect();
+
+    let mut audio_configs: HashMap<u16, [u8; 2]> = HashMap::new();
+    let mut encrypted = ts::rewrite_pes(segment, &pids, |pid, payload| {
+        if video.contains(&pid) {
+            *payload = encrypt_h264(cipher, iv, payload);
+        } else {
+            if let Some(config) = audio_specific_config(payload) {
+                audio_configs.entry(pid).or_insert(config);
+            }
+            encrypt_adts(cipher, iv, payload);
+        }
+    })?;
+
+    let streams: Vec<ElementaryStream> = streams
+        .iter()
+        .cloned()
+        .map(|mut stream| {
+            match stream.stream_type {
+                STREAM_TYPE_H264 => {
+                    stream.stream_type = STREAM_TYPE_H264_SAMPLE_AES;
+                    stream.descriptors.extend(private_data_indicator(b"zavc"));
+                }
+                STREAM_TYPE_ADTS => {
+                    stream.stream_type = STREAM_TYPE_ADTS_SAMPLE_AES;
+                    stream.descriptors.extend(private_data_indicator(b"aacd"));
+                    let config = audio_configs.get(&stream.pid).map_or(&[][..], |c| &c[..]);
+                    stream.descriptors.extend(audio_setup_information(config));
+                }
+                _ => {}
+            }
+            stream
+        })
+        .collect();
+    ts::rewrite_pmt(&mut encrypted, &streams)?;
+    Ok(encrypted)
+}
+
+/// Encrypt the slices of an H.264 elementary stream in Annex B format, leaving the
+/// start codes and other NAL units as they are.
+fn encrypt_h264(cipher: &impl BlockCipher, iv: [u8; BLOCK_SIZE], es: &[u8]) -> Vec<u8> {
+    let mut encrypted = Vec::with_capacity(es.len() + es.len() / 64);
+    let mut copied = 0;
+    for nal in nal_units(es) {
+        encrypted.extend_from_slice(&es[copied..nal.start]);
+        encrypted.extend(encrypt_nal(cipher, iv, &es[nal.clone()]));
+        copied = nal.end;
+    }
+    encrypted.extend_from_slice(&es[copied..]);
+    encrypted
+}
+
+/// Ranges of the NAL units of an Annex B stream, without start codes or the zero
+/// bytes before them.
+fn nal_units(es: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let starts: Vec<usize> = es
+        .windows(3)
+        .enumerate()
+        .filter(|(_, window)| *window == [0, 0, 1])
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut units = Vec::with_capacity(starts.len());
+    for (i, &start) in starts.iter().enumerate() {
+        let begin = start + 3;
+        let mut end = starts.get(i + 1).copied().unwrap_or(es.len());
+        while end > begin && es[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > begin {
+            units.push(begin..end);
+        }
+    }
+    units
+}
+
+/// A NAL unit as SAMPLE-AES encrypts it: slices (types 1 and 5) over 48 bytes have
+/// one 16 byte block in ten encrypted after a clear leader of 32 bytes, chaining
+/// from `iv`. Encryption applies to the unit without its emulation prevention
+/// bytes, which are inserted again after.
+fn encrypt_nal(cipher: &impl BlockCipher, iv: [u8; BLOCK_SIZE], nal: &[u8]) -> Vec<u8> {
+    let nal_type = nal[0] & 0x1f;
+    let mut unit = unescape(nal);
+    if !matches!(nal_type, 1 | 5) || unit.len() <= 48 {
+        return nal.to_vec();
+    }
+
+    let mut offsets = Vec::new();
+    let mut at = NAL_CLEAR_LEADER;
+    while at < unit.len() && unit.len() - at > BLOCK_SIZE {
+        offsets.push(at);
+        at += BLOCK_SIZE + NAL_CLEAR_BLOCK;
+    }
+    // The encrypted blocks chain across the clear bytes between them.
+    let mut blocks: Vec<u8> = offsets
+        .iter()
+        .flat_map(|&at| unit[at..at + BLOCK_SIZE].iter().copied())
+        .collect();
+    cipher.encrypt_cbc(iv, &mut blocks);
+    for (block, &at) in blocks.chunks_exact(BLOCK_SIZE).zip(&offsets) {
+        unit[at..at + BLOCK_SIZE].copy_from_slice(block);
+    }
+    escape(&unit)
+}
+
+/// A NAL unit without its emulation prevention bytes.
+fn unescape(nal: &[u8]) -> Vec<u8> {
+    let mut unit = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        unit.push(byte);
+    }
+    unit
+}
+
+/// A NAL unit with emulation prevention bytes, so that it holds no start code.
+fn escape(unit: &[u8]) -> Vec<u8> {
+    let mut nal = Vec::with_capacity(unit.len() + unit.len() / 64);
+    let mut zeros = 0;
+    for &byte in unit {
+        if zeros >= 2 && byte <= 3 {
+            nal.push(3);
+            zeros = 0;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        nal.push(byte);
+    }
+    // Nor may it end with a zero byte.
+    if nal.last() == Some(&0) {
+        nal.push(3);
+    }
+    nal
+}
+
+/// Header and frame lengths of the ADTS frame starting `data`.
+fn adts_frame(data: &[u8]) -> Option<(usize, usize)> {
+    if data.len() < 7 || data[0] != 0xff || data[1] & 0xf6 != 0xf0 {
+        return None;
+    }
+    let header_length = if data[1] & 0x01 != 0 { 7 } else { 9 };
+    let frame_length = (usize::from(data[3] & 0x03) << 11)
+        | (usize::from(data[4]) << 3)
+        | (usize::from(data[5]) >> 5);
+    (frame_length >= header_length).then_some((header_length, frame_length))
+}
+
+/// Encrypt the ADTS frames of an AAC elementary stream in place: every whole
+/// block after a clear leader of 16 bytes, chaining from `iv`.
+fn encrypt_adts(cipher: &impl BlockCipher, iv: [u8; BLOCK_SIZE], es: &mut [u8]) {
+    let mut at = 0;
+    while let Some((header_length, frame_length)) = adts_frame(&es[at..]) {
+        if at + frame_length > es.len() {
+            break;
+        }
+        let frame = &mut es[at + header_length..at + frame_length];
+        if frame.len() > AAC_CLEAR_LEADER {
+            let encrypted = (frame.len() - AAC_CLEAR_LEADER) / BLOCK_SIZE * BLOCK_SIZE;
+            cipher.encrypt_cbc(
+                iv,
+                &mut frame[AAC_CLEAR_LEADER..AAC_CLEAR_LEADER + encrypted],
+            );
+        }
+        at += frame_length;
+    }
+}
+
+/// The AudioSpecificConfig (ISO/IEC 14496-3) of the first ADTS frame of `es`.
+fn audio_specific_config(es: &[u8]) -> Option<[u8; 2]> {
+    adts_frame(es)?;
+    let object_type = (es[2] >> 6) + 1;
+    let frequency_index = (es[2] >> 2) & 0x0f;
+    let channels = ((es[2] & 0x01) << 2) | (es[3] >> 6);
+    Some([
+        (object_type << 3) | (frequency_index >> 1),
+        ((frequency_index & 0x01) << 7) | (channels << 3),
+    ])
+}
+
+/// A private data indicator descriptor, identifying an encrypted stream format.
+fn private_data_indicator(format: &[u8; 4]) -> Vec<u8> {
+    let mut descriptor = vec![0x0f, 4];
+    descriptor.extend(format);
+    descriptor
+}
+
+/// The registration descriptor carrying the setup of encrypted AAC: the stream's
+/// AudioSpecificConfig, which the clear ADTS headers no longer stand for.
+fn audio_setup_information(config: &[u8]) -> Vec<u8> {
+    let mut info = b"zaac".to_vec();
+    // Priming, version, then the setup data.
+    info.extend([0, 0, 1, config.len() as u8]);
+    info.extend(config);
+
+    let mut descriptor = vec![0x05, (4 + info.len()) as u8];
+    descriptor.extend(b"apad");
+    descriptor.extend(info);
+    descriptor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Not AES: every byte is XORed with the key and the previous ciphertext block,
+    /// which keeps CBC chaining visible.
+    struct XorCipher([u8; BLOCK_SIZE]);
+
+    impl BlockCipher for XorCipher {
+        fn encrypt_blocks(&self, data: &mut [u8]) {
+            for block in data.chunks_exact_mut(BLOCK_SIZE) {
+                for (byte, key) in block.iter_mut().zip(self.0) {
+                    *byte ^= key;
+                }
+            }
+        }
+
+        fn encrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+            let mut previous = iv;
+            for block in data.chunks_exact_mut(BLOCK_SIZE) {
+                for i in 0..BLOCK_SIZE {
+                    block[i] ^= previous[i] ^ self.0[i];
+                }
+                previous.copy_from_slice(block);
+            }
+        }
+
+        fn decrypt_cbc(&self, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+            let mut previous = iv;
+            for block in data.chunks_exact_mut(BLOCK_SIZE) {
+                let encrypted: [u8; BLOCK_SIZE] = block.try_into().unwrap();
+                for i in 0..BLOCK_SIZE {
+                    block[i] ^= previous[i] ^ self.0[i];
+                }
+                previous = encrypted;
+            }
+        }
+    }
+
+    const CIPHER: XorCipher = XorCipher([0x5a; BLOCK_SIZE]);
+
+    #[test]
+    fn test_key_periods() {
+        let mut encryption = Encryption {
+            method: EncryptionMethod::parse("AES-128").unwrap(),
+            key_rotation: None,
+            key_url: "/keys/".to_string(),
+        };
+        assert_eq!(encryption.key_period(41), 0);
+        assert_eq!(encryption.key_count(42), 1);
+        assert_eq!(encryption.key_uri("v1", 0), "/keys/v1/0");
+
+        encryption.key_rotation = Some(10);
+        assert_eq!(encryption.key_period(9), 0);
+        assert_eq!(encryption.key_period(10), 1);
+        assert_eq!(encryption.key_count(41), 5);
+        assert_eq!(encryption.key_count(0), 0);
+
+        assert_eq!(segment_iv(258)[14..], [1, 2]);
+        assert!(EncryptionMethod::parse("rot13").is_err());
+        assert_eq!(encryption.license_url("v1"), "/keys/v1/license");
+    }
+
+    #[test]
+    fn test_key_ids() {
+        let video_id = "0d2f6e1a-7c3b-4e8f-9a1d-5b6c7d8e9f00";
+        let key_id = KeyId::new(video_id, 0);
+        assert_eq!(key_id.to_uuid(), video_id);
+        let rotated = KeyId::new(video_id, 258);
+        assert_eq!(rotated.0[..14], key_id.0[..14]);
+        assert_eq!(rotated.period(video_id), Some(258));
+        assert_eq!(key_id.period(video_id), Some(0));
+        assert_eq!(rotated.period("1e3f6e1a-7c3b-4e8f-9a1d-5b6c7d8e9f00"), None);
+
+        // Other ids are folded.
+        let key_id = KeyId::new("upload-42", 3);
+        assert_eq!(key_id.period("upload-42"), Some(3));
+        assert_eq!(key_id.period("upload-43"), None);
+        assert!(EncryptionMethod::parse("CBCS").unwrap().is_common());
+    }
+
+    #[test]
+    fn test_content_key_hex() {
+        let key = ContentKey([0xab; BLOCK_SIZE]);
+        assert_eq!(ContentKey::from_hex(&key.to_hex()).unwrap(), key);
+        assert!(ContentKey::from_hex("abcd").is_err());
+        assert_eq!(format!("{:?}", key), "ContentKey(..)");
+    }
+
+    #[test]
+    fn test_encrypt_segment() {
+        for length in [0, 15, 16, 100] {
+            let data: Vec<u8> = (0..length as u8).collect();
+            let encrypted = encrypt_segment(&CIPHER, segment_iv(3), &data);
+            assert_eq!(encrypted.len(), (length / 16 + 1) * 16);
+            if length > 0 {
+                assert_ne!(&encrypted[..length], &data[..]);
+            }
+            assert_eq!(
+                decrypt_segment(&CIPHER, segment_iv(3), &encrypted),
+                Ok(data)
+            );
+        }
+        let encrypted = encrypt_segment(&CIPHER, segment_iv(3), b"segment");
+        assert!(decrypt_segment(&XorCipher([1; BLOCK_SIZE]), segment_iv(3), &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_nal() {
+        // A slice of 300 bytes, holding a would-be start code once encrypted.
+        let mut slice = vec![0x65];
+        slice.extend((1..300).map(|i| (i % 7) as u8 + 1));
+        let encrypted = encrypt_nal(&CIPHER, [0; BLOCK_SIZE], &slice);
+        let unit = unescape(&encrypted);
+
+        assert_eq!(unit.len(), slice.len());
+        assert_eq!(unit[..32], slice[..32]);
+        assert_ne!(unit[32..48], slice[32..48]);
+        assert_eq!(unit[48..192], slice[48..192]);
+        assert_ne!(unit[192..208], slice[192..208]);
+        // 92 bytes after that block, the rest stays clear.
+        assert_eq!(unit[208..], slice[208..]);
+        assert!(!encrypted.windows(3).any(|window| window == [0, 0, 1]));
+
+        // Parameter sets and short slices are left clear.
+        let sps = [0x67; 64];
+        assert_eq!(encrypt_nal(&CIPHER, [0; BLOCK_SIZE], &sps), sps.to_vec());
+        let short = [0x41; 48];
+        assert_eq!(
+            encrypt_nal(&CIPHER, [0; BLOCK_SIZE], &short),
+            short.to_vec()
+        );
+
+        assert_eq!(escape(&[0, 0, 1, 0, 0]), vec![0, 0, 3, 1, 0, 0, 3]);
+        assert_eq!(unescape(&[0, 0, 3, 1, 0, 0, 3]), vec![0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_encrypt_adts() {
+        // Two AAC LC frames, 44.1 kHz stereo, of 7 + 40 bytes.
+        let header = [0xff, 0xf1, 0x50, 0x80, 0x05, 0xff, 0xfc];
+        let mut es = Vec::new();
+        for _ in 0..2 {
+            es.extend(header);
+            es.extend([0x21; 40]);
+        }
+        let clear = es.clone();
+        encrypt_adts(&CIPHER, [0; BLOCK_SIZE], &mut es);
+
+        for frame in [0, 47] {
+            assert_eq!(es[frame..frame + 23], clear[frame..frame + 23]);
+            assert_ne!(es[frame + 23..frame + 39], clear[frame + 23..frame + 39]);
+            assert_eq!(es[frame + 39..frame + 47], clear[frame + 39..frame + 47]);
+        }
+        // IVs start over with every frame.
+        assert_eq!(es[..47], es[47..]);
+
+        assert_eq!(audio_specific_config(&clear), Some([0x12, 0x10]));
+    }
+}
@@ -0,0 +1,252 @@
+//! Frame timing of video: whether its frames come at a constant rate, told from
+//! the intervals between their timestamps, and the constant rate variable frame
+//! rate video is normalized to when encoded.
+
+use serde::{Deserialize, Serialize};
+
+/// How far an interval may be from the median one, as a fraction of it, and still
+/// be regular. Timestamps rounded to a coarse time base stay well within it.
+const TOLERANCE: f64 = 0.1;
+
+/// Share of irregular intervals above which video has a variable frame rate.
+const MAX_IRREGULAR: f64 = 0.05;
+
+/// Rates the typical rate of a source snaps to, when within 1% of one.
+const STANDARD_RATES: &[(u32, u32)] = &[
+    (24000, 1001),
+    (24, 1),
+    (25, 1),
+    (30000, 1001),
+    (30, 1),
+    (50, 1),
+    (60000, 1001),
+    (60, 1),
+];
+
+/// A frame rate, in frames per `denominator` seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRate {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl FrameRate {
+    /// A rate such as `30`, `29.97` or `30000/1001`. Decimal rates snap to the
+    /// standard rate they stand for.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid frame rate '{}'", value);
+        let value = value.trim();
+        let rate = match value.split_once('/') {
+            Some((numerator, denominator)) => {
+                let numerator: u32 = numerator.trim().parse().map_err(|_| invalid())?;
+                let denominator: u32 = denominator.trim().parse().map_err(|_| invalid())?;
+                Self {
+                    numerator,
+                    denominator,
+                }
+            }
+            None => Self::nearest(value.parse().map_err(|_| invalid())?),
+        };
+        match rate.numerator > 0 && rate.denominator > 0 && rate.as_f64() <= 240.0 {
+            true => Ok(rate),
+            false => Err(invalid()),
+        }
+    }
+
+    /// The standard rate within 1% of `fps`, or `fps` rounded to whole frames.
+    pub fn nearest(fps: f64) -> Self {
+        STANDARD_RATES
+            .iter()
+            .map(|&(numerator, denominator)| Self {
+                numerator,
+                denominator,
+            })
+            .filter(|rate| (rate.as_f64() - fps).abs() <= fps * 0.01)
+            .min_by(|a, b| {
+                (a.as_f64() - fps)
+                    .abs()
+                    .total_cmp(&(b.as_f64() - fps).abs())
+            })
+            .unwrap_or(Self {
+                numerator: fps.round().max(0.0) as u32,
+                denominator: 1,
+            })
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        f64::from(self.numerator) / f64::from(self.denominator)
+    }
+
+    /// Filters duplicating and dropping frames to this rate, their timestamps
+    /// brought back to `time_base` (`numerator/denominator`) for the encoder.
+    pub fn filter(&self, time_base: (i32, i32)) -> String {
+        format!(
+            "fps=fps={}/{},settb={}/{}",
+            self.numerator, self.denominator, time_base.0, time_base.1
+        )
+    }
+}
+
+impl std::fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.3} fps", self.as_f64())
+    }
+}
+
+/// What the video of an upload is normalized to when encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRateTarget {
+    /// The typical rate of the source, when its frame rate is variable.
+    Constant,
+    /// This rate, whatever the source.
+    Fixed(FrameRate),
+}
+
+impl FrameRateTarget {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "constant" | "cfr" => Ok(FrameRateTarget::Constant),
+            _ => FrameRate::parse(value).map(FrameRateTarget::Fixed),
+        }
+    }
+
+    /// The rate a source of `timing` is normalized to, if any.
+    pub fn rate_for(&self, timing: Option<&FrameTiming>) -> Option<FrameRate> {
+        match self {
+            FrameRateTarget::Constant => timing
+                .filter(|timing| timing.is_variable())
+                .map(FrameTiming::typical_rate),
+            FrameRateTarget::Fixed(rate) => Some(*rate),
+        }
+    }
+}
+
+/// How regularly the frames of a video come.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameTiming {
+    /// Intervals measured, between frames in presentation order.
+    pub intervals: usize,
+    /// The typical interval, in seconds: the mean of the regular ones.
+    pub typical_interval: f64,
+    pub min_interval: f64,
+    pub max_interval: f64,
+    /// Share of the intervals further than `TOLERANCE` from the median, which are
+    /// irregular.
+    pub irregular: f64,
+}
+
+impl FrameTiming {
+    /// Timing of frames presented at `times`, in seconds and in any order, or
+    /// `None` with fewer than two intervals between them.
+    pub fn from_times(times: &mut [f64]) -> Option<Self> {
+        times.sort_by(f64::total_cmp);
+        let mut intervals: Vec<f64> = times
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .filter(|interval| *interval > 0.0)
+            .collect();
+        if intervals.len() < 2 {
+            return None;
+        }
+        intervals.sort_by(f64::total_cmp);
+        let median = intervals[intervals.len() / 2];
+        let regular: Vec<f64> = intervals
+            .iter()
+            .copied()
+            .filter(|interval| (interval - median).abs() <= median * TOLERANCE)
+            .collect();
+        Some(Self {
+            intervals: intervals.len(),
+            typical_interval: regular.iter().sum::<f64>() / regular.len() as f64,
+            min_interval: intervals[0],
+            max_interval: intervals[intervals.len() - 1],
+            irregular: 1.0 - regular.len() as f64 / intervals.len() as f64,
+        })
+    }
+
+    pub fn is_variable(&self) -> bool {
+        self.irregular > MAX_IRREGULAR
+    }
+
+    /// The rate of the typical interval.
+    pub fn typical_rate(&self) -> FrameRate {
+        FrameRate::nearest(1.0 / self.typical_interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_timing() {
+        // 29.97 fps in a millisecond time base: 33 and 34 ms intervals.
+        let mut constant: Vec<f64> = (0..300).map(|i| (i * 1001 / 30) as f64 / 1000.0).collect();
+        constant.reverse();
+        let timing = FrameTiming::from_times(&mut constant).unwrap();
+        assert_eq!(timing.intervals, 299);
+        assert!(!timing.is_variable());
+        assert_eq!(
+            timing.typical_rate(),
+            FrameRate {
+                numerator: 30000,
+                denominator: 1001
+            }
+        );
+
+        // A screen capture: 60 fps while things move, 10 fps when they don't.
+        let mut time = 0.0;
+        let mut capture = Vec::new();
+        for i in 0..200 {
+            capture.push(time);
+            time += if i % 40 < 30 { 1.0 / 60.0 } else { 0.1 };
+        }
+        let timing = FrameTiming::from_times(&mut capture).unwrap();
+        assert!(timing.is_variable());
+        assert_eq!(timing.typical_rate().to_string(), "60.000 fps");
+        assert!((timing.max_interval - 0.1).abs() < 1e-9);
+
+        assert_eq!(FrameTiming::from_times(&mut [0.0, 0.04]), None);
+    }
+
+    #[test]
+    fn test_frame_rate_target() {
+        assert_eq!(
+            FrameRate::parse("29.97"),
+            Ok(FrameRate {
+                numerator: 30000,
+                denominator: 1001
+            })
+        );
+        assert_eq!(FrameRate::parse("12").unwrap().as_f64(), 12.0);
+        assert!(FrameRate::parse("0").is_err());
+        assert!(FrameRate::parse("30/0").is_err());
+        assert!(FrameRate::parse("fast").is_err());
+
+        let variable = FrameTiming {
+            intervals: 100,
+            typical_interval: 0.04,
+            min_interval: 0.01,
+            max_interval: 0.5,
+            irregular: 0.3,
+        };
+        let constant = FrameTiming {
+            irregular: 0.0,
+            ..variable
+        };
+        let cfr = FrameRateTarget::parse("CFR").unwrap();
+        assert_eq!(
+            cfr.rate_for(Some(&variable)).unwrap().to_string(),
+            "25.000 fps"
+        );
+        assert_eq!(cfr.rate_for(Some(&constant)), None);
+        assert_eq!(cfr.rate_for(None), None);
+        let fixed = FrameRateTarget::parse("24000/1001").unwrap();
+        assert_eq!(fixed.rate_for(None).unwrap().to_string(), "23.976 fps");
+
+        assert_eq!(
+            FrameRate::parse("30").unwrap().filter((1, 90000)),
+            "fps=fps=30/1,settb=1/90000"
+        );
+    }
+}
@@ -1,10 +1,60 @@
+use super::color::VideoRange;
+use super::encryption::{EncryptionMethod, BLOCK_SIZE};
 use std::path::PathBuf;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+/// A sub-range of a resource, as written by `EXT-X-BYTERANGE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub length: u64,
+    pub offset: u64,
+}
+
+impl std::fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.length, self.offset)
+    }
+}
+
+/// An `EXT-X-KEY` tag: how the segments after it are encrypted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyTag {
+    pub method: EncryptionMethod,
+    /// The key, or for Common Encryption the ClearKey license with every key.
+    pub uri: String,
+    /// Without one, the IV is the media sequence number of each segment.
+    pub iv: Option<[u8; BLOCK_SIZE]>,
+}
+
+impl std::fmt::Display for KeyTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#EXT-X-KEY:METHOD={},URI=\"{}\"",
+            self.method.as_str(),
+            self.uri
+        )?;
+        // Common Encryption is decrypted through EME, which needs to know the key
+        // system the license is for.
+        if self.method.is_common() {
+            f.write_str(",KEYFORMAT=\"org.w3.clearkey\",KEYFORMATVERSIONS=\"1\"")?;
+        }
+        if let Some(iv) = &self.iv {
+            f.write_str(",IV=0x")?;
+            for byte in iv {
+                write!(f, "{:02X}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct MediaSegment {
     pub duration: f64,
     pub uri: String,
+    /// Part of `uri` holding the segment, when segments share a file.
+    pub byte_range: Option<ByteRange>,
 }
 
 pub struct MediaPlaylist {
@@ -15,7 +65,18 @@ pub struct MediaPlaylist {
     pub end_list: bool,
     pub playlist_type: Option<String>,
     pub independent_segments: bool,
+    /// Each segment is the byte range of a single keyframe, for trick play.
+    pub iframes_only: bool,
     pub init_segment: Option<String>,
+    /// Part of `init_segment` holding the header, when it shares a file with the
+    /// segments.
+    pub init_byte_range: Option<ByteRange>,
+    /// Key tags, each written before the segment at its index.
+    pub keys: Vec<(usize, KeyTag)>,
+    /// Indices of the segments preceded by `EXT-X-DISCONTINUITY`.
+    pub discontinuities: Vec<usize>,
+    /// Headers of the segments from an index on, replacing `init_segment` there.
+    pub maps: Vec<(usize, String, Option<ByteRange>)>,
 }
 
 impl MediaPlaylist {
@@ -28,12 +89,74 @@ impl MediaPlaylist {
             end_list: true,
             playlist_type: None,
             independent_segments: false,
+            iframes_only: false,
             init_segment: None,
+            init_byte_range: None,
+            keys: Vec::new(),
+            discontinuities: Vec::new(),
+            maps: Vec::new(),
+        }
+    }
+
+    /// A playlist of MPEG-TS segments for legacy players: version 3, without
+    /// `EXT-X-MAP` since transport streams carry their own headers.
+    pub fn transport_stream(target_duration: u64) -> Self {
+        Self {
+            version: 3,
+            ..Self::new(target_duration)
         }
     }
 
     pub fn add_segment(&mut self, duration: f64, uri: String) {
-        self.segments.push(MediaSegment { duration, uri });
+        self.segments.push(MediaSegment {
+            duration,
+            uri,
+            byte_range: None,
+        });
+    }
+
+    /// Add a segment stored at `byte_range` of `uri`.
+    pub fn add_segment_range(&mut self, duration: f64, uri: String, byte_range: ByteRange) {
+        self.segments.push(MediaSegment {
+            duration,
+            uri,
+            byte_range: Some(byte_range),
+        });
+    }
+
+    /// Encrypt the segments added from now on as `key` says. The init segment stays
+    /// in the clear. Encrypted samples need version 5.
+    pub fn add_key(&mut self, key: KeyTag) {
+        if key.method != EncryptionMethod::Aes128 {
+            self.version = self.version.max(5);
+        }
+        self.keys.push((self.segments.len(), key));
+    }
+
+    /// Mark the segment added next as encoded unlike the previous one, e.g. with
+    /// other timestamps or encoder settings.
+    pub fn add_discontinuity(&mut self) {
+        self.discontinuities.push(self.segments.len());
+    }
+
+    /// Read the segments added from now on with the header at `byte_range` of
+    /// `uri`, or all of it.
+    pub fn add_map(&mut self, uri: String, byte_range: Option<ByteRange>) {
+        self.maps.push((self.segments.len(), uri, byte_range));
+    }
+
+    /// An `EXT-X-I-FRAMES-ONLY` playlist of the same segments as `self`, to be
+    /// filled with keyframe ranges. Byte ranges need version 4.
+    pub fn iframes(&self) -> Self {
+        Self {
+            version: self.version.max(4),
+            playlist_type: self.playlist_type.clone(),
+            independent_segments: self.independent_segments,
+            iframes_only: true,
+            init_segment: self.init_segment.clone(),
+            init_byte_range: self.init_byte_range,
+            ..Self::new(0)
+        }
     }
 
     pub async fn write_to(&self, path: &PathBuf) -> Result<(), std::io::Error> {
@@ -56,14 +179,32 @@ impl MediaPlaylist {
             file.write_all(b"#EXT-X-INDEPENDENT-SEGMENTS\n").await?;
         }
 
+        if self.iframes_only {
+            file.write_all(b"#EXT-X-I-FRAMES-ONLY\n").await?;
+        }
+
         if let Some(init) = &self.init_segment {
-            file.write_all(format!("#EXT-X-MAP:URI=\"{}\"\n", init).as_bytes())
+            file.write_all(map_tag(init, self.init_byte_range.as_ref()).as_bytes())
                 .await?;
         }
 
-        for segment in &self.segments {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if self.discontinuities.contains(&i) {
+                file.write_all(b"#EXT-X-DISCONTINUITY\n").await?;
+            }
+            for (_, uri, range) in self.maps.iter().filter(|(index, _, _)| *index == i) {
+                file.write_all(map_tag(uri, range.as_ref()).as_bytes())
+                    .await?;
+            }
+            for (_, key) in self.keys.iter().filter(|(index, _)| *index == i) {
+                file.write_all(format!("{}\n", key).as_bytes()).await?;
+            }
             file.write_all(format!("#EXTINF:{:.6},\n", segment.duration).as_bytes())
                 .await?;
+            if let Some(range) = &segment.byte_range {
+                file.write_all(format!("#EXT-X-BYTERANGE:{}\n", range).as_bytes())
+                    .await?;
+            }
             file.write_all(segment.uri.as_bytes()).await?;
             file.write_all(b"\n").await?;
         }
@@ -76,9 +217,332 @@ impl MediaPlaylist {
     }
 }
 
+fn map_tag(uri: &str, byte_range: Option<&ByteRange>) -> String {
+    match byte_range {
+        Some(range) => format!("#EXT-X-MAP:URI=\"{}\",BYTERANGE=\"{}\"\n", uri, range),
+        None => format!("#EXT-X-MAP:URI=\"{}\"\n", uri),
+    }
+}
+
+/// One `EXT-X-STREAM-INF` entry of a master playlist.
+pub struct VariantStream {
+    /// Peak segment bitrate, in bits per second.
+    pub bandwidth: u64,
+    /// Average bitrate over the whole stream, in bits per second.
+    pub average_bandwidth: Option<u64>,
+    /// RFC 6381 codec strings of every track, e.g. `avc1.64001f,mp4a.40.2`.
+    pub codecs: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+    /// Dynamic range of the video; players assume SDR without one.
+    pub video_range: Option<VideoRange>,
+    /// GROUP-ID of the alternate audio renditions to play along.
+    pub audio: Option<String>,
+    /// GROUP-ID of the subtitle renditions to show along.
+    pub subtitles: Option<String>,
+    pub uri: String,
+}
+
+/// One `EXT-X-I-FRAME-STREAM-INF` entry of a master playlist: the I-frame playlist
+/// of a variant, for fast forward and scrubbing.
+pub struct IFrameStream {
+    /// Peak keyframe bitrate, in bits per second.
+    pub bandwidth: u64,
+    pub average_bandwidth: Option<u64>,
+    /// RFC 6381 codec strings of the video track only.
+    pub codecs: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub video_range: Option<VideoRange>,
+    pub uri: String,
+}
+
+/// `TYPE` of an `EXT-X-MEDIA` entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    Audio,
+    Subtitles,
+}
+
+impl MediaType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Audio => "AUDIO",
+            MediaType::Subtitles => "SUBTITLES",
+        }
+    }
+}
+
+/// One `EXT-X-MEDIA` entry of a master playlist: an alternate rendition.
+#[derive(Clone)]
+pub struct AlternateMedia {
+    pub media_type: MediaType,
+    pub group_id: String,
+    /// RFC 5646 language tag.
+    pub language: Option<String>,
+    pub name: String,
+    pub default: bool,
+    pub autoselect: bool,
+    /// Shown whatever the user's subtitle setting, for subtitles.
+    pub forced: bool,
+    /// Channel count, for audio.
+    pub channels: Option<u16>,
+    pub uri: String,
+}
+
+impl AlternateMedia {
+    /// The `EXT-X-MEDIA` line of this rendition.
+    fn tag(&self) -> String {
+        let mut attributes = format!(
+            "TYPE={},GROUP-ID=\"{}\"",
+            self.media_type.as_str(),
+            self.group_id
+        );
+        if let Some(language) = &self.language {
+            attributes.push_str(&format!(",LANGUAGE=\"{}\"", language));
+        }
+        attributes.push_str(&format!(",NAME=\"{}\"", self.name.replace('"', "'")));
+        attributes.push_str(if self.default {
+            ",DEFAULT=YES"
+        } else {
+            ",DEFAULT=NO"
+        });
+        attributes.push_str(if self.autoselect {
+            ",AUTOSELECT=YES"
+        } else {
+            ",AUTOSELECT=NO"
+        });
+        if self.forced {
+            attributes.push_str(",FORCED=YES");
+        }
+        if let Some(channels) = self.channels {
+            attributes.push_str(&format!(",CHANNELS=\"{}\"", channels));
+        }
+        attributes.push_str(&format!(",URI=\"{}\"", self.uri));
+        format!("#EXT-X-MEDIA:{}", attributes)
+    }
+}
+
+/// Make `media` the whole `group_id` group of `media_type` of an already written
+/// master playlist: its entries replace those of the group, and every variant
+/// refers to the group while it isn't empty.
+pub fn set_media_group(
+    master: &str,
+    media_type: MediaType,
+    group_id: &str,
+    media: &[AlternateMedia],
+) -> String {
+    let group_attribute = match media_type {
+        MediaType::Audio => "AUDIO",
+        MediaType::Subtitles => "SUBTITLES",
+    };
+    let entry = format!(
+        "#EXT-X-MEDIA:TYPE={},GROUP-ID=\"{}\",",
+        media_type.as_str(),
+        group_id
+    );
+    let reference = format!(",{}=\"{}\"", group_attribute, group_id);
+
+    let mut lines: Vec<String> = master
+        .lines()
+        .filter(|line| !line.starts_with(&entry))
+        .map(|line| {
+            if !line.starts_with("#EXT-X-STREAM-INF:") {
+                line.to_string()
+            } else if media.is_empty() {
+                line.replace(&reference, "")
+            } else if !line.contains(&format!(",{}=", group_attribute)) {
+                format!("{}{}", line, reference)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+
+    // Media entries go after the others, ahead of the variants.
+    let at = lines
+        .iter()
+        .position(|line| line.starts_with("#EXT-X-STREAM-INF:"))
+        .unwrap_or(lines.len());
+    lines.splice(at..at, media.iter().map(AlternateMedia::tag));
+
+    let mut master = lines.join("\n");
+    master.push('\n');
+    master
+}
+
+/// One `EXT-X-SESSION-DATA` entry of a master playlist: data about the
+/// presentation for the player, identified by a reverse DNS `data_id`.
+pub struct SessionData {
+    pub data_id: String,
+    pub value: String,
+    /// RFC 5646 language tag of the value.
+    pub language: Option<String>,
+}
+
+/// Peak and average bitrates of a stream, in bits per second, from the size in
+/// bytes and the duration in seconds of each of its segments.
+pub fn measured_bandwidth(sizes: &[u64], durations: &[f64]) -> (u64, u64) {
+    let mut peak: f64 = 0.0;
+    let mut total_bits = 0.0;
+    let mut total_duration = 0.0;
+
+    for (&size, &duration) in sizes.iter().zip(durations) {
+        let bits = size as f64 * 8.0;
+        if duration > 0.0 {
+            peak = peak.max(bits / duration);
+        }
+        total_bits += bits;
+        total_duration += duration;
+    }
+
+    let average = if total_duration > 0.0 {
+        total_bits / total_duration
+    } else {
+        0.0
+    };
+    (peak.ceil() as u64, average.ceil() as u64)
+}
+
+pub struct MasterPlaylist {
+    pub version: u8,
+    pub independent_segments: bool,
+    pub session_data: Vec<SessionData>,
+    pub media: Vec<AlternateMedia>,
+    pub variants: Vec<VariantStream>,
+    pub iframe_streams: Vec<IFrameStream>,
+}
+
+impl Default for MasterPlaylist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MasterPlaylist {
+    pub fn new() -> Self {
+        Self {
+            version: 7,
+            independent_segments: false,
+            session_data: Vec::new(),
+            media: Vec::new(),
+            variants: Vec::new(),
+            iframe_streams: Vec::new(),
+        }
+    }
+
+    /// A master playlist of MPEG-TS renditions for legacy players: version 3, as
+    /// long as neither it nor its media playlists use later features. The version
+    /// of a master playlist has to cover those of its media playlists.
+    pub fn transport_stream() -> Self {
+        Self {
+            version: 3,
+            ..Self::new()
+        }
+    }
+
+    pub fn add_session_data(&mut self, data: SessionData) {
+        self.session_data.push(data);
+    }
+
+    pub fn add_media(&mut self, media: AlternateMedia) {
+        self.media.push(media);
+    }
+
+    pub fn add_variant(&mut self, variant: VariantStream) {
+        self.variants.push(variant);
+    }
+
+    /// I-frame streams need version 4.
+    pub fn add_iframe_stream(&mut self, stream: IFrameStream) {
+        self.version = self.version.max(4);
+        self.iframe_streams.push(stream);
+    }
+
+    pub async fn write_to(&self, path: &PathBuf) -> Result<(), std::io::Error> {
+        let mut file = File::create(path).await?;
+
+        file.write_all(b"#EXTM3U\n").await?;
+        file.write_all(format!("#EXT-X-VERSION:{}\n", self.version).as_bytes())
+            .await?;
+
+        if self.independent_segments {
+            file.write_all(b"#EXT-X-INDEPENDENT-SEGMENTS\n").await?;
+        }
+
+        for data in &self.session_data {
+            let mut attributes = format!(
+                "DATA-ID=\"{}\",VALUE=\"{}\"",
+                data.data_id,
+                data.value.replace('"', "'")
+            );
+            if let Some(language) = &data.language {
+                attributes.push_str(&format!(",LANGUAGE=\"{}\"", language));
+            }
+            file.write_all(format!("#EXT-X-SESSION-DATA:{}\n", attributes).as_bytes())
+                .await?;
+        }
+
+        for media in &self.media {
+            file.write_all(format!("{}\n", media.tag()).as_bytes())
+                .await?;
+        }
+
+        for variant in &self.variants {
+            let mut attributes = format!("BANDWIDTH={}", variant.bandwidth);
+            if let Some(average) = variant.average_bandwidth {
+                attributes.push_str(&format!(",AVERAGE-BANDWIDTH={}", average));
+            }
+            if let Some(codecs) = &variant.codecs {
+                attributes.push_str(&format!(",CODECS=\"{}\"", codecs));
+            }
+            if let Some((width, height)) = variant.resolution {
+                attributes.push_str(&format!(",RESOLUTION={}x{}", width, height));
+            }
+            if let Some(frame_rate) = variant.frame_rate {
+                attributes.push_str(&format!(",FRAME-RATE={:.3}", frame_rate));
+            }
+            if let Some(video_range) = variant.video_range {
+                attributes.push_str(&format!(",VIDEO-RANGE={}", video_range.as_str()));
+            }
+            if let Some(audio) = &variant.audio {
+                attributes.push_str(&format!(",AUDIO=\"{}\"", audio));
+            }
+            if let Some(subtitles) = &variant.subtitles {
+                attributes.push_str(&format!(",SUBTITLES=\"{}\"", subtitles));
+            }
+            file.write_all(format!("#EXT-X-STREAM-INF:{}\n", attributes).as_bytes())
+                .await?;
+            file.write_all(variant.uri.as_bytes()).await?;
+            file.write_all(b"\n").await?;
+        }
+
+        for stream in &self.iframe_streams {
+            let mut attributes = format!("BANDWIDTH={}", stream.bandwidth);
+            if let Some(average) = stream.average_bandwidth {
+                attributes.push_str(&format!(",AVERAGE-BANDWIDTH={}", average));
+            }
+            if let Some(codecs) = &stream.codecs {
+                attributes.push_str(&format!(",CODECS=\"{}\"", codecs));
+            }
+            if let Some((width, height)) = stream.resolution {
+                attributes.push_str(&format!(",RESOLUTION={}x{}", width, height));
+            }
+            if let Some(video_range) = stream.video_range {
+                attributes.push_str(&format!(",VIDEO-RANGE={}", video_range.as_str()));
+            }
+            attributes.push_str(&format!(",URI=\"{}\"", stream.uri));
+            file.write_all(format!("#EXT-X-I-FRAME-STREAM-INF:{}\n", attributes).as_bytes())
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::encryption::segment_iv;
     use tokio::fs;
 
     #[tokio::test]
@@ -105,4 +569,421 @@ mod tests {
 
         let _ = fs::remove_file(path).await;
     }
+
+    #[tokio::test]
+    async fn test_byte_range_playlist() {
+        let mut playlist = MediaPlaylist::new(6);
+        playlist.init_segment = Some("media.mp4".to_string());
+        playlist.init_byte_range = Some(ByteRange {
+            length: 720,
+            offset: 0,
+        });
+        playlist.add_segment_range(
+            6.0,
+            "media.mp4".to_string(),
+            ByteRange {
+                length: 150_000,
+                offset: 720,
+            },
+        );
+        playlist.add_segment_range(
+            4.0,
+            "media.mp4".to_string(),
+            ByteRange {
+                length: 90_000,
+                offset: 150_720,
+            },
+        );
+
+        let path = std::env::temp_dir().join("test_byte_range_playlist.m3u8");
+        playlist.write_to(&path).await.unwrap();
+
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains("#EXT-X-MAP:URI=\"media.mp4\",BYTERANGE=\"720@0\"\n"));
+        assert!(content.contains(
+            "#EXTINF:6.000000,\n#EXT-X-BYTERANGE:150000@720\nmedia.mp4\n\
+             #EXTINF:4.000000,\n#EXT-X-BYTERANGE:90000@150720\nmedia.mp4\n"
+        ));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_discontinuities() {
+        let mut playlist = MediaPlaylist::new(6);
+        playlist.init_segment = Some("init.mp4".to_string());
+        playlist.add_discontinuity();
+        playlist.add_map("init_0.mp4".to_string(), None);
+        playlist.add_segment(1.5, "segment_0.mp4".to_string());
+        playlist.add_discontinuity();
+        playlist.add_map("init.mp4".to_string(), None);
+        playlist.add_segment(6.0, "segment_1.mp4".to_string());
+
+        let path = std::env::temp_dir().join("test_discontinuities.m3u8");
+        playlist.write_to(&path).await.unwrap();
+
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains(
+            "#EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"init_0.mp4\"\n\
+             #EXTINF:1.500000,\nsegment_0.mp4\n\
+             #EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXTINF:6.000000,\nsegment_1.mp4\n"
+        ));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_transport_stream_playlist() {
+        let mut playlist = MediaPlaylist::transport_stream(6);
+        playlist.add_segment(6.0, "segment_0.ts".to_string());
+
+        let path = std::env::temp_dir().join("test_transport_stream_playlist.m3u8");
+        playlist.write_to(&path).await.unwrap();
+
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains("#EXT-X-VERSION:3\n"));
+        assert!(!content.contains("#EXT-X-MAP"));
+        assert!(content.contains("#EXTINF:6.000000,\nsegment_0.ts\n"));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_master_playlist() {
+        let mut master = MasterPlaylist::new();
+        master.independent_segments = true;
+        master.add_variant(VariantStream {
+            bandwidth: 2_928_000,
+            average_bandwidth: Some(2_500_000),
+            codecs: Some("avc1.64001f,mp4a.40.2".to_string()),
+            resolution: Some((1280, 720)),
+            frame_rate: Some(29.97),
+            video_range: None,
+            audio: None,
+            subtitles: None,
+            uri: "720p/playlist.m3u8".to_string(),
+        });
+        master.add_variant(VariantStream {
+            bandwidth: 8_000_000,
+            average_bandwidth: None,
+            codecs: Some("hvc1.2.4.L150.B0".to_string()),
+            resolution: Some((3840, 2160)),
+            frame_rate: None,
+            video_range: Some(VideoRange::Pq),
+            audio: None,
+            subtitles: None,
+            uri: "2160p-hevc/playlist.m3u8".to_string(),
+        });
+
+        let path = std::env::temp_dir().join("test_master.m3u8");
+        master.write_to(&path).await.unwrap();
+
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.starts_with("#EXTM3U\n"));
+        assert!(content.contains("#EXT-X-INDEPENDENT-SEGMENTS"));
+        assert!(content.contains(
+            "#EXT-X-STREAM-INF:BANDWIDTH=2928000,AVERAGE-BANDWIDTH=2500000,\
+             CODECS=\"avc1.64001f,mp4a.40.2\",RESOLUTION=1280x720,FRAME-RATE=29.970\n\
+             720p/playlist.m3u8\n"
+        ));
+        assert!(content.contains(
+            "#EXT-X-STREAM-INF:BANDWIDTH=8000000,CODECS=\"hvc1.2.4.L150.B0\",\
+             RESOLUTION=3840x2160,VIDEO-RANGE=PQ\n"
+        ));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_iframe_playlists() {
+        let mut playlist = MediaPlaylist::transport_stream(0);
+        playlist.playlist_type = Some("VOD".to_string());
+        let mut iframes = playlist.iframes();
+        iframes.add_segment_range(
+            6.0,
+            "segment_0.ts".to_string(),
+            ByteRange {
+                length: 37_600,
+                offset: 0,
+            },
+        );
+        iframes.target_duration = 6;
+
+        let path = std::env::temp_dir().join("test_iframes.m3u8");
+        iframes.write_to(&path).await.unwrap();
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains("#EXT-X-VERSION:4\n"));
+        assert!(content.contains("#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-I-FRAMES-ONLY\n"));
+        assert!(content.contains("#EXTINF:6.000000,\n#EXT-X-BYTERANGE:37600@0\nsegment_0.ts\n"));
+
+        let mut master = MasterPlaylist::transport_stream();
+        master.add_variant(VariantStream {
+            bandwidth: 800_000,
+            average_bandwidth: None,
+            codecs: None,
+            resolution: None,
+            frame_rate: None,
+            video_range: None,
+            audio: None,
+            subtitles: None,
+            uri: "360p/playlist.m3u8".to_string(),
+        });
+        master.add_iframe_stream(IFrameStream {
+            bandwidth: 50_133,
+            average_bandwidth: Some(41_000),
+            codecs: Some("avc1.64001e".to_string()),
+            resolution: Some((640, 360)),
+            video_range: None,
+            uri: "360p/iframes.m3u8".to_string(),
+        });
+
+        master.write_to(&path).await.unwrap();
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains("#EXT-X-VERSION:4\n"));
+        assert!(content.ends_with(
+            "360p/playlist.m3u8\n\
+             #EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=50133,AVERAGE-BANDWIDTH=41000,\
+             CODECS=\"avc1.64001e\",RESOLUTION=640x360,URI=\"360p/iframes.m3u8\"\n"
+        ));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_playlist() {
+        let mut playlist = MediaPlaylist::transport_stream(6);
+        for i in 0..3 {
+            if i != 1 {
+                playlist.add_key(KeyTag {
+                    method: EncryptionMethod::SampleAes,
+                    uri: format!("/keys/2f1c/{}", i / 2),
+                    iv: None,
+                });
+            }
+            playlist.add_segment(6.0, format!("segment_{}.ts", i));
+        }
+
+        let path = std::env::temp_dir().join("test_encrypted.m3u8");
+        playlist.write_to(&path).await.unwrap();
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains("#EXT-X-VERSION:5\n"));
+        assert!(content.contains(
+            "#EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"/keys/2f1c/0\"\n\
+             #EXTINF:6.000000,\nsegment_0.ts\n\
+             #EXTINF:6.000000,\nsegment_1.ts\n\
+             #EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"/keys/2f1c/1\"\n\
+             #EXTINF:6.000000,\nsegment_2.ts\n"
+        ));
+
+        // The init segment stays in the clear, before the first key.
+        let mut playlist = MediaPlaylist::new(6);
+        playlist.init_segment = Some("init.mp4".to_string());
+        playlist.add_key(KeyTag {
+            method: EncryptionMethod::Aes128,
+            uri: "/keys/2f1c/0".to_string(),
+            iv: Some(segment_iv(258)),
+        });
+        playlist.add_segment(6.0, "segment_0.mp4".to_string());
+        playlist.write_to(&path).await.unwrap();
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains("#EXT-X-VERSION:7\n"));
+        assert!(content.contains(
+            "#EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"/keys/2f1c/0\",\
+             IV=0x00000000000000000000000000000102\n"
+        ));
+
+        // Common Encryption has its key IDs and IVs in the fragments, and its keys
+        // in a ClearKey license.
+        let mut playlist = MediaPlaylist::new(6);
+        playlist.init_segment = Some("init.mp4".to_string());
+        playlist.add_key(KeyTag {
+            method: EncryptionMethod::Cbcs,
+            uri: "/keys/2f1c/license".to_string(),
+            iv: None,
+        });
+        playlist.add_segment(6.0, "segment_0.mp4".to_string());
+        playlist.write_to(&path).await.unwrap();
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains(
+            "#EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"/keys/2f1c/license\",\
+             KEYFORMAT=\"org.w3.clearkey\",KEYFORMATVERSIONS=\"1\"\n"
+        ));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_alternate_audio() {
+        let mut master = MasterPlaylist::new();
+        for (language, name, default) in [("en", "English", true), ("fr", "Français", false)] {
+            master.add_media(AlternateMedia {
+                media_type: MediaType::Audio,
+                group_id: "audio".to_string(),
+                language: Some(language.to_string()),
+                name: name.to_string(),
+                default,
+                autoselect: true,
+                forced: false,
+                channels: Some(2),
+                uri: format!("audio_{}/playlist.m3u8", language),
+            });
+            master.add_session_data(SessionData {
+                data_id: format!("com.example.loudness.audio_{}", language),
+                value: "I=-23.0,LRA=7.0,TP=-1.0".to_string(),
+                language: Some(language.to_string()),
+            });
+        }
+        master.add_variant(VariantStream {
+            bandwidth: 800_000,
+            average_bandwidth: None,
+            codecs: None,
+            resolution: None,
+            frame_rate: None,
+            video_range: None,
+            audio: Some("audio".to_string()),
+            subtitles: None,
+            uri: "360p/playlist.m3u8".to_string(),
+        });
+
+        let path = std::env::temp_dir().join("test_master_audio.m3u8");
+        master.write_to(&path).await.unwrap();
+
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains(
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",LANGUAGE=\"en\",NAME=\"English\",\
+             DEFAULT=YES,AUTOSELECT=YES,CHANNELS=\"2\",URI=\"audio_en/playlist.m3u8\"\n"
+        ));
+        assert!(content.contains("LANGUAGE=\"fr\",NAME=\"Français\",DEFAULT=NO"));
+        assert!(content.contains(
+            "#EXT-X-SESSION-DATA:DATA-ID=\"com.example.loudness.audio_fr\",\
+             VALUE=\"I=-23.0,LRA=7.0,TP=-1.0\",LANGUAGE=\"fr\"\n"
+        ));
+        assert!(content.contains("#EXT-X-STREAM-INF:BANDWIDTH=800000,AUDIO=\"audio\"\n"));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[tokio::test]
+    async fn test_subtitles() {
+        let mut master = MasterPlaylist::new();
+        for (language, name, forced) in [("en", "English", false), ("en", "English (forced)", true)]
+        {
+            master.add_media(AlternateMedia {
+                media_type: MediaType::Subtitles,
+                group_id: "subs".to_string(),
+                language: Some(language.to_string()),
+                name: name.to_string(),
+                default: false,
+                autoselect: true,
+                forced,
+                channels: None,
+                uri: "subs_0/playlist.m3u8".to_string(),
+            });
+        }
+        master.add_variant(VariantStream {
+            bandwidth: 800_000,
+            average_bandwidth: None,
+            codecs: None,
+            resolution: None,
+            frame_rate: None,
+            video_range: None,
+            audio: None,
+            subtitles: Some("subs".to_string()),
+            uri: "360p/playlist.m3u8".to_string(),
+        });
+
+        let path = std::env::temp_dir().join("test_master_subtitles.m3u8");
+        master.write_to(&path).await.unwrap();
+
+        let content = fs::read_to_string(&path).await.unwrap();
+
+        assert!(content.contains(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",LANGUAGE=\"en\",NAME=\"English\",\
+             DEFAULT=NO,AUTOSELECT=YES,URI=\"subs_0/playlist.m3u8\"\n"
+        ));
+        assert!(content.contains("NAME=\"English (forced)\",DEFAULT=NO,AUTOSELECT=YES,FORCED=YES,"));
+        assert!(content.contains("#EXT-X-STREAM-INF:BANDWIDTH=800000,SUBTITLES=\"subs\"\n"));
+
+        let _ = fs::remove_file(path).await;
+    }
+
+    #[test]
+    fn test_set_media_group() {
+        let master = "#EXTM3U\n#EXT-X-VERSION:7\n\
+                      #EXT-X-STREAM-INF:BANDWIDTH=800000,SUBTITLES=\"subs\"\n360p/playlist.m3u8\n\
+                      #EXT-X-STREAM-INF:BANDWIDTH=2000000\n720p/playlist.m3u8\n";
+        let captions = AlternateMedia {
+            media_type: MediaType::Subtitles,
+            group_id: "subs".to_string(),
+            language: Some("fr".to_string()),
+            name: "fr".to_string(),
+            default: false,
+            autoselect: true,
+            forced: false,
+            channels: None,
+            uri: "captions_fr/playlist.m3u8".to_string(),
+        };
+
+        let updated = set_media_group(
+            master,
+            MediaType::Subtitles,
+            "subs",
+            std::slice::from_ref(&captions),
+        );
+        assert_eq!(
+            updated,
+            "#EXTM3U\n#EXT-X-VERSION:7\n\
+             #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",LANGUAGE=\"fr\",NAME=\"fr\",\
+             DEFAULT=NO,AUTOSELECT=YES,URI=\"captions_fr/playlist.m3u8\"\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=800000,SUBTITLES=\"subs\"\n360p/playlist.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=2000000,SUBTITLES=\"subs\"\n720p/playlist.m3u8\n"
+        );
+
+        // The group is rebuilt whole, whatever the master listed before.
+        let renamed = AlternateMedia {
+            name: "Français".to_string(),
+            ..captions.clone()
+        };
+        let english = AlternateMedia {
+            language: Some("en".to_string()),
+            name: "English".to_string(),
+            uri: "captions_en/playlist.m3u8".to_string(),
+            ..captions
+        };
+        let updated = set_media_group(&updated, MediaType::Subtitles, "subs", &[renamed, english]);
+        assert_eq!(updated.matches("#EXT-X-MEDIA:").count(), 2);
+        assert!(updated.contains("NAME=\"Français\""));
+        assert_eq!(updated.matches("SUBTITLES=\"subs\"").count(), 2);
+        assert_eq!(
+            set_media_group(&updated, MediaType::Subtitles, "subs", &[]),
+            "#EXTM3U\n#EXT-X-VERSION:7\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=800000\n360p/playlist.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=2000000\n720p/playlist.m3u8\n"
+        );
+    }
+
+    #[test]
+    fn test_measured_bandwidth() {
+        // 1 Mbit over 2s, then 3 Mbit over 2s.
+        let (peak, average) = measured_bandwidth(&[125_000, 375_000], &[2.0, 2.0]);
+        assert_eq!(peak, 1_500_000);
+        assert_eq!(average, 1_000_000);
+
+        assert_eq!(measured_bandwidth(&[], &[]), (0, 0));
+    }
 }
@@ -0,0 +1,130 @@
+//! Interlaced video: the order of its fields, told from its codec parameters and an
+//! `idet` pass over a sample of its frames, and the filter that deinterlaces it.
+
+use serde::{Deserialize, Serialize};
+
+/// Fewest frames `idet` has to tell apart for its counts to outweigh the codec
+/// parameters.
+const MIN_DETECTED_FRAMES: u64 = 20;
+
+/// How the lines of a video are scanned, in display order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldOrder {
+    #[default]
+    Progressive,
+    /// Interlaced, the top field displayed first.
+    TopFirst,
+    /// Interlaced, the bottom field displayed first.
+    BottomFirst,
+}
+
+impl FieldOrder {
+    pub fn is_interlaced(&self) -> bool {
+        *self != FieldOrder::Progressive
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldOrder::Progressive => "progressive",
+            FieldOrder::TopFirst => "top field first",
+            FieldOrder::BottomFirst => "bottom field first",
+        }
+    }
+
+    /// Field order from the codec parameters, refined by the frames `idet` counted
+    /// in a sample of the video. Codecs often flag progressive video as interlaced
+    /// and leave the order of interlaced video unknown, so enough counted frames
+    /// decide, most of them interlaced making the video interlaced.
+    pub fn detect(codec: FieldOrder, counts: Option<&FieldCounts>) -> FieldOrder {
+        let Some(counts) = counts.filter(|c| c.detected() >= MIN_DETECTED_FRAMES) else {
+            return codec;
+        };
+        if (counts.top_first + counts.bottom_first) * 2 <= counts.detected() {
+            FieldOrder::Progressive
+        } else if counts.top_first >= counts.bottom_first {
+            FieldOrder::TopFirst
+        } else {
+            FieldOrder::BottomFirst
+        }
+    }
+
+    /// Filter deinterlacing the video into a frame per frame, `bwdif` when the
+    /// FFmpeg build has it and `yadif` otherwise, or `None` for progressive video.
+    pub fn deinterlace_filter(&self, bwdif: bool) -> Option<String> {
+        let parity = match self {
+            FieldOrder::Progressive => return None,
+            FieldOrder::TopFirst => "tff",
+            FieldOrder::BottomFirst => "bff",
+        };
+        let filter = if bwdif { "bwdif" } else { "yadif" };
+        // Every frame, whatever its flags say: they are what got the order wrong.
+        Some(format!(
+            "{}=mode=send_frame:parity={}:deint=all",
+            filter, parity
+        ))
+    }
+}
+
+/// Frames of a sample `idet` classified, looking at several frames at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FieldCounts {
+    pub top_first: u64,
+    pub bottom_first: u64,
+    pub progressive: u64,
+    pub undetermined: u64,
+}
+
+impl FieldCounts {
+    /// Frames told interlaced or progressive.
+    pub fn detected(&self) -> u64 {
+        self.top_first + self.bottom_first + self.progressive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let counts = |top_first, bottom_first, progressive| FieldCounts {
+            top_first,
+            bottom_first,
+            progressive,
+            undetermined: 12,
+        };
+        let progressive = FieldOrder::Progressive;
+        assert_eq!(
+            FieldOrder::detect(progressive, Some(&counts(150, 3, 40))),
+            FieldOrder::TopFirst
+        );
+        assert_eq!(
+            FieldOrder::detect(progressive, Some(&counts(2, 90, 10))),
+            FieldOrder::BottomFirst
+        );
+        // Flagged interlaced, scanned progressive.
+        assert_eq!(
+            FieldOrder::detect(FieldOrder::TopFirst, Some(&counts(20, 0, 180))),
+            FieldOrder::Progressive
+        );
+        // Too few frames told apart to go against the codec.
+        assert_eq!(
+            FieldOrder::detect(FieldOrder::BottomFirst, Some(&counts(0, 0, 10))),
+            FieldOrder::BottomFirst
+        );
+        assert_eq!(FieldOrder::detect(progressive, None), progressive);
+    }
+
+    #[test]
+    fn test_deinterlace_filter() {
+        assert_eq!(FieldOrder::Progressive.deinterlace_filter(true), None);
+        assert_eq!(
+            FieldOrder::TopFirst.deinterlace_filter(true).as_deref(),
+            Some("bwdif=mode=send_frame:parity=tff:deint=all")
+        );
+        assert_eq!(
+            FieldOrder::BottomFirst.deinterlace_filter(false).as_deref(),
+            Some("yadif=mode=send_frame:parity=bff:deint=all")
+        );
+    }
+}
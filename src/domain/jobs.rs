@@ -1,3 +1,9 @@
+use super::encryption::Encryption;
+use super::loudness::Loudness;
+use super::options::{Container, Packaging};
+use super::preflight::{Preflight, ProcessingPlan};
+use super::renditions::{AudioDecision, AudioTrack, Rendition, SubtitleTrack};
+use super::segment_plan::{PlannedSegment, SegmentPlan};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -11,11 +17,27 @@ pub struct ThumbnailStripJob {
     pub width: u32,
 }
 
+/// The picture attached to an audio-only source, published in place of a
+/// thumbnail strip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverArtJob {
+    pub id: String,
+    pub video_id: String,
+    pub source_path: PathBuf,
+    pub output_path: PathBuf,
+    /// Index of the attached picture stream in the source.
+    pub stream_index: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Job {
     Segment(SegmentJob),
+    AudioSegment(AudioSegmentJob),
+    Subtitles(SubtitleJob),
+    Captions(CaptionJob),
     ThumbnailStrip(ThumbnailStripJob),
+    CoverArt(CoverArtJob),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,10 +45,67 @@ pub struct SegmentJob {
     pub id: String,
     pub video_id: String,
     pub segment_index: usize,
+    pub rendition: Rendition,
+    pub source_path: PathBuf,
+    pub output_path: PathBuf,
+    pub start_time: f64,
+    pub duration: f64,
+    /// Whether the segment is part of a source GOP, cut at a trim point.
+    #[serde(default)]
+    pub partial: bool,
+    #[serde(default)]
+    pub container: Container,
+    /// Plan of the video the segment belongs to.
+    #[serde(default)]
+    pub plan: ProcessingPlan,
+    /// How the segment is encrypted, in the clear without.
+    #[serde(default)]
+    pub encryption: Option<Encryption>,
+}
+
+/// A segment of an alternate audio rendition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioSegmentJob {
+    pub id: String,
+    pub video_id: String,
+    pub segment_index: usize,
+    pub track: AudioTrack,
     pub source_path: PathBuf,
     pub output_path: PathBuf,
     pub start_time: f64,
     pub duration: f64,
+    #[serde(default)]
+    pub container: Container,
+    /// Plan of the video the segment belongs to.
+    #[serde(default)]
+    pub plan: ProcessingPlan,
+    /// How the segment is encrypted, in the clear without.
+    #[serde(default)]
+    pub encryption: Option<Encryption>,
+}
+
+/// Every segment of a subtitle rendition, extracted from the source at once since
+/// cues are few and may start long before the segment showing them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitleJob {
+    pub id: String,
+    pub video_id: String,
+    pub track: SubtitleTrack,
+    pub source_path: PathBuf,
+    /// Directory the `segment_<i>.vtt` files are uploaded to.
+    pub output_dir: PathBuf,
+    pub segments: Vec<PlannedSegment>,
+}
+
+/// A sidecar caption file for a video already processed, or being processed,
+/// segmented into a subtitle rendition of its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptionJob {
+    pub id: String,
+    pub video_id: String,
+    pub track: SubtitleTrack,
+    /// The uploaded `.srt` or `.vtt` file.
+    pub caption_path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,5 +114,25 @@ pub struct VideoStatus {
     pub source_path: PathBuf,
     pub hls_dir: PathBuf,
     pub total_segments: usize,
-    pub segment_durations: Vec<f64>,
+    pub segment_plan: SegmentPlan,
+    pub renditions: Vec<Rendition>,
+    pub audio_tracks: Vec<AudioTrack>,
+    #[serde(default)]
+    pub subtitle_tracks: Vec<SubtitleTrack>,
+    #[serde(default)]
+    pub packaging: Packaging,
+    #[serde(default)]
+    pub container: Container,
+    /// Loudness of the audio muxed into the renditions, when measured.
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+    /// How each source audio stream is carried, and why when re-encoded.
+    #[serde(default)]
+    pub audio_decisions: Vec<AudioDecision>,
+    /// What the preflight made of the source.
+    #[serde(default)]
+    pub preflight: Preflight,
+    /// How the segments are encrypted, in the clear without.
+    #[serde(default)]
+    pub encryption: Option<Encryption>,
 }
@@ -0,0 +1,98 @@
+//! Tokens letting players get the content keys of an encrypted video.
+//!
+//! Playlists are public and cached, so the key URIs they carry can't hold a token:
+//! anyone scraping them would get it too. Whoever decides who may watch a video,
+//! the application embedding the player, issues a short-lived token for it with
+//! [`issue`] and the same `KEY_SECRET` as the key endpoint, and has the player send
+//! it with its key and license requests, as `Authorization: Bearer <token>` or by
+//! appending `?token=<token>` to the key URIs (see [`with_token`]).
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// A token letting its holder get the keys of `video_id` until `expires`, in
+/// seconds since the Unix epoch: `<expires>.<HMAC-SHA256 of "<video id>:<expires>">`.
+pub fn issue(secret: &str, video_id: &str, expires: u64) -> String {
+    let signature = signature(secret, video_id, expires).finalize().into_bytes();
+    format!("{}.{}", expires, hex(&signature))
+}
+
+/// Whether `token` is a token for `video_id` that hasn't expired at `now`.
+pub fn verify(secret: &str, video_id: &str, token: &str, now: u64) -> bool {
+    let Some((expires, signature)) = token.split_once('.') else {
+        return false;
+    };
+    let (Ok(expires), Some(signature)) = (expires.parse::<u64>(), unhex(signature)) else {
+        return false;
+    };
+    // Compared in constant time, so that the signature can't be guessed byte by byte.
+    let matches = self::signature(secret, video_id, expires)
+        .verify_slice(&signature)
+        .is_ok();
+    matches && now <= expires
+}
+
+/// `uri`, a key URI of a playlist, with `token` in its query, for players that
+/// can't set headers on key requests.
+pub fn with_token(uri: &str, token: &str) -> String {
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", uri, separator, token)
+}
+
+fn signature(secret: &str, video_id: &str, expires: u64) -> Hmac<Sha256> {
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}:{}", video_id, expires).as_bytes());
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token() {
+        let token = issue("secret", "2f1c", 1_000);
+        assert!(verify("secret", "2f1c", &token, 999));
+        assert!(verify("secret", "2f1c", &token, 1_000));
+        assert!(!verify("secret", "2f1c", &token, 1_001));
+        assert!(!verify("secret", "3a2d", &token, 999));
+        assert!(!verify("other", "2f1c", &token, 999));
+        // The expiry is signed.
+        let extended = token.replacen("1000.", "2000.", 1);
+        assert!(!verify("secret", "2f1c", &extended, 1_001));
+        assert!(!verify("secret", "2f1c", "1000", 999));
+        assert!(!verify("secret", "2f1c", "1000.zz", 999));
+
+        // RFC 4231, test case 2.
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&mac.finalize().into_bytes()),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        assert_eq!(
+            with_token("/keys/2f1c/0", &token),
+            format!("/keys/2f1c/0?token={}", token)
+        );
+        assert_eq!(
+            with_token("/keys/2f1c/0?v=1", "t"),
+            "/keys/2f1c/0?v=1&token=t"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! EBU R128 loudness of audio, and the gain bringing it to a target.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Integrated loudness below which audio is treated as silence and left alone.
+const SILENCE: f64 = -70.0;
+
+/// Loudness of an audio stream, measured over its whole duration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness, in LUFS.
+    pub integrated: f64,
+    /// Loudness range, in LU.
+    pub range: f64,
+    /// True peak, in dBTP.
+    pub true_peak: f64,
+}
+
+impl fmt::Display for Loudness {
+    /// `I=<LUFS>,LRA=<LU>,TP=<dBTP>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "I={:.1},LRA={:.1},TP={:.1}",
+            self.integrated, self.range, self.true_peak
+        )
+    }
+}
+
+/// Loudness encoded audio is normalized to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoudnessTarget {
+    /// Integrated loudness, in LUFS.
+    pub integrated: f64,
+    /// Ceiling of the true peak, in dBTP.
+    pub true_peak: f64,
+}
+
+impl LoudnessTarget {
+    /// Gain, in dB, bringing audio of `measured` loudness to the target. The gain is
+    /// the same for the whole stream, so that segments encoded apart match, and is
+    /// lowered as needed to keep the true peak under the ceiling rather than
+    /// limiting. Silent audio gets none.
+    pub fn gain(&self, measured: &Loudness) -> f64 {
+        if !measured.integrated.is_finite() || measured.integrated <= SILENCE {
+            return 0.0;
+        }
+        let mut gain = self.integrated - measured.integrated;
+        if measured.true_peak.is_finite() {
+            gain = gain.min(self.true_peak - measured.true_peak);
+        }
+        (gain * 10.0).round() / 10.0
+    }
+}
+
+/// Parse a loudness target such as `"-16"` or `"-16:-1.5"` (integrated LUFS and
+/// true peak dBTP). The true peak ceiling defaults to -1 dBTP.
+pub fn parse_loudness_target(spec: &str) -> Result<LoudnessTarget, String> {
+    let values = spec
+        .split(':')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid loudness target '{}'", spec))?;
+
+    let target = match values[..] {
+        [integrated] => LoudnessTarget {
+            integrated,
+            true_peak: -1.0,
+        },
+        [integrated, true_peak] => LoudnessTarget {
+            integrated,
+            true_peak,
+        },
+        _ => return Err(format!("Invalid loudness target '{}'", spec)),
+    };
+
+    if !(target.integrated < 0.0 && target.integrated > SILENCE && target.true_peak <= 0.0) {
+        return Err(format!(
+            "Loudness target '{}' needs {} < integrated < 0 and true peak <= 0",
+            spec, SILENCE
+        ));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_loudness_target() {
+        let mobile = parse_loudness_target("-16").unwrap();
+        assert_eq!((mobile.integrated, mobile.true_peak), (-16.0, -1.0));
+        let broadcast = parse_loudness_target("-23:-2").unwrap();
+        assert_eq!((broadcast.integrated, broadcast.true_peak), (-23.0, -2.0));
+
+        assert!(parse_loudness_target("loud").is_err());
+        assert!(parse_loudness_target("-16:-1:7").is_err());
+        assert!(parse_loudness_target("16").is_err());
+        assert!(parse_loudness_target("-16:1").is_err());
+    }
+
+    #[test]
+    fn test_gain() {
+        let target = parse_loudness_target("-16").unwrap();
+        let quiet = Loudness {
+            integrated: -31.04,
+            range: 6.0,
+            true_peak: -20.0,
+        };
+        assert_eq!(target.gain(&quiet), 15.0);
+        assert_eq!(quiet.to_string(), "I=-31.0,LRA=6.0,TP=-20.0");
+
+        // Raising this one to -16 LUFS would clip: the peak ceiling wins.
+        let peaky = Loudness {
+            true_peak: -4.5,
+            ..quiet
+        };
+        assert_eq!(target.gain(&peaky), 3.5);
+
+        let loud = Loudness {
+            integrated: -9.0,
+            range: 3.0,
+            true_peak: 0.5,
+        };
+        assert_eq!(target.gain(&loud), -7.0);
+
+        let silence = Loudness {
+            integrated: f64::NEG_INFINITY,
+            range: 0.0,
+            true_peak: f64::NEG_INFINITY,
+        };
+        assert_eq!(target.gain(&silence), 0.0);
+    }
+}
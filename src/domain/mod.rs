@@ -8,9 +8,56 @@
 ))]
 pub mod av;
 
-// HLS generation (only local + worker, not orchestrator)
-#[cfg(any(feature = "local", feature = "aws_worker"))]
+// HLS playlists (always available, pure)
 pub mod hls;
 
+// DASH manifest of the same fragments (always available, pure like hls)
+pub mod dash;
+
 // Job definitions (always available)
 pub mod jobs;
+
+// Segment planning (always available, stored with the video status)
+pub mod segment_plan;
+
+// Per-video options (always available, set from upload metadata)
+pub mod options;
+
+// Source compatibility and processing plan (always available, carried by jobs)
+pub mod preflight;
+
+// Rendition ladder (always available, carried by jobs)
+pub mod renditions;
+
+// Frame timing and constant frame rates (always available, carried by renditions)
+pub mod frame_rate;
+
+// Field order of interlaced video (always available, carried by renditions)
+pub mod interlace;
+
+// Logos and text burnt into encoded video (always available, carried by renditions)
+pub mod watermark;
+
+// Colour description and HDR metadata (always available, carried by renditions)
+pub mod color;
+
+// Loudness measurement and targets (always available, stored with the video status)
+pub mod loudness;
+
+// ISO-BMFF box reading (always available, pure)
+pub mod mp4;
+
+// MPEG-TS packet rewriting (always available, pure)
+pub mod ts;
+
+// Segment encryption and content keys (always available, carried by jobs)
+pub mod encryption;
+
+// Common Encryption of fMP4 fragments and ClearKey licenses (always available, pure)
+pub mod cenc;
+
+// Tokens of the content key endpoint (always available, issued by applications)
+pub mod key_token;
+
+// WebVTT subtitle segments (always available, pure)
+pub mod webvtt;
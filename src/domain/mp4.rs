@@ -0,0 +1,1241 @@
+//! Minimal ISO-BMFF (MP4) box reading, used to inspect the fragments we produce
+//! and to fix up their timing and colour description.
+
+use super::color::ColorInfo;
+use super::segment_plan::Keyframe;
+
+/// A box: its four-character type and its payload (everything after the header).
+#[derive(Debug, Clone, Copy)]
+pub struct Mp4Box<'a> {
+    pub kind: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+/// Iterator over consecutive boxes in a buffer. Stops at the first malformed box.
+pub struct Boxes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Mp4Box<'a>;
+
+    fn next(&mut self) -> Option<Mp4Box<'a>> {
+        if self.data.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(self.data[0..4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = self.data[4..8].try_into().unwrap();
+
+        let (header, size) = match size {
+            0 => (8, self.data.len() as u64),
+            1 if self.data.len() >= 16 => {
+                (16, u64::from_be_bytes(self.data[8..16].try_into().unwrap()))
+            }
+            _ => (8, size),
+        };
+        if size < header as u64 || size > self.data.len() as u64 {
+            self.data = &[];
+            return None;
+        }
+
+        let payload = &self.data[header..size as usize];
+        self.data = &self.data[size as usize..];
+        Some(Mp4Box { kind, payload })
+    }
+}
+
+pub fn boxes(data: &[u8]) -> Boxes<'_> {
+    Boxes { data }
+}
+
+/// First child of `data` of type `kind`.
+pub fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|b| &b.kind == kind).map(|b| b.payload)
+}
+
+/// Follow `path` down the box hierarchy, taking the first match at each level.
+pub fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, kind| child(data, kind))
+}
+
+/// RFC 6381 codec string of every track described by an init segment (ftyp + moov),
+/// in track order. Tracks with an unknown sample entry are skipped.
+pub fn codecs(init: &[u8]) -> Vec<String> {
+    let Some(moov) = child(init, b"moov") else {
+        return Vec::new();
+    };
+
+    boxes(moov)
+        .filter(|b| &b.kind == b"trak")
+        .filter_map(|trak| find(trak.payload, &[b"mdia", b"minf", b"stbl", b"stsd"]))
+        // stsd is a full box followed by an entry count.
+        .filter_map(|stsd| boxes(stsd.get(8..)?).next())
+        .filter_map(|entry| sample_entry_codec(&entry))
+        .collect()
+}
+
+/// Codec string of one `stsd` sample entry.
+fn sample_entry_codec(entry: &Mp4Box<'_>) -> Option<String> {
+    let kind = std::str::from_utf8(&entry.kind).ok()?;
+    match &entry.kind {
+        b"avc1" | b"avc3" => {
+            let avcc = child(visual_children(entry.payload)?, b"avcC")?;
+            let [_, profile, compatibility, level] = avcc.get(0..4)?.try_into().ok()?;
+            Some(format!(
+                "{}.{:02x}{:02x}{:02x}",
+                kind, profile, compatibility, level
+            ))
+        }
+        b"hvc1" | b"hev1" => {
+            let hvcc = child(visual_children(entry.payload)?, b"hvcC")?;
+            hevc_codec(kind, hvcc)
+        }
+        b"av01" => {
+            let av1c = child(visual_children(entry.payload)?, b"av1C")?;
+            av1_codec(av1c)
+        }
+        b"vp09" => {
+            let vpcc = child(visual_children(entry.payload)?, b"vpcC")?;
+            // Full box, then profile, level and bit depth in the high nibble.
+            let [profile, level, depth] = vpcc.get(4..7)?.try_into().ok()?;
+            Some(format!(
+                "vp09.{:02}.{:02}.{:02}",
+                profile,
+                level,
+                depth >> 4
+            ))
+        }
+        b"mp4a" => {
+            let esds = child(audio_children(entry.payload)?, b"esds")?;
+            aac_codec(esds)
+        }
+        b"ac-3" | b"ec-3" | b"fLaC" => Some(kind.to_string()),
+        b"Opus" => Some("opus".to_string()),
+        _ => None,
+    }
+}
+
+/// Child boxes of a VisualSampleEntry, which follow 78 bytes of fixed fields.
+pub(super) fn visual_children(payload: &[u8]) -> Option<&[u8]> {
+    payload.get(78..)
+}
+
+/// Child boxes of an AudioSampleEntry. QuickTime-style version 1 and 2 entries
+/// carry extra fields before them.
+fn audio_children(payload: &[u8]) -> Option<&[u8]> {
+    let version = u16::from_be_bytes(payload.get(8..10)?.try_into().ok()?);
+    let offset = match version {
+        1 => 28 + 16,
+        2 => 28 + 36,
+        _ => 28,
+    };
+    payload.get(offset..)
+}
+
+/// `hvc1.<space><profile>.<compatibility>.<tier><level>[.<constraints>]`, as
+/// specified by ISO/IEC 14496-15 Annex E.
+fn hevc_codec(kind: &str, hvcc: &[u8]) -> Option<String> {
+    let header = hvcc.get(1)?;
+    let profile_space = ["", "A", "B", "C"][(header >> 6) as usize];
+    let tier = if header & 0x20 != 0 { 'H' } else { 'L' };
+    let profile = header & 0x1f;
+
+    // The compatibility flags are written with their bits in reverse order.
+    let compatibility = u32::from_be_bytes(hvcc.get(2..6)?.try_into().ok()?).reverse_bits();
+    let constraints = hvcc.get(6..12)?;
+    let level = hvcc.get(12)?;
+
+    let mut codec = format!(
+        "{}.{}{}.{:X}.{}{}",
+        kind, profile_space, profile, compatibility, tier, level
+    );
+    let used = constraints
+        .iter()
+        .rposition(|&b| b != 0)
+        .map_or(0, |i| i + 1);
+    for byte in &constraints[..used] {
+        codec.push_str(&format!(".{:X}", byte));
+    }
+    Some(codec)
+}
+
+/// `av01.<profile>.<level><tier>.<bit depth>`, as specified by the AV1 ISO-BMFF
+/// binding; the optional trailing fields are left at their defaults.
+fn av1_codec(av1c: &[u8]) -> Option<String> {
+    let [_, profile_level, flags] = av1c.get(0..3)?.try_into().ok()?;
+    let tier = if flags & 0x80 != 0 { 'H' } else { 'M' };
+    let depth = match (flags & 0x40 != 0, flags & 0x20 != 0) {
+        (true, true) => 12,
+        (true, false) => 10,
+        _ => 8,
+    };
+    Some(format!(
+        "av01.{}.{:02}{}.{:02}",
+        profile_level >> 5,
+        profile_level & 0x1f,
+        tier,
+        depth
+    ))
+}
+
+/// `mp4a.40.<audio object type>` for MPEG-4 audio, `mp4a.<object type>` otherwise.
+fn aac_codec(esds: &[u8]) -> Option<String> {
+    // esds is a full box holding an ES_Descriptor (tag 3).
+    let (tag, mut es) = descriptor(esds.get(4..)?)?;
+    if tag != 0x03 {
+        return None;
+    }
+    let flags = *es.get(2)?;
+    let mut offset = 3;
+    if flags & 0x80 != 0 {
+        offset += 2;
+    }
+    if flags & 0x40 != 0 {
+        offset += 1 + *es.get(offset)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        offset += 2;
+    }
+    es = es.get(offset..)?;
+
+    // DecoderConfigDescriptor (tag 4), then DecoderSpecificInfo (tag 5).
+    let (tag, config) = descriptor(es)?;
+    if tag != 0x04 {
+        return None;
+    }
+    let object_type = *config.first()?;
+    if object_type != 0x40 {
+        return Some(format!("mp4a.{:02X}", object_type));
+    }
+
+    let (tag, specific) = descriptor(config.get(13..)?)?;
+    if tag != 0x05 {
+        return Some("mp4a.40".to_string());
+    }
+    let first = *specific.first()?;
+    let audio_object_type = match first >> 3 {
+        31 => 32 + (((first & 0x07) << 3) | (specific.get(1)? >> 5)),
+        aot => aot,
+    };
+    Some(format!("mp4a.40.{}", audio_object_type))
+}
+
+/// Split an MPEG-4 descriptor into its tag and body. The size is a base-128 number
+/// of up to four bytes, each but the last having its high bit set.
+fn descriptor(data: &[u8]) -> Option<(u8, &[u8])> {
+    let tag = *data.first()?;
+    let mut size = 0usize;
+    let mut offset = 1;
+    loop {
+        let byte = *data.get(offset)?;
+        offset += 1;
+        size = (size << 7) | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 || offset == 5 {
+            break;
+        }
+    }
+    Some((tag, data.get(offset..offset + size)?))
+}
+
+/// Add the colour description of `color` to the video sample entries of `init`:
+/// `colr`, plus `mdcv` and `clli` for the HDR metadata it has. Boxes the muxer
+/// already wrote are kept. Returns `None` when nothing was added.
+pub fn add_color_boxes(init: &[u8], color: &ColorInfo) -> Option<Vec<u8>> {
+    if !color.is_specified() {
+        return None;
+    }
+    let mut added = false;
+    let edited = edit_path(
+        init,
+        &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+        &mut |stsd| {
+            // stsd is a full box followed by an entry count.
+            let mut entries = stsd.get(..8)?.to_vec();
+            for entry in boxes(stsd.get(8..)?) {
+                let mut payload = entry.payload.to_vec();
+                if let Some(children) = is_visual(&entry.kind)
+                    .then(|| visual_children(entry.payload))
+                    .flatten()
+                {
+                    for (kind, body) in color_boxes(color) {
+                        if child(children, &kind).is_none() {
+                            payload.extend(mp4_box(&kind, &body));
+                            added = true;
+                        }
+                    }
+                }
+                entries.extend(mp4_box(&entry.kind, &payload));
+            }
+            Some(entries)
+        },
+    );
+    added.then_some(edited)
+}
+
+pub(super) fn is_visual(kind: &[u8; 4]) -> bool {
+    matches!(
+        kind,
+        b"avc1" | b"avc3" | b"hvc1" | b"hev1" | b"av01" | b"vp09"
+    )
+}
+
+/// Payloads of the `colr` (nclx), `mdcv` and `clli` boxes describing `color`.
+fn color_boxes(color: &ColorInfo) -> Vec<([u8; 4], Vec<u8>)> {
+    let mut colr = b"nclx".to_vec();
+    for code in [color.primaries, color.transfer, color.matrix] {
+        colr.extend(u16::from(code).to_be_bytes());
+    }
+    colr.push(if color.full_range { 0x80 } else { 0 });
+    let mut boxes = vec![(*b"colr", colr)];
+
+    if let Some(display) = &color.mastering_display {
+        let mut mdcv = Vec::new();
+        for (x, y) in display.primaries.iter().chain([&display.white_point]) {
+            mdcv.extend(x.to_be_bytes());
+            mdcv.extend(y.to_be_bytes());
+        }
+        mdcv.extend(display.max_luminance.to_be_bytes());
+        mdcv.extend(display.min_luminance.to_be_bytes());
+        boxes.push((*b"mdcv", mdcv));
+    }
+    if let Some(light) = &color.content_light {
+        let mut clli = light.max_cll.to_be_bytes().to_vec();
+        clli.extend(light.max_fall.to_be_bytes());
+        boxes.push((*b"clli", clli));
+    }
+    boxes
+}
+
+/// Clockwise rotation of a display `matrix`, laid out like the `tkhd` one and
+/// FFmpeg's display matrix side data, rounded to a quarter turn. Mirroring and
+/// scaling are ignored.
+pub fn matrix_rotation(matrix: &[i32; 9]) -> u16 {
+    if matrix[0] == 0 && matrix[1] == 0 {
+        return 0;
+    }
+    let degrees = f64::from(matrix[1])
+        .atan2(f64::from(matrix[0]))
+        .to_degrees();
+    ((degrees / 90.0).round() as i32).rem_euclid(4) as u16 * 90
+}
+
+/// Display matrix rotating `rotation` degrees clockwise, in 16.16 and 2.30 fixed
+/// point.
+fn rotation_matrix(rotation: u16) -> [i32; 9] {
+    let (cos, sin) = match rotation % 360 {
+        90 => (0, 1),
+        180 => (-1, 0),
+        270 => (0, -1),
+        _ => (1, 0),
+    };
+    [
+        cos << 16,
+        sin << 16,
+        0,
+        -sin << 16,
+        cos << 16,
+        0,
+        0,
+        0,
+        1 << 30,
+    ]
+}
+
+/// Offset of the matrix in a `tkhd` payload, after its times, track ID, layer,
+/// alternate group and volume.
+fn tkhd_matrix_offset(tkhd: &[u8]) -> Option<usize> {
+    match tkhd.first()? {
+        0 => Some(40),
+        1 => Some(52),
+        _ => None,
+    }
+}
+
+/// Clockwise rotation players apply to the first video track of `init`.
+pub fn rotation(init: &[u8]) -> u16 {
+    let Some(moov) = child(init, b"moov") else {
+        return 0;
+    };
+    boxes(moov)
+        .filter(|b| &b.kind == b"trak" && is_video_track(b.payload))
+        .find_map(|trak| {
+            let tkhd = child(trak.payload, b"tkhd")?;
+            let at = tkhd_matrix_offset(tkhd)?;
+            let mut matrix = [0i32; 9];
+            for (i, value) in matrix.iter_mut().enumerate() {
+                *value = read_u32(tkhd, at + i * 4)? as i32;
+            }
+            Some(matrix_rotation(&matrix))
+        })
+        .unwrap_or(0)
+}
+
+/// Make players rotate the video tracks of `init` by `rotation` degrees clockwise,
+/// through the `tkhd` matrix. Returns `None` when they already do.
+pub fn set_rotation(init: &[u8], rotation: u16) -> Option<Vec<u8>> {
+    let matrix: Vec<u8> = rotation_matrix(rotation)
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect();
+    let mut changed = false;
+    let edited = edit_path(init, &[b"moov", b"trak"], &mut |trak| {
+        if !is_video_track(trak) {
+            return None;
+        }
+        Some(edit_path(trak, &[b"tkhd"], &mut |tkhd| {
+            let at = tkhd_matrix_offset(tkhd)?;
+            if tkhd.get(at..at + matrix.len())? == matrix.as_slice() {
+                return None;
+            }
+            let mut tkhd = tkhd.to_vec();
+            tkhd[at..at + matrix.len()].copy_from_slice(&matrix);
+            changed = true;
+            Some(tkhd)
+        }))
+    });
+    changed.then_some(edited)
+}
+
+fn is_video_track(trak: &[u8]) -> bool {
+    find(trak, &[b"mdia", b"hdlr"]).and_then(|hdlr| hdlr.get(8..12)) == Some(b"vide")
+}
+
+/// Rebuild the boxes of `data`, replacing the payload of every box at `path` with
+/// what `edit` makes of it. The boxes along the way get their sizes updated;
+/// payloads `edit` gives up on are kept.
+pub(super) fn edit_path(
+    data: &[u8],
+    path: &[&[u8; 4]],
+    edit: &mut dyn FnMut(&[u8]) -> Option<Vec<u8>>,
+) -> Vec<u8> {
+    let mut rebuilt = Vec::with_capacity(data.len());
+    for b in boxes(data) {
+        let payload = match path {
+            [kind] if &b.kind == *kind => edit(b.payload).unwrap_or_else(|| b.payload.to_vec()),
+            [kind, rest @ ..] if &b.kind == *kind => edit_path(b.payload, rest, edit),
+            _ => b.payload.to_vec(),
+        };
+        rebuilt.extend(mp4_box(&b.kind, &payload));
+    }
+    rebuilt
+}
+
+/// A box of type `kind` around `payload`.
+pub(super) fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(payload);
+    data
+}
+
+/// A track declared by an init segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Track {
+    pub(super) id: u32,
+    pub(super) timescale: u32,
+    /// Sample duration of fragments that don't give one, from `trex`.
+    pub(super) default_duration: u32,
+    /// Sample size of fragments that don't give one, from `trex`.
+    pub(super) default_size: u32,
+    pub(super) video: bool,
+}
+
+/// Tracks of an init segment, in track order.
+pub(super) fn tracks(init: &[u8]) -> Vec<Track> {
+    let Some(moov) = child(init, b"moov") else {
+        return Vec::new();
+    };
+    let defaults: Vec<(u32, u32, u32)> = child(moov, b"mvex")
+        .map(|mvex| {
+            boxes(mvex)
+                .filter(|b| &b.kind == b"trex")
+                .filter_map(|trex| {
+                    Some((
+                        read_u32(trex.payload, 4)?,
+                        read_u32(trex.payload, 12)?,
+                        read_u32(trex.payload, 16).unwrap_or(0),
+                    ))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    boxes(moov)
+        .filter(|b| &b.kind == b"trak")
+        .filter_map(|trak| {
+            let tkhd = child(trak.payload, b"tkhd")?;
+            let id = read_u32(tkhd, if *tkhd.first()? == 1 { 20 } else { 12 })?;
+            let mdhd = find(trak.payload, &[b"mdia", b"mdhd"])?;
+            let timescale = read_u32(mdhd, if *mdhd.first()? == 1 { 20 } else { 12 })?;
+            let (default_duration, default_size) = defaults
+                .iter()
+                .find(|&&(track, _, _)| track == id)
+                .map_or((0, 0), |&(_, duration, size)| (duration, size));
+            Some(Track {
+                id,
+                timescale,
+                default_duration,
+                default_size,
+                video: is_video_track(trak.payload),
+            })
+        })
+        .collect()
+}
+
+/// A `moof`, with the offsets of the fields we rewrite within the buffer it was
+/// read from.
+#[derive(Debug)]
+struct MovieFragment {
+    sequence: u32,
+    sequence_at: usize,
+    tracks: Vec<TrackFragment>,
+}
+
+/// A `traf`: the samples of one track in a movie fragment.
+#[derive(Debug)]
+struct TrackFragment {
+    track_id: u32,
+    /// `tfdt` baseMediaDecodeTime, in track timescale units.
+    decode_time: u64,
+    /// Offset and version of the `tfdt` payload.
+    decode_time_at: (usize, u8),
+    /// Total duration of the samples, in track timescale units.
+    duration: u64,
+    /// Offset of the duration of the first sample, when the `trun` lists them.
+    first_duration_at: Option<usize>,
+}
+
+/// Movie fragments of `data`, a sequence of top-level boxes. Fragments of tracks
+/// missing from `tracks` or without a `tfdt` are errors.
+fn movie_fragments(data: &[u8], tracks: &[Track]) -> Result<Vec<MovieFragment>, String> {
+    let offset_of = |payload: &[u8]| payload.as_ptr() as usize - data.as_ptr() as usize;
+
+    let mut fragments = Vec::new();
+    for moof in boxes(data).filter(|b| &b.kind == b"moof") {
+        let mfhd = child(moof.payload, b"mfhd").ok_or("moof without mfhd")?;
+        let mut fragment = MovieFragment {
+            sequence: read_u32(mfhd, 4).ok_or("Truncated mfhd")?,
+            sequence_at: offset_of(mfhd) + 4,
+            tracks: Vec::new(),
+        };
+
+        for traf in boxes(moof.payload).filter(|b| &b.kind == b"traf") {
+            let tfhd = child(traf.payload, b"tfhd").ok_or("traf without tfhd")?;
+            let track_id = read_u32(tfhd, 4).ok_or("Truncated tfhd")?;
+            let track = tracks
+                .iter()
+                .find(|track| track.id == track_id)
+                .ok_or_else(|| format!("Fragment of unknown track {}", track_id))?;
+
+            // Optional tfhd fields: base data offset, sample description index,
+            // then the default sample duration.
+            let tfhd_flags = read_u32(tfhd, 0).ok_or("Truncated tfhd")? & 0x00ff_ffff;
+            let mut default_duration = track.default_duration;
+            if tfhd_flags & 0x08 != 0 {
+                let mut at = 8;
+                if tfhd_flags & 0x01 != 0 {
+                    at += 8;
+                }
+                if tfhd_flags & 0x02 != 0 {
+                    at += 4;
+                }
+                default_duration = read_u32(tfhd, at).ok_or("Truncated tfhd")?;
+            }
+
+            let tfdt = child(traf.payload, b"tfdt")
+                .ok_or_else(|| format!("Fragment of track {} without tfdt", track_id))?;
+            let version = *tfdt.first().ok_or("Truncated tfdt")?;
+            let decode_time = match version {
+                1 => read_u64(tfdt, 4),
+                _ => read_u32(tfdt, 4).map(u64::from),
+            }
+            .ok_or("Truncated tfdt")?;
+
+            let mut duration = 0;
+            let mut first_duration_at = None;
+            for trun in boxes(traf.payload).filter(|b| &b.kind == b"trun") {
+                let trun = trun.payload;
+                let flags = read_u32(trun, 0).ok_or("Truncated trun")? & 0x00ff_ffff;
+                let count = read_u32(trun, 4).ok_or("Truncated trun")? as usize;
+                // Data offset and first sample flags, then per sample fields.
+                let mut at = 8;
+                if flags & 0x01 != 0 {
+                    at += 4;
+                }
+                if flags & 0x04 != 0 {
+                    at += 4;
+                }
+                if flags & 0x100 == 0 {
+                    duration += count as u64 * u64::from(default_duration);
+                    continue;
+                }
+                let sample_size = 4 * (flags & 0xf00).count_ones() as usize;
+                if first_duration_at.is_none() && count > 0 {
+                    first_duration_at = Some(offset_of(trun) + at);
+                }
+                for sample in 0..count {
+                    let sample_duration =
+                        read_u32(trun, at + sample * sample_size).ok_or("Truncated trun")?;
+                    duration += u64::from(sample_duration);
+                }
+            }
+
+            fragment.tracks.push(TrackFragment {
+                track_id,
+                decode_time,
+                decode_time_at: (offset_of(tfdt), version),
+                duration,
+                first_duration_at,
+            });
+        }
+        fragments.push(fragment);
+    }
+    Ok(fragments)
+}
+
+/// Rewrite the movie fragments of a segment muxed on its own, so that its decode
+/// times carry on the source timeline rather than restarting at 0.
+///
+/// `init` is the header the segment was muxed with, and `fragments` what follows
+/// it. `starts` is the decode time, in seconds, of the first sample of each track,
+/// in track order. Fragment sequence numbers are renumbered from `first_sequence`.
+///
+/// Muxers starting every track at the same time stretch the first sample of the
+/// tracks starting later to cover the gap; the stretch is taken back, so every
+/// sample keeps its source decode time.
+pub fn rebase_fragments(
+    init: &[u8],
+    fragments: &mut [u8],
+    starts: &[Option<f64>],
+    first_sequence: u32,
+) -> Result<(), String> {
+    let tracks = tracks(init);
+    let parsed = movie_fragments(fragments, &tracks)?;
+
+    let first_fragment = |id: u32| {
+        parsed
+            .iter()
+            .flat_map(|fragment| &fragment.tracks)
+            .find(|traf| traf.track_id == id)
+    };
+    // Where the muxer put time zero, and the source time it stands for.
+    let seconds = |ticks: u64, track: &Track| ticks as f64 / f64::from(track.timescale.max(1));
+    let muxed_zero = tracks
+        .iter()
+        .filter_map(|track| Some(seconds(first_fragment(track.id)?.decode_time, track)))
+        .fold(f64::INFINITY, f64::min);
+    let source_zero = starts
+        .iter()
+        .flatten()
+        .cloned()
+        .fold(f64::INFINITY, f64::min);
+
+    for (index, track) in tracks.iter().enumerate() {
+        let Some(first) = first_fragment(track.id) else {
+            continue;
+        };
+        let start = starts
+            .get(index)
+            .copied()
+            .flatten()
+            .ok_or_else(|| format!("No start time for track {}", track.id))?;
+
+        let ticks = |seconds: f64| (seconds * f64::from(track.timescale)).round() as i64;
+        let stretch =
+            (ticks(start - source_zero) - (first.decode_time as i64 - ticks(muxed_zero))).max(0);
+        let shift = ticks(start) - first.decode_time as i64;
+
+        let mut first_seen = false;
+        for traf in parsed
+            .iter()
+            .flat_map(|fragment| &fragment.tracks)
+            .filter(|traf| traf.track_id == track.id)
+        {
+            let decode_time = match first_seen {
+                false => traf.decode_time as i64 + shift,
+                true => traf.decode_time as i64 + shift - stretch,
+            };
+            let decode_time = u64::try_from(decode_time)
+                .map_err(|_| format!("Negative decode time for track {}", track.id))?;
+            let (at, version) = traf.decode_time_at;
+            match version {
+                1 => fragments[at + 4..at + 12].copy_from_slice(&decode_time.to_be_bytes()),
+                _ => {
+                    let decode_time = u32::try_from(decode_time)
+                        .map_err(|_| format!("Decode time overflows tfdt of track {}", track.id))?;
+                    fragments[at + 4..at + 8].copy_from_slice(&decode_time.to_be_bytes());
+                }
+            }
+
+            if !first_seen && stretch > 0 {
+                if let Some(at) = traf.first_duration_at {
+                    let duration = read_u32(fragments, at).unwrap_or(0) as i64;
+                    let duration = u32::try_from(duration - stretch).unwrap_or(0);
+                    fragments[at..at + 4].copy_from_slice(&duration.to_be_bytes());
+                }
+            }
+            first_seen = true;
+        }
+    }
+
+    for (i, fragment) in parsed.iter().enumerate() {
+        let sequence = first_sequence + i as u32;
+        let at = fragment.sequence_at;
+        fragments[at..at + 4].copy_from_slice(&sequence.to_be_bytes());
+    }
+    Ok(())
+}
+
+/// Keyframes of `segment`, muxed with header `init`, for I-frame playlists: every
+/// movie fragment whose first video sample is a sync sample, from its `moof`
+/// through the end of that sample. Fragments start on keyframes, so that is one
+/// per fragment.
+pub fn keyframes(init: &[u8], segment: &[u8]) -> Vec<Keyframe> {
+    let tracks = tracks(init);
+    let offset_of = |payload: &[u8]| payload.as_ptr() as usize - segment.as_ptr() as usize;
+
+    let mut keyframes = Vec::new();
+    for moof in boxes(segment).filter(|b| &b.kind == b"moof") {
+        // moof boxes are small enough for a 32-bit size, so an 8 byte header.
+        let moof_offset = offset_of(moof.payload) - 8;
+        let video = boxes(moof.payload)
+            .filter(|b| &b.kind == b"traf")
+            .find_map(|traf| {
+                let track_id = read_u32(child(traf.payload, b"tfhd")?, 4)?;
+                let track = tracks.iter().find(|t| t.id == track_id && t.video)?;
+                Some((traf.payload, track))
+            });
+        let Some((traf, track)) = video else {
+            continue;
+        };
+        if let Some(keyframe) = first_sample(traf, track) {
+            let (time, data_offset, size) = keyframe;
+            keyframes.push(Keyframe {
+                time,
+                offset: moof_offset as u64,
+                length: data_offset + size,
+            });
+        }
+    }
+    keyframes
+}
+
+/// Decode time in seconds, data offset from the start of the `moof` and size of
+/// the first sample of `traf`, if it is a sync sample.
+fn first_sample(traf: &[u8], track: &Track) -> Option<(f64, u64, u64)> {
+    let tfhd = child(traf, b"tfhd")?;
+    let tfhd_flags = read_u32(tfhd, 0)? & 0x00ff_ffff;
+    // Optional tfhd fields: base data offset, sample description index, default
+    // duration, default size and default flags.
+    let mut at = 8;
+    for (flag, length) in [(0x01, 8), (0x02, 4), (0x08, 4)] {
+        if tfhd_flags & flag != 0 {
+            at += length;
+        }
+    }
+    let mut default_size = track.default_size;
+    if tfhd_flags & 0x10 != 0 {
+        default_size = read_u32(tfhd, at)?;
+        at += 4;
+    }
+    let mut sample_flags = None;
+    if tfhd_flags & 0x20 != 0 {
+        sample_flags = read_u32(tfhd, at);
+    }
+
+    let tfdt = child(traf, b"tfdt")?;
+    let decode_time = match tfdt.first()? {
+        1 => read_u64(tfdt, 4)?,
+        _ => u64::from(read_u32(tfdt, 4)?),
+    };
+
+    let trun = child(traf, b"trun")?;
+    let flags = read_u32(trun, 0)? & 0x00ff_ffff;
+    if read_u32(trun, 4)? == 0 || flags & 0x01 == 0 {
+        return None;
+    }
+    let data_offset = read_u32(trun, 8)?;
+    let mut at = 12;
+    if flags & 0x04 != 0 {
+        sample_flags = read_u32(trun, at);
+        at += 4;
+    }
+    // Per sample fields of the first sample: duration, size, flags.
+    if flags & 0x100 != 0 {
+        at += 4;
+    }
+    let size = match flags & 0x200 {
+        0 => default_size,
+        _ => {
+            let size = read_u32(trun, at)?;
+            at += 4;
+            size
+        }
+    };
+    if flags & 0x400 != 0 && flags & 0x04 == 0 {
+        sample_flags = read_u32(trun, at);
+    }
+
+    // sample_is_non_sync_sample
+    if sample_flags.is_some_and(|flags| flags & 0x0001_0000 != 0) || size == 0 {
+        return None;
+    }
+    let time = decode_time as f64 / f64::from(track.timescale.max(1));
+    Some((time, u64::from(data_offset), u64::from(size)))
+}
+
+/// Check that `media`, an init segment followed by the segments of a rendition,
+/// plays as one timeline: fragment sequence numbers increase, and the fragments of
+/// every track follow each other without going back in time.
+pub fn check_timeline(media: &[u8]) -> Result<(), String> {
+    let tracks = tracks(media);
+    if tracks.is_empty() {
+        return Err("No init segment".to_string());
+    }
+
+    let mut last_sequence = None;
+    let mut track_ends: Vec<(u32, u64)> = Vec::new();
+    for fragment in movie_fragments(media, &tracks)? {
+        if last_sequence.is_some_and(|last| fragment.sequence <= last) {
+            return Err(format!(
+                "Fragment sequence number {} after {}",
+                fragment.sequence,
+                last_sequence.unwrap_or_default()
+            ));
+        }
+        last_sequence = Some(fragment.sequence);
+
+        for traf in &fragment.tracks {
+            let end = traf.decode_time + traf.duration;
+            match track_ends.iter_mut().find(|(id, _)| *id == traf.track_id) {
+                Some((_, last_end)) if traf.decode_time < *last_end => {
+                    return Err(format!(
+                        "Track {} goes back to {} after reaching {}",
+                        traf.track_id, traf.decode_time, last_end
+                    ));
+                }
+                Some((_, last_end)) => *last_end = end,
+                None => track_ends.push((traf.track_id, end)),
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+pub(crate) mod fixtures {
+    //! Synthetic ISO-BMFF boxes, for the tests of the modules reading and rewriting
+    //! them.
+
+    use super::mp4_box;
+
+    /// An init segment with a fragmented track per sample entry, of ids from 1 and
+    /// a 90 kHz timescale.
+    pub(crate) fn init_segment(entries: &[Vec<u8>]) -> Vec<u8> {
+        let (mut moov, mut mvex) = (Vec::new(), Vec::new());
+        for (i, entry) in entries.iter().enumerate() {
+            let id = i as u32 + 1;
+            let mut tkhd = vec![0; 12];
+            tkhd.extend(id.to_be_bytes());
+            let mut mdhd = vec![0; 12];
+            mdhd.extend(90_000_u32.to_be_bytes());
+            let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stsd.extend_from_slice(entry);
+            let minf = mp4_box(b"minf", &mp4_box(b"stbl", &mp4_box(b"stsd", &stsd)));
+            let mdia = mp4_box(b"mdia", &[mp4_box(b"mdhd", &mdhd), minf].concat());
+            moov.extend(mp4_box(b"trak", &[mp4_box(b"tkhd", &tkhd), mdia].concat()));
+
+            let mut trex = vec![0; 4];
+            trex.extend(id.to_be_bytes());
+            trex.extend([0; 16]);
+            mvex.extend(mp4_box(b"trex", &trex));
+        }
+        moov.extend(mp4_box(b"mvex", &mvex));
+        [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"moov", &moov)].concat()
+    }
+
+    /// A sample entry of video, its 78 bytes of fields zeroed, then `config`.
+    pub(crate) fn visual_entry(kind: &[u8; 4], config: Vec<u8>) -> Vec<u8> {
+        let mut payload = vec![0; 78];
+        payload.extend(config);
+        mp4_box(kind, &payload)
+    }
+
+    /// A sample entry of audio, its 28 bytes of fields zeroed, then `config`.
+    pub(crate) fn audio_entry(kind: &[u8; 4], config: Vec<u8>) -> Vec<u8> {
+        let mut payload = vec![0; 28];
+        payload.extend(config);
+        mp4_box(kind, &payload)
+    }
+
+    /// A run of samples of a track: `(track id, decode time, samples)`, samples
+    /// being `(duration, data)`.
+    pub(crate) type Run = (u32, u64, Vec<(u32, Vec<u8>)>);
+
+    /// A moof and its mdat, with a traf per run, the data offsets of its samples
+    /// from the moof.
+    pub(crate) fn fragment(sequence: u32, trafs: &[Run]) -> Vec<u8> {
+        let moof = |offsets: &[u32]| {
+            let mut mfhd = vec![0; 4];
+            mfhd.extend(sequence.to_be_bytes());
+            let mut payload = mp4_box(b"mfhd", &mfhd);
+            for (&(id, decode_time, ref samples), &offset) in trafs.iter().zip(offsets) {
+                // Data offsets from the moof.
+                let mut tfhd = vec![0, 0x02, 0, 0];
+                tfhd.extend(id.to_be_bytes());
+                let mut tfdt = vec![1, 0, 0, 0];
+                tfdt.extend(decode_time.to_be_bytes());
+                // Data offset, then sample durations and sizes.
+                let mut trun = vec![0, 0, 0x03, 0x01];
+                trun.extend((samples.len() as u32).to_be_bytes());
+                trun.extend(offset.to_be_bytes());
+                for (duration, data) in samples {
+                    trun.extend(duration.to_be_bytes());
+                    trun.extend((data.len() as u32).to_be_bytes());
+                }
+                let traf = [
+                    mp4_box(b"tfhd", &tfhd),
+                    mp4_box(b"tfdt", &tfdt),
+                    mp4_box(b"trun", &trun),
+                ];
+                payload.extend(mp4_box(b"traf", &traf.concat()));
+            }
+            mp4_box(b"moof", &payload)
+        };
+        let data: Vec<Vec<u8>> = trafs
+            .iter()
+            .map(|(_, _, samples)| samples.iter().flat_map(|(_, data)| data.clone()).collect())
+            .collect();
+        let mut offset = moof(&vec![0; trafs.len()]).len() as u32 + 8;
+        let offsets: Vec<u32> = data
+            .iter()
+            .map(|data| {
+                offset += data.len() as u32;
+                offset - data.len() as u32
+            })
+            .collect();
+        [moof(&offsets), mp4_box(b"mdat", &data.concat())].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{audio_entry, fragment, init_segment, visual_entry};
+    use super::*;
+
+    #[test]
+    fn test_codecs() {
+        let avc = visual_entry(b"avc1", mp4_box(b"avcC", &[1, 0x64, 0x00, 0x1f, 0xff]));
+        let hevc = visual_entry(
+            b"hvc1",
+            mp4_box(b"hvcC", &[1, 0x01, 0x60, 0, 0, 0, 0xb0, 0, 0, 0, 0, 0, 93]),
+        );
+        let av1 = visual_entry(b"av01", mp4_box(b"av1C", &[0x81, 0x08, 0x4c, 0]));
+
+        // ES_Descriptor > DecoderConfigDescriptor > AudioSpecificConfig (AAC-LC).
+        let mut config = vec![0x40, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        config.extend([0x05, 0x02, 0x12, 0x10]);
+        let mut es = vec![0x04, config.len() as u8];
+        es.extend(config);
+        let mut esds = vec![
+            0,
+            0,
+            0,
+            0,
+            0x03,
+            0x80,
+            0x80,
+            0x80,
+            (es.len() + 3) as u8,
+            0,
+            1,
+            0,
+        ];
+        esds.extend(es);
+        let aac = audio_entry(b"mp4a", mp4_box(b"esds", &esds));
+
+        let init = init_segment(&[avc, hevc, av1, aac]);
+        assert_eq!(
+            codecs(&init),
+            vec![
+                "avc1.64001f",
+                "hvc1.1.6.L93.B0",
+                "av01.0.08M.10",
+                "mp4a.40.2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_boxes() {
+        assert!(codecs(b"").is_empty());
+        assert!(codecs(&[0, 0, 0, 42, b'm', b'o', b'o', b'v']).is_empty());
+        assert_eq!(boxes(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']).count(), 0);
+    }
+
+    /// An init segment of tracks `(id, timescale)`, without sample entries.
+    fn timed_init(tracks: &[(u32, u32)]) -> Vec<u8> {
+        let mut moov = Vec::new();
+        let mut mvex = Vec::new();
+        for &(id, timescale) in tracks {
+            let mut tkhd = vec![0u8; 12];
+            tkhd.extend(id.to_be_bytes());
+            let mut mdhd = vec![0u8; 12];
+            mdhd.extend(timescale.to_be_bytes());
+            let mut trak = mp4_box(b"tkhd", &tkhd);
+            trak.extend(mp4_box(b"mdia", &mp4_box(b"mdhd", &mdhd)));
+            moov.extend(mp4_box(b"trak", &trak));
+
+            let mut trex = vec![0u8; 4];
+            trex.extend(id.to_be_bytes());
+            trex.extend([0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            mvex.extend(mp4_box(b"trex", &trex));
+        }
+        moov.extend(mp4_box(b"mvex", &mvex));
+        let mut init = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        init.extend(mp4_box(b"moov", &moov));
+        init
+    }
+
+    /// Samples of 100 bytes lasting `durations`.
+    fn samples(durations: &[u32]) -> Vec<(u32, Vec<u8>)> {
+        durations
+            .iter()
+            .map(|&duration| (duration, vec![0; 100]))
+            .collect()
+    }
+
+    /// Two segments of a 1000 Hz video track and a 48 kHz audio track, muxed on
+    /// their own: every track of every segment restarts at 0, and the audio, which
+    /// starts 100 ms after the video, has its first sample stretched over the gap.
+    fn muxed_segments() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let init = timed_init(&[(1, 1000), (2, 48000)]);
+        let first = [
+            fragment(
+                1,
+                &[(1, 0, samples(&[500; 4])), (2, 0, samples(&[1024; 2]))],
+            ),
+            fragment(
+                2,
+                &[
+                    (1, 2000, samples(&[500; 4])),
+                    (2, 2048, samples(&[1024; 2])),
+                ],
+            ),
+        ]
+        .concat();
+        let second = fragment(
+            1,
+            &[
+                (1, 0, samples(&[500; 4])),
+                (2, 0, samples(&[4800 + 1024, 1024])),
+            ],
+        );
+        (init, first, second)
+    }
+
+    #[test]
+    fn test_rebase_fragments() {
+        let (init, mut first, mut second) = muxed_segments();
+        let media = [init.clone(), first.clone(), second.clone()].concat();
+        assert!(check_timeline(&media).is_err());
+
+        rebase_fragments(&init, &mut first, &[Some(0.0), Some(0.0)], 1).unwrap();
+        rebase_fragments(&init, &mut second, &[Some(4.0), Some(4.1)], 10_001).unwrap();
+
+        let tracks = tracks(&init);
+        let fragments = movie_fragments(&second, &tracks).unwrap();
+        assert_eq!(fragments[0].sequence, 10_001);
+        assert_eq!(fragments[0].tracks[0].decode_time, 4000);
+        assert_eq!(fragments[0].tracks[1].decode_time, 4000 * 48 + 4800);
+        assert_eq!(fragments[0].tracks[1].duration, 2048);
+
+        let media = [init, first, second].concat();
+        assert_eq!(check_timeline(&media), Ok(()));
+    }
+
+    #[test]
+    fn test_check_timeline() {
+        let init = timed_init(&[(1, 1000)]);
+        let overlapping = [
+            init.clone(),
+            fragment(1, &[(1, 0, samples(&[1000; 2]))]),
+            fragment(2, &[(1, 1500, samples(&[1000]))]),
+        ]
+        .concat();
+        assert!(check_timeline(&overlapping).is_err());
+
+        let out_of_order = [
+            init.clone(),
+            fragment(2, &[(1, 0, samples(&[1000]))]),
+            fragment(1, &[(1, 1000, samples(&[1000]))]),
+        ]
+        .concat();
+        assert!(check_timeline(&out_of_order).is_err());
+
+        let with_gap = [
+            init,
+            fragment(1, &[(1, 0, samples(&[1000]))]),
+            fragment(3, &[(1, 1500, samples(&[1000]))]),
+        ]
+        .concat();
+        assert_eq!(check_timeline(&with_gap), Ok(()));
+        assert!(check_timeline(&fragment(1, &[(1, 0, samples(&[1000]))])).is_err());
+    }
+
+    #[test]
+    fn test_keyframes() {
+        // A 90 kHz video track and a 1000 Hz track of another kind.
+        let mut moov = Vec::new();
+        for (id, timescale, handler) in [(1u32, 90000u32, b"vide"), (2, 1000, b"soun")] {
+            let mut tkhd = vec![0u8; 12];
+            tkhd.extend(id.to_be_bytes());
+            let mut mdhd = vec![0u8; 12];
+            mdhd.extend(timescale.to_be_bytes());
+            let mut hdlr = vec![0u8; 8];
+            hdlr.extend(handler);
+            let mut mdia = mp4_box(b"mdhd", &mdhd);
+            mdia.extend(mp4_box(b"hdlr", &hdlr));
+            let mut trak = mp4_box(b"tkhd", &tkhd);
+            trak.extend(mp4_box(b"mdia", &mdia));
+            moov.extend(mp4_box(b"trak", &trak));
+        }
+        let mut init = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        init.extend(mp4_box(b"moov", &moov));
+
+        // Data offset, first sample flags, then durations and sizes.
+        let moof = |decode_time: u64, first_flags: u32| {
+            let mut tfhd = vec![0u8; 4];
+            tfhd.extend(1u32.to_be_bytes());
+            let mut tfdt = vec![1, 0, 0, 0];
+            tfdt.extend(decode_time.to_be_bytes());
+            let mut trun = vec![0, 0, 0x03, 0x05];
+            trun.extend(2u32.to_be_bytes());
+            trun.extend(120u32.to_be_bytes());
+            trun.extend(first_flags.to_be_bytes());
+            for size in [5000u32, 300] {
+                trun.extend(3000u32.to_be_bytes());
+                trun.extend(size.to_be_bytes());
+            }
+            let mut traf = mp4_box(b"tfhd", &tfhd);
+            traf.extend(mp4_box(b"tfdt", &tfdt));
+            traf.extend(mp4_box(b"trun", &trun));
+            let mut mfhd = vec![0u8; 4];
+            mfhd.extend(1u32.to_be_bytes());
+            let mut moof = mp4_box(b"mfhd", &mfhd);
+            moof.extend(mp4_box(b"traf", &traf));
+            [mp4_box(b"moof", &moof), mp4_box(b"mdat", &[0; 5300])].concat()
+        };
+        let first = moof(900_000, 0x0200_0000);
+        let segment = [first.clone(), moof(918_000, 0x0001_0000), moof(936_000, 0)].concat();
+
+        let keyframes = keyframes(&init, &segment);
+        assert_eq!(
+            keyframes,
+            vec![
+                Keyframe {
+                    time: 10.0,
+                    offset: 0,
+                    length: 5120,
+                },
+                Keyframe {
+                    time: 10.4,
+                    offset: 2 * first.len() as u64,
+                    length: 5120,
+                },
+            ]
+        );
+        assert!(super::keyframes(&timed_init(&[(1, 90000)]), &segment).is_empty());
+    }
+
+    #[test]
+    fn test_add_color_boxes() {
+        use crate::domain::color::{ContentLight, BT2020, PQ};
+
+        let avc = visual_entry(b"avc1", mp4_box(b"avcC", &[1, 0x64, 0x00, 0x1f, 0xff]));
+        let aac = audio_entry(b"mp4a", mp4_box(b"esds", &[0; 4]));
+        let init = init_segment(&[avc, aac]);
+
+        let hdr10 = ColorInfo {
+            primaries: BT2020,
+            transfer: PQ,
+            matrix: BT2020,
+            content_light: Some(ContentLight {
+                max_cll: 1000,
+                max_fall: 400,
+            }),
+            ..ColorInfo::default()
+        };
+        let edited = add_color_boxes(&init, &hdr10).unwrap();
+        assert_eq!(edited.len(), init.len() + 19 + 12);
+        assert_eq!(codecs(&edited)[0], "avc1.64001f");
+
+        let stsd = find(
+            &edited,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+        )
+        .unwrap();
+        let entry = boxes(&stsd[8..]).next().unwrap();
+        let children = visual_children(entry.payload).unwrap();
+        assert_eq!(
+            child(children, b"colr").unwrap(),
+            &[b'n', b'c', b'l', b'x', 0, 9, 0, 16, 0, 9, 0]
+        );
+        assert_eq!(child(children, b"clli").unwrap(), &[0x03, 0xe8, 0x01, 0x90]);
+        assert!(child(children, b"mdcv").is_none());
+
+        // Nothing left to add, and nothing to add without a colour description.
+        assert_eq!(add_color_boxes(&edited, &hdr10), None);
+        assert_eq!(add_color_boxes(&init, &ColorInfo::default()), None);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let tkhd = |version: u8| {
+            let mut payload = vec![version, 0, 0, 7];
+            payload.extend(vec![0u8; if version == 1 { 48 } else { 36 }]);
+            for value in rotation_matrix(0) {
+                payload.extend(value.to_be_bytes());
+            }
+            payload.extend([0u8; 8]);
+            mp4_box(b"tkhd", &payload)
+        };
+        let trak = |version: u8, handler: &[u8; 4]| {
+            let mut hdlr = vec![0u8; 8];
+            hdlr.extend(handler);
+            hdlr.extend([0u8; 13]);
+            let mut trak = tkhd(version);
+            trak.extend(mp4_box(b"mdia", &mp4_box(b"hdlr", &hdlr)));
+            mp4_box(b"trak", &trak)
+        };
+        let mut moov = trak(0, b"soun");
+        moov.extend(trak(1, b"vide"));
+        let init = mp4_box(b"moov", &moov);
+        assert_eq!(rotation(&init), 0);
+
+        let rotated = set_rotation(&init, 90).unwrap();
+        assert_eq!(rotated.len(), init.len());
+        assert_eq!(rotation(&rotated), 90);
+        assert_eq!(set_rotation(&rotated, 90), None);
+        assert_eq!(rotation(&set_rotation(&rotated, 270).unwrap()), 270);
+        // The audio track keeps its identity matrix.
+        let audio = boxes(child(&rotated, b"moov").unwrap()).next().unwrap();
+        assert_eq!(audio.payload, &trak(0, b"soun")[8..]);
+
+        // As iPhone portrait recordings have it.
+        let portrait = [0, 65536, 0, -65536, 0, 0, 0, 0, 1 << 30];
+        assert_eq!(matrix_rotation(&portrait), 90);
+        let counter_clockwise = [0, -65536, 0, 65536, 0, 0, 0, 0, 1 << 30];
+        assert_eq!(matrix_rotation(&counter_clockwise), 270);
+        assert_eq!(matrix_rotation(&rotation_matrix(180)), 180);
+        assert_eq!(matrix_rotation(&[0; 9]), 0);
+    }
+}
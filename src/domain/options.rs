@@ -0,0 +1,406 @@
+//! Per-video processing options, chosen at upload time through object metadata.
+
+use super::encryption::EncryptionMethod;
+use super::frame_rate::FrameRateTarget;
+use super::watermark::{Mark, Position, Watermark};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Metadata key (`x-amz-meta-packaging`) selecting the packaging of an upload.
+pub const PACKAGING_KEY: &str = "packaging";
+
+/// Metadata key (`x-amz-meta-container`) selecting the segment container of an upload.
+pub const CONTAINER_KEY: &str = "container";
+
+/// Metadata key (`x-amz-meta-encryption`) selecting how the segments of an upload are
+/// encrypted: `aes-128` or `sample-aes`, or `cenc` or `cbcs` for Common Encryption.
+pub const ENCRYPTION_KEY: &str = "encryption";
+
+/// Metadata key (`x-amz-meta-key-rotation`) giving how many segments are encrypted
+/// under each content key.
+pub const KEY_ROTATION_KEY: &str = "key-rotation";
+
+/// Metadata key (`x-amz-meta-trim-start`) giving where the published part of an
+/// upload starts, in seconds or as `HH:MM:SS.mmm`.
+pub const TRIM_START_KEY: &str = "trim-start";
+
+/// Metadata key (`x-amz-meta-trim-end`) giving where the published part of an upload
+/// ends, like `TRIM_START_KEY`.
+pub const TRIM_END_KEY: &str = "trim-end";
+
+/// Metadata key (`x-amz-meta-frame-rate`) giving the constant frame rate encoded
+/// video is normalized to: a rate such as `30` or `30000/1001`, or `constant` for
+/// the typical rate of sources with a variable frame rate.
+pub const FRAME_RATE_KEY: &str = "frame-rate";
+
+/// Metadata key (`x-amz-meta-watermark`) giving the storage key of a logo burnt into
+/// the encoded video of an upload, under `watermark::ASSETS_PREFIX`. Keys suffixed `-position`, `-opacity` and
+/// `-scale` place it.
+pub const WATERMARK_KEY: &str = "watermark";
+
+/// Metadata key (`x-amz-meta-watermark-text`) giving text burnt into the encoded
+/// video of an upload, placed like `WATERMARK_KEY`.
+pub const WATERMARK_TEXT_KEY: &str = "watermark-text";
+
+/// Metadata key (`x-amz-meta-video-id`) naming the video sidecar captions are for.
+pub const VIDEO_ID_KEY: &str = "video-id";
+
+/// Metadata key (`x-amz-meta-language`) giving the language of sidecar captions.
+pub const LANGUAGE_KEY: &str = "language";
+
+/// How the segments of each rendition are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Packaging {
+    /// One object per segment.
+    #[default]
+    Segments,
+    /// One file per rendition, its segments addressed by byte range.
+    SingleFile,
+}
+
+impl Packaging {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "segments" => Ok(Packaging::Segments),
+            "single-file" | "byterange" => Ok(Packaging::SingleFile),
+            _ => Err(format!("Invalid packaging '{}'", value)),
+        }
+    }
+}
+
+/// Container of the media segments.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Container {
+    /// Fragmented MP4 (CMAF), HLS version 7.
+    #[default]
+    Fmp4,
+    /// MPEG-TS, HLS version 3, for legacy players.
+    MpegTs,
+}
+
+impl Container {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "fmp4" | "mp4" | "cmaf" => Ok(Container::Fmp4),
+            "ts" | "mpegts" => Ok(Container::MpegTs),
+            _ => Err(format!("Invalid container '{}'", value)),
+        }
+    }
+
+    /// File extension of the segments.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Fmp4 => "mp4",
+            Container::MpegTs => "ts",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VideoOptions {
+    pub packaging: Packaging,
+    pub container: Container,
+    /// Segments are in the clear without one.
+    pub encryption: Option<EncryptionMethod>,
+    /// A single key for the whole video without one.
+    pub key_rotation: Option<usize>,
+    /// Seconds of the source before which nothing is published.
+    pub trim_start: Option<f64>,
+    /// Seconds of the source after which nothing is published.
+    pub trim_end: Option<f64>,
+    /// Marks burnt into every rendition, which are all encoded then.
+    pub watermarks: Vec<Watermark>,
+    /// Video keeps the timestamps of the source without one.
+    pub frame_rate: Option<FrameRateTarget>,
+}
+
+impl VideoOptions {
+    /// Read the options of an upload from its metadata, keyed without the
+    /// `x-amz-meta-` prefix. Other keys are ignored.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        if let Some(packaging) = metadata.get(PACKAGING_KEY) {
+            options.packaging = Packaging::parse(packaging)?;
+        }
+        if let Some(container) = metadata.get(CONTAINER_KEY) {
+            options.container = Container::parse(container)?;
+        }
+        if let Some(encryption) = metadata.get(ENCRYPTION_KEY) {
+            options.encryption = Some(EncryptionMethod::parse(encryption)?);
+        }
+        if let Some(rotation) = metadata.get(KEY_ROTATION_KEY) {
+            match rotation.trim().parse::<usize>() {
+                Ok(rotation) if rotation > 0 => options.key_rotation = Some(rotation),
+                _ => return Err(format!("Invalid key rotation '{}'", rotation)),
+            }
+            if options.encryption.is_none() {
+                return Err("Key rotation without encryption".to_string());
+            }
+        }
+        if let Some(start) = metadata.get(TRIM_START_KEY) {
+            options.trim_start = Some(parse_time(start)?);
+        }
+        if let Some(end) = metadata.get(TRIM_END_KEY) {
+            options.trim_end = Some(parse_time(end)?);
+        }
+        if let (Some(start), Some(end)) = (options.trim_start, options.trim_end) {
+            if end <= start {
+                return Err(format!(
+                    "Trim end {}s is not after trim start {}s",
+                    end, start
+                ));
+            }
+        }
+        if let Some(frame_rate) = metadata.get(FRAME_RATE_KEY) {
+            options.frame_rate = Some(FrameRateTarget::parse(frame_rate)?);
+        }
+        if let Some(image) = metadata.get(WATERMARK_KEY) {
+            let mark = Mark::image(image)?;
+            options
+                .watermarks
+                .push(parse_watermark(metadata, WATERMARK_KEY, mark)?);
+        }
+        if let Some(text) = metadata.get(WATERMARK_TEXT_KEY) {
+            let mark = Mark::Text(text.trim().to_string());
+            options
+                .watermarks
+                .push(parse_watermark(metadata, WATERMARK_TEXT_KEY, mark)?);
+        }
+        Ok(options)
+    }
+
+    /// Whether only part of the upload is published.
+    pub fn is_trimmed(&self) -> bool {
+        self.trim_start.is_some() || self.trim_end.is_some()
+    }
+}
+
+/// Seconds of a trim point: plain seconds such as `90.5`, or `MM:SS` or `HH:MM:SS`
+/// with optional fractional seconds.
+fn parse_time(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid trim point '{}'", value);
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let part: f64 = part.parse().map_err(|_| invalid())?;
+        let last = i == parts.len() - 1;
+        // Only the seconds have a fraction, and only hours go past 59.
+        if !part.is_finite() || part < 0.0 || (!last && part.fract() != 0.0) {
+            return Err(invalid());
+        }
+        if i > 0 && part >= 60.0 {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Ok(seconds)
+}
+
+/// A watermark of `mark`, given at `key`, placed by the keys suffixed to it.
+fn parse_watermark(
+    metadata: &HashMap<String, String>,
+    key: &str,
+    mark: Mark,
+) -> Result<Watermark, String> {
+    if matches!(&mark, Mark::Image(value) | Mark::Text(value) if value.is_empty()) {
+        return Err(format!("Empty {}", key));
+    }
+    let setting = |suffix: &str| metadata.get(&format!("{}-{}", key, suffix));
+    let fraction = |suffix: &str, value: &String| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid {}-{} '{}'", key, suffix, value))
+    };
+    let mut watermark = Watermark::new(mark);
+    if let Some(position) = setting("position") {
+        watermark = watermark.with_position(Position::parse(position)?);
+    }
+    if let Some(opacity) = setting("opacity") {
+        watermark = watermark.with_opacity(fraction("opacity", opacity)?)?;
+    }
+    if let Some(scale) = setting("scale") {
+        watermark = watermark.with_scale(fraction("scale", scale)?)?;
+    }
+    Ok(watermark)
+}
+
+/// What a sidecar caption upload is attached to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptionOptions {
+    pub video_id: String,
+    /// RFC 5646 language tag, e.g. `en` or `pt-BR`.
+    pub language: String,
+}
+
+impl CaptionOptions {
+    /// Read the video id and language of a caption upload from its metadata, keyed
+    /// like `VideoOptions::from_metadata`. Both are required.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Result<Self, String> {
+        let required = |key: &str| {
+            metadata
+                .get(key)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("Missing x-amz-meta-{}", key))
+        };
+        let video_id = required(VIDEO_ID_KEY)?;
+        let language = required(LANGUAGE_KEY)?;
+
+        // The language ends up in a directory name and a playlist attribute.
+        let valid = language.split('-').all(|part| {
+            !part.is_empty() && part.len() <= 8 && part.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+        if !valid {
+            return Err(format!("Invalid language '{}'", language));
+        }
+        Ok(Self { video_id, language })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_metadata() {
+        let mut metadata = HashMap::new();
+        assert_eq!(
+            VideoOptions::from_metadata(&metadata).unwrap().packaging,
+            Packaging::Segments
+        );
+
+        metadata.insert("packaging".to_string(), "Single-File".to_string());
+        assert_eq!(
+            VideoOptions::from_metadata(&metadata).unwrap().packaging,
+            Packaging::SingleFile
+        );
+
+        metadata.insert("container".to_string(), "ts".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.container, Container::MpegTs);
+        assert_eq!(options.container.extension(), "ts");
+
+        metadata.insert("packaging".to_string(), "zip".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+    }
+
+    #[test]
+    fn test_encryption_options() {
+        let mut metadata = HashMap::new();
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.encryption, None);
+
+        metadata.insert("key-rotation".to_string(), "10".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+
+        metadata.insert("encryption".to_string(), "SAMPLE-AES".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.encryption, Some(EncryptionMethod::SampleAes));
+        assert_eq!(options.key_rotation, Some(10));
+
+        metadata.insert("key-rotation".to_string(), "0".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+
+        metadata.remove("key-rotation");
+        metadata.insert("encryption".to_string(), "cbcs".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.encryption, Some(EncryptionMethod::Cbcs));
+
+        metadata.insert("encryption".to_string(), "widevine".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+    }
+
+    #[test]
+    fn test_trim_options() {
+        let mut metadata = HashMap::new();
+        assert!(!VideoOptions::from_metadata(&metadata).unwrap().is_trimmed());
+
+        metadata.insert("trim-start".to_string(), "90.5".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert!(options.is_trimmed());
+        assert_eq!((options.trim_start, options.trim_end), (Some(90.5), None));
+
+        metadata.insert("trim-end".to_string(), "01:02:03.25".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.trim_end, Some(3723.25));
+
+        metadata.insert("trim-end".to_string(), "1:30".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+
+        for invalid in ["-5", "1:75", "1.5:00", "1:2:3:4", "soon", "inf"] {
+            metadata.insert("trim-end".to_string(), invalid.to_string());
+            assert!(
+                VideoOptions::from_metadata(&metadata).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_frame_rate_option() {
+        let mut metadata = HashMap::new();
+        metadata.insert("frame-rate".to_string(), "constant".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.frame_rate, Some(FrameRateTarget::Constant));
+
+        metadata.insert("frame-rate".to_string(), "variable".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+    }
+
+    #[test]
+    fn test_watermark_options() {
+        let mut metadata = HashMap::new();
+        metadata.insert("watermark".to_string(), "assets/logo.png".to_string());
+        metadata.insert("watermark-position".to_string(), "top-right".to_string());
+        metadata.insert("watermark-opacity".to_string(), "0.6".to_string());
+        metadata.insert("watermark-text".to_string(), "Screener".to_string());
+        let options = VideoOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(
+            options.watermarks,
+            vec![
+                Watermark::new(Mark::Image("assets/logo.png".to_string()))
+                    .with_position(Position::TopRight)
+                    .with_opacity(0.6)
+                    .unwrap(),
+                Watermark::new(Mark::Text("Screener".to_string())),
+            ]
+        );
+
+        metadata.insert("watermark-text-scale".to_string(), "big".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+        metadata.remove("watermark-text-scale");
+        metadata.insert("watermark".to_string(), " ".to_string());
+        assert!(VideoOptions::from_metadata(&metadata).is_err());
+        // Logos only come from the assets, not other uploads or their segments.
+        for key in [
+            "stream/other.mp4",
+            "hls/other/720p/segment_0.m4s",
+            "assets/",
+            "assets/../stream/other.mp4",
+            "assets//logo.png",
+        ] {
+            metadata.insert("watermark".to_string(), key.to_string());
+            assert!(VideoOptions::from_metadata(&metadata).is_err(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_caption_options() {
+        let mut metadata = HashMap::new();
+        metadata.insert("video-id".to_string(), "2f1c".to_string());
+        assert!(CaptionOptions::from_metadata(&metadata).is_err());
+
+        metadata.insert("language".to_string(), "pt-BR".to_string());
+        let options = CaptionOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.video_id, "2f1c");
+        assert_eq!(options.language, "pt-BR");
+
+        metadata.insert("language".to_string(), "../en".to_string());
+        assert!(CaptionOptions::from_metadata(&metadata).is_err());
+    }
+}
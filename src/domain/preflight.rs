@@ -0,0 +1,368 @@
+//! Preflight of an upload: its streams checked against what we can package before
+//! any job is queued, and the processing plan that follows.
+
+use super::frame_rate::FrameTiming;
+use super::interlace::FieldOrder;
+use super::options::Container;
+use super::renditions::{AudioCodec, AudioTrack, Rendition};
+use serde::{Deserialize, Serialize};
+
+/// Longest source accepted, in seconds.
+pub const MAX_DURATION: f64 = 6.0 * 3600.0;
+/// Largest width or height accepted, in pixels.
+pub const MAX_DIMENSION: u32 = 8192;
+/// Smallest width or height accepted, in pixels.
+pub const MIN_DIMENSION: u32 = 16;
+
+/// Demuxers, as FFmpeg names them, of the containers we take.
+const DEMUXERS: &[&str] = &[
+    "mov,mp4,m4a,3gp,3g2,mj2",
+    "matroska,webm",
+    "avi",
+    "mpegts",
+    "mpeg",
+    "flv",
+    "mxf",
+    "asf",
+    "dv",
+    "mp3",
+    "aac",
+    "flac",
+    "wav",
+    "ogg",
+];
+
+/// Video codecs we can decode, and so re-encode.
+const DECODABLE_VIDEO: &[&str] = &[
+    "h264",
+    "hevc",
+    "av1",
+    "vp8",
+    "vp9",
+    "mpeg1video",
+    "mpeg2video",
+    "mpeg4",
+    "h263",
+    "prores",
+    "dnxhd",
+    "mjpeg",
+    "dvvideo",
+    "vc1",
+    "wmv3",
+    "theora",
+];
+
+/// Video codecs players decode from our segments, with the pixel formats they
+/// take, in `Container::Fmp4` (all of them) and `Container::MpegTs` (the first two).
+const COPYABLE_VIDEO: &[(&str, &[&str])] = &[
+    ("h264", &["yuv420p", "yuvj420p"]),
+    ("hevc", &["yuv420p", "yuv420p10le"]),
+    ("av1", &["yuv420p", "yuv420p10le"]),
+];
+
+/// The parts of a probed source the preflight looks at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// Demuxer FFmpeg opened the source with.
+    pub format: String,
+    /// Duration in seconds.
+    pub duration: f64,
+    pub video: Vec<SourceVideo>,
+    pub audio_streams: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceVideo {
+    /// Codec, as FFmpeg names it.
+    pub codec: String,
+    pub pix_fmt: String,
+    pub width: u32,
+    pub height: u32,
+    pub field_order: FieldOrder,
+    pub frame_timing: Option<FrameTiming>,
+}
+
+/// What the preflight found a source can go through.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Compatibility {
+    /// Why the video can't be stream-copied, when it can't.
+    pub video_copy_blocker: Option<String>,
+    /// Things about the source worth knowing that don't stop it.
+    pub notes: Vec<String>,
+}
+
+/// Check `source` against what we can package into `container` segments. An error
+/// is the reason to reject it. Sources without video are packaged as audio only.
+pub fn check_source(source: &Source, container: Container) -> Result<Compatibility, String> {
+    if !DEMUXERS.contains(&source.format.as_str()) {
+        return Err(format!("{} files are not supported", source.format));
+    }
+    if source.duration.is_nan() || source.duration <= 0.0 {
+        return Err("The video has no duration".to_string());
+    }
+    if source.duration > MAX_DURATION {
+        return Err(format!(
+            "The video lasts {:.0}s, longer than the {:.0}s limit",
+            source.duration, MAX_DURATION
+        ));
+    }
+    let Some(video) = source.video.first() else {
+        if source.audio_streams == 0 {
+            return Err("The file has no video or audio stream".to_string());
+        }
+        return Ok(Compatibility {
+            video_copy_blocker: None,
+            notes: vec!["The file has no video and is packaged as audio only".to_string()],
+        });
+    };
+
+    if !DECODABLE_VIDEO.contains(&video.codec.as_str()) {
+        return Err(format!("{} video is not supported", video.codec));
+    }
+    let (width, height) = (video.width, video.height);
+    if width.min(height) < MIN_DIMENSION || width.max(height) > MAX_DIMENSION {
+        return Err(format!(
+            "{}x{} video is outside the {}-{} pixel limits",
+            width, height, MIN_DIMENSION, MAX_DIMENSION
+        ));
+    }
+
+    let mut compatibility = Compatibility {
+        video_copy_blocker: video_copy_blocker(video, container),
+        notes: Vec::new(),
+    };
+    if source.video.len() > 1 {
+        compatibility.notes.push(format!(
+            "Only the first of {} video streams is packaged",
+            source.video.len()
+        ));
+    }
+    if source.audio_streams == 0 {
+        compatibility
+            .notes
+            .push("The video has no audio stream".to_string());
+    }
+    if let Some(timing) = video.frame_timing.filter(FrameTiming::is_variable) {
+        compatibility.notes.push(format!(
+            "The video has a variable frame rate: {:.0}% of frame intervals are off the \
+             typical {}, ranging {:.3}s-{:.3}s",
+            timing.irregular * 100.0,
+            timing.typical_rate(),
+            timing.min_interval,
+            timing.max_interval
+        ));
+    }
+    Ok(compatibility)
+}
+
+/// Why `video` can't be stream-copied into `container` segments, or `None` when it
+/// can. Interlaced video would show combing on progressive displays, so it is
+/// deinterlaced whatever its codec.
+fn video_copy_blocker(video: &SourceVideo, container: Container) -> Option<String> {
+    if video.field_order.is_interlaced() {
+        return Some(format!(
+            "{} video is interlaced ({}) and is deinterlaced",
+            video.codec,
+            video.field_order.as_str()
+        ));
+    }
+    let copyable = match container {
+        Container::Fmp4 => COPYABLE_VIDEO,
+        Container::MpegTs => &COPYABLE_VIDEO[..2],
+    };
+    let Some((_, pix_fmts)) = copyable.iter().find(|(codec, _)| *codec == video.codec) else {
+        return Some(format!(
+            "{} video is not playable from {} segments",
+            video.codec,
+            container.extension()
+        ));
+    };
+    let pix_fmt = video.pix_fmt.to_lowercase();
+    (!pix_fmts.contains(&pix_fmt.as_str()))
+        .then(|| format!("{} video in {} is not playable", video.codec, pix_fmt))
+}
+
+/// How a video is processed, from stream copies only to re-encoding its video.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessingPlan {
+    /// Every stream is copied.
+    #[default]
+    Remux,
+    /// The video is copied, some audio is transcoded.
+    PartialTranscode,
+    /// The video is re-encoded.
+    FullTranscode,
+    /// The video can't be processed; nothing is queued.
+    Reject,
+}
+
+impl ProcessingPlan {
+    /// The plan producing `renditions` and `audio_tracks`.
+    pub fn for_outputs(renditions: &[Rendition], audio_tracks: &[AudioTrack]) -> Self {
+        if renditions.iter().any(|r| !r.is_copy()) {
+            ProcessingPlan::FullTranscode
+        } else if renditions
+            .iter()
+            .any(|r| r.muxed_audio && r.audio_codec == AudioCodec::Aac)
+            || audio_tracks.iter().any(|t| t.codec == AudioCodec::Aac)
+        {
+            ProcessingPlan::PartialTranscode
+        } else {
+            ProcessingPlan::Remux
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingPlan::Remux => "remux",
+            ProcessingPlan::PartialTranscode => "partial transcode",
+            ProcessingPlan::FullTranscode => "full transcode",
+            ProcessingPlan::Reject => "reject",
+        }
+    }
+}
+
+/// Outcome of the preflight, stored with the video status.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preflight {
+    pub plan: ProcessingPlan,
+    /// Why streams are re-encoded or the video is rejected, and notes about the
+    /// source.
+    pub reasons: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(codec: &str, pix_fmt: &str) -> Source {
+        Source {
+            format: "mov,mp4,m4a,3gp,3g2,mj2".to_string(),
+            duration: 60.0,
+            video: vec![SourceVideo {
+                codec: codec.to_string(),
+                pix_fmt: pix_fmt.to_string(),
+                width: 1920,
+                height: 1080,
+                field_order: FieldOrder::Progressive,
+                frame_timing: None,
+            }],
+            audio_streams: 1,
+        }
+    }
+
+    #[test]
+    fn test_check_source() {
+        let h264 = check_source(&source("h264", "YUV420P"), Container::Fmp4).unwrap();
+        assert_eq!(h264, Compatibility::default());
+
+        for (codec, pix_fmt) in [
+            ("vp8", "YUV420P"),
+            ("mpeg2video", "YUV420P"),
+            ("prores", "YUV422P10LE"),
+            ("dvvideo", "YUV411P"),
+        ] {
+            let transcoded = check_source(&source(codec, pix_fmt), Container::Fmp4).unwrap();
+            assert!(transcoded.video_copy_blocker.is_some(), "{}", codec);
+        }
+        let high_422 = check_source(&source("h264", "YUV422P"), Container::Fmp4).unwrap();
+        assert_eq!(
+            high_422.video_copy_blocker.as_deref(),
+            Some("h264 video in yuv422p is not playable")
+        );
+        let mut interlaced = source("h264", "YUV420P");
+        interlaced.video[0].field_order = FieldOrder::TopFirst;
+        let interlaced = check_source(&interlaced, Container::MpegTs).unwrap();
+        assert_eq!(
+            interlaced.video_copy_blocker.as_deref(),
+            Some("h264 video is interlaced (top field first) and is deinterlaced")
+        );
+        let av1 = check_source(&source("av1", "YUV420P"), Container::MpegTs).unwrap();
+        assert_eq!(
+            av1.video_copy_blocker.as_deref(),
+            Some("av1 video is not playable from ts segments")
+        );
+
+        let mut silent = source("hevc", "YUV420P10LE");
+        silent.audio_streams = 0;
+        silent.video.push(silent.video[0].clone());
+        let silent = check_source(&silent, Container::Fmp4).unwrap();
+        assert_eq!(silent.video_copy_blocker, None);
+        assert_eq!(silent.notes.len(), 2);
+
+        let mut capture = source("h264", "YUV420P");
+        capture.video[0].frame_timing = Some(FrameTiming {
+            intervals: 1800,
+            typical_interval: 1.0 / 60.0,
+            min_interval: 1.0 / 60.0,
+            max_interval: 1.5,
+            irregular: 0.4,
+        });
+        let capture = check_source(&capture, Container::Fmp4).unwrap();
+        assert_eq!(capture.video_copy_blocker, None);
+        assert_eq!(
+            capture.notes,
+            vec![
+                "The video has a variable frame rate: 40% of frame intervals are off the \
+                 typical 60.000 fps, ranging 0.017s-1.500s"
+            ]
+        );
+
+        let mut podcast = source("h264", "YUV420P");
+        podcast.format = "mp3".to_string();
+        podcast.video.clear();
+        let podcast = check_source(&podcast, Container::MpegTs).unwrap();
+        assert_eq!(podcast.video_copy_blocker, None);
+        assert_eq!(podcast.notes.len(), 1);
+    }
+
+    #[test]
+    fn test_check_source_rejects() {
+        let reject = |source: &Source| check_source(source, Container::Fmp4).unwrap_err();
+
+        assert_eq!(
+            reject(&source("cinepak", "RGB24")),
+            "cinepak video is not supported"
+        );
+
+        let mut gif = source("gif", "PAL8");
+        gif.format = "gif".to_string();
+        assert_eq!(reject(&gif), "gif files are not supported");
+
+        let mut long = source("h264", "YUV420P");
+        long.duration = MAX_DURATION + 1.0;
+        assert!(reject(&long).starts_with("The video lasts"));
+
+        let mut huge = source("h264", "YUV420P");
+        huge.video[0].width = 16384;
+        assert!(reject(&huge).starts_with("16384x1080 video"));
+
+        let mut empty = source("h264", "YUV420P");
+        empty.video.clear();
+        empty.audio_streams = 0;
+        assert_eq!(reject(&empty), "The file has no video or audio stream");
+    }
+
+    #[test]
+    fn test_processing_plan() {
+        let source = Rendition::source();
+        let encoded = Rendition::h264(720, 2800, 128);
+        assert_eq!(
+            ProcessingPlan::for_outputs(std::slice::from_ref(&source), &[]),
+            ProcessingPlan::Remux
+        );
+        assert_eq!(
+            ProcessingPlan::for_outputs(&[source.clone(), encoded], &[]),
+            ProcessingPlan::FullTranscode
+        );
+
+        let aac_audio = Rendition {
+            audio_codec: AudioCodec::Aac,
+            ..source
+        };
+        assert_eq!(
+            ProcessingPlan::for_outputs(&[aac_audio], &[]),
+            ProcessingPlan::PartialTranscode
+        );
+    }
+}
@@ -0,0 +1,686 @@
+//! Output renditions: the adaptive bitrate ladder a video is packaged into.
+
+use super::color::VideoRange;
+use super::frame_rate::FrameRate;
+use super::interlace::FieldOrder;
+use super::loudness::Loudness;
+use super::options::Container;
+use super::watermark::Watermark;
+use serde::{Deserialize, Serialize};
+
+/// Ladder used when none is configured.
+pub const DEFAULT_LADDER: &str = "1080p,720p,480p,360p";
+
+/// How the video track of a rendition is produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VideoCodec {
+    /// Stream-copy the source video without re-encoding.
+    Copy,
+    H264,
+    /// HEVC, tagged `hvc1` so that Apple devices play it.
+    Hevc,
+    Av1,
+}
+
+impl VideoCodec {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "h264" | "avc" => Ok(VideoCodec::H264),
+            "hevc" | "h265" => Ok(VideoCodec::Hevc),
+            "av1" => Ok(VideoCodec::Av1),
+            _ => Err(format!("Invalid video codec '{}'", value)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VideoCodec::Copy => "copy",
+            VideoCodec::H264 => "h264",
+            VideoCodec::Hevc => "hevc",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+
+    /// Bitrate needed for the quality H.264 gets at 100, which the presets are for.
+    fn bitrate_percent(&self) -> u64 {
+        match self {
+            VideoCodec::Copy | VideoCodec::H264 => 100,
+            VideoCodec::Hevc => 60,
+            VideoCodec::Av1 => 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rendition {
+    /// Name of the rendition, also used as its directory in the HLS output.
+    pub name: String,
+    /// Output width in pixels, resolved against the source by `fit_to`.
+    pub width: u32,
+    /// Output height in pixels, resolved against the source by `fit_to`.
+    pub height: u32,
+    /// Target video bitrate in bits per second (ignored when copying).
+    pub video_bitrate: u64,
+    /// Target audio bitrate in bits per second (ignored when copying).
+    pub audio_bitrate: u64,
+    pub video_codec: VideoCodec,
+    /// Whether the source audio is muxed in; false when audio is packaged as
+    /// alternate audio renditions instead.
+    pub muxed_audio: bool,
+    /// Dynamic range of the video: that of the source, unless tone-mapped.
+    #[serde(default)]
+    pub video_range: VideoRange,
+    /// Whether HDR source video is tone-mapped to SDR.
+    #[serde(default)]
+    pub tone_map: bool,
+    /// Gain in dB bringing encoded audio to the loudness target (ignored when
+    /// copying).
+    #[serde(default)]
+    pub audio_gain: f64,
+    /// How muxed audio goes along with copied video: copied unless players can't
+    /// decode it. Encoded video always gets AAC audio.
+    #[serde(default)]
+    pub audio_codec: AudioCodec,
+    /// Marks burnt into the video, after scaling (encoded renditions only).
+    #[serde(default)]
+    pub watermarks: Vec<Watermark>,
+    /// Field order of the source, whose video is deinterlaced first when
+    /// interlaced (encoded renditions only).
+    #[serde(default)]
+    pub field_order: FieldOrder,
+    /// Constant frame rate the video is normalized to, the timestamps of the
+    /// source being kept without one (encoded renditions only).
+    #[serde(default)]
+    pub frame_rate: Option<FrameRate>,
+}
+
+/// Known rungs: (short side, video kbps, audio kbps).
+const PRESETS: &[(u32, u64, u64)] = &[
+    (2160, 14_000, 192),
+    (1440, 8_000, 192),
+    (1080, 5_000, 128),
+    (720, 2_800, 128),
+    (480, 1_400, 96),
+    (360, 800, 96),
+    (240, 400, 64),
+];
+
+impl Rendition {
+    /// Passthrough rendition: the source streams, stream-copied.
+    pub fn source() -> Self {
+        Self {
+            name: "source".to_string(),
+            width: 0,
+            height: 0,
+            video_bitrate: 0,
+            audio_bitrate: 0,
+            video_codec: VideoCodec::Copy,
+            muxed_audio: true,
+            video_range: VideoRange::Sdr,
+            tone_map: false,
+            audio_gain: 0.0,
+            audio_codec: AudioCodec::Copy,
+            watermarks: Vec::new(),
+            field_order: FieldOrder::Progressive,
+            frame_rate: None,
+        }
+    }
+
+    /// An H.264 rung whose short side is `size` pixels.
+    pub fn h264(size: u32, video_kbps: u64, audio_kbps: u64) -> Self {
+        Self::encoded_as(VideoCodec::H264, size, video_kbps, audio_kbps)
+    }
+
+    /// A rung encoded to `codec` whose short side is `size` pixels. Rungs other than
+    /// H.264 have the codec in their name, so that a ladder can hold several codecs
+    /// at the same size.
+    pub fn encoded_as(codec: VideoCodec, size: u32, video_kbps: u64, audio_kbps: u64) -> Self {
+        let name = match codec {
+            VideoCodec::H264 | VideoCodec::Copy => format!("{}p", size),
+            _ => format!("{}p-{}", size, codec.name()),
+        };
+        Self {
+            name,
+            width: size,
+            height: size,
+            video_bitrate: video_kbps * 1000,
+            audio_bitrate: audio_kbps * 1000,
+            video_codec: codec,
+            muxed_audio: true,
+            video_range: VideoRange::Sdr,
+            tone_map: false,
+            audio_gain: 0.0,
+            audio_codec: AudioCodec::Copy,
+            watermarks: Vec::new(),
+            field_order: FieldOrder::Progressive,
+            frame_rate: None,
+        }
+    }
+
+    pub fn is_copy(&self) -> bool {
+        self.video_codec == VideoCodec::Copy
+    }
+
+    /// The rendition with its video re-encoded. A passthrough rendition becomes an
+    /// H.264 one at the size it was resolved to, with the bitrate of the largest
+    /// preset that fits.
+    pub fn encoded(&self) -> Self {
+        self.encoded_to(VideoCodec::H264)
+    }
+
+    /// Like `encoded`, a passthrough rendition becoming one of `codec`.
+    pub fn encoded_to(&self, codec: VideoCodec) -> Self {
+        if !self.is_copy() {
+            return self.clone();
+        }
+        let short = self.short_side();
+        let (_, video_kbps, audio_kbps) = PRESETS
+            .iter()
+            .find(|(size, _, _)| *size <= short)
+            .unwrap_or(&PRESETS[PRESETS.len() - 1]);
+        Self {
+            video_bitrate: video_kbps * 1000 * codec.bitrate_percent() / 100,
+            audio_bitrate: audio_kbps * 1000,
+            video_codec: codec,
+            ..self.clone()
+        }
+    }
+
+    /// What the partial GOPs at the trim points of a passthrough rendition are
+    /// encoded as: video of the source codec, `source_codec` as FFmpeg names it, so
+    /// that players of the copied video can play them too.
+    pub fn for_partial_gops(&self, source_codec: &str) -> Self {
+        self.encoded_to(VideoCodec::parse(source_codec).unwrap_or(VideoCodec::H264))
+    }
+
+    /// Short side of the rendition, which is what names like "720p" refer to.
+    pub fn short_side(&self) -> u32 {
+        self.width.min(self.height)
+    }
+
+    /// Resolve the output dimensions against a `source_width`x`source_height` source,
+    /// keeping its aspect ratio and orientation. Dimensions are rounded to even
+    /// numbers as required by 4:2:0 chroma subsampling.
+    pub fn fit_to(&self, source_width: u32, source_height: u32) -> Self {
+        let mut fitted = self.clone();
+        if self.is_copy() || source_width == 0 || source_height == 0 {
+            fitted.width = source_width;
+            fitted.height = source_height;
+            return fitted;
+        }
+
+        let short = self.short_side().min(source_width.min(source_height));
+        let scale = |long: u32, short_source: u32| -> u32 {
+            let value = (long as f64 * short as f64 / short_source as f64).round() as u32;
+            value + value % 2
+        };
+        let short = short + short % 2;
+        if source_width >= source_height {
+            fitted.height = short;
+            fitted.width = scale(source_width, source_height);
+        } else {
+            fitted.width = short;
+            fitted.height = scale(source_height, source_width);
+        }
+        fitted
+    }
+}
+
+/// How the audio of a rendition is produced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioCodec {
+    /// Stream-copy the source audio without re-encoding.
+    #[default]
+    Copy,
+    Aac,
+}
+
+/// Sample rates AAC has an index for.
+const AAC_SAMPLE_RATES: &[u32] = &[
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Sample rates of AC-3 and E-AC-3 at full rate.
+const AC3_SAMPLE_RATES: &[u32] = &[48000, 44100, 32000];
+
+/// Why audio of `codec` (as FFmpeg names it) at `sample_rate` Hz can't be
+/// stream-copied into `container` segments that players decode, or `None` when
+/// it can.
+pub fn audio_copy_blocker(codec: &str, sample_rate: u32, container: Container) -> Option<String> {
+    let rates = match (codec, container) {
+        ("aac", _) => AAC_SAMPLE_RATES,
+        ("ac3" | "eac3", _) => AC3_SAMPLE_RATES,
+        ("mp3", Container::MpegTs) => return None,
+        _ => {
+            return Some(format!(
+                "{} audio is not playable from {} segments",
+                codec,
+                container.extension()
+            ))
+        }
+    };
+    (!rates.contains(&sample_rate))
+        .then(|| format!("{} audio at {} Hz is not playable", codec, sample_rate))
+}
+
+/// How a source audio stream is carried, recorded with the video status so that
+/// re-encoded streams say why.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioDecision {
+    /// Index of the audio stream in the source.
+    pub stream_index: usize,
+    /// Codec of the source stream, as FFmpeg names it.
+    pub source_codec: String,
+    pub sample_rate: u32,
+    /// How the stream is carried when the video is copied; encoded renditions always
+    /// get AAC.
+    pub codec: AudioCodec,
+    /// Why the stream is re-encoded rather than copied.
+    pub reason: Option<String>,
+}
+
+impl AudioDecision {
+    /// Copy stream `stream_index` of `codec` at `sample_rate` Hz into `container`
+    /// segments if players can decode it, else transcode it to AAC.
+    pub fn new(stream_index: usize, codec: &str, sample_rate: u32, container: Container) -> Self {
+        let reason = audio_copy_blocker(codec, sample_rate, container);
+        Self {
+            stream_index,
+            source_codec: codec.to_string(),
+            sample_rate,
+            codec: match reason {
+                Some(_) => AudioCodec::Aac,
+                None => AudioCodec::Copy,
+            },
+            reason,
+        }
+    }
+}
+
+/// Bitrate of alternate audio renditions encoded to AAC.
+pub const AUDIO_TRACK_BITRATE: u64 = 128_000;
+
+/// One audio stream of the source, packaged as its own audio-only rendition and
+/// advertised with `EXT-X-MEDIA`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioTrack {
+    /// Name of the track, also used as its directory in the HLS output.
+    pub name: String,
+    /// Index of the audio stream in the source.
+    pub stream_index: usize,
+    /// RFC 5646 language tag, when the source declares one.
+    pub language: Option<String>,
+    /// Human readable name shown by players.
+    pub title: String,
+    pub default: bool,
+    pub channels: u16,
+    pub codec: AudioCodec,
+    /// Target bitrate in bits per second (ignored when copying).
+    pub bitrate: u64,
+    /// Loudness of the source stream, when measured.
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+    /// Gain in dB bringing the audio to the loudness target (ignored when copying).
+    #[serde(default)]
+    pub gain: f64,
+}
+
+/// One text subtitle stream of the source, or a sidecar caption file, converted to
+/// segmented WebVTT and advertised with `EXT-X-MEDIA`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    /// Name of the track, also used as its directory in the HLS output.
+    pub name: String,
+    /// Index of the subtitle stream in the source, `None` for captions uploaded
+    /// on their own.
+    pub stream_index: Option<usize>,
+    /// RFC 5646 language tag, when the source declares one.
+    pub language: Option<String>,
+    /// Human readable name shown by players.
+    pub title: String,
+    pub default: bool,
+    /// Shown even when the user hasn't turned subtitles on, e.g. to translate
+    /// foreign dialogue.
+    pub forced: bool,
+}
+
+/// ISO 639-2 codes of common languages, with their ISO 639-1 code and English name.
+const LANGUAGES: &[(&str, &str, &str)] = &[
+    ("ara", "ar", "Arabic"),
+    ("chi", "zh", "Chinese"),
+    ("zho", "zh", "Chinese"),
+    ("cze", "cs", "Czech"),
+    ("ces", "cs", "Czech"),
+    ("dan", "da", "Danish"),
+    ("dut", "nl", "Dutch"),
+    ("nld", "nl", "Dutch"),
+    ("eng", "en", "English"),
+    ("fin", "fi", "Finnish"),
+    ("fre", "fr", "French"),
+    ("fra", "fr", "French"),
+    ("ger", "de", "German"),
+    ("deu", "de", "German"),
+    ("gre", "el", "Greek"),
+    ("ell", "el", "Greek"),
+    ("heb", "he", "Hebrew"),
+    ("hin", "hi", "Hindi"),
+    ("hun", "hu", "Hungarian"),
+    ("ita", "it", "Italian"),
+    ("jpn", "ja", "Japanese"),
+    ("kor", "ko", "Korean"),
+    ("nor", "no", "Norwegian"),
+    ("pol", "pl", "Polish"),
+    ("por", "pt", "Portuguese"),
+    ("rum", "ro", "Romanian"),
+    ("ron", "ro", "Romanian"),
+    ("rus", "ru", "Russian"),
+    ("spa", "es", "Spanish"),
+    ("swe", "sv", "Swedish"),
+    ("tha", "th", "Thai"),
+    ("tur", "tr", "Turkish"),
+    ("ukr", "uk", "Ukrainian"),
+    ("vie", "vi", "Vietnamese"),
+];
+
+/// Map an ISO 639-2 code, as found in container metadata, to the shortest RFC 5646
+/// tag for it. Unknown codes are kept as they are; "und" means no language.
+pub fn language_tag(code: &str) -> Option<String> {
+    let code = code.trim().to_lowercase();
+    if code.is_empty() || code == "und" {
+        return None;
+    }
+    let tag = LANGUAGES
+        .iter()
+        .find(|(long, _, _)| *long == code)
+        .map(|(_, short, _)| short.to_string());
+    Some(tag.unwrap_or(code))
+}
+
+/// English name of the language of RFC 5646 `tag`, for track names players show,
+/// e.g. `Portuguese (pt-br)`. `None` for languages we have no name for.
+pub fn language_name(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    let primary = tag.split('-').next()?;
+    let (_, _, name) = LANGUAGES
+        .iter()
+        .find(|(long, short, _)| *short == primary || *long == primary)?;
+    Some(match tag.contains('-') {
+        true => format!("{} ({})", name, tag),
+        false => name.to_string(),
+    })
+}
+
+/// Parse a ladder description such as `"2160p@hevc,1080p,720p:2500,source"`.
+///
+/// Each entry is `source` (stream copy) or `<short side>p`, optionally followed by
+/// `:<video kbps>` to override the preset bitrate, then by `@<codec>` (`h264`,
+/// `hevc` or `av1`, H.264 by default). Preset bitrates are lowered for the more
+/// efficient codecs. Sizes without a preset need an explicit bitrate.
+pub fn parse_ladder(spec: &str) -> Result<Vec<Rendition>, String> {
+    let mut ladder = Vec::new();
+
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        if entry == "source" {
+            ladder.push(Rendition::source());
+            continue;
+        }
+
+        let (rung, codec) = match entry.split_once('@') {
+            Some((rung, codec)) => (rung, VideoCodec::parse(codec)?),
+            None => (entry, VideoCodec::H264),
+        };
+        let (size, bitrate) = match rung.split_once(':') {
+            Some((size, bitrate)) => (size, Some(bitrate)),
+            None => (rung, None),
+        };
+        let size: u32 = size
+            .strip_suffix('p')
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| format!("Invalid rendition '{}'", entry))?;
+        let preset = PRESETS.iter().find(|(s, _, _)| *s == size);
+
+        let video_kbps = match (bitrate, preset) {
+            (Some(b), _) => b
+                .parse()
+                .map_err(|_| format!("Invalid bitrate in rendition '{}'", entry))?,
+            (None, Some((_, kbps, _))) => kbps * codec.bitrate_percent() / 100,
+            (None, None) => return Err(format!("No preset bitrate for rendition '{}'", entry)),
+        };
+        let audio_kbps = preset.map(|(_, _, a)| *a).unwrap_or(128);
+
+        ladder.push(Rendition::encoded_as(codec, size, video_kbps, audio_kbps));
+    }
+
+    if ladder.is_empty() {
+        return Err("Ladder has no renditions".to_string());
+    }
+    Ok(ladder)
+}
+
+pub fn default_ladder() -> Vec<Rendition> {
+    parse_ladder(DEFAULT_LADDER).expect("default ladder is valid")
+}
+
+/// Select the rungs of `ladder` worth producing for a source, resolved to its size.
+///
+/// Rungs larger than the source are dropped since upscaling only wastes bits; if
+/// that leaves no encoded rung, the smallest one is kept at the source size.
+pub fn ladder_for_source(ladder: &[Rendition], width: u32, height: u32) -> Vec<Rendition> {
+    let source_short = width.min(height);
+    let mut selected: Vec<Rendition> = ladder
+        .iter()
+        .filter(|r| r.is_copy() || r.short_side() <= source_short)
+        .map(|r| r.fit_to(width, height))
+        .collect();
+
+    if !selected.iter().any(|r| !r.is_copy()) {
+        if let Some(smallest) = ladder
+            .iter()
+            .filter(|r| !r.is_copy())
+            .min_by_key(|r| r.short_side())
+        {
+            selected.push(smallest.fit_to(width, height));
+        }
+    }
+    selected
+}
+
+/// Adapt `renditions` of a `width`x`height` source to its dynamic range `range`.
+///
+/// HDR is kept by stream copies and by the codecs that carry it (HEVC and AV1),
+/// while H.264 rungs are tone-mapped to SDR. When that leaves no SDR rung, a
+/// tone-mapped H.264 one of up to 1080p is added for displays without HDR.
+pub fn for_video_range(
+    renditions: &[Rendition],
+    range: VideoRange,
+    width: u32,
+    height: u32,
+) -> Vec<Rendition> {
+    if range == VideoRange::Sdr {
+        return renditions.to_vec();
+    }
+
+    let mut adapted: Vec<Rendition> = renditions
+        .iter()
+        .map(|rendition| match rendition.video_codec {
+            VideoCodec::H264 => Rendition {
+                video_range: VideoRange::Sdr,
+                tone_map: true,
+                ..rendition.clone()
+            },
+            _ => Rendition {
+                video_range: range,
+                ..rendition.clone()
+            },
+        })
+        .collect();
+
+    if adapted.iter().all(|r| r.video_range != VideoRange::Sdr) {
+        let short = width.min(height).min(1080);
+        let (size, video_kbps, audio_kbps) = PRESETS
+            .iter()
+            .find(|(size, _, _)| *size <= short)
+            .unwrap_or(&PRESETS[PRESETS.len() - 1]);
+        adapted.push(Rendition {
+            tone_map: true,
+            ..Rendition::h264(*size, *video_kbps, *audio_kbps).fit_to(width, height)
+        });
+    }
+    adapted
+}
+
+/// Every rung of the ladder is a stream copy, so nothing gets re-encoded.
+pub fn is_passthrough(ladder: &[Rendition]) -> bool {
+    ladder.iter().all(Rendition::is_copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ladder() {
+        let ladder = parse_ladder("1080p, 720p:2500,source").unwrap();
+        assert_eq!(ladder.len(), 3);
+        assert_eq!(ladder[0].video_bitrate, 5_000_000);
+        assert_eq!(ladder[1].video_bitrate, 2_500_000);
+        assert!(ladder[2].is_copy());
+
+        assert!(parse_ladder("540p").is_err());
+        assert!(parse_ladder("540p:1800").is_ok());
+        assert!(parse_ladder("big").is_err());
+    }
+
+    #[test]
+    fn test_parse_ladder_codecs() {
+        let ladder = parse_ladder("2160p@hevc,1080p@AV1,1080p,720p:2000@hevc").unwrap();
+        let names: Vec<_> = ladder.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["2160p-hevc", "1080p-av1", "1080p", "720p-hevc"]);
+        assert_eq!(ladder[0].video_codec, VideoCodec::Hevc);
+        assert_eq!(ladder[0].video_bitrate, 8_400_000);
+        assert_eq!(ladder[1].video_codec, VideoCodec::Av1);
+        assert_eq!(ladder[1].video_bitrate, 2_500_000);
+        // Explicit bitrates are kept as they are.
+        assert_eq!(ladder[3].video_bitrate, 2_000_000);
+
+        assert!(parse_ladder("1080p@vp8").is_err());
+    }
+
+    #[test]
+    fn test_ladder_for_source() {
+        let ladder = default_ladder();
+
+        let landscape = ladder_for_source(&ladder, 1280, 720);
+        let sizes: Vec<_> = landscape.iter().map(|r| (r.width, r.height)).collect();
+        assert_eq!(sizes, vec![(1280, 720), (854, 480), (640, 360)]);
+
+        let portrait = ladder_for_source(&ladder, 1080, 1920);
+        assert_eq!((portrait[0].width, portrait[0].height), (1080, 1920));
+        assert_eq!((portrait[3].width, portrait[3].height), (360, 640));
+
+        let tiny = ladder_for_source(&ladder, 320, 240);
+        assert_eq!(tiny.len(), 1);
+        assert_eq!((tiny[0].width, tiny[0].height), (320, 240));
+    }
+
+    #[test]
+    fn test_encoded() {
+        let source = Rendition::source().fit_to(1280, 720);
+        let encoded = source.encoded();
+        assert_eq!(encoded.name, "source");
+        assert_eq!((encoded.width, encoded.height), (1280, 720));
+        assert_eq!(encoded.video_codec, VideoCodec::H264);
+        assert_eq!(encoded.video_bitrate, 2_800_000);
+
+        let h264 = Rendition::h264(480, 1_400, 96);
+        assert_eq!(h264.encoded(), h264);
+
+        let hevc = source.for_partial_gops("hevc");
+        assert_eq!(hevc.video_codec, VideoCodec::Hevc);
+        assert_eq!(hevc.video_bitrate, 1_680_000);
+    }
+
+    #[test]
+    fn test_for_video_range() {
+        let ladder = parse_ladder("2160p@hevc,1080p@hevc,1080p").unwrap();
+        let ladder = ladder_for_source(&ladder, 3840, 2160);
+
+        assert_eq!(
+            for_video_range(&ladder, VideoRange::Sdr, 3840, 2160),
+            ladder
+        );
+
+        let hdr = for_video_range(&ladder, VideoRange::Pq, 3840, 2160);
+        let ranges: Vec<_> = hdr.iter().map(|r| (r.video_range, r.tone_map)).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (VideoRange::Pq, false),
+                (VideoRange::Pq, false),
+                (VideoRange::Sdr, true)
+            ]
+        );
+
+        // Without an H.264 rung, one is added for SDR displays.
+        let hlg = for_video_range(&[Rendition::source()], VideoRange::Hlg, 1920, 1080);
+        assert_eq!(hlg.len(), 2);
+        assert_eq!(hlg[0].video_range, VideoRange::Hlg);
+        assert_eq!(hlg[1].name, "1080p");
+        assert_eq!((hlg[1].width, hlg[1].height), (1920, 1080));
+        assert!(hlg[1].tone_map);
+    }
+
+    #[test]
+    fn test_language_tag() {
+        assert_eq!(language_tag("eng").as_deref(), Some("en"));
+        assert_eq!(language_tag("FRE").as_deref(), Some("fr"));
+        assert_eq!(language_tag("tlh").as_deref(), Some("tlh"));
+        assert_eq!(language_tag("und"), None);
+        assert_eq!(language_tag(""), None);
+    }
+
+    #[test]
+    fn test_language_name() {
+        assert_eq!(language_name("en").as_deref(), Some("English"));
+        assert_eq!(
+            language_name("pt-BR").as_deref(),
+            Some("Portuguese (pt-br)")
+        );
+        assert_eq!(language_name("ger").as_deref(), Some("German"));
+        assert_eq!(language_name("tlh"), None);
+    }
+
+    #[test]
+    fn test_audio_decision() {
+        let aac = AudioDecision::new(1, "aac", 48000, Container::Fmp4);
+        assert_eq!((aac.codec, aac.reason), (AudioCodec::Copy, None));
+        assert_eq!(
+            AudioDecision::new(1, "eac3", 44100, Container::MpegTs).codec,
+            AudioCodec::Copy
+        );
+
+        let opus = AudioDecision::new(2, "opus", 48000, Container::Fmp4);
+        assert_eq!(opus.codec, AudioCodec::Aac);
+        assert_eq!(
+            opus.reason.as_deref(),
+            Some("opus audio is not playable from mp4 segments")
+        );
+        for codec in ["vorbis", "pcm_s16le", "flac"] {
+            assert_eq!(
+                AudioDecision::new(0, codec, 48000, Container::Fmp4).codec,
+                AudioCodec::Aac
+            );
+        }
+
+        let ac3 = AudioDecision::new(1, "ac3", 22050, Container::Fmp4);
+        assert_eq!(ac3.codec, AudioCodec::Aac);
+        assert_eq!(
+            ac3.reason.as_deref(),
+            Some("ac3 audio at 22050 Hz is not playable")
+        );
+
+        // MP3 only has a place in transport streams.
+        assert_eq!(audio_copy_blocker("mp3", 44100, Container::MpegTs), None);
+        assert!(audio_copy_blocker("mp3", 44100, Container::Fmp4).is_some());
+    }
+}
@@ -0,0 +1,415 @@
+//! Segment planning: grouping the keyframes of a source into the segments it is
+//! packaged as.
+
+use serde::{Deserialize, Serialize};
+
+/// Segment duration used when none is configured, as `target:min:max` seconds.
+pub const DEFAULT_SEGMENT_DURATION: &str = "6:2:10";
+
+/// Durations, in seconds, segments are planned towards.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SegmentTarget {
+    pub target: f64,
+    /// Segments are only cut shorter than this at the end of the video.
+    pub min: f64,
+    /// Segments only run longer than this when the source has no keyframe to cut at.
+    pub max: f64,
+}
+
+impl Default for SegmentTarget {
+    fn default() -> Self {
+        parse_segment_target(DEFAULT_SEGMENT_DURATION).expect("default segment duration is valid")
+    }
+}
+
+/// Parse a segment duration such as `"6"` or `"6:2:10"` (target, min and max
+/// seconds). A lone target is bounded by a third of it and twice it.
+pub fn parse_segment_target(spec: &str) -> Result<SegmentTarget, String> {
+    let values = spec
+        .split(':')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid segment duration '{}'", spec))?;
+
+    let target = match values[..] {
+        [target] => SegmentTarget {
+            target,
+            min: target / 3.0,
+            max: target * 2.0,
+        },
+        [target, min, max] => SegmentTarget { target, min, max },
+        _ => return Err(format!("Invalid segment duration '{}'", spec)),
+    };
+
+    if !(target.min > 0.0 && target.min <= target.target && target.target <= target.max) {
+        return Err(format!(
+            "Segment duration '{}' needs 0 < min <= target <= max",
+            spec
+        ));
+    }
+    Ok(target)
+}
+
+/// Keyframes and trim points this close, in seconds, are the same time.
+const TIME_TOLERANCE: f64 = 0.001;
+
+/// One planned segment, starting on a source keyframe.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlannedSegment {
+    pub start: f64,
+    pub duration: f64,
+    /// Whether the segment is part of a source GOP, cut at a trim point, so that
+    /// even copied renditions re-encode its video.
+    #[serde(default)]
+    pub partial: bool,
+}
+
+/// A keyframe of a written segment, as an I-frame playlist addresses it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Decode time in seconds, on the source timeline.
+    pub time: f64,
+    /// Byte offset in the segment of what a player loads to show the keyframe:
+    /// the movie fragment it starts, or the transport stream tables before it.
+    pub offset: u64,
+    /// Bytes from `offset` through the end of the keyframe.
+    pub length: u64,
+}
+
+/// The segments a video is cut into, in order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentPlan {
+    pub segments: Vec<PlannedSegment>,
+    /// Whether segments start regardless of the source keyframes, so every
+    /// rendition has to be re-encoded with a keyframe forced at each start.
+    #[serde(default)]
+    pub forced_keyframes: bool,
+}
+
+impl SegmentPlan {
+    /// Plan segments over `keyframes`, the keyframe timestamps of the source followed
+    /// by its end.
+    ///
+    /// Each segment ends on the keyframe that brings it closest to the target while
+    /// staying within bounds, avoiding cuts that would leave a tail shorter than the
+    /// minimum. Without a keyframe within bounds, the segment runs to the first
+    /// keyframe past the minimum.
+    pub fn new(keyframes: &[f64], target: &SegmentTarget) -> Self {
+        let mut segments = Vec::new();
+        let Some(&end) = keyframes.last() else {
+            return Self::default();
+        };
+
+        let mut i = 0;
+        while i + 1 < keyframes.len() {
+            let start = keyframes[i];
+            let next = if end - start <= target.max {
+                keyframes.len() - 1
+            } else {
+                let leaves_short_tail = |j: usize| {
+                    let tail = end - keyframes[j];
+                    tail > 0.0 && tail < target.min
+                };
+                let in_bounds = (i + 1..keyframes.len()).filter(|&j| {
+                    let duration = keyframes[j] - start;
+                    duration >= target.min && duration <= target.max
+                });
+                in_bounds
+                    .clone()
+                    .filter(|&j| !leaves_short_tail(j))
+                    .min_by(|&a, &b| {
+                        let distance = |j: usize| (keyframes[j] - start - target.target).abs();
+                        distance(a).total_cmp(&distance(b))
+                    })
+                    .or_else(|| in_bounds.max())
+                    .or_else(|| {
+                        (i + 1..keyframes.len()).find(|&j| keyframes[j] - start >= target.min)
+                    })
+                    .unwrap_or(keyframes.len() - 1)
+            };
+
+            segments.push(PlannedSegment {
+                start,
+                duration: keyframes[next] - start,
+                partial: false,
+            });
+            i = next;
+        }
+
+        Self {
+            segments,
+            forced_keyframes: false,
+        }
+    }
+
+    /// Plan segments of about the target duration over the span of `keyframes`,
+    /// ignoring where the keyframes themselves are. For sources whose keyframes are
+    /// too sparse to cut at.
+    pub fn forced(keyframes: &[f64], target: &SegmentTarget) -> Self {
+        let (Some(&start), Some(&end)) = (keyframes.first(), keyframes.last()) else {
+            return Self::default();
+        };
+        if end <= start {
+            return Self::default();
+        }
+
+        // Equal segments, as many as makes them closest to the target.
+        let count = ((end - start) / target.target).round().max(1.0) as usize;
+        let duration = (end - start) / count as f64;
+        let segments = (0..count)
+            .map(|i| PlannedSegment {
+                start: start + i as f64 * duration,
+                duration,
+                partial: false,
+            })
+            .collect();
+
+        Self {
+            segments,
+            forced_keyframes: true,
+        }
+    }
+
+    /// Plan segments of the target duration over audio from `start` to `end`
+    /// seconds, which can be cut at any frame. A tail shorter than the minimum joins
+    /// the last segment when that keeps it within the maximum.
+    pub fn fixed(start: f64, end: f64, target: &SegmentTarget) -> Self {
+        let duration = end - start;
+        if duration.is_nan() || duration <= 0.0 {
+            return Self::default();
+        }
+
+        let count = (duration / target.target).floor() as usize;
+        let mut segments: Vec<PlannedSegment> = (0..count)
+            .map(|i| PlannedSegment {
+                start: start + i as f64 * target.target,
+                duration: target.target,
+                partial: false,
+            })
+            .collect();
+        // Less than a millisecond left over is rounding, not audio.
+        let tail = duration - count as f64 * target.target;
+        if tail > TIME_TOLERANCE {
+            match segments.last_mut() {
+                Some(last) if tail < target.min && last.duration + tail <= target.max => {
+                    last.duration += tail
+                }
+                _ => segments.push(PlannedSegment {
+                    start: start + count as f64 * target.target,
+                    duration: tail,
+                    partial: false,
+                }),
+            }
+        }
+
+        Self {
+            segments,
+            forced_keyframes: false,
+        }
+    }
+
+    /// Plan segments over the part of a source with `keyframes` from `start` to `end`
+    /// seconds, like `new`. A trim point inside a GOP leaves the part of the GOP
+    /// on the published side as a partial segment of its own, so that only it has
+    /// to be re-encoded.
+    pub fn trimmed(keyframes: &[f64], target: &SegmentTarget, start: f64, end: f64) -> Self {
+        if end - start <= TIME_TOLERANCE {
+            return Self::default();
+        }
+        let on_keyframe = |time: f64| {
+            keyframes
+                .iter()
+                .any(|keyframe| (keyframe - time).abs() <= TIME_TOLERANCE)
+        };
+        let inside: Vec<f64> = keyframes
+            .iter()
+            .copied()
+            .filter(|&keyframe| keyframe > start + TIME_TOLERANCE)
+            .filter(|&keyframe| keyframe < end - TIME_TOLERANCE)
+            .collect();
+        let (starts_on_keyframe, ends_on_keyframe) = (on_keyframe(start), on_keyframe(end));
+
+        // Whole GOPs are planned as usual, between the partial ones.
+        let whole: Vec<f64> = starts_on_keyframe
+            .then_some(start)
+            .into_iter()
+            .chain(inside.iter().copied())
+            .chain(ends_on_keyframe.then_some(end))
+            .collect();
+        let (Some(&first), Some(&last)) = (whole.first(), whole.last()) else {
+            // Not a single keyframe to cut at.
+            return Self {
+                segments: vec![PlannedSegment {
+                    start,
+                    duration: end - start,
+                    partial: true,
+                }],
+                forced_keyframes: false,
+            };
+        };
+        let partial = |from: f64, to: f64| PlannedSegment {
+            start: from,
+            duration: to - from,
+            partial: true,
+        };
+
+        let mut segments = Vec::new();
+        if !starts_on_keyframe {
+            segments.push(partial(start, first));
+        }
+        segments.extend(Self::new(&whole, target).segments);
+        if !ends_on_keyframe {
+            segments.push(partial(last, end));
+        }
+        Self {
+            segments,
+            forced_keyframes: false,
+        }
+    }
+
+    /// Whether the plan has partial segments at trim points.
+    pub fn has_partial(&self) -> bool {
+        self.segments.iter().any(|s| s.partial)
+    }
+
+    /// Whether cutting at keyframes left a segment longer than the maximum.
+    pub fn exceeds(&self, target: &SegmentTarget) -> bool {
+        self.segments.iter().any(|s| s.duration > target.max)
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn durations(&self) -> Vec<f64> {
+        self.segments.iter().map(|s| s.duration).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn durations(keyframes: &[f64], target: &str) -> Vec<f64> {
+        SegmentPlan::new(keyframes, &parse_segment_target(target).unwrap()).durations()
+    }
+
+    #[test]
+    fn test_parse_segment_target() {
+        assert_eq!(
+            parse_segment_target("6").unwrap(),
+            SegmentTarget {
+                target: 6.0,
+                min: 2.0,
+                max: 12.0
+            }
+        );
+        assert_eq!(SegmentTarget::default().max, 10.0);
+        assert!(parse_segment_target("6:8:10").is_err());
+        assert!(parse_segment_target("6:2").is_err());
+        assert!(parse_segment_target("six").is_err());
+    }
+
+    #[test]
+    fn test_plan_coalesces_short_gops() {
+        // Half-second GOPs over 20 seconds.
+        let keyframes: Vec<f64> = (0..=40).map(|i| i as f64 * 0.5).collect();
+        assert_eq!(durations(&keyframes, "6:2:10"), vec![6.0, 6.0, 8.0]);
+    }
+
+    #[test]
+    fn test_plan_long_gops() {
+        // Keyframes every 20 seconds: segments cannot be cut any shorter.
+        assert_eq!(
+            durations(&[0.0, 20.0, 40.0, 45.0], "6:2:10"),
+            vec![20.0, 20.0, 5.0]
+        );
+        // Irregular GOPs: keep the cut closest to the target.
+        assert_eq!(
+            durations(&[0.0, 1.0, 5.0, 9.0, 15.0, 16.0], "6:2:10"),
+            vec![5.0, 4.0, 7.0]
+        );
+    }
+
+    #[test]
+    fn test_forced_plan() {
+        let target = SegmentTarget::default();
+        // A single keyframe at the start of a minute long recording.
+        let keyframes = [0.0, 60.0];
+        let plan = SegmentPlan::new(&keyframes, &target);
+        assert!(plan.exceeds(&target));
+
+        let plan = SegmentPlan::forced(&keyframes, &target);
+        assert!(plan.forced_keyframes);
+        assert_eq!(plan.durations(), vec![6.0; 10]);
+        assert_eq!(plan.segments[9].start, 54.0);
+
+        assert_eq!(
+            SegmentPlan::forced(&[0.0, 15.0], &target).durations(),
+            vec![5.0, 5.0, 5.0]
+        );
+        assert!(SegmentPlan::forced(&[2.0], &target).is_empty());
+        assert!(!SegmentPlan::new(&[0.0, 6.0, 12.0], &target).exceeds(&target));
+    }
+
+    #[test]
+    fn test_fixed_plan() {
+        let target = SegmentTarget::default();
+        let plan = SegmentPlan::fixed(0.0, 19.5, &target);
+        assert!(!plan.forced_keyframes);
+        assert_eq!(plan.durations(), vec![6.0, 6.0, 7.5]);
+        assert_eq!(plan.segments[2].start, 12.0);
+
+        assert_eq!(
+            SegmentPlan::fixed(0.0, 22.5, &target).durations(),
+            vec![6.0, 6.0, 6.0, 4.5]
+        );
+        assert_eq!(SegmentPlan::fixed(0.0, 1.5, &target).durations(), vec![1.5]);
+        assert_eq!(
+            SegmentPlan::fixed(30.0, 40.0, &target).segments[1].start,
+            36.0
+        );
+        assert!(SegmentPlan::fixed(5.0, 5.0, &target).is_empty());
+    }
+
+    #[test]
+    fn test_trimmed_plan() {
+        let target = SegmentTarget::default();
+        // Two-second GOPs over a minute.
+        let keyframes: Vec<f64> = (0..=30).map(|i| i as f64 * 2.0).collect();
+
+        // Inside GOPs at both ends: the partial GOPs are segments of their own.
+        let plan = SegmentPlan::trimmed(&keyframes, &target, 9.5, 31.0);
+        assert_eq!(plan.durations(), vec![0.5, 6.0, 6.0, 8.0, 1.0]);
+        let partial: Vec<bool> = plan.segments.iter().map(|s| s.partial).collect();
+        assert_eq!(partial, vec![true, false, false, false, true]);
+        assert_eq!(plan.segments[1].start, 10.0);
+        assert!(plan.has_partial());
+
+        // On keyframes, nothing is partial.
+        let plan = SegmentPlan::trimmed(&keyframes, &target, 10.0, 60.0);
+        assert!(!plan.has_partial());
+        assert_eq!(plan.segments[0].start, 10.0);
+        assert_eq!(plan.durations().iter().sum::<f64>(), 50.0);
+
+        // Within a single GOP.
+        let plan = SegmentPlan::trimmed(&keyframes, &target, 10.5, 11.5);
+        assert_eq!(plan.segments.len(), 1);
+        assert!(plan.segments[0].partial);
+        assert!(SegmentPlan::trimmed(&keyframes, &target, 12.0, 12.0).is_empty());
+    }
+
+    #[test]
+    fn test_plan_edge_cases() {
+        assert!(SegmentPlan::new(&[], &SegmentTarget::default()).is_empty());
+        assert!(SegmentPlan::new(&[3.0], &SegmentTarget::default()).is_empty());
+        assert_eq!(durations(&[0.0, 4.0], "6:2:10"), vec![4.0]);
+
+        let plan = SegmentPlan::new(&[0.0, 6.0, 12.0, 14.0], &SegmentTarget::default());
+        assert_eq!(plan.segments[1].start, 6.0);
+    }
+}
@@ -0,0 +1,600 @@
+//! MPEG transport stream packet rewriting, used to join segments muxed on their own
+//! and to encrypt their samples.
+
+use super::segment_plan::Keyframe;
+use std::collections::HashMap;
+
+pub const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+/// Continuity counter of every PID across consecutive segments.
+#[derive(Debug, Default)]
+pub struct Continuity {
+    counters: HashMap<u16, u8>,
+}
+
+impl Continuity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renumber the continuity counters of `segment` so it carries on from the
+    /// segments seen before. Every segment is muxed on its own and starts its
+    /// counters from 0, which players would take as lost packets at each boundary.
+    ///
+    /// Packets without payload keep the counter of the previous packet, as
+    /// ISO/IEC 13818-1 requires. Stops at the first packet that is out of sync.
+    pub fn renumber(&mut self, segment: &mut [u8]) {
+        for packet in segment.chunks_exact_mut(PACKET_SIZE) {
+            if packet[0] != SYNC_BYTE {
+                return;
+            }
+            let pid = (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2]);
+            // Null packets carry no counter.
+            if pid == 0x1fff {
+                continue;
+            }
+            let has_payload = packet[3] & 0x10 != 0;
+
+            let counter = match (self.counters.get(&pid), has_payload) {
+                (Some(&last), true) => (last + 1) & 0x0f,
+                (Some(&last), false) => last,
+                (None, _) => packet[3] & 0x0f,
+            };
+            packet[3] = (packet[3] & 0xf0) | counter;
+            self.counters.insert(pid, counter);
+        }
+    }
+}
+
+/// The keyframe opening `segment`, which starts at `time` seconds, for I-frame
+/// playlists: from the first packet, so the PAT and PMT come along, through the
+/// last packet of the first video PES. Segments are cut on keyframes, so that PES
+/// is one. `None` if the segment has no video.
+pub fn keyframe(segment: &[u8], time: f64) -> Option<Keyframe> {
+    let mut video_pid = None;
+    let mut end = None;
+    for (i, packet) in segment.chunks_exact(PACKET_SIZE).enumerate() {
+        if packet[0] != SYNC_BYTE {
+            break;
+        }
+        let pid = (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2]);
+        let unit_start = packet[1] & 0x40 != 0;
+        match video_pid {
+            None if unit_start && is_video_pes(packet) => video_pid = Some(pid),
+            // The next PES of the video PID starts the next frame.
+            Some(video) if pid == video && unit_start => {
+                end = Some(i * PACKET_SIZE);
+                break;
+            }
+            _ => {}
+        }
+    }
+    video_pid?;
+
+    let length = end.unwrap_or(segment.len() - segment.len() % PACKET_SIZE);
+    Some(Keyframe {
+        time,
+        offset: 0,
+        length: length as u64,
+    })
+}
+
+/// Whether `packet` starts a PES of a video stream, stream ids 0xE0 to 0xEF.
+fn is_video_pes(packet: &[u8]) -> bool {
+    let payload = match packet[3] & 0x30 {
+        0x10 => 4,
+        0x30 => 5 + usize::from(packet[4]),
+        _ => return false,
+    };
+    matches!(
+        packet.get(payload..payload + 4),
+        Some([0x00, 0x00, 0x01, stream_id]) if stream_id & 0xf0 == 0xe0
+    )
+}
+
+/// PID of the program association table.
+const PAT_PID: u16 = 0;
+
+/// An elementary stream of the program, as the program map table lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementaryStream {
+    pub stream_type: u8,
+    pub pid: u16,
+    /// Raw descriptors of the stream.
+    pub descriptors: Vec<u8>,
+}
+
+fn pid(packet: &[u8]) -> u16 {
+    (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2])
+}
+
+/// Payload of `packet`, after its adaptation field if any.
+fn payload(packet: &[u8]) -> Option<&[u8]> {
+    match packet[3] & 0x30 {
+        0x10 => Some(&packet[4..]),
+        0x30 => packet.get(5 + usize::from(packet[4])..),
+        _ => None,
+    }
+}
+
+/// The PSI section starting in `packet`, if it starts one, from its table id.
+fn section(packet: &[u8]) -> Option<&[u8]> {
+    if packet[1] & 0x40 == 0 {
+        return None;
+    }
+    let payload = payload(packet)?;
+    let section = payload.get(1 + usize::from(*payload.first()?)..)?;
+    let length = (usize::from(section.get(1)? & 0x0f) << 8) | usize::from(*section.get(2)?);
+    section.get(..3 + length)
+}
+
+fn packets(segment: &[u8]) -> impl Iterator<Item = &[u8]> {
+    segment
+        .chunks_exact(PACKET_SIZE)
+        .take_while(|packet| packet[0] == SYNC_BYTE)
+}
+
+/// PID of the program map table, from the first program of the PAT.
+fn pmt_pid(segment: &[u8]) -> Option<u16> {
+    let pat = packets(segment)
+        .filter(|packet| pid(packet) == PAT_PID)
+        .find_map(section)?;
+    // Program loop after the 8 byte header, before the CRC; program 0 is the NIT.
+    pat.get(8..pat.len().checked_sub(4)?)?
+        .chunks_exact(4)
+        .find(|program| program[..2] != [0, 0])
+        .map(|program| (u16::from(program[2] & 0x1f) << 8) | u16::from(program[3]))
+}
+
+/// The elementary streams of the program of `segment`, in PMT order.
+pub fn streams(segment: &[u8]) -> Option<Vec<ElementaryStream>> {
+    let pmt_pid = pmt_pid(segment)?;
+    let pmt = packets(segment)
+        .filter(|packet| pid(packet) == pmt_pid)
+        .find_map(section)?;
+    let (_, entries) = split_pmt(pmt)?;
+
+    let mut streams = Vec::new();
+    let mut rest = entries;
+    while rest.len() >= 5 {
+        let info_length = (usize::from(rest[3] & 0x0f) << 8) | usize::from(rest[4]);
+        let descriptors = rest.get(5..5 + info_length)?;
+        streams.push(ElementaryStream {
+            stream_type: rest[0],
+            pid: (u16::from(rest[1] & 0x1f) << 8) | u16::from(rest[2]),
+            descriptors: descriptors.to_vec(),
+        });
+        rest = &rest[5 + info_length..];
+    }
+    Some(streams)
+}
+
+/// A PMT section split into its header through the program descriptors, and its
+/// stream entries before the CRC.
+fn split_pmt(pmt: &[u8]) -> Option<(&[u8], &[u8])> {
+    if pmt.first() != Some(&0x02) {
+        return None;
+    }
+    let program_info_length = (usize::from(pmt.get(10)? & 0x0f) << 8) | usize::from(*pmt.get(11)?);
+    let header_length = 12 + program_info_length;
+    let entries = pmt.get(header_length..pmt.len().checked_sub(4)?)?;
+    Some((&pmt[..header_length], entries))
+}
+
+/// Replace the stream entries of every PMT in `segment` with `streams`. The new
+/// table has to fit in the packet of the old one.
+pub fn rewrite_pmt(segment: &mut [u8], streams: &[ElementaryStream]) -> Result<(), String> {
+    let pmt_pid = pmt_pid(segment).ok_or("No program association table")?;
+
+    for packet in segment.chunks_exact_mut(PACKET_SIZE) {
+        if packet[0] != SYNC_BYTE {
+            break;
+        }
+        if pid(packet) != pmt_pid {
+            continue;
+        }
+        let Some(old) = section(packet) else {
+            continue;
+        };
+        let (header, _) = split_pmt(old).ok_or("Invalid program map table")?;
+
+        let mut pmt = header.to_vec();
+        for stream in streams {
+            pmt.push(stream.stream_type);
+            pmt.extend((0xe000 | stream.pid).to_be_bytes());
+            pmt.extend((0xf000 | stream.descriptors.len() as u16).to_be_bytes());
+            pmt.extend(&stream.descriptors);
+        }
+        // The section length counts from after itself through the CRC.
+        let length = (pmt.len() + 4 - 3) as u16;
+        pmt[1] = (pmt[1] & 0xf0) | (length >> 8) as u8;
+        pmt[2] = length as u8;
+        let crc = crc32(&pmt);
+        pmt.extend(crc.to_be_bytes());
+
+        // The section starts right after the pointer field of the payload.
+        let start = PACKET_SIZE - payload(packet).map_or(0, <[u8]>::len);
+        let start = start + 1 + usize::from(packet[start]);
+        if start + pmt.len() > PACKET_SIZE {
+            return Err("Program map table no longer fits in a packet".to_string());
+        }
+        packet[start..start + pmt.len()].copy_from_slice(&pmt);
+        packet[start + pmt.len()..].fill(0xff);
+    }
+    Ok(())
+}
+
+/// CRC-32 of PSI sections, as ISO/IEC 13818-1 specifies it.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// A PES of `pid` being reassembled, with the adaptation field of its first
+/// packet.
+struct Pes {
+    pid: u16,
+    adaptation: Option<Vec<u8>>,
+    counter: u8,
+    data: Vec<u8>,
+}
+
+/// Rewrite the PES payloads of `pids` in `segment` with `rewrite`, which is given
+/// each payload, after its PES header, to change in place. Every rewritten PES is
+/// packetized again where its first packet was, keeping the PCR and random access
+/// flag of that packet; other packets are kept as they are.
+pub fn rewrite_pes<F>(segment: &[u8], pids: &[u16], mut rewrite: F) -> Result<Vec<u8>, String>
+where
+    F: FnMut(u16, &mut Vec<u8>),
+{
+    enum Unit {
+        Packet(usize),
+        Pes(usize),
+    }
+    let mut units = Vec::new();
+    let mut pes: Vec<Pes> = Vec::new();
+    let mut open: HashMap<u16, usize> = HashMap::new();
+
+    for (i, packet) in packets(segment).enumerate() {
+        let pid = pid(packet);
+        let payload = payload(packet).filter(|_| pids.contains(&pid));
+        let Some(payload) = payload else {
+            units.push(Unit::Packet(i));
+            continue;
+        };
+        if packet[1] & 0x40 != 0 {
+            open.insert(pid, pes.len());
+            units.push(Unit::Pes(pes.len()));
+            pes.push(Pes {
+                pid,
+                adaptation: adaptation_fields(packet),
+                counter: packet[3] & 0x0f,
+                data: payload.to_vec(),
+            });
+        } else if let Some(&open) = open.get(&pid) {
+            pes[open].data.extend_from_slice(payload);
+        } else {
+            // The end of a PES started in an earlier segment.
+            units.push(Unit::Packet(i));
+        }
+    }
+
+    let mut counters: HashMap<u16, u8> = HashMap::new();
+    let mut out = Vec::with_capacity(segment.len() + segment.len() / 16);
+    for unit in units {
+        match unit {
+            Unit::Packet(i) => {
+                out.extend_from_slice(&segment[i * PACKET_SIZE..(i + 1) * PACKET_SIZE]);
+            }
+            Unit::Pes(i) => {
+                let Pes {
+                    pid,
+                    adaptation,
+                    counter,
+                    data,
+                } = &pes[i];
+                if data.len() < 9 || data[..3] != [0, 0, 1] {
+                    return Err(format!("Invalid PES on PID {}", pid));
+                }
+                let header_length = 9 + usize::from(data[8]);
+                let mut payload = data.get(header_length..).unwrap_or_default().to_vec();
+                rewrite(*pid, &mut payload);
+
+                let mut pes = data[..header_length.min(data.len())].to_vec();
+                pes.extend(payload);
+                // A length of 0 leaves it unbounded, which only video may be.
+                if pes[4..6] != [0, 0] {
+                    let length = pes.len() - 6;
+                    let length = match u16::try_from(length) {
+                        Ok(length) => length,
+                        Err(_) if (0xe0..=0xef).contains(&pes[3]) => 0,
+                        Err(_) => return Err(format!("PES on PID {} is too long", pid)),
+                    };
+                    pes[4..6].copy_from_slice(&length.to_be_bytes());
+                }
+                let counter = counters.entry(*pid).or_insert(*counter);
+                packetize(&mut out, *pid, adaptation.as_deref(), &pes, counter);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// The adaptation field of `packet` without its length and stuffing: flags, PCR
+/// and OPCR. Splicing, private data and extensions are dropped.
+fn adaptation_fields(packet: &[u8]) -> Option<Vec<u8>> {
+    if packet[3] & 0x20 == 0 || packet[4] == 0 {
+        return None;
+    }
+    let field = packet.get(5..5 + usize::from(packet[4]))?;
+    let flags = field[0] & 0xf8;
+    let mut fields = vec![flags];
+    let mut at = 1;
+    for flag in [0x10, 0x08] {
+        if flags & flag != 0 {
+            fields.extend_from_slice(field.get(at..at + 6)?);
+            at += 6;
+        }
+    }
+    Some(fields)
+}
+
+/// Append `pes` to `out` as packets of `pid`, the first one with adaptation
+/// `fields`, the last one stuffed to size.
+fn packetize(out: &mut Vec<u8>, pid: u16, fields: Option<&[u8]>, pes: &[u8], counter: &mut u8) {
+    let mut rest = pes;
+    let mut first = true;
+    while first || !rest.is_empty() {
+        let fields = fields.filter(|_| first).unwrap_or_default();
+        let fields_length = if fields.is_empty() {
+            0
+        } else {
+            1 + fields.len()
+        };
+        let room = PACKET_SIZE - 4 - fields_length;
+        let (chunk, tail) = rest.split_at(rest.len().min(room));
+        let adaptation_length = fields_length + room - chunk.len();
+
+        let unit_start = if first { 0x40 } else { 0 };
+        out.extend([SYNC_BYTE, unit_start | (pid >> 8) as u8, pid as u8]);
+        let has_adaptation = adaptation_length > 0;
+        out.push(if has_adaptation { 0x30 } else { 0x10 } | *counter);
+        if has_adaptation {
+            out.push((adaptation_length - 1) as u8);
+            if adaptation_length > 1 {
+                match fields.split_first() {
+                    Some((flags, fields)) => {
+                        out.push(*flags);
+                        out.extend_from_slice(fields);
+                    }
+                    None => out.push(0),
+                }
+                let stuffing = adaptation_length - 1 - fields.len().max(1);
+                out.extend(std::iter::repeat_n(0xff, stuffing));
+            }
+        }
+        out.extend_from_slice(chunk);
+
+        *counter = (*counter + 1) & 0x0f;
+        rest = tail;
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(pid: u16, counter: u8, payload: bool) -> Vec<u8> {
+        let mut packet = vec![0xff; PACKET_SIZE];
+        packet[0] = SYNC_BYTE;
+        packet[1] = (pid >> 8) as u8 & 0x1f;
+        packet[2] = pid as u8;
+        packet[3] = if payload { 0x10 } else { 0x20 } | counter;
+        packet
+    }
+
+    fn pes(pid: u16, stream_id: u8) -> Vec<u8> {
+        let mut packet = packet(pid, 0, true);
+        packet[1] |= 0x40;
+        packet[4..8].copy_from_slice(&[0x00, 0x00, 0x01, stream_id]);
+        packet
+    }
+
+    fn counters(segment: &[u8]) -> Vec<u8> {
+        segment
+            .chunks_exact(PACKET_SIZE)
+            .map(|packet| packet[3] & 0x0f)
+            .collect()
+    }
+
+    #[test]
+    fn test_renumber() {
+        let mut continuity = Continuity::new();
+
+        // Both segments start their counters from 0, as muxed.
+        let mut first: Vec<u8> = [
+            packet(0, 0, true),
+            packet(256, 0, true),
+            packet(256, 1, true),
+        ]
+        .concat();
+        let mut second: Vec<u8> = [
+            packet(0, 0, true),
+            packet(256, 0, true),
+            packet(256, 0, false),
+            packet(256, 1, true),
+        ]
+        .concat();
+
+        continuity.renumber(&mut first);
+        continuity.renumber(&mut second);
+
+        assert_eq!(counters(&first), vec![0, 0, 1]);
+        assert_eq!(counters(&second), vec![1, 2, 2, 3]);
+    }
+
+    #[test]
+    fn test_keyframe() {
+        // PAT, PMT, audio, the keyframe over two packets, then the next frame.
+        let segment: Vec<u8> = [
+            packet(0, 0, true),
+            packet(4096, 0, true),
+            pes(257, 0xc0),
+            pes(256, 0xe0),
+            packet(256, 1, true),
+            pes(256, 0xe0),
+        ]
+        .concat();
+
+        let keyframe = keyframe(&segment, 12.0).unwrap();
+        assert_eq!(
+            keyframe,
+            Keyframe {
+                time: 12.0,
+                offset: 0,
+                length: 5 * PACKET_SIZE as u64,
+            }
+        );
+
+        let audio_only: Vec<u8> = [packet(0, 0, true), pes(257, 0xc0)].concat();
+        assert_eq!(super::keyframe(&audio_only, 0.0), None);
+    }
+
+    /// A packet of `pid` starting PSI `section`, CRC added.
+    fn psi(pid: u16, mut section: Vec<u8>) -> Vec<u8> {
+        let length = (section.len() + 4 - 3) as u16;
+        section[1] = 0xb0 | (length >> 8) as u8;
+        section[2] = length as u8;
+        section.extend(crc32(&section).to_be_bytes());
+
+        let mut packet = vec![SYNC_BYTE, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0];
+        packet.extend(section);
+        packet.resize(PACKET_SIZE, 0xff);
+        packet
+    }
+
+    /// PAT and PMT of a program of H.264 on PID 256 and AAC on PID 257.
+    fn tables() -> Vec<u8> {
+        let pat = vec![0x00, 0, 0, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00];
+        let pmt = vec![
+            0x02, 0, 0, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00, // header
+            0x1b, 0xe1, 0x00, 0xf0, 0x00, // H.264
+            0x0f, 0xe1, 0x01, 0xf0, 0x00, // AAC
+        ];
+        [psi(0, pat), psi(4096, pmt)].concat()
+    }
+
+    /// The PES of `pid` in `segment`, reassembled.
+    fn pes_of(segment: &[u8], pid: u16) -> Vec<Vec<u8>> {
+        let mut pes: Vec<Vec<u8>> = Vec::new();
+        for packet in packets(segment).filter(|packet| super::pid(packet) == pid) {
+            if packet[1] & 0x40 != 0 {
+                pes.push(Vec::new());
+            }
+            pes.last_mut().unwrap().extend(payload(packet).unwrap());
+        }
+        pes
+    }
+
+    #[test]
+    fn test_rewrite_pes() {
+        // A video PES over two packets, the first with a PCR, then an audio PES.
+        let mut video = vec![0, 0, 1, 0xe0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1];
+        video.extend((0..300).map(|i| i as u8));
+        let mut first = vec![SYNC_BYTE, 0x41, 0x00, 0x30, 7, 0x50, 1, 2, 3, 4, 5, 6];
+        let split = PACKET_SIZE - first.len();
+        first.extend(&video[..split]);
+        let mut second = vec![
+            SYNC_BYTE,
+            0x01,
+            0x00,
+            0x31,
+            (PACKET_SIZE - 5 - (video.len() - split)) as u8,
+            0,
+        ];
+        second.resize(PACKET_SIZE - (video.len() - split), 0xff);
+        second.extend(&video[split..]);
+        let audio = [
+            &[0, 0, 1, 0xc0, 0, 14, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1][..],
+            &[0xaa; 6],
+        ]
+        .concat();
+        let mut third = vec![
+            SYNC_BYTE,
+            0x41,
+            0x01,
+            0x30,
+            (PACKET_SIZE - 5 - audio.len()) as u8,
+            0,
+        ];
+        third.resize(PACKET_SIZE - audio.len(), 0xff);
+        third.extend(&audio);
+        let segment = [tables(), first, second, third].concat();
+
+        let rewritten = rewrite_pes(&segment, &[256, 257], |pid, payload| {
+            if pid == 256 {
+                payload.extend([0xee; 100]);
+            }
+        })
+        .unwrap();
+
+        assert_eq!(rewritten.len(), 6 * PACKET_SIZE);
+        assert_eq!(rewritten[..2 * PACKET_SIZE], segment[..2 * PACKET_SIZE]);
+        // The PCR is kept, and the counters run on.
+        let packets: Vec<&[u8]> = packets(&rewritten).collect();
+        assert_eq!(packets[2][4..12], [7, 0x50, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(counters(&rewritten)[2..], [0, 1, 2, 0]);
+
+        let video_pes = pes_of(&rewritten, 256);
+        assert_eq!(video_pes.len(), 1);
+        assert_eq!(video_pes[0], [video.clone(), vec![0xee; 100]].concat());
+        assert_eq!(pes_of(&rewritten, 257), vec![audio]);
+    }
+
+    #[test]
+    fn test_rewrite_pmt() {
+        let mut segment = tables();
+        let mut streams = streams(&segment).unwrap();
+        assert_eq!(
+            streams
+                .iter()
+                .map(|s| (s.stream_type, s.pid))
+                .collect::<Vec<_>>(),
+            vec![(0x1b, 256), (0x0f, 257)]
+        );
+
+        streams[0].stream_type = 0xdb;
+        streams[0].descriptors = vec![0x0f, 4, b'z', b'a', b'v', b'c'];
+        rewrite_pmt(&mut segment, &streams).unwrap();
+
+        assert_eq!(super::streams(&segment).unwrap(), streams);
+        // The CRC of a section with its CRC is 0.
+        let pmt = section(&segment[PACKET_SIZE..]).unwrap();
+        assert_eq!(crc32(pmt), 0);
+        assert_eq!(pmt_pid(&segment), Some(4096));
+    }
+
+    #[test]
+    fn test_renumber_wraps() {
+        let mut continuity = Continuity::new();
+        let mut first: Vec<u8> = (0..16).flat_map(|i| packet(256, i, true)).collect();
+        let mut second = packet(256, 0, true);
+
+        continuity.renumber(&mut first);
+        continuity.renumber(&mut second);
+
+        assert_eq!(counters(&second), vec![0]);
+    }
+}
@@ -0,0 +1,289 @@
+//! Logos and text burnt into the video of encoded renditions.
+//!
+//! Watermarks come with an upload's metadata, or a bucket's default metadata, and
+//! are kept on the renditions of the video status, so that reprocessing burns in
+//! the same marks. They are FFmpeg filters appended to the encode filter graph.
+
+use serde::{Deserialize, Serialize};
+
+/// Width of a logo, as a fraction of the video width, unless set.
+pub const DEFAULT_IMAGE_SCALE: f64 = 0.15;
+
+/// Height of text, as a fraction of the video height, unless set.
+pub const DEFAULT_TEXT_SCALE: f64 = 0.04;
+
+/// Prefix of the storage keys logos are read from. Uploads land under `stream/` and
+/// their packages under `hls/`, so the metadata of an upload can't burn another
+/// upload, or its segments, into its video.
+pub const ASSETS_PREFIX: &str = "assets/";
+
+/// Distance of a mark from the edges of the frame, as a fraction of its short side.
+const MARGIN: f64 = 0.03;
+
+/// What is burnt in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Mark {
+    /// A logo: the storage key of an image FFmpeg decodes, PNG for transparency.
+    Image(String),
+    /// Text, the same for every viewer: segments are shared by all of them, so a
+    /// mark per viewer would take packaging per viewer.
+    Text(String),
+}
+
+impl Mark {
+    /// A logo at storage key `key`, which must name an object under `ASSETS_PREFIX`.
+    pub fn image(key: &str) -> Result<Self, String> {
+        let key = key.trim();
+        let name = key.strip_prefix(ASSETS_PREFIX).unwrap_or_default();
+        let escapes = name
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..");
+        if escapes {
+            return Err(format!(
+                "Invalid watermark image '{}': logos are read from {}",
+                key, ASSETS_PREFIX
+            ));
+        }
+        Ok(Mark::Image(key.to_string()))
+    }
+}
+
+/// Where a mark sits in the frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl Position {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "top-left" => Ok(Position::TopLeft),
+            "top-right" => Ok(Position::TopRight),
+            "bottom-left" => Ok(Position::BottomLeft),
+            "bottom-right" => Ok(Position::BottomRight),
+            "center" | "centre" => Ok(Position::Center),
+            _ => Err(format!("Invalid watermark position '{}'", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Position::TopLeft => "top-left",
+            Position::TopRight => "top-right",
+            Position::BottomLeft => "bottom-left",
+            Position::BottomRight => "bottom-right",
+            Position::Center => "center",
+        }
+    }
+
+    /// `x` and `y` expressions placing a mark `width` by `height` (FFmpeg variable
+    /// names) in the frame, `margin` pixels from its edges.
+    fn coordinates(&self, margin: u32, width: &str, height: &str) -> (String, String) {
+        let left = margin.to_string();
+        let right = format!("main_w-{}-{}", width, margin);
+        let top = margin.to_string();
+        let bottom = format!("main_h-{}-{}", height, margin);
+        match self {
+            Position::TopLeft => (left, top),
+            Position::TopRight => (right, top),
+            Position::BottomLeft => (left, bottom),
+            Position::BottomRight => (right, bottom),
+            Position::Center => (
+                format!("(main_w-{})/2", width),
+                format!("(main_h-{})/2", height),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub mark: Mark,
+    pub position: Position,
+    /// From 0, transparent, to 1, opaque.
+    pub opacity: f64,
+    /// Width of a logo, or height of text, as a fraction of that of the video.
+    pub scale: f64,
+}
+
+impl Watermark {
+    /// An opaque mark in the bottom right corner, of the default scale for its kind.
+    pub fn new(mark: Mark) -> Self {
+        let scale = match mark {
+            Mark::Image(_) => DEFAULT_IMAGE_SCALE,
+            Mark::Text(_) => DEFAULT_TEXT_SCALE,
+        };
+        Self {
+            mark,
+            position: Position::default(),
+            opacity: 1.0,
+            scale,
+        }
+    }
+
+    pub fn with_position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f64) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(format!("Watermark opacity {} is not within 0-1", opacity));
+        }
+        self.opacity = opacity;
+        Ok(self)
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> Result<Self, String> {
+        if !(scale > 0.0 && scale <= 1.0) {
+            return Err(format!("Watermark scale {} is not within 0-1", scale));
+        }
+        self.scale = scale;
+        Ok(self)
+    }
+
+    /// The watermark with its image read from `path`, a local copy of the asset.
+    pub fn with_image_at(&self, path: &str) -> Self {
+        match self.mark {
+            Mark::Image(_) => Self {
+                mark: Mark::Image(path.to_string()),
+                ..self.clone()
+            },
+            Mark::Text(_) => self.clone(),
+        }
+    }
+
+    /// FFmpeg filters burning the mark in, which not every build has.
+    pub fn filters(&self) -> &'static [&'static str] {
+        match self.mark {
+            Mark::Image(_) => &["movie", "colorchannelmixer", "overlay"],
+            Mark::Text(_) => &["drawtext"],
+        }
+    }
+
+    /// Filters burning the mark into video of `width` by `height` taken from the pad
+    /// labelled `input`, their output left unlabelled. An image mark is read from
+    /// where its key says, so the worker points it at a local copy first.
+    pub fn filter_spec(&self, width: u32, height: u32, input: &str) -> String {
+        let margin = (f64::from(width.min(height)) * MARGIN).round() as u32;
+        match &self.mark {
+            Mark::Image(path) => {
+                let (x, y) = self.position.coordinates(margin, "overlay_w", "overlay_h");
+                let mark_width = (f64::from(width) * self.scale).round().max(1.0);
+                format!(
+                    "movie=filename={path},format=rgba,scale={width}:-1,\
+                     colorchannelmixer=aa={opacity:.3}[{input}_mark];\
+                     [{input}][{input}_mark]overlay=x={x}:y={y}:format=auto",
+                    path = quote(path),
+                    width = mark_width,
+                    opacity = self.opacity,
+                    input = input,
+                    x = x,
+                    y = y
+                )
+            }
+            Mark::Text(text) => {
+                let (x, y) = self.position.coordinates(margin, "text_w", "text_h");
+                let size = (f64::from(height) * self.scale).round().max(1.0) as u32;
+                format!(
+                    "[{input}]drawtext=text={text}:expansion=none:fontsize={size}:\
+                     fontcolor=white@{opacity:.3}:borderw={border}:\
+                     bordercolor=black@{opacity:.3}:x={x}:y={y}",
+                    input = input,
+                    text = quote(text),
+                    size = size,
+                    opacity = self.opacity,
+                    border = (size / 16).max(1),
+                    x = x,
+                    y = y
+                )
+            }
+        }
+    }
+
+    /// What the mark is and where, for the preflight notes.
+    pub fn describe(&self) -> String {
+        let mark = match &self.mark {
+            Mark::Image(key) => format!("logo {}", key),
+            Mark::Text(text) => format!("text \"{}\"", text),
+        };
+        format!(
+            "Watermarked with {} at {}, {:.0}% opaque",
+            mark,
+            self.position.as_str(),
+            self.opacity * 100.0
+        )
+    }
+}
+
+/// `value` as the value of a filter option in a filter graph: quoted for the
+/// filter's option parser, then escaped for the graph parser.
+fn quote(value: &str) -> String {
+    let quoted = format!("'{}'", value.replace('\'', "'\\''"));
+    let mut escaped = String::with_capacity(quoted.len());
+    for c in quoted.chars() {
+        if "\\'[],;".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_spec() {
+        let watermark = Watermark::new(Mark::Image("/tmp/logo.png".to_string()))
+            .with_opacity(0.5)
+            .unwrap();
+        assert_eq!(
+            watermark.filter_spec(1280, 720, "v0"),
+            "movie=filename=\\'/tmp/logo.png\\',format=rgba,scale=192:-1,\
+             colorchannelmixer=aa=0.500[v0_mark];\
+             [v0][v0_mark]overlay=x=main_w-overlay_w-22:y=main_h-overlay_h-22:format=auto"
+        );
+        assert_eq!(
+            watermark.filters(),
+            &["movie", "colorchannelmixer", "overlay"]
+        );
+
+        let local = watermark.with_image_at("/tmp/asset");
+        assert_eq!(local.mark, Mark::Image("/tmp/asset".to_string()));
+        assert_eq!(local.opacity, 0.5);
+    }
+
+    #[test]
+    fn test_text_spec() {
+        let watermark = Watermark::new(Mark::Text("Partner's cut: 1,2".to_string()))
+            .with_position(Position::TopLeft);
+        assert_eq!(
+            watermark.filter_spec(1920, 1080, "v1"),
+            "[v1]drawtext=text=\\'Partner\\'\\\\\\'\\'s cut: 1\\,2\\':expansion=none:\
+             fontsize=43:fontcolor=white@1.000:borderw=2:bordercolor=black@1.000:\
+             x=32:y=32"
+        );
+        assert_eq!(
+            watermark.describe(),
+            "Watermarked with text \"Partner's cut: 1,2\" at top-left, 100% opaque"
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        assert_eq!(Position::parse("Top-Right"), Ok(Position::TopRight));
+        assert!(Position::parse("left").is_err());
+        let watermark = Watermark::new(Mark::Text("Preview".to_string()));
+        assert_eq!(watermark.scale, DEFAULT_TEXT_SCALE);
+        assert!(watermark.clone().with_opacity(1.5).is_err());
+        assert!(watermark.clone().with_scale(0.0).is_err());
+        assert_eq!(watermark.with_scale(0.1).unwrap().scale, 0.1);
+    }
+}
@@ -0,0 +1,262 @@
+//! WebVTT subtitle segments, cut from the cues of a text subtitle stream.
+
+/// Subtitle codecs, as FFmpeg names them, whose cues are text we can convert.
+/// Bitmap subtitles (PGS, DVD, DVB) would need OCR.
+const TEXT_CODECS: &[&str] = &["subrip", "srt", "ass", "ssa", "mov_text", "webvtt", "text"];
+
+/// Maps the cue times of every segment onto the media timeline. Cues are timed on
+/// the source timeline, which the audio and video segments keep too, so local time
+/// 0 is media time 0.
+const TIMESTAMP_MAP: &str = "X-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000";
+
+/// Whether subtitles of `codec` can be converted to WebVTT.
+pub fn is_text_codec(codec: &str) -> bool {
+    TEXT_CODECS.contains(&codec)
+}
+
+/// A subtitle shown from `start` to `end`, in seconds on the source timeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    /// Cue payload, already escaped for WebVTT.
+    pub text: String,
+}
+
+impl Cue {
+    /// A cue of plain `text`.
+    pub fn new(start: f64, end: f64, text: &str) -> Self {
+        Self {
+            start,
+            end,
+            text: escape(text.trim()),
+        }
+    }
+
+    /// A cue from an ASS dialogue event as FFmpeg decodes every text subtitle:
+    /// `ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text`. Override
+    /// tags such as `{\i1}` are dropped and hard line breaks kept.
+    pub fn from_ass(start: f64, end: f64, event: &str) -> Self {
+        let text = event.splitn(9, ',').nth(8).unwrap_or(event);
+
+        let mut plain = String::with_capacity(text.len());
+        let mut in_tag = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => in_tag = true,
+                '}' if in_tag => in_tag = false,
+                _ if in_tag => {}
+                '\\' => match chars.peek() {
+                    Some('N') | Some('n') => {
+                        chars.next();
+                        plain.push('\n');
+                    }
+                    Some('h') => {
+                        chars.next();
+                        plain.push(' ');
+                    }
+                    _ => plain.push(c),
+                },
+                _ => plain.push(c),
+            }
+        }
+        Self::new(start, end, &plain)
+    }
+}
+
+/// Parse a sidecar caption file, SubRip (`.srt`) or WebVTT (`.vtt`), into cues in
+/// order. WebVTT cue text is kept as is; SubRip keeps its `<b>`, `<i>` and `<u>`
+/// tags, the others being dropped.
+pub fn parse_captions(text: &str) -> Result<Vec<Cue>, String> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let is_webvtt = text.starts_with("WEBVTT");
+
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        // Blocks without timings are headers, numbers alone, NOTE or STYLE.
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let (start, end) = timing
+            .split_once("-->")
+            .and_then(|(start, rest)| {
+                let end = rest.split_whitespace().next()?;
+                Some((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+            })
+            .ok_or_else(|| format!("Invalid cue timing '{}'", timing.trim()))?;
+
+        let payload = lines.collect::<Vec<_>>().join("\n");
+        let text = match is_webvtt {
+            true => payload.trim().to_string(),
+            false => srt_text(payload.trim()),
+        };
+        cues.push(Cue { start, end, text });
+    }
+
+    if cues.is_empty() {
+        return Err("No cues found".to_string());
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+/// Seconds of a cue timestamp: `HH:MM:SS,mmm` in SubRip, `HH:MM:SS.mmm` or
+/// `MM:SS.mmm` in WebVTT.
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let (clock, millis) = timestamp.split_once([',', '.'])?;
+    let millis: u32 = millis.parse().ok()?;
+    let mut seconds = 0.0;
+    for part in clock.split(':') {
+        seconds = seconds * 60.0 + f64::from(part.parse::<u32>().ok()?);
+    }
+    Some(seconds + f64::from(millis) / 1000.0)
+}
+
+/// SubRip cue text as WebVTT: the tags both share are kept, other tags such as
+/// `<font>` and ASS overrides such as `{\an8}` dropped, the rest escaped.
+fn srt_text(text: &str) -> String {
+    const KEPT: &[&str] = &["b", "i", "u", "/b", "/i", "/u"];
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let tag_end = match c {
+            '<' => rest.find('>'),
+            '{' if rest.starts_with("{\\") => rest.find('}'),
+            _ => None,
+        };
+        if let Some(tag_end) = tag_end {
+            let tag = &rest[1..tag_end];
+            if c == '<' && KEPT.contains(&tag.to_lowercase().as_str()) {
+                out.push_str(&format!("<{}>", tag.to_lowercase()));
+            }
+            rest = &rest[tag_end + 1..];
+            continue;
+        }
+        out.push_str(&escape(&c.to_string()));
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Escape the characters WebVTT cue text reserves.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// `HH:MM:SS.mmm`, as WebVTT cue timings are written.
+fn timestamp(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// The WebVTT segment running `duration` seconds from `start`: every cue shown in
+/// that time, whole, so that cues across a boundary are in both segments as HLS
+/// requires. A segment without cues still gets the header.
+pub fn segment(cues: &[Cue], start: f64, duration: f64) -> String {
+    let end = start + duration;
+    let mut vtt = format!("WEBVTT\n{}\n", TIMESTAMP_MAP);
+    for cue in cues
+        .iter()
+        .filter(|cue| cue.start < end && cue.end > start && !cue.text.is_empty())
+    {
+        vtt.push_str(&format!(
+            "\n{} --> {}\n{}\n",
+            timestamp(cue.start),
+            timestamp(cue.end),
+            cue.text
+        ));
+    }
+    vtt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_ass() {
+        let cue = Cue::from_ass(
+            1.0,
+            2.5,
+            "0,0,Default,,0,0,0,,{\\i1}Hello,{\\i0} world\\Nof <tags>",
+        );
+        assert_eq!(cue.text, "Hello, world\nof &lt;tags&gt;");
+
+        let cue = Cue::from_ass(1.0, 2.5, "3,0,Default,,0,0,0,,Non\\hbreaking");
+        assert_eq!(cue.text, "Non breaking");
+
+        assert!(is_text_codec("subrip"));
+        assert!(!is_text_codec("hdmv_pgs_subtitle"));
+    }
+
+    #[test]
+    fn test_parse_captions() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i> & \
+                   <font color=\"red\">bye</font>\r\n\r\n\
+                   2\r\n01:00:00,250 --> 01:00:03,000\r\n{\\an8}Two\r\nlines\r\n";
+        let cues = parse_captions(srt).unwrap();
+        assert_eq!(
+            cues,
+            vec![
+                Cue {
+                    start: 1.0,
+                    end: 2.5,
+                    text: "<i>Hello</i> &amp; bye".to_string()
+                },
+                Cue {
+                    start: 3600.25,
+                    end: 3603.0,
+                    text: "Two\nlines".to_string()
+                },
+            ]
+        );
+
+        let vtt = "WEBVTT\n\nNOTE from the editor\n\nintro\n00:05.000 --> 00:07.000 align:start\n\
+                   <v Ann>Hi</v>\n";
+        let cues = parse_captions(vtt).unwrap();
+        assert_eq!(cues.len(), 1);
+        assert_eq!((cues[0].start, cues[0].end), (5.0, 7.0));
+        assert_eq!(cues[0].text, "<v Ann>Hi</v>");
+
+        assert!(parse_captions("WEBVTT\n").is_err());
+        assert!(parse_captions("1\n00:00:01 --> soon\nHi\n").is_err());
+    }
+
+    #[test]
+    fn test_segment() {
+        let cues = vec![
+            Cue::new(1.0, 3.0, "First"),
+            Cue::new(5.5, 6.5, "Across"),
+            Cue::new(3725.25, 3727.0, "Late"),
+        ];
+
+        let first = segment(&cues, 0.0, 6.0);
+        assert!(first.starts_with("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n"));
+        assert!(first.contains("\n00:00:01.000 --> 00:00:03.000\nFirst\n"));
+        assert!(first.contains("\n00:00:05.500 --> 00:00:06.500\nAcross\n"));
+        assert!(!first.contains("Late"));
+
+        let second = segment(&cues, 6.0, 6.0);
+        assert!(second.contains("Across"));
+        assert!(!second.contains("First"));
+
+        let last = segment(&cues, 3720.0, 10.0);
+        assert!(last.contains("01:02:05.250 --> 01:02:07.000\nLate\n"));
+
+        assert_eq!(
+            segment(&cues, 10.0, 6.0),
+            "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n"
+        );
+    }
+}
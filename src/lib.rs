@@ -1,7 +1,7 @@
 //! Sinatra - Video Processing Library
 //!
 //! Hexagonal Architecture:
-//! - domain/: Pure business logic (av, hls, jobs)
+//! - domain/: Pure business logic (av, hls, dash, jobs)
 //! - ports/: Trait definitions
 //! - adapters/: Concrete implementations
 //! - application/: Generic services
@@ -41,3 +41,6 @@ pub use domain::av;
 // hls module only for local and worker (not orchestrator)
 #[cfg(any(feature = "local", feature = "aws_worker"))]
 pub use domain::hls;
+
+#[cfg(any(feature = "local", feature = "aws_worker"))]
+pub use domain::dash;
@@ -0,0 +1,21 @@
+use crate::domain::encryption::ContentKey;
+use async_trait::async_trait;
+use std::error::Error;
+
+#[async_trait]
+pub trait KeyStorePort: Send + Sync {
+    /// Save the content key of a key period of a video
+    async fn save_key(
+        &self,
+        video_id: &str,
+        period: usize,
+        key: &ContentKey,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Get the content key of a key period of a video, if there is one
+    async fn get_key(
+        &self,
+        video_id: &str,
+        period: usize,
+    ) -> Result<Option<ContentKey>, Box<dyn Error + Send + Sync>>;
+}
@@ -1,3 +1,4 @@
+pub mod keys;
 pub mod queue;
 pub mod repository;
 pub mod storage;
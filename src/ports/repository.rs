@@ -1,4 +1,6 @@
 use crate::domain::jobs::VideoStatus;
+use crate::domain::renditions::SubtitleTrack;
+use crate::domain::segment_plan::Keyframe;
 use async_trait::async_trait;
 use std::error::Error;
 
@@ -29,6 +31,49 @@ pub trait VideoStateRepository: Send + Sync {
         video_id: &str,
     ) -> Result<usize, Box<dyn Error + Send + Sync>>;
 
-    /// Cleanup video state (after completion)
+    /// Record the size in bytes of an uploaded segment of a rendition
+    async fn record_segment_size(
+        &self,
+        video_id: &str,
+        rendition: &str,
+        segment_index: usize,
+        size: u64,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Get the recorded segment sizes of a rendition, indexed by segment
+    /// (0 for segments with no recorded size)
+    async fn get_segment_sizes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+    ) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>>;
+
+    /// Record the keyframes of an uploaded segment of a rendition
+    async fn record_keyframes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+        segment_index: usize,
+        keyframes: &[Keyframe],
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Get the recorded keyframes of a rendition, indexed by segment
+    /// (empty for segments with none recorded)
+    async fn get_keyframes(
+        &self,
+        video_id: &str,
+        rendition: &str,
+    ) -> Result<Vec<Vec<Keyframe>>, Box<dyn Error + Send + Sync>>;
+
+    /// Add a subtitle track to a video, replacing the one of the same name, without
+    /// touching its progress
+    async fn add_subtitle_track(
+        &self,
+        video_id: &str,
+        track: &SubtitleTrack,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Cleanup the progress state of a video (after completion). The status is
+    /// kept, for captions uploaded later.
     async fn cleanup_video(&self, video_id: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
 }
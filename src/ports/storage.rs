@@ -17,4 +17,7 @@ pub trait StoragePort: Send + Sync {
         local_path: &Path,
         key: &str,
     ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Delete a file from storage
+    async fn delete(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
 }